@@ -0,0 +1,83 @@
+// Read-heavy traffic on an InMemoryStore from several threads, against the
+// same store behind one Mutex, which is how every call used to be
+// serialized.
+//
+//     cargo bench --bench memory_contention
+use blob_store::object_store::memory::InMemoryStore;
+use blob_store::object_store::{IfMatch, ObjectStore, Result};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const KEYS: usize = 1024;
+const BODY_BYTES: usize = 4096;
+const OPS_PER_THREAD: usize = 200_000;
+// One op in this many is a put
+const WRITE_EVERY: usize = 20;
+
+// The store with every call taking one lock
+struct Serialized(Mutex<InMemoryStore>);
+
+impl ObjectStore for Serialized {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.0.lock().unwrap().get(key)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        self.0.lock().unwrap().put(key, body, cond)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        self.0.lock().unwrap().list(prefix, continuation)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.0.lock().unwrap().delete(key)
+    }
+}
+
+fn filled() -> InMemoryStore {
+    let store = InMemoryStore::default();
+    for i in 0..KEYS {
+        store.put(&format!("key/{i}"), &[i as u8; BODY_BYTES], IfMatch::Any).unwrap();
+    }
+    store
+}
+
+// Wall time for `threads` threads to each run their share of ops
+fn run(store: &dyn ObjectStore, threads: usize) -> Duration {
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for t in 0..threads {
+            scope.spawn(move || {
+                let body = [t as u8; BODY_BYTES];
+                for op in 0..OPS_PER_THREAD {
+                    // A cheap spread over the keys that differs per thread
+                    let key = format!("key/{}", (op * 7919 + t * 104_729) % KEYS);
+                    if op % WRITE_EVERY == 0 {
+                        store.put(&key, &body, IfMatch::Any).unwrap();
+                    } else {
+                        store.get(&key).unwrap();
+                    }
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn main() {
+    println!("{:>7}  {:>14}  {:>14}  {:>7}", "threads", "mutex ops/s", "rwlock ops/s", "speedup");
+    // The gap only opens up with as many cores as threads
+    for threads in [1, 2, 4, 8] {
+        let ops = (threads * OPS_PER_THREAD) as f64;
+        let serialized = run(&Serialized(Mutex::new(filled())), threads);
+        let shared = run(&filled(), threads);
+        println!(
+            "{threads:>7}  {:>14.0}  {:>14.0}  {:>6.2}x",
+            ops / serialized.as_secs_f64(),
+            ops / shared.as_secs_f64(),
+            serialized.as_secs_f64() / shared.as_secs_f64()
+        );
+    }
+}
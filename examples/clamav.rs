@@ -0,0 +1,61 @@
+// Scans uploads with a running clamd before they reach the store.
+//
+// Start clamd listening on TCP (e.g. `TCPSocket 3310` in clamd.conf) and run:
+//
+//     cargo run --example clamav -- 127.0.0.1:3310
+use blob_store::object_store::memory::InMemoryStore;
+use blob_store::object_store::scan::{ScanVerdict, Scanner, ScanningStore};
+use blob_store::object_store::{IfMatch, ObjectStore, ObjectStoreError, Result};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+// Speaks clamd's INSTREAM protocol: a command, then length-prefixed chunks,
+// terminated by a zero-length chunk.
+struct ClamdScanner {
+    addr: String,
+}
+
+impl Scanner for ClamdScanner {
+    fn scan(&self, _key: &str, body: &[u8]) -> Result<ScanVerdict> {
+        let mut conn = TcpStream::connect(&self.addr).map_err(ObjectStoreError::Io)?;
+        conn.write_all(b"zINSTREAM\0").map_err(ObjectStoreError::Io)?;
+        for chunk in body.chunks(64 * 1024) {
+            conn.write_all(&(chunk.len() as u32).to_be_bytes()).map_err(ObjectStoreError::Io)?;
+            conn.write_all(chunk).map_err(ObjectStoreError::Io)?;
+        }
+        conn.write_all(&0u32.to_be_bytes()).map_err(ObjectStoreError::Io)?;
+
+        let mut reply = String::new();
+        conn.read_to_string(&mut reply).map_err(ObjectStoreError::Io)?;
+        let reply = reply.trim_end_matches('\0').trim();
+
+        // Replies look like "stream: OK" or "stream: <signature> FOUND"
+        match reply.strip_prefix("stream: ") {
+            Some("OK") => Ok(ScanVerdict::Clean),
+            Some(found) if found.ends_with(" FOUND") => Ok(ScanVerdict::Infected(
+                found.trim_end_matches(" FOUND").to_string(),
+            )),
+            _ => Err(ObjectStoreError::Other(format!("clamd error: {reply}"))),
+        }
+    }
+}
+
+fn main() {
+    let addr = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:3310".to_string());
+    let store = ScanningStore::new(InMemoryStore::default(), ClamdScanner { addr })
+        .with_quarantine("quarantine/");
+
+    match store.put("uploads/readme.txt", b"Hello, world!", IfMatch::Any) {
+        Ok(etag) => println!("clean upload stored with etag {etag}"),
+        Err(e) => println!("clean upload failed: {e:?}"),
+    }
+
+    let eicar = br"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+    match store.put("uploads/eicar.com", eicar, IfMatch::Any) {
+        Err(ObjectStoreError::Blocked(reason)) => {
+            println!("eicar upload blocked: {reason}");
+            println!("quarantined objects: {:?}", store.quarantined().unwrap());
+        }
+        other => println!("unexpected result for eicar upload: {other:?}"),
+    }
+}
@@ -0,0 +1,19 @@
+// Serves a directory over gRPC so other processes can use it through GrpcStore.
+//
+//     cargo run --example grpc_server --features grpc -- ./data 127.0.0.1:50051
+use blob_store::object_store::grpc;
+use blob_store::object_store::local::LocalStore;
+use std::sync::Arc;
+
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let root = args.next().unwrap_or_else(|| "./data".to_string());
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:50051".to_string());
+
+    let store = Arc::new(LocalStore::new(&root));
+    println!("serving {root} on {addr}");
+    grpc::serve(store, addr.parse().expect("invalid listen address"))
+        .await
+        .unwrap();
+}
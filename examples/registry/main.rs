@@ -0,0 +1,37 @@
+// A content-addressed artifact registry built from the crate's pieces: a
+// LocalStore for durability, a read cache in front of it, and an HTTP API
+// with bearer-token auth.
+//
+//     REGISTRY_PUSH_TOKEN=secret cargo run --example registry --features gateway -- ./registry 127.0.0.1:5000
+//
+// Set REGISTRY_PULL_TOKEN too to require a token for pulls; otherwise anyone
+// may pull.
+mod registry;
+mod server;
+
+use blob_store::object_store::cache::CachedStore;
+use blob_store::object_store::local::LocalStore;
+use registry::Registry;
+use server::{Access, Auth};
+use std::sync::Arc;
+use tiny_http::Server;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let root = args.next().unwrap_or_else(|| "./registry".to_string());
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:5000".to_string());
+
+    let push_token = std::env::var("REGISTRY_PUSH_TOKEN").expect("REGISTRY_PUSH_TOKEN not set");
+    let mut auth = Auth::default().with_token(push_token, Access::Push);
+    match std::env::var("REGISTRY_PULL_TOKEN") {
+        Ok(token) => auth = auth.with_token(token, Access::Pull),
+        Err(_) => auth = auth.with_anonymous(Access::Pull),
+    }
+
+    // Blobs never change once written, so cached reads can't go stale
+    let store = CachedStore::new(LocalStore::new(&root), 256 << 20);
+    let registry = Arc::new(Registry::new(Arc::new(store)));
+    let server = Arc::new(Server::http(&addr).expect("could not bind"));
+    println!("serving registry in {root} on {addr}");
+    server::serve(registry, Arc::new(auth), server, 8);
+}
@@ -0,0 +1,251 @@
+// Content-addressed artifact storage: blobs and manifests are keyed by their
+// SHA-256, tags are small mutable pointers to manifests.
+//
+//     blobs/sha256/<hex>          layer and config bytes
+//     manifests/sha256/<hex>      manifest JSON
+//     repos/<repo>/tags/<tag>     digest of the tagged manifest
+use blob_store::object_store::{IfMatch, ObjectStore, ObjectStoreError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum RegistryError {
+    // The request itself is wrong: bad name, bad digest, dangling manifest
+    Invalid(String),
+    Store(ObjectStoreError),
+}
+
+impl From<ObjectStoreError> for RegistryError {
+    fn from(e: ObjectStoreError) -> Self {
+        RegistryError::Store(e)
+    }
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::Invalid(reason) => write!(f, "{reason}"),
+            RegistryError::Store(e) => write!(f, "storage error: {e:?}"),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, RegistryError>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub config: String,
+    pub layers: Vec<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct GcReport {
+    pub manifests_deleted: usize,
+    pub blobs_deleted: usize,
+}
+
+pub fn digest_of(data: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(data))
+}
+
+// The hex part of a well-formed `sha256:<64 hex>` digest
+fn digest_hex(digest: &str) -> Result<&str> {
+    digest
+        .strip_prefix("sha256:")
+        .filter(|hex| hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)))
+        .ok_or_else(|| RegistryError::Invalid(format!("invalid digest {digest:?}")))
+}
+
+fn check_repo(repo: &str) -> Result<()> {
+    let valid_segment = |s: &str| {
+        !s.is_empty()
+            && s.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"._-".contains(&b))
+            && s != "."
+            && s != ".."
+    };
+    if repo.split('/').all(valid_segment) {
+        Ok(())
+    } else {
+        Err(RegistryError::Invalid(format!("invalid repository name {repo:?}")))
+    }
+}
+
+fn check_tag(tag: &str) -> Result<()> {
+    let valid = (1..=128).contains(&tag.len())
+        && !tag.starts_with(['.', '-'])
+        && tag.bytes().all(|b| b.is_ascii_alphanumeric() || b"._-".contains(&b));
+    if valid {
+        Ok(())
+    } else {
+        Err(RegistryError::Invalid(format!("invalid tag {tag:?}")))
+    }
+}
+
+pub struct Registry {
+    store: Arc<dyn ObjectStore>,
+}
+
+impl Registry {
+    pub fn new(store: Arc<dyn ObjectStore>) -> Self {
+        Self { store }
+    }
+
+    // Stores `data` if `expected` really is its digest; uploading a blob
+    // that is already there is a no-op
+    pub fn put_blob(&self, expected: &str, data: &[u8]) -> Result<()> {
+        let hex = digest_hex(expected)?;
+        if digest_of(data) != expected {
+            return Err(RegistryError::Invalid(format!("content does not match digest {expected}")));
+        }
+        self.put_immutable(&format!("blobs/sha256/{hex}"), data)
+    }
+
+    pub fn get_blob(&self, digest: &str) -> Result<Option<Vec<u8>>> {
+        let key = format!("blobs/sha256/{}", digest_hex(digest)?);
+        self.get_verified(&key, digest)
+    }
+
+    pub fn has_blob(&self, digest: &str) -> Result<Option<u64>> {
+        let key = format!("blobs/sha256/{}", digest_hex(digest)?);
+        Ok(self.store.head(&key)?.map(|meta| meta.size))
+    }
+
+    /// Stores a manifest whose config and layers have all been uploaded,
+    /// and points `reference` at it when that's a tag. Returns its digest.
+    pub fn put_manifest(&self, repo: &str, reference: &str, body: &[u8]) -> Result<String> {
+        check_repo(repo)?;
+        let manifest: Manifest =
+            serde_json::from_slice(body).map_err(|e| RegistryError::Invalid(format!("invalid manifest: {e}")))?;
+        for blob in std::iter::once(&manifest.config).chain(&manifest.layers) {
+            if self.has_blob(blob)?.is_none() {
+                return Err(RegistryError::Invalid(format!("manifest references unknown blob {blob}")));
+            }
+        }
+
+        let digest = digest_of(body);
+        let is_digest = reference.starts_with("sha256:");
+        if is_digest && reference != digest {
+            return Err(RegistryError::Invalid(format!("manifest does not match digest {reference}")));
+        }
+        if !is_digest {
+            check_tag(reference)?;
+        }
+        self.put_immutable(&format!("manifests/sha256/{}", digest_hex(&digest)?), body)?;
+        if !is_digest {
+            self.store.put(&tag_key(repo, reference), digest.as_bytes(), IfMatch::Any)?;
+        }
+        Ok(digest)
+    }
+
+    // A manifest by tag or digest, with its digest
+    pub fn get_manifest(&self, repo: &str, reference: &str) -> Result<Option<(String, Vec<u8>)>> {
+        check_repo(repo)?;
+        let digest = if reference.starts_with("sha256:") {
+            reference.to_string()
+        } else {
+            check_tag(reference)?;
+            match self.store.get(&tag_key(repo, reference))? {
+                Some(digest) => String::from_utf8_lossy(&digest).into_owned(),
+                None => return Ok(None),
+            }
+        };
+        let key = format!("manifests/sha256/{}", digest_hex(&digest)?);
+        Ok(self.get_verified(&key, &digest)?.map(|body| (digest, body)))
+    }
+
+    pub fn tags(&self, repo: &str) -> Result<Vec<String>> {
+        check_repo(repo)?;
+        let prefix = format!("repos/{repo}/tags/");
+        // Nested repositories share the prefix; their tags have a slash left
+        let mut tags: Vec<String> = self
+            .list(&prefix)?
+            .into_iter()
+            .map(|key| key[prefix.len()..].to_string())
+            .filter(|tag| !tag.contains('/'))
+            .collect();
+        tags.sort();
+        Ok(tags)
+    }
+
+    // Untags; the manifest and its blobs go at the next gc
+    pub fn delete_tag(&self, repo: &str, tag: &str) -> Result<()> {
+        check_repo(repo)?;
+        check_tag(tag)?;
+        Ok(self.store.delete(&tag_key(repo, tag))?)
+    }
+
+    /// Deletes manifests no tag points at and blobs no remaining manifest
+    /// references. The stores don't record upload times, so a blob pushed
+    /// for a manifest that hasn't arrived yet looks unreferenced: run this
+    /// while pushes are paused.
+    pub fn gc(&self) -> Result<GcReport> {
+        let mut live_manifests = HashSet::new();
+        let mut live_blobs = HashSet::new();
+        for key in self.list("repos/")? {
+            if let Some(digest) = self.store.get(&key)? {
+                live_manifests.insert(String::from_utf8_lossy(&digest).into_owned());
+            }
+        }
+        for digest in &live_manifests {
+            let key = format!("manifests/sha256/{}", digest_hex(digest)?);
+            if let Some(body) = self.store.get(&key)? {
+                let manifest: Manifest = serde_json::from_slice(&body)
+                    .map_err(|e| RegistryError::Invalid(format!("corrupt manifest {digest}: {e}")))?;
+                live_blobs.insert(manifest.config);
+                live_blobs.extend(manifest.layers);
+            }
+        }
+
+        let mut report = GcReport::default();
+        for key in self.list("manifests/sha256/")? {
+            if !live_manifests.contains(&format!("sha256:{}", &key["manifests/sha256/".len()..])) {
+                self.store.delete(&key)?;
+                report.manifests_deleted += 1;
+            }
+        }
+        for key in self.list("blobs/sha256/")? {
+            if !live_blobs.contains(&format!("sha256:{}", &key["blobs/sha256/".len()..])) {
+                self.store.delete(&key)?;
+                report.blobs_deleted += 1;
+            }
+        }
+        Ok(report)
+    }
+
+    fn put_immutable(&self, key: &str, data: &[u8]) -> Result<()> {
+        match self.store.put(key, data, IfMatch::NoneMatch) {
+            Ok(_) | Err(ObjectStoreError::PreconditionFailed) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Refuses to serve content that no longer hashes to its digest
+    fn get_verified(&self, key: &str, digest: &str) -> Result<Option<Vec<u8>>> {
+        match self.store.get(key)? {
+            Some(data) if digest_of(&data) != digest => {
+                Err(RegistryError::Store(ObjectStoreError::Other(format!("{key} is corrupt"))))
+            }
+            data => Ok(data),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut token = None;
+        loop {
+            let (page, next) = self.store.list(prefix, token)?;
+            keys.extend(page);
+            match next {
+                Some(next) => token = Some(next),
+                None => return Ok(keys),
+            }
+        }
+    }
+}
+
+fn tag_key(repo: &str, tag: &str) -> String {
+    format!("repos/{repo}/tags/{tag}")
+}
@@ -0,0 +1,201 @@
+// HTTP API for the registry, shaped after the OCI distribution API:
+//
+//     GET         /v2/                               check credentials
+//     HEAD, GET   /v2/<repo>/blobs/<digest>
+//     PUT         /v2/<repo>/blobs/<digest>          body is the whole blob
+//     HEAD, GET   /v2/<repo>/manifests/<reference>   tag or digest
+//     PUT         /v2/<repo>/manifests/<reference>
+//     DELETE      /v2/<repo>/manifests/<tag>         untag
+//     GET         /v2/<repo>/tags/list
+//     POST        /v2/_gc                            collect garbage
+//
+// Clients authenticate with `Authorization: Bearer <token>`.
+use crate::registry::{Registry, RegistryError};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Pull,
+    // Pull, push, untag and gc
+    Push,
+}
+
+#[derive(Default)]
+pub struct Auth {
+    tokens: HashMap<String, Access>,
+    anonymous: Option<Access>,
+}
+
+impl Auth {
+    pub fn with_token(mut self, token: impl Into<String>, access: Access) -> Self {
+        self.tokens.insert(token.into(), access);
+        self
+    }
+
+    // What requests without credentials may do; nothing by default
+    pub fn with_anonymous(mut self, access: Access) -> Self {
+        self.anonymous = Some(access);
+        self
+    }
+
+    fn access(&self, request: &Request) -> Option<Access> {
+        let token = header(request, "authorization").and_then(|value| value.strip_prefix("Bearer "));
+        match token {
+            Some(token) => self.tokens.get(token.trim()).copied(),
+            None => self.anonymous,
+        }
+    }
+}
+
+type HttpResponse = Response<Box<dyn Read + Send>>;
+
+// Serves requests on `threads` worker threads; blocks until the server is dropped
+pub fn serve(registry: Arc<Registry>, auth: Arc<Auth>, server: Arc<Server>, threads: usize) {
+    let workers: Vec<_> = (0..threads.max(1))
+        .map(|_| {
+            let (registry, auth, server) = (registry.clone(), auth.clone(), server.clone());
+            thread::spawn(move || {
+                while let Ok(mut request) = server.recv() {
+                    let response = handle(&registry, &auth, &mut request);
+                    let _ = request.respond(response);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+fn handle(registry: &Registry, auth: &Auth, request: &mut Request) -> HttpResponse {
+    let path = request.url().split('?').next().unwrap_or("").to_string();
+    let Some(path) = path.strip_prefix("/v2/") else {
+        return error(404, "not found");
+    };
+    let method = request.method().clone();
+    let needed = match method {
+        Method::Get | Method::Head => Access::Pull,
+        _ => Access::Push,
+    };
+    match auth.access(request) {
+        None => {
+            return error(401, "authentication required")
+                .with_header(make_header("WWW-Authenticate", "Bearer realm=\"registry\""));
+        }
+        Some(access) if access < needed => return error(403, "token does not allow this"),
+        Some(_) => {}
+    }
+
+    let result = if path.is_empty() {
+        Ok(json(200, "{}".into()))
+    } else if path == "_gc" && method == Method::Post {
+        registry.gc().map(|report| json(200, serde_json::to_string(&report).expect("report serializes")))
+    } else if let Some(repo) = path.strip_suffix("/tags/list") {
+        registry.tags(repo).map(|tags| json(200, serde_json::json!({ "name": repo, "tags": tags }).to_string()))
+    } else if let Some((_, digest)) = path.rsplit_once("/blobs/") {
+        blob(registry, request, &method, digest)
+    } else if let Some((repo, reference)) = path.rsplit_once("/manifests/") {
+        manifest(registry, request, &method, repo, reference)
+    } else {
+        Ok(error(404, "not found"))
+    };
+
+    result.unwrap_or_else(|e| match e {
+        RegistryError::Invalid(reason) => error(400, &reason),
+        RegistryError::Store(_) => error(500, &e.to_string()),
+    })
+}
+
+fn blob(registry: &Registry, request: &mut Request, method: &Method, digest: &str) -> Result<HttpResponse, RegistryError> {
+    Ok(match method {
+        Method::Head => match registry.has_blob(digest)? {
+            Some(size) => sized(200, size).with_header(digest_header(digest)),
+            None => error(404, "blob unknown"),
+        },
+        Method::Get => match registry.get_blob(digest)? {
+            Some(data) => body(200, data).with_header(digest_header(digest)),
+            None => error(404, "blob unknown"),
+        },
+        Method::Put => {
+            registry.put_blob(digest, &read_body(request)?)?;
+            body(201, Vec::new()).with_header(digest_header(digest))
+        }
+        _ => error(405, "method not allowed"),
+    })
+}
+
+fn manifest(
+    registry: &Registry,
+    request: &mut Request,
+    method: &Method,
+    repo: &str,
+    reference: &str,
+) -> Result<HttpResponse, RegistryError> {
+    Ok(match method {
+        Method::Head | Method::Get => match registry.get_manifest(repo, reference)? {
+            Some((digest, data)) if *method == Method::Head => {
+                sized(200, data.len() as u64).with_header(digest_header(&digest))
+            }
+            Some((digest, data)) => json(200, String::from_utf8_lossy(&data).into_owned()).with_header(digest_header(&digest)),
+            None => error(404, "manifest unknown"),
+        },
+        Method::Put => {
+            let digest = registry.put_manifest(repo, reference, &read_body(request)?)?;
+            body(201, Vec::new()).with_header(digest_header(&digest))
+        }
+        Method::Delete => {
+            registry.delete_tag(repo, reference)?;
+            body(202, Vec::new())
+        }
+        _ => error(405, "method not allowed"),
+    })
+}
+
+fn read_body(request: &mut Request) -> Result<Vec<u8>, RegistryError> {
+    let mut data = Vec::new();
+    request
+        .as_reader()
+        .read_to_end(&mut data)
+        .map_err(|e| RegistryError::Invalid(format!("reading request body: {e}")))?;
+    Ok(data)
+}
+
+fn header<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(name))
+        .map(|h| h.value.as_str())
+}
+
+fn make_header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("valid header")
+}
+
+fn digest_header(digest: &str) -> Header {
+    make_header("Docker-Content-Digest", digest)
+}
+
+fn body(status: u16, data: Vec<u8>) -> HttpResponse {
+    let len = data.len();
+    let reader: Box<dyn Read + Send> = Box::new(Cursor::new(data));
+    Response::new(StatusCode(status), Vec::new(), reader, Some(len), None)
+}
+
+// A HEAD response announcing `size` bytes without sending them
+fn sized(status: u16, size: u64) -> HttpResponse {
+    let reader: Box<dyn Read + Send> = Box::new(std::io::empty());
+    Response::new(StatusCode(status), Vec::new(), reader, Some(size as usize), None)
+}
+
+fn json(status: u16, text: String) -> HttpResponse {
+    body(status, text.into_bytes()).with_header(make_header("Content-Type", "application/json"))
+}
+
+fn error(status: u16, message: &str) -> HttpResponse {
+    json(status, serde_json::json!({ "errors": [{ "message": message }] }).to_string())
+}
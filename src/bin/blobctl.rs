@@ -0,0 +1,195 @@
+// Command-line access to any backend.
+//
+//     blobctl ls   s3://bucket/logs/
+//     blobctl cp   file:///tmp/report.csv s3://bucket/reports/
+//     blobctl cat  s3://bucket/reports/report.csv
+//     blobctl rm   -r file:///tmp/scratch/
+//     blobctl sync --delete file:///srv/assets/ s3://bucket/assets/
+//
+// Locations are URLs: s3://bucket/key, file:///path/key or mem://key (an
+// empty in-memory store, handy for smoke tests). S3 credentials, region and
+// endpoint come from the usual AWS environment.
+use blob_store::object_store::local::LocalStore;
+use blob_store::object_store::memory::InMemoryStore;
+use blob_store::object_store::s3::S3Store;
+use blob_store::object_store::sync::{sync, SyncOptions};
+use blob_store::object_store::{IfMatch, ObjectStore, ObjectStoreError};
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: blobctl <command> [args]
+
+commands:
+  ls <url>                   list keys under a prefix
+  cat <url>                  write an object to stdout
+  cp <src> <dst>             copy one object; '-' is stdin/stdout, a trailing '/' on dst keeps the name
+  rm [-r] <url>              delete an object, or everything under a prefix with -r
+  sync [--delete] <src> <dst>  copy keys under src that are missing or differ at dst
+
+urls: s3://bucket/key, file:///path/key, mem://key";
+
+// A store plus the key (or key prefix) a URL points at within it
+struct Location {
+    store: Box<dyn ObjectStore>,
+    key: String,
+}
+
+fn parse_location(url: &str) -> Result<Location, String> {
+    if let Some(rest) = url.strip_prefix("s3://") {
+        let (bucket, key) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(format!("missing bucket in {url}"));
+        }
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let config = rt.block_on(aws_config::load_defaults(aws_config::BehaviorVersion::latest()));
+        let client = aws_sdk_s3::Client::new(&config);
+        Ok(Location {
+            store: Box::new(S3Store::new(bucket.to_string(), client)),
+            key: key.to_string(),
+        })
+    } else if let Some(path) = url.strip_prefix("file://") {
+        // Everything up to the last '/' is the store root; the rest is the key
+        let (root, key) = match path.rfind('/') {
+            Some(i) => (&path[..=i], &path[i + 1..]),
+            None => (".", path),
+        };
+        Ok(Location {
+            store: Box::new(LocalStore::new(root)),
+            key: key.to_string(),
+        })
+    } else if let Some(key) = url.strip_prefix("mem://") {
+        Ok(Location {
+            store: Box::new(InMemoryStore::default()),
+            key: key.to_string(),
+        })
+    } else {
+        Err(format!("unsupported url {url} (expected s3://, file:// or mem://)"))
+    }
+}
+
+fn describe(e: ObjectStoreError) -> String {
+    e.to_string()
+}
+
+fn list_all(loc: &Location) -> Result<Vec<String>, String> {
+    let mut keys = Vec::new();
+    let mut token = None;
+    loop {
+        let (page, next) = loc.store.list(&loc.key, token).map_err(describe)?;
+        keys.extend(page);
+        match next {
+            Some(next) => token = Some(next),
+            None => return Ok(keys),
+        }
+    }
+}
+
+fn read_object(loc: &Location) -> Result<Vec<u8>, String> {
+    loc.store
+        .get(&loc.key)
+        .map_err(describe)?
+        .ok_or_else(|| format!("{}: no such object", loc.key))
+}
+
+fn cmd_ls(url: &str) -> Result<(), String> {
+    let loc = parse_location(url)?;
+    let mut out = io::stdout().lock();
+    for key in list_all(&loc)? {
+        writeln!(out, "{key}").map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn cmd_cat(url: &str) -> Result<(), String> {
+    let data = read_object(&parse_location(url)?)?;
+    io::stdout().write_all(&data).map_err(|e| e.to_string())
+}
+
+fn cmd_cp(src: &str, dst: &str) -> Result<(), String> {
+    let (data, name) = if src == "-" {
+        let mut data = Vec::new();
+        io::stdin().read_to_end(&mut data).map_err(|e| e.to_string())?;
+        (data, None)
+    } else {
+        let loc = parse_location(src)?;
+        let name = loc.key.rsplit('/').next().map(str::to_string);
+        (read_object(&loc)?, name)
+    };
+
+    if dst == "-" {
+        return io::stdout().write_all(&data).map_err(|e| e.to_string());
+    }
+    let mut loc = parse_location(dst)?;
+    if loc.key.is_empty() || loc.key.ends_with('/') {
+        let name = name.ok_or("destination needs a key when copying from stdin")?;
+        loc.key.push_str(&name);
+    }
+    let etag = loc.store.put(&loc.key, &data, IfMatch::Any).map_err(describe)?;
+    eprintln!("{} ({} bytes, etag {etag})", loc.key, data.len());
+    Ok(())
+}
+
+fn cmd_rm(url: &str, recursive: bool) -> Result<(), String> {
+    let loc = parse_location(url)?;
+    if !recursive && (loc.key.is_empty() || loc.key.ends_with('/')) {
+        return Err(format!("{url} is a prefix; use rm -r to delete everything under it"));
+    }
+    let keys = if recursive { list_all(&loc)? } else { vec![loc.key.clone()] };
+    for key in keys {
+        loc.store.delete(&key).map_err(describe)?;
+        eprintln!("deleted {key}");
+    }
+    Ok(())
+}
+
+fn cmd_sync(src: &str, dst: &str, delete: bool) -> Result<(), String> {
+    let src = parse_location(src)?;
+    let dst = parse_location(dst)?;
+    let options = SyncOptions {
+        delete,
+        dest_prefix: Some(dst.key.clone()),
+        ..Default::default()
+    };
+
+    let report = sync(&*src.store, &*dst.store, &src.key, &options).map_err(describe)?;
+    for (key, e) in &report.errors {
+        eprintln!("{key}: {}", e);
+    }
+    eprintln!(
+        "{} copied, {} up to date, {} deleted",
+        report.copied, report.skipped, report.deleted
+    );
+    if report.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} keys failed", report.errors.len()))
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["ls", url] => cmd_ls(url),
+        ["cat", url] => cmd_cat(url),
+        ["cp", src, dst] => cmd_cp(src, dst),
+        ["rm", url] => cmd_rm(url, false),
+        ["rm", "-r", url] => cmd_rm(url, true),
+        ["sync", src, dst] => cmd_sync(src, dst, false),
+        ["sync", "--delete", src, dst] => cmd_sync(src, dst, true),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("blobctl: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,739 @@
+//! Minimal S3 REST gateway over any `ObjectStore`.
+//!
+//! Serves one bucket in path style (`http://host:port/<bucket>/<key>`) with
+//! GetObject, HeadObject, PutObject, DeleteObject, ListObjectsV2 and
+//! ListBuckets, including `Range`, `If-Match` and `If-None-Match` headers.
+//! Requests are not authenticated; signatures are accepted and ignored, so
+//! this is meant for development environments only.
+
+use crate::object_store::{GetOptions, GetResult, IfMatch, ObjectStore, ObjectStoreError, Result};
+use aws_smithy_types::base64;
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Cursor, Read};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+// The stores don't track modification times
+const EPOCH: &str = "1970-01-01T00:00:00.000Z";
+const S3_NS: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+pub struct Gateway {
+    store: Arc<dyn ObjectStore>,
+    bucket: String,
+}
+
+pub struct GatewayServer {
+    server: Arc<Server>,
+    gateway: Arc<Gateway>,
+}
+
+type GatewayResponse = Response<Box<dyn Read + Send>>;
+
+// Where a truncated ListObjectsV2 response stopped: the store page it was
+// reading, how many of that page's keys it had consumed, and the last
+// common prefix it emitted. Sent to the client as the opaque continuation
+// token, so the next request resumes that page instead of relisting.
+#[derive(Default, Serialize, Deserialize)]
+struct ListPosition {
+    store_token: Option<String>,
+    skip: usize,
+    last_prefix: Option<String>,
+}
+
+impl ListPosition {
+    fn encode(&self) -> String {
+        base64::encode(serde_json::to_vec(self).expect("list position serializes"))
+    }
+
+    fn decode(token: &str) -> Option<Self> {
+        serde_json::from_slice(&base64::decode(token).ok()?).ok()
+    }
+}
+
+impl Gateway {
+    pub fn new(store: Arc<dyn ObjectStore>, bucket: impl Into<String>) -> Self {
+        Self {
+            store,
+            bucket: bucket.into(),
+        }
+    }
+
+    pub fn bind(self, addr: impl ToSocketAddrs) -> Result<GatewayServer> {
+        let server = Server::http(addr)
+            .map_err(|e| ObjectStoreError::Other(format!("gateway bind error: {e}")))?;
+        Ok(GatewayServer {
+            server: Arc::new(server),
+            gateway: Arc::new(self),
+        })
+    }
+
+    fn handle(&self, request: &mut Request) -> GatewayResponse {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let query = parse_query(query);
+        let path = percent_decode_str(path).decode_utf8_lossy().to_string();
+        let path = path.trim_start_matches('/');
+
+        if path.is_empty() {
+            return match request.method() {
+                Method::Get => self.list_buckets(),
+                _ => error_response(405, "MethodNotAllowed", "unsupported method"),
+            };
+        }
+
+        let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+        if bucket != self.bucket {
+            return error_response(404, "NoSuchBucket", "the specified bucket does not exist");
+        }
+        if query.iter().any(|(k, _)| k == "uploads" || k == "uploadId")
+            || header(request, "x-amz-copy-source").is_some()
+        {
+            return error_response(501, "NotImplemented", "multipart and copy are not supported");
+        }
+
+        let result = match (request.method(), key) {
+            (Method::Get, "") => self.list_objects(&query),
+            (Method::Head, "") => Ok(empty_response(200)),
+            (Method::Get, key) => self.get_object(request, key),
+            (Method::Head, key) => self.head_object(request, key),
+            (Method::Put, key) if !key.is_empty() => self.put_object(request, key),
+            (Method::Delete, key) if !key.is_empty() => {
+                self.store.delete(key).map(|_| empty_response(204))
+            }
+            _ => Ok(error_response(405, "MethodNotAllowed", "unsupported method")),
+        };
+
+        result.unwrap_or_else(|e| match e.into_root() {
+            ObjectStoreError::PreconditionFailed => {
+                error_response(412, "PreconditionFailed", "at least one precondition failed")
+            }
+            ObjectStoreError::Blocked(reason) => error_response(403, "AccessDenied", &reason),
+            ObjectStoreError::Unsupported(what) => error_response(501, "NotImplemented", &what),
+            ObjectStoreError::ChecksumMismatch(key) => {
+                error_response(500, "InternalError", &format!("stored object {key} is corrupt"))
+            }
+            ObjectStoreError::QuotaExceeded(limit) => error_response(403, "QuotaExceeded", &limit),
+            ObjectStoreError::ReadOnly(key) => {
+                error_response(403, "AccessDenied", &format!("{key} is in a read-only store"))
+            }
+            ObjectStoreError::InvalidKey(key) => {
+                error_response(400, "InvalidArgument", &format!("invalid key {key:?}"))
+            }
+            ObjectStoreError::InvalidArgument(what) => error_response(400, "InvalidArgument", &what),
+            ObjectStoreError::NotFound(what) => error_response(404, "NoSuchKey", &what),
+            ObjectStoreError::PermissionDenied(msg) => error_response(403, "AccessDenied", &msg),
+            ObjectStoreError::Throttled(msg) => error_response(503, "SlowDown", &msg),
+            ObjectStoreError::Timeout(msg) => error_response(504, "GatewayTimeout", &msg),
+            ObjectStoreError::Unavailable(msg) => error_response(503, "ServiceUnavailable", &msg),
+            ObjectStoreError::Io(e) => error_response(500, "InternalError", &e.to_string()),
+            e @ (ObjectStoreError::Backend { .. } | ObjectStoreError::Context { .. }) => error_response(500, "InternalError", &e.to_string()),
+            ObjectStoreError::Other(msg) => error_response(500, "InternalError", &msg),
+        })
+    }
+
+    fn list_buckets(&self) -> GatewayResponse {
+        let body = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+             <ListAllMyBucketsResult xmlns=\"{S3_NS}\">\
+             <Owner><ID>blob_store</ID><DisplayName>blob_store</DisplayName></Owner>\
+             <Buckets><Bucket><Name>{}</Name><CreationDate>{EPOCH}</CreationDate></Bucket></Buckets>\
+             </ListAllMyBucketsResult>",
+            xml_escape(&self.bucket)
+        );
+        xml_response(200, body)
+    }
+
+    // Returns 304/412 if the request's conditional headers rule out serving `etag`
+    fn check_read_conditions(request: &Request, etag: &str) -> Option<GatewayResponse> {
+        if let Some(expected) = header(request, "if-match")
+            && !etag_matches(expected, etag)
+        {
+            return Some(error_response(412, "PreconditionFailed", "at least one precondition failed"));
+        }
+        if let Some(expected) = header(request, "if-none-match")
+            && etag_matches(expected, etag)
+        {
+            return Some(empty_response(304).with_header(etag_header(etag)));
+        }
+        None
+    }
+
+    fn head_object(&self, request: &Request, key: &str) -> Result<GatewayResponse> {
+        let Some(meta) = self.store.head(key)? else {
+            return Ok(empty_response(404));
+        };
+        if let Some(resp) = Self::check_read_conditions(request, &meta.etag) {
+            return Ok(resp);
+        }
+
+        let headers = vec![etag_header(&meta.etag), last_modified_header()];
+        let reader: Box<dyn Read + Send> = Box::new(io::empty());
+        Ok(Response::new(StatusCode(200), headers, reader, Some(meta.size as usize), None))
+    }
+
+    fn get_object(&self, request: &Request, key: &str) -> Result<GatewayResponse> {
+        let Some(meta) = self.store.head(key)? else {
+            return Ok(error_response(404, "NoSuchKey", "the specified key does not exist"));
+        };
+        if let Some(resp) = Self::check_read_conditions(request, &meta.etag) {
+            return Ok(resp);
+        }
+
+        let range = header(request, "range").and_then(|r| parse_range(r, meta.size));
+        let (status, range, content_range) = match range {
+            Some((start, _)) if start >= meta.size => {
+                let resp = error_response(416, "InvalidRange", "the requested range is not satisfiable")
+                    .with_header(make_header("Content-Range", &format!("bytes */{}", meta.size)));
+                return Ok(resp);
+            }
+            Some((start, end)) => {
+                let content_range = format!("bytes {}-{}/{}", start, end - 1, meta.size);
+                (206, Some(start..end), Some(content_range))
+            }
+            None => (200, None, None),
+        };
+        // Pin the read to the ETag checked above, so a concurrent overwrite
+        // fails with 412 instead of mixing one version's headers with another's bytes
+        let opts = GetOptions {
+            range,
+            if_match: Some(&meta.etag),
+            ..Default::default()
+        };
+        let Some(GetResult::Body { data, .. }) = self.store.get_opts(key, opts)? else {
+            return Ok(error_response(404, "NoSuchKey", "the specified key does not exist"));
+        };
+
+        let mut resp = data_response(status, data)
+            .with_header(etag_header(&meta.etag))
+            .with_header(last_modified_header())
+            .with_header(make_header("Accept-Ranges", "bytes"));
+        if let Some(content_range) = content_range {
+            resp.add_header(make_header("Content-Range", &content_range));
+        }
+        Ok(resp)
+    }
+
+    fn put_object(&self, request: &mut Request, key: &str) -> Result<GatewayResponse> {
+        let mut body = Vec::new();
+        request.as_reader().read_to_end(&mut body).map_err(ObjectStoreError::Io)?;
+
+        let streaming = header(request, "x-amz-content-sha256").is_some_and(|v| v.starts_with("STREAMING-"))
+            || header(request, "content-encoding").is_some_and(|v| v.contains("aws-chunked"));
+        if streaming {
+            body = decode_aws_chunked(&body)?;
+        }
+
+        let if_match = header(request, "if-match").map(|v| v.trim().trim_matches('"').to_string());
+        let cond = match (&if_match, header(request, "if-none-match")) {
+            (Some(tag), _) => IfMatch::Tag(tag),
+            (None, Some("*")) => IfMatch::NoneMatch,
+            (None, Some(_)) => {
+                return Ok(error_response(501, "NotImplemented", "If-None-Match only supports *"));
+            }
+            (None, None) => IfMatch::Any,
+        };
+
+        let etag = self.store.put(key, &body, cond)?;
+        Ok(empty_response(200).with_header(etag_header(&etag)))
+    }
+
+    fn list_objects(&self, query: &[(String, String)]) -> Result<GatewayResponse> {
+        let param = |name: &str| query.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str());
+        let prefix = param("prefix").unwrap_or("");
+        let delimiter = param("delimiter").filter(|d| !d.is_empty());
+        let max_keys = param("max-keys").and_then(|v| v.parse().ok()).unwrap_or(1000usize);
+        let continuation = param("continuation-token");
+        // Only a first request starts after a key; continuations pick up
+        // where the store's listing left off, whatever its order
+        let start_after = param("start-after").filter(|_| continuation.is_none()).unwrap_or("");
+        let position = match continuation.map(ListPosition::decode) {
+            Some(Some(position)) => position,
+            Some(None) => return Ok(error_response(400, "InvalidArgument", "the continuation token is not valid")),
+            None => ListPosition::default(),
+        };
+        let ListPosition { mut store_token, mut skip, mut last_prefix } = position;
+
+        let mut contents = Vec::new();
+        let mut common_prefixes: Vec<String> = Vec::new();
+        let mut resume = None;
+
+        'pages: loop {
+            let (keys, next) = self.store.list(prefix, store_token.clone())?;
+            for (index, key) in keys.into_iter().enumerate().skip(skip) {
+                if key.as_str() <= start_after {
+                    continue;
+                }
+                let grouped = delimiter.and_then(|d| {
+                    key[prefix.len()..]
+                        .find(d)
+                        .map(|i| key[..prefix.len() + i + d.len()].to_string())
+                });
+                // Keys under a common prefix we've already emitted are consumed silently
+                if let Some(common) = &grouped
+                    && (last_prefix.as_ref() == Some(common) || common_prefixes.contains(common))
+                {
+                    continue;
+                }
+                if contents.len() + common_prefixes.len() >= max_keys {
+                    // Asking for no keys gets a complete, empty answer
+                    if max_keys > 0 {
+                        resume = Some(ListPosition {
+                            store_token: store_token.clone(),
+                            skip: index,
+                            last_prefix: last_prefix.clone(),
+                        });
+                    }
+                    break 'pages;
+                }
+                match grouped {
+                    Some(common) => {
+                        last_prefix = Some(common.clone());
+                        common_prefixes.push(common);
+                    }
+                    None => {
+                        let meta = self.store.head(&key)?;
+                        contents.push((key, meta));
+                    }
+                }
+            }
+            match next {
+                Some(token) => {
+                    store_token = Some(token);
+                    skip = 0;
+                }
+                None => break,
+            }
+        }
+        let truncated = resume.is_some();
+
+        let mut body = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><ListBucketResult xmlns=\"{S3_NS}\">\
+             <Name>{}</Name><Prefix>{}</Prefix><KeyCount>{}</KeyCount><MaxKeys>{max_keys}</MaxKeys>\
+             <IsTruncated>{truncated}</IsTruncated>",
+            xml_escape(&self.bucket),
+            xml_escape(prefix),
+            contents.len() + common_prefixes.len(),
+        );
+        if let Some(d) = delimiter {
+            body.push_str(&format!("<Delimiter>{}</Delimiter>", xml_escape(d)));
+        }
+        if let Some(token) = continuation {
+            body.push_str(&format!("<ContinuationToken>{}</ContinuationToken>", xml_escape(token)));
+        }
+        if let Some(resume) = &resume {
+            body.push_str(&format!("<NextContinuationToken>{}</NextContinuationToken>", resume.encode()));
+        }
+        for (key, meta) in &contents {
+            // A key deleted mid-listing is still reported, just without details
+            let (etag, size) = meta.as_ref().map(|m| (m.etag.as_str(), m.size)).unwrap_or(("", 0));
+            body.push_str(&format!(
+                "<Contents><Key>{}</Key><LastModified>{EPOCH}</LastModified><ETag>&quot;{}&quot;</ETag>\
+                 <Size>{size}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+                xml_escape(key),
+                xml_escape(etag),
+            ));
+        }
+        for common in &common_prefixes {
+            body.push_str(&format!("<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>", xml_escape(common)));
+        }
+        body.push_str("</ListBucketResult>");
+
+        Ok(xml_response(200, body))
+    }
+}
+
+impl GatewayServer {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.server
+            .server_addr()
+            .to_ip()
+            .expect("gateway is bound to a TCP address")
+    }
+
+    // Serves requests on `threads` worker threads; blocks forever
+    pub fn run(self, threads: usize) {
+        let workers: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                let server = self.server.clone();
+                let gateway = self.gateway.clone();
+                thread::spawn(move || {
+                    while let Ok(mut request) = server.recv() {
+                        let response = gateway.handle(&mut request);
+                        let _ = request.respond(response);
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn header<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(name))
+        .map(|h| h.value.as_str())
+}
+
+fn make_header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("valid header")
+}
+
+fn etag_header(etag: &str) -> Header {
+    make_header("ETag", &format!("\"{etag}\""))
+}
+
+fn last_modified_header() -> Header {
+    make_header("Last-Modified", "Thu, 01 Jan 1970 00:00:00 GMT")
+}
+
+fn etag_matches(header_value: &str, etag: &str) -> bool {
+    header_value
+        .split(',')
+        .map(|v| v.trim())
+        .any(|v| v == "*" || v.trim_start_matches("W/").trim_matches('"') == etag)
+}
+
+fn data_response(status: u16, data: Vec<u8>) -> GatewayResponse {
+    let len = data.len();
+    let reader: Box<dyn Read + Send> = Box::new(Cursor::new(data));
+    Response::new(StatusCode(status), Vec::new(), reader, Some(len), None)
+}
+
+fn empty_response(status: u16) -> GatewayResponse {
+    data_response(status, Vec::new())
+}
+
+fn xml_response(status: u16, body: String) -> GatewayResponse {
+    data_response(status, body.into_bytes()).with_header(make_header("Content-Type", "application/xml"))
+}
+
+fn error_response(status: u16, code: &str, message: &str) -> GatewayResponse {
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Error><Code>{code}</Code><Message>{}</Message></Error>",
+        xml_escape(message)
+    );
+    xml_response(status, body)
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |s: &str| percent_decode_str(&s.replace('+', " ")).decode_utf8_lossy().to_string();
+            (decode(k), decode(v))
+        })
+        .collect()
+}
+
+// Parses a single `bytes=` range into a half-open range clamped to `size`
+fn parse_range(value: &str, size: u64) -> Option<(u64, u64)> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let n: u64 = suffix.parse().ok()?;
+            Some((size.saturating_sub(n), size))
+        }
+        (start, "") => Some((start.parse().ok()?, size)),
+        (start, end) => {
+            let start: u64 = start.parse().ok()?;
+            let end: u64 = end.parse().ok()?;
+            if end < start {
+                return None;
+            }
+            Some((start, (end + 1).min(size)))
+        }
+    }
+}
+
+// Strips aws-chunked framing: `<hex size>[;ext]\r\n<data>\r\n ... 0\r\n<trailers>`
+fn decode_aws_chunked(body: &[u8]) -> Result<Vec<u8>> {
+    let malformed = || ObjectStoreError::Other("malformed aws-chunked body".to_string());
+    let mut out = Vec::with_capacity(body.len());
+    let mut rest = body;
+    loop {
+        let line_end = rest.windows(2).position(|w| w == b"\r\n").ok_or_else(malformed)?;
+        let line = std::str::from_utf8(&rest[..line_end]).map_err(|_| malformed())?;
+        let size_hex = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_hex, 16).map_err(|_| malformed())?;
+        rest = &rest[line_end + 2..];
+        if size == 0 {
+            return Ok(out);
+        }
+        if rest.len() < size + 2 {
+            return Err(malformed());
+        }
+        out.extend_from_slice(&rest[..size]);
+        rest = &rest[size + 2..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::sharded::ShardedStore;
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+    use aws_sdk_s3::primitives::ByteStream;
+    use aws_sdk_s3::Client;
+
+    fn spawn_gateway() -> Client {
+        spawn_gateway_over(Arc::new(InMemoryStore::default()))
+    }
+
+    fn spawn_gateway_over(store: Arc<dyn ObjectStore>) -> Client {
+        let gateway = Gateway::new(store, "dev-bucket");
+        let server = gateway.bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", server.local_addr());
+        thread::spawn(move || server.run(4));
+
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("dev", "dev", None, None, "static"))
+            .endpoint_url(endpoint)
+            .force_path_style(true)
+            .build();
+        Client::from_conf(config)
+    }
+
+    #[tokio::test]
+    async fn test_put_get_head_delete() {
+        let client = spawn_gateway();
+        let put = client
+            .put_object()
+            .bucket("dev-bucket")
+            .key("dir/hello world.txt")
+            .body(ByteStream::from_static(b"Hello, gateway!"))
+            .send()
+            .await
+            .unwrap();
+        let etag = put.e_tag().unwrap().trim_matches('"').to_string();
+        assert_eq!(etag, format!("{:x}", md5::compute(b"Hello, gateway!")));
+
+        let got = client.get_object().bucket("dev-bucket").key("dir/hello world.txt").send().await.unwrap();
+        let body = got.body.collect().await.unwrap().into_bytes();
+        assert_eq!(&body[..], b"Hello, gateway!");
+
+        let head = client.head_object().bucket("dev-bucket").key("dir/hello world.txt").send().await.unwrap();
+        assert_eq!(head.content_length(), Some(15));
+
+        let ranged = client
+            .get_object()
+            .bucket("dev-bucket")
+            .key("dir/hello world.txt")
+            .range("bytes=7-13")
+            .send()
+            .await
+            .unwrap();
+        let body = ranged.body.collect().await.unwrap().into_bytes();
+        assert_eq!(&body[..], b"gateway");
+
+        client.delete_object().bucket("dev-bucket").key("dir/hello world.txt").send().await.unwrap();
+        let missing = client.get_object().bucket("dev-bucket").key("dir/hello world.txt").send().await;
+        let err = missing.unwrap_err();
+        assert!(err.as_service_error().is_some_and(|e| e.is_no_such_key()));
+        let missing = client.head_object().bucket("dev-bucket").key("dir/hello world.txt").send().await;
+        assert!(missing.unwrap_err().as_service_error().is_some_and(|e| e.is_not_found()));
+    }
+
+    #[tokio::test]
+    async fn test_conditional_put() {
+        let client = spawn_gateway();
+        let first = client
+            .put_object()
+            .bucket("dev-bucket")
+            .key("cas.txt")
+            .body(ByteStream::from_static(b"one"))
+            .if_none_match("*")
+            .send()
+            .await
+            .unwrap();
+
+        let again = client
+            .put_object()
+            .bucket("dev-bucket")
+            .key("cas.txt")
+            .body(ByteStream::from_static(b"two"))
+            .if_none_match("*")
+            .send()
+            .await;
+        assert_eq!(again.unwrap_err().raw_response().unwrap().status().as_u16(), 412);
+
+        let stale = client
+            .put_object()
+            .bucket("dev-bucket")
+            .key("cas.txt")
+            .body(ByteStream::from_static(b"two"))
+            .if_match("\"wrong-etag\"")
+            .send()
+            .await;
+        assert_eq!(stale.unwrap_err().raw_response().unwrap().status().as_u16(), 412);
+
+        client
+            .put_object()
+            .bucket("dev-bucket")
+            .key("cas.txt")
+            .body(ByteStream::from_static(b"two"))
+            .if_match(first.e_tag().unwrap())
+            .send()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_objects_v2() {
+        let client = spawn_gateway();
+        for key in ["a/1.txt", "a/2.txt", "a/sub/3.txt", "a/sub/4.txt", "b.txt"] {
+            client
+                .put_object()
+                .bucket("dev-bucket")
+                .key(key)
+                .body(ByteStream::from_static(b"x"))
+                .send()
+                .await
+                .unwrap();
+        }
+
+        let all = client.list_objects_v2().bucket("dev-bucket").send().await.unwrap();
+        let keys: Vec<_> = all.contents().iter().filter_map(|o| o.key()).collect();
+        assert_eq!(keys, vec!["a/1.txt", "a/2.txt", "a/sub/3.txt", "a/sub/4.txt", "b.txt"]);
+        assert_eq!(all.contents()[0].size(), Some(1));
+
+        let dirs = client
+            .list_objects_v2()
+            .bucket("dev-bucket")
+            .prefix("a/")
+            .delimiter("/")
+            .send()
+            .await
+            .unwrap();
+        let keys: Vec<_> = dirs.contents().iter().filter_map(|o| o.key()).collect();
+        let prefixes: Vec<_> = dirs.common_prefixes().iter().filter_map(|p| p.prefix()).collect();
+        assert_eq!(keys, vec!["a/1.txt", "a/2.txt"]);
+        assert_eq!(prefixes, vec!["a/sub/"]);
+
+        // Page through two keys at a time
+        let mut seen = Vec::new();
+        let mut token = None;
+        loop {
+            let page = client
+                .list_objects_v2()
+                .bucket("dev-bucket")
+                .max_keys(2)
+                .set_continuation_token(token)
+                .send()
+                .await
+                .unwrap();
+            seen.extend(page.contents().iter().filter_map(|o| o.key().map(str::to_string)));
+            token = page.next_continuation_token().map(str::to_string);
+            if !page.is_truncated().unwrap_or(false) {
+                break;
+            }
+        }
+        assert_eq!(seen.len(), 5);
+    }
+
+    // Every key, or common prefix, listed in pages of `page_size`
+    async fn list_paged(client: &Client, delimiter: Option<&str>, page_size: i32) -> Vec<String> {
+        let mut seen = Vec::new();
+        let mut token = None;
+        loop {
+            let page = client
+                .list_objects_v2()
+                .bucket("dev-bucket")
+                .set_delimiter(delimiter.map(str::to_string))
+                .max_keys(page_size)
+                .set_continuation_token(token)
+                .send()
+                .await
+                .unwrap();
+            seen.extend(page.contents().iter().filter_map(|o| o.key().map(str::to_string)));
+            seen.extend(page.common_prefixes().iter().filter_map(|p| p.prefix().map(str::to_string)));
+            token = page.next_continuation_token().map(str::to_string);
+            if !page.is_truncated().unwrap_or(false) {
+                return seen;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_pages_resume_unordered_listings() {
+        // Sharded listings are ordered only within each shard
+        let store = ShardedStore::new()
+            .with_shard("a", Arc::new(InMemoryStore::default()))
+            .with_shard("b", Arc::new(InMemoryStore::default()))
+            .with_shard("c", Arc::new(InMemoryStore::default()));
+        let mut keys: Vec<_> = (0..20).map(|i| format!("k{i:02}")).collect();
+        keys.extend((0..5).map(|i| format!("dir/{i}")));
+        for key in &keys {
+            store.put(key, b"x", IfMatch::Any).unwrap();
+        }
+        let client = spawn_gateway_over(Arc::new(store));
+
+        for page_size in [1, 3, 1000] {
+            let mut seen = list_paged(&client, None, page_size).await;
+            seen.sort();
+            let mut expected = keys.clone();
+            expected.sort();
+            assert_eq!(seen, expected, "pages of {page_size}");
+        }
+        let mut seen = list_paged(&client, Some("/"), 2).await;
+        seen.sort();
+        let mut expected: Vec<_> = keys.iter().filter(|k| !k.contains('/')).cloned().collect();
+        expected.push("dir/".to_string());
+        expected.sort();
+        assert_eq!(seen, expected);
+    }
+
+    #[tokio::test]
+    async fn test_list_edge_cases() {
+        let client = spawn_gateway();
+        client.put_object().bucket("dev-bucket").key("a").body(ByteStream::from_static(b"x")).send().await.unwrap();
+
+        let none = client.list_objects_v2().bucket("dev-bucket").max_keys(0).send().await.unwrap();
+        assert_eq!(none.is_truncated(), Some(false));
+        assert!(none.contents().is_empty());
+        assert_eq!(none.next_continuation_token(), None);
+
+        let bad = client.list_objects_v2().bucket("dev-bucket").continuation_token("a").send().await;
+        assert_eq!(bad.unwrap_err().raw_response().unwrap().status().as_u16(), 400);
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-4", 10), Some((0, 5)));
+        assert_eq!(parse_range("bytes=5-", 10), Some((5, 10)));
+        assert_eq!(parse_range("bytes=-3", 10), Some((7, 10)));
+        assert_eq!(parse_range("bytes=8-100", 10), Some((8, 10)));
+        assert_eq!(parse_range("bytes=5-2", 10), None);
+        assert_eq!(parse_range("bytes=0-1,4-5", 10), None);
+    }
+
+    #[test]
+    fn test_decode_aws_chunked() {
+        let body = b"5;chunk-signature=abc\r\nhello\r\n6\r\n world\r\n0\r\nx-amz-checksum-crc32:AAAA\r\n\r\n";
+        assert_eq!(decode_aws_chunked(body).unwrap(), b"hello world");
+        assert!(decode_aws_chunked(b"zz\r\n").is_err());
+    }
+}
@@ -1 +1,3 @@
+#[cfg(feature = "gateway")]
+pub mod gateway;
 pub mod object_store;
@@ -0,0 +1,336 @@
+use super::{check_conditions, slice_range, GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::{Bound, Range};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use zip::ZipArchive;
+
+enum Location {
+    // Byte offset of the entry's data within the tar file
+    Tar(u64),
+    // Position of the entry in the zip central directory
+    Zip(usize),
+}
+
+struct Entry {
+    meta: ObjectMeta,
+    location: Location,
+}
+
+enum Source {
+    Tar(PathBuf),
+    Zip(Mutex<ZipArchive<File>>),
+}
+
+/// Read-only store over a `.tar` or `.zip` file.
+///
+/// Regular file entries become objects keyed by their path inside the
+/// archive. The whole archive is indexed (size and ETag per entry) when the
+/// store is opened, so get, head and list never scan it again. Tar entries
+/// are read in place; zip entries are decompressed on demand.
+pub struct ArchiveStore {
+    index: BTreeMap<String, Entry>,
+    source: Source,
+}
+
+impl ArchiveStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = File::open(path).map_err(ObjectStoreError::Io)?;
+
+        let mut magic = [0u8; 4];
+        let is_zip = match file.read_exact(&mut magic) {
+            Ok(()) => magic == *b"PK\x03\x04" || magic == *b"PK\x05\x06",
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => false,
+            Err(e) => return Err(ObjectStoreError::Io(e)),
+        };
+        file.rewind().map_err(ObjectStoreError::Io)?;
+
+        if is_zip {
+            Self::index_zip(file)
+        } else {
+            Self::index_tar(file, path)
+        }
+    }
+
+    fn index_tar(file: File, path: &Path) -> Result<Self> {
+        let mut index = BTreeMap::new();
+        let mut archive = tar::Archive::new(file);
+        for entry in archive.entries().map_err(ObjectStoreError::Io)? {
+            let mut entry = entry.map_err(ObjectStoreError::Io)?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let key = normalize_key(&entry.path().map_err(ObjectStoreError::Io)?.to_string_lossy());
+            let offset = entry.raw_file_position();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).map_err(ObjectStoreError::Io)?;
+            index.insert(key, Entry {
+                meta: ObjectMeta {
+                    size: data.len() as u64,
+                    etag: compute_etag(&data),
+                },
+                location: Location::Tar(offset),
+            });
+        }
+
+        Ok(Self {
+            index,
+            source: Source::Tar(path.to_path_buf()),
+        })
+    }
+
+    fn index_zip(file: File) -> Result<Self> {
+        let mut index = BTreeMap::new();
+        let mut archive = ZipArchive::new(file).map_err(map_zip_err)?;
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i).map_err(map_zip_err)?;
+            if entry.is_dir() {
+                continue;
+            }
+            let key = normalize_key(entry.name());
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).map_err(ObjectStoreError::Io)?;
+            index.insert(key, Entry {
+                meta: ObjectMeta {
+                    size: data.len() as u64,
+                    etag: compute_etag(&data),
+                },
+                location: Location::Zip(i),
+            });
+        }
+
+        Ok(Self {
+            index,
+            source: Source::Zip(Mutex::new(archive)),
+        })
+    }
+
+    fn read(&self, entry: &Entry, range: Range<u64>) -> Result<Vec<u8>> {
+        let start = range.start.min(entry.meta.size);
+        let end = range.end.clamp(start, entry.meta.size);
+
+        match (&self.source, &entry.location) {
+            (Source::Tar(path), Location::Tar(offset)) => {
+                let mut file = File::open(path).map_err(ObjectStoreError::Io)?;
+                file.seek(SeekFrom::Start(offset + start)).map_err(ObjectStoreError::Io)?;
+                let mut data = Vec::with_capacity((end - start) as usize);
+                file.take(end - start).read_to_end(&mut data).map_err(ObjectStoreError::Io)?;
+                Ok(data)
+            }
+            (Source::Zip(archive), Location::Zip(i)) => {
+                let mut archive = archive.lock().unwrap();
+                let mut zip_entry = archive.by_index(*i).map_err(map_zip_err)?;
+                let mut data = Vec::with_capacity(entry.meta.size as usize);
+                zip_entry.read_to_end(&mut data).map_err(ObjectStoreError::Io)?;
+                Ok(slice_range(&data, start..end).to_vec())
+            }
+            _ => unreachable!("entry location does not match archive format"),
+        }
+    }
+}
+
+fn compute_etag(data: &[u8]) -> String {
+    format!("{:x}", md5::compute(data))
+}
+
+fn normalize_key(path: &str) -> String {
+    path.trim_start_matches("./").to_string()
+}
+
+fn map_zip_err(e: zip::result::ZipError) -> ObjectStoreError {
+    match e {
+        zip::result::ZipError::Io(e) => ObjectStoreError::Io(e),
+        e => ObjectStoreError::backend(format!("zip error: {e}"), e),
+    }
+}
+
+impl ObjectStore for ArchiveStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.index.get(key) {
+            Some(entry) => Ok(Some(self.read(entry, 0..entry.meta.size)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, _key: &str, _body: &[u8], _cond: IfMatch) -> Result<String> {
+        Err(ObjectStoreError::Unsupported("put on ArchiveStore".to_string()))
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        // Simple pagination: 1000 per page, resuming after the last key returned
+        let page_size = 1000;
+        let start = match &continuation {
+            Some(token) if token.as_str() >= prefix => Bound::Excluded(token.as_str()),
+            _ => Bound::Included(prefix),
+        };
+
+        let mut keys: Vec<String> = self
+            .index
+            .range::<str, _>((start, Bound::Unbounded))
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .take(page_size + 1)
+            .cloned()
+            .collect();
+
+        let next_token = if keys.len() > page_size {
+            keys.truncate(page_size);
+            keys.last().cloned()
+        } else {
+            None
+        };
+
+        Ok((keys, next_token))
+    }
+
+    fn delete(&self, _key: &str) -> Result<()> {
+        Err(ObjectStoreError::Unsupported("delete on ArchiveStore".to_string()))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        Ok(self.index.get(key).map(|entry| entry.meta.clone()))
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        match self.index.get(key) {
+            Some(entry) => Ok(Some(self.read(entry, range)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Conditions are checked against the indexed ETag, so a miss reads nothing
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        let Some(entry) = self.index.get(key) else {
+            return Ok(None);
+        };
+        if let Some(result) = check_conditions(&entry.meta.etag, &opts)? {
+            return Ok(Some(result));
+        }
+        let data = self.read(entry, opts.range.clone().unwrap_or(0..entry.meta.size))?;
+        let meta = opts.include_metadata.then(|| entry.meta.clone());
+        Ok(Some(GetResult::Body { data, meta }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    const FILES: &[(&str, &[u8])] = &[
+        ("assets/app.js", b"console.log('hi');"),
+        ("assets/img/logo.bin", &[0, 159, 146, 150, 255]),
+        ("index.html", b"<html></html>"),
+    ];
+
+    fn build_tar(dir: &Path) -> PathBuf {
+        let path = dir.join("bundle.tar");
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        for (name, data) in FILES {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, format!("./{name}"), *data).unwrap();
+        }
+        builder.finish().unwrap();
+        path
+    }
+
+    fn build_zip(dir: &Path) -> PathBuf {
+        let path = dir.join("bundle.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        writer.add_directory("assets/", options).unwrap();
+        for (name, data) in FILES {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap();
+        path
+    }
+
+    fn check_store(store: &ArchiveStore) {
+        for (name, data) in FILES {
+            assert_eq!(store.get(name).unwrap(), Some(data.to_vec()));
+            let meta = store.head(name).unwrap().unwrap();
+            assert_eq!(meta.size, data.len() as u64);
+            assert_eq!(meta.etag, compute_etag(data));
+        }
+        assert_eq!(store.get("missing.txt").unwrap(), None);
+        assert_eq!(store.head("assets/").unwrap(), None);
+
+        let (keys, next) = store.list("assets/", None).unwrap();
+        assert_eq!(keys, vec!["assets/app.js", "assets/img/logo.bin"]);
+        assert!(next.is_none());
+        let (all, _) = store.list("", None).unwrap();
+        assert_eq!(all.len(), 3);
+
+        assert_eq!(
+            store.get_range("assets/img/logo.bin", 1..3).unwrap(),
+            Some(vec![159, 146])
+        );
+        assert_eq!(store.get_range("index.html", 6..100).unwrap(), Some(b"</html>".to_vec()));
+        assert_eq!(store.get_range("index.html", 100..200).unwrap(), Some(Vec::new()));
+
+        let etag = compute_etag(b"<html></html>");
+        let opts = GetOptions {
+            range: Some(1..5),
+            if_match: Some(&etag),
+            include_metadata: true,
+            ..Default::default()
+        };
+        let result = store.get_opts("index.html", opts).unwrap();
+        let meta = ObjectMeta { size: 13, etag: etag.clone() };
+        assert_eq!(result, Some(GetResult::Body { data: b"html".to_vec(), meta: Some(meta) }));
+        let opts = GetOptions {
+            if_none_match: Some(&etag),
+            ..Default::default()
+        };
+        assert_eq!(store.get_opts("index.html", opts).unwrap(), Some(GetResult::NotModified));
+
+        let result = store.put("new.txt", b"data", IfMatch::Any);
+        assert!(matches!(result, Err(ObjectStoreError::Unsupported(_))));
+        let result = store.delete("index.html");
+        assert!(matches!(result, Err(ObjectStoreError::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_tar_archive() {
+        let tmp = TempDir::new().unwrap();
+        let store = ArchiveStore::open(build_tar(tmp.path())).unwrap();
+        check_store(&store);
+    }
+
+    #[test]
+    fn test_zip_archive() {
+        let tmp = TempDir::new().unwrap();
+        let store = ArchiveStore::open(build_zip(tmp.path())).unwrap();
+        check_store(&store);
+    }
+
+    #[test]
+    fn test_list_pagination() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("many.tar");
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        for i in 0..1003 {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(1);
+            header.set_cksum();
+            builder.append_data(&mut header, format!("f/{i:04}"), &b"x"[..]).unwrap();
+        }
+        builder.finish().unwrap();
+
+        let store = ArchiveStore::open(&path).unwrap();
+        let (first, token) = store.list("f/", None).unwrap();
+        assert_eq!(first.len(), 1000);
+        let (second, token) = store.list("f/", token).unwrap();
+        assert_eq!(second, vec!["f/1000", "f/1001", "f/1002"]);
+        assert!(token.is_none());
+    }
+}
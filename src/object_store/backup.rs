@@ -0,0 +1,239 @@
+use super::sync::list_all;
+use super::{IfMatch, ObjectStore, ObjectStoreError, Result};
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// PAX header records carrying what a tar path can't hold exactly
+const KEY_RECORD: &str = "BLOBSTORE.key";
+const ETAG_RECORD: &str = "BLOBSTORE.etag";
+
+/// Which keys a backup or restore takes. Patterns are globs where `*`
+/// matches any run of characters, `/` included, and `?` any one character.
+#[derive(Debug, Clone, Default)]
+pub struct BackupFilter {
+    // Only keys matching one of these, or every key when empty
+    pub include: Vec<String>,
+    // Keys matching any of these are left out
+    pub exclude: Vec<String>,
+}
+
+impl BackupFilter {
+    pub fn matches(&self, key: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|pattern| glob_match(pattern, key)))
+            && !self.exclude.iter().any(|pattern| glob_match(pattern, key))
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BackupReport {
+    pub objects: usize,
+    pub bytes: u64,
+    // Left out by the filter
+    pub skipped: usize,
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and how much of the text it has taken so far
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// The key itself when it makes a plain relative path, else a stand-in;
+// either way the exact key travels in a PAX record
+fn tar_path(key: &str) -> String {
+    let plain = key.split('/').all(|part| !part.is_empty() && part != "." && part != "..");
+    if plain {
+        key.to_string()
+    } else {
+        format!("unsafe-keys/{:x}", md5::compute(key))
+    }
+}
+
+/// Writes every object under `prefix` that passes `filter` into a tar
+/// stream, for disaster recovery or moving data somewhere offline.
+///
+/// Each object becomes a regular file at its key's path, so the archive
+/// can be unpacked with ordinary tools or served by `ArchiveStore`. The
+/// exact key and the source's ETag are kept in PAX records, which also
+/// covers keys that aren't valid relative paths. Objects are read one at a
+/// time; any failure stops the backup.
+pub fn backup<W: Write>(store: &dyn ObjectStore, prefix: &str, writer: W, filter: &BackupFilter) -> Result<BackupReport> {
+    let mut builder = tar::Builder::new(writer);
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut report = BackupReport::default();
+    for key in list_all(store, prefix)? {
+        if !filter.matches(&key) {
+            report.skipped += 1;
+            continue;
+        }
+        // Deleted since it was listed
+        let Some(meta) = store.head(&key)? else {
+            continue;
+        };
+        let Some(data) = store.get(&key)? else {
+            continue;
+        };
+        builder
+            .append_pax_extensions([(KEY_RECORD, key.as_bytes()), (ETAG_RECORD, meta.etag.as_bytes())])
+            .map_err(ObjectStoreError::Io)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        builder
+            .append_data(&mut header, tar_path(&key), data.as_slice())
+            .map_err(ObjectStoreError::Io)?;
+        report.objects += 1;
+        report.bytes += data.len() as u64;
+    }
+    builder.into_inner().map_err(ObjectStoreError::Io)?.flush().map_err(ObjectStoreError::Io)?;
+    Ok(report)
+}
+
+/// Puts every regular file of a tar stream that passes `filter` back into
+/// `store`, overwriting existing objects. Keys come from the PAX records
+/// `backup` writes, or from the entry's path in archives made by other
+/// tools.
+pub fn restore<R: Read>(store: &dyn ObjectStore, reader: R, filter: &BackupFilter) -> Result<BackupReport> {
+    let mut archive = tar::Archive::new(reader);
+    let mut report = BackupReport::default();
+    for entry in archive.entries().map_err(ObjectStoreError::Io)? {
+        let mut entry = entry.map_err(ObjectStoreError::Io)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let mut key = None;
+        if let Some(records) = entry.pax_extensions().map_err(ObjectStoreError::Io)? {
+            for record in records {
+                let record = record.map_err(ObjectStoreError::Io)?;
+                if record.key() == Ok(KEY_RECORD) {
+                    key = Some(String::from_utf8_lossy(record.value_bytes()).to_string());
+                }
+            }
+        }
+        let key = match key {
+            Some(key) => key,
+            None => {
+                let path = entry.path().map_err(ObjectStoreError::Io)?;
+                path.to_string_lossy().trim_start_matches("./").to_string()
+            }
+        };
+        if !filter.matches(&key) {
+            report.skipped += 1;
+            continue;
+        }
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).map_err(ObjectStoreError::Io)?;
+        store.put(&key, &data, IfMatch::Any)?;
+        report.objects += 1;
+        report.bytes += data.len() as u64;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::archive::ArchiveStore;
+    use crate::object_store::memory::InMemoryStore;
+    use tempfile::TempDir;
+
+    fn source() -> InMemoryStore {
+        let store = InMemoryStore::default();
+        for (key, body) in [
+            ("data/a.json", &b"{}"[..]),
+            ("data/logs/1.log", b"line"),
+            ("data/tmp/scratch", b"junk"),
+            ("data//odd/../key", b"odd"),
+            ("other/x", b"x"),
+        ] {
+            store.put(key, body, IfMatch::Any).unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let src = source();
+        let mut archive = Vec::new();
+        let filter = BackupFilter {
+            include: Vec::new(),
+            exclude: vec!["data/tmp/*".to_string()],
+        };
+        let report = backup(&src, "data/", &mut archive, &filter).unwrap();
+        assert_eq!(report, BackupReport { objects: 3, bytes: 9, skipped: 1 });
+
+        let dst = InMemoryStore::default();
+        let report = restore(&dst, archive.as_slice(), &BackupFilter::default()).unwrap();
+        assert_eq!(report.objects, 3);
+        let mut keys = dst.list("", None).unwrap().0;
+        keys.sort();
+        assert_eq!(keys, vec!["data//odd/../key", "data/a.json", "data/logs/1.log"]);
+        assert_eq!(dst.get("data//odd/../key").unwrap(), Some(b"odd".to_vec()));
+
+        // Restoring only part of the archive
+        let logs = InMemoryStore::default();
+        let filter = BackupFilter {
+            include: vec!["*.log".to_string()],
+            exclude: Vec::new(),
+        };
+        let report = restore(&logs, archive.as_slice(), &filter).unwrap();
+        assert_eq!((report.objects, report.skipped), (1, 2));
+    }
+
+    #[test]
+    fn test_archive_is_portable() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("backup.tar");
+        backup(&source(), "", std::fs::File::create(&path).unwrap(), &BackupFilter::default()).unwrap();
+
+        let archive = ArchiveStore::open(&path).unwrap();
+        assert_eq!(archive.get("data/logs/1.log").unwrap(), Some(b"line".to_vec()));
+
+        // Archives without our PAX records restore by path
+        let mut plain = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o644);
+        plain.append_data(&mut header, "./docs/readme.md", &b"hi!"[..]).unwrap();
+        let dst = InMemoryStore::default();
+        restore(&dst, plain.into_inner().unwrap().as_slice(), &BackupFilter::default()).unwrap();
+        assert_eq!(dst.get("docs/readme.md").unwrap(), Some(b"hi!".to_vec()));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", "anything/at/all"));
+        assert!(glob_match("data/*.json", "data/a/b.json"));
+        assert!(glob_match("?.txt", "a.txt"));
+        assert!(!glob_match("?.txt", "ab.txt"));
+        assert!(!glob_match("data/*", "other/data/x"));
+        assert!(glob_match("*a*b*", "xxaxxbxx"));
+        assert!(!glob_match("*a*b", "xxaxxbxx"));
+    }
+}
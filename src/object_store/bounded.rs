@@ -0,0 +1,173 @@
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, Result};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::{Condvar, Mutex};
+
+// Counting semaphore; std doesn't have one
+struct Semaphore {
+    in_flight: Mutex<usize>,
+    freed: Condvar,
+    limit: usize,
+}
+
+// Releases its slot when dropped, including on panic
+struct Permit<'a>(&'a Semaphore);
+
+impl Semaphore {
+    fn acquire(&self) -> Permit<'_> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        while *in_flight >= self.limit {
+            in_flight = self.freed.wait(in_flight).unwrap();
+        }
+        *in_flight += 1;
+        Permit(self)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.in_flight.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// Wraps a store so at most `max_in_flight` operations run against it at
+/// once; callers beyond that block until a slot frees up.
+///
+/// Share one instance (e.g. behind an `Arc`) between all the threads of a
+/// batch job to keep it from exhausting file descriptors or the backend's
+/// connection pool.
+pub struct BoundedStore<S> {
+    inner: S,
+    semaphore: Semaphore,
+}
+
+impl<S: ObjectStore> BoundedStore<S> {
+    pub fn new(inner: S, max_in_flight: usize) -> Self {
+        Self {
+            inner,
+            semaphore: Semaphore {
+                in_flight: Mutex::new(0),
+                freed: Condvar::new(),
+                limit: max_in_flight.max(1),
+            },
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    // Operations currently running against the inner store
+    pub fn in_flight(&self) -> usize {
+        *self.semaphore.in_flight.lock().unwrap()
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for BoundedStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let _permit = self.semaphore.acquire();
+        self.inner.get(key)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        let _permit = self.semaphore.acquire();
+        self.inner.put(key, body, cond)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let _permit = self.semaphore.acquire();
+        self.inner.list(prefix, continuation)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let _permit = self.semaphore.acquire();
+        self.inner.delete(key)
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let _permit = self.semaphore.acquire();
+        self.inner.head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        let _permit = self.semaphore.acquire();
+        self.inner.get_range(key, range)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        let _permit = self.semaphore.acquire();
+        self.inner.get_opts(key, opts)
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        let _permit = self.semaphore.acquire();
+        self.inner.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        let _permit = self.semaphore.acquire();
+        self.inner.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    // Records the highest number of concurrent gets it has seen
+    #[derive(Default)]
+    struct SlowStore {
+        inner: InMemoryStore,
+        current: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    impl ObjectStore for SlowStore {
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            let now = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(10));
+            self.current.fetch_sub(1, Ordering::SeqCst);
+            self.inner.get(key)
+        }
+        fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+            self.inner.put(key, body, cond)
+        }
+        fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+            self.inner.list(prefix, continuation)
+        }
+        fn delete(&self, key: &str) -> Result<()> {
+            self.inner.delete(key)
+        }
+    }
+
+    #[test]
+    fn test_bounded_object_store() {
+        let store = BoundedStore::new(InMemoryStore::default(), 2);
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+        assert_eq!(store.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_caps_concurrency() {
+        let store = BoundedStore::new(SlowStore::default(), 3);
+        thread::scope(|s| {
+            for _ in 0..16 {
+                s.spawn(|| {
+                    for _ in 0..4 {
+                        store.get("k").unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(store.inner().peak.load(Ordering::SeqCst), 3);
+        assert_eq!(store.in_flight(), 0);
+    }
+}
@@ -0,0 +1,312 @@
+use super::retry::is_transient;
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    // Calls pass through
+    Closed,
+    // Calls fail fast until the cooldown ends
+    Open,
+    // Cooldown over; a single trial call decides whether to close again
+    HalfOpen,
+}
+
+// Called with (from, to) on every state change
+type Listener = Box<dyn Fn(BreakerState, BreakerState) + Send + Sync>;
+
+struct Breaker {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Instant,
+    // A half-open trial call is in flight
+    probing: bool,
+}
+
+/// Wraps a store with a circuit breaker.
+///
+/// After `threshold` consecutive transient failures (as classified by
+/// `retry::is_transient`) the breaker opens and every call fails
+/// immediately with `ObjectStoreError::Unavailable` for `cooldown`. The first call
+/// after that is let through as a trial: success closes the breaker,
+/// failure opens it for another cooldown. Errors the caller caused, such as
+/// `PreconditionFailed`, neither trip nor reset it.
+pub struct CircuitBreakerStore<S> {
+    inner: S,
+    threshold: u32,
+    cooldown: Duration,
+    breaker: Mutex<Breaker>,
+    listener: Option<Listener>,
+}
+
+impl<S: ObjectStore> CircuitBreakerStore<S> {
+    pub fn new(inner: S, threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            inner,
+            threshold: threshold.max(1),
+            cooldown,
+            breaker: Mutex::new(Breaker {
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                opened_at: Instant::now(),
+                probing: false,
+            }),
+            listener: None,
+        }
+    }
+
+    pub fn with_listener(mut self, listener: impl Fn(BreakerState, BreakerState) + Send + Sync + 'static) -> Self {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.breaker.lock().unwrap().state
+    }
+
+    fn transition(&self, breaker: &mut Breaker, to: BreakerState) {
+        let from = breaker.state;
+        if from == to {
+            return;
+        }
+        breaker.state = to;
+        if to == BreakerState::Open {
+            breaker.opened_at = Instant::now();
+        }
+        if let Some(listener) = &self.listener {
+            listener(from, to);
+        }
+    }
+
+    // Decides whether a call may go ahead; Ok(true) means it's the half-open trial
+    fn admit(&self) -> Result<bool> {
+        let mut breaker = self.breaker.lock().unwrap();
+        if breaker.state == BreakerState::Open && breaker.opened_at.elapsed() >= self.cooldown {
+            self.transition(&mut breaker, BreakerState::HalfOpen);
+        }
+        match breaker.state {
+            BreakerState::Closed => Ok(false),
+            BreakerState::HalfOpen if !breaker.probing => {
+                breaker.probing = true;
+                Ok(true)
+            }
+            _ => Err(ObjectStoreError::Unavailable("circuit breaker open: backend is failing".to_string())),
+        }
+    }
+
+    fn call<T>(&self, op: impl FnOnce() -> Result<T>) -> Result<T> {
+        let probe = self.admit()?;
+        let result = op();
+
+        let mut breaker = self.breaker.lock().unwrap();
+        if probe {
+            breaker.probing = false;
+        }
+        match &result {
+            Err(e) if is_transient(e) => {
+                breaker.consecutive_failures += 1;
+                if probe || breaker.consecutive_failures >= self.threshold {
+                    self.transition(&mut breaker, BreakerState::Open);
+                }
+            }
+            // Caller errors say nothing about backend health, except that it answered
+            Err(_) if !probe => {}
+            _ => {
+                breaker.consecutive_failures = 0;
+                self.transition(&mut breaker, BreakerState::Closed);
+            }
+        }
+        result
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for CircuitBreakerStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.call(|| self.inner.get(key))
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        self.call(|| self.inner.put(key, body, cond))
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        self.call(|| self.inner.list(prefix, continuation))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.call(|| self.inner.delete(key))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.call(|| self.inner.head(key))
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.call(|| self.inner.get_range(key, range))
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.call(|| self.inner.get_opts(key, opts))
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.call(|| self.inner.put_tags(key, tags))
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.call(|| self.inner.get_tags(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::{run_object_store_tests, CountingStore};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use uuid::Uuid;
+
+    // Backend that fails every call with an I/O error while `down` is set
+    struct Switchable {
+        inner: InMemoryStore,
+        down: AtomicBool,
+    }
+
+    impl Switchable {
+        fn check(&self) -> Result<()> {
+            if self.down.load(Ordering::SeqCst) {
+                Err(ObjectStoreError::Io(std::io::Error::other("connection refused")))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl ObjectStore for Switchable {
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            self.check()?;
+            self.inner.get(key)
+        }
+        fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+            self.check()?;
+            self.inner.put(key, body, cond)
+        }
+        fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+            self.check()?;
+            self.inner.list(prefix, continuation)
+        }
+        fn delete(&self, key: &str) -> Result<()> {
+            self.check()?;
+            self.inner.delete(key)
+        }
+        fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+            self.check()?;
+            self.inner.put_tags(key, tags)
+        }
+        fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+            self.check()?;
+            self.inner.get_tags(key)
+        }
+    }
+
+    type Events = Arc<Mutex<Vec<(BreakerState, BreakerState)>>>;
+
+    fn breaker(cooldown: Duration) -> (CircuitBreakerStore<CountingStore<Switchable>>, Events) {
+        let backend = Switchable {
+            inner: InMemoryStore::default(),
+            down: AtomicBool::new(false),
+        };
+        let events: Events = Arc::default();
+        let recorded = events.clone();
+        let store = CircuitBreakerStore::new(CountingStore::new(backend), 3, cooldown)
+            .with_listener(move |from, to| recorded.lock().unwrap().push((from, to)));
+        (store, events)
+    }
+
+    fn set_down(store: &CircuitBreakerStore<CountingStore<Switchable>>, down: bool) {
+        store.inner().inner.down.store(down, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_breaker_object_store() {
+        let (store, _) = breaker(Duration::from_secs(1));
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+        assert_eq!(store.state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn test_trips_and_fails_fast() {
+        let (store, events) = breaker(Duration::from_secs(60));
+        set_down(&store, true);
+        for _ in 0..3 {
+            assert!(matches!(store.get("k"), Err(ObjectStoreError::Io(_))));
+        }
+        assert_eq!(store.state(), BreakerState::Open);
+
+        // Fails fast without touching the backend
+        for _ in 0..10 {
+            assert!(matches!(store.get("k"), Err(ObjectStoreError::Unavailable(_))));
+        }
+        assert_eq!(store.inner().count("get"), 3);
+        assert_eq!(*events.lock().unwrap(), vec![(BreakerState::Closed, BreakerState::Open)]);
+    }
+
+    #[test]
+    fn test_success_resets_failure_count() {
+        let (store, _) = breaker(Duration::from_secs(60));
+        for _ in 0..5 {
+            set_down(&store, true);
+            store.get("k").unwrap_err();
+            store.get("k").unwrap_err();
+            set_down(&store, false);
+            store.get("k").unwrap();
+        }
+        assert_eq!(store.state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn test_caller_errors_do_not_trip() {
+        let (store, _) = breaker(Duration::from_secs(60));
+        store.put("k", b"v", IfMatch::Any).unwrap();
+        for _ in 0..10 {
+            let result = store.put("k", b"v", IfMatch::NoneMatch);
+            assert!(matches!(result, Err(ObjectStoreError::PreconditionFailed)));
+        }
+        assert_eq!(store.state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn test_half_open_trial() {
+        let (store, events) = breaker(Duration::from_millis(30));
+        set_down(&store, true);
+        for _ in 0..3 {
+            store.get("k").unwrap_err();
+        }
+
+        // Trial fails: straight back to open
+        thread::sleep(Duration::from_millis(40));
+        assert!(matches!(store.get("k"), Err(ObjectStoreError::Io(_))));
+        assert_eq!(store.state(), BreakerState::Open);
+
+        // Trial succeeds: closed again
+        set_down(&store, false);
+        thread::sleep(Duration::from_millis(40));
+        assert_eq!(store.get("k").unwrap(), None);
+        assert_eq!(store.state(), BreakerState::Closed);
+
+        use BreakerState::*;
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![(Closed, Open), (Open, HalfOpen), (HalfOpen, Open), (Open, HalfOpen), (HalfOpen, Closed)]
+        );
+    }
+}
@@ -0,0 +1,417 @@
+use super::{
+    get_from_body, slice_range, GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct CacheEntry {
+    data: Arc<Vec<u8>>,
+    etag: String,
+    // When the entry was last fetched or revalidated
+    validated_at: Instant,
+    // Position in the LRU order; larger is more recent
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<String, CacheEntry>,
+    // tick -> key, oldest first
+    order: BTreeMap<u64, String>,
+    bytes: usize,
+    next_tick: u64,
+    // Keys being fetched: how many fetches, and whether a write landed
+    // since the first of them began
+    fetching: HashMap<String, (usize, bool)>,
+}
+
+impl Lru {
+    fn touch(&mut self, key: &str) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.order.remove(&entry.tick);
+            entry.tick = self.next_tick;
+            self.order.insert(self.next_tick, key.to_string());
+            self.next_tick += 1;
+        }
+    }
+
+    fn insert(&mut self, key: &str, data: Arc<Vec<u8>>, etag: String, capacity: usize) {
+        self.remove(key);
+        while self.bytes + data.len() > capacity {
+            let Some((_, oldest)) = self.order.pop_first() else { break };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.bytes -= entry.data.len();
+            }
+        }
+        self.bytes += data.len();
+        self.order.insert(self.next_tick, key.to_string());
+        self.entries.insert(key.to_string(), CacheEntry {
+            data,
+            etag,
+            validated_at: Instant::now(),
+            tick: self.next_tick,
+        });
+        self.next_tick += 1;
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.tick);
+            self.bytes -= entry.data.len();
+        }
+    }
+
+    fn begin_fetch(&mut self, key: &str) {
+        self.fetching.entry(key.to_string()).or_default().0 += 1;
+    }
+
+    // Whether what the fetch read may still be current
+    fn end_fetch(&mut self, key: &str) -> bool {
+        let Some((count, written)) = self.fetching.get_mut(key) else {
+            return true;
+        };
+        let current = !*written;
+        *count -= 1;
+        if *count == 0 {
+            self.fetching.remove(key);
+        }
+        current
+    }
+}
+
+/// Wraps a store with an in-memory LRU cache of object bodies.
+///
+/// Gets are served from memory when possible; the cache holds at most
+/// `capacity_bytes` of bodies and objects larger than that bypass it. With
+/// `with_ttl`, an entry older than the TTL is revalidated with a head
+/// request and refetched only if its ETag changed. Puts and deletes through
+/// the wrapper invalidate the key, and a read that overlaps one isn't
+/// cached, so it can't put back what the write replaced. Writes made
+/// directly to the backend are only noticed on revalidation.
+pub struct CachedStore<S> {
+    inner: S,
+    lru: Mutex<Lru>,
+    capacity_bytes: usize,
+    ttl: Option<Duration>,
+}
+
+impl<S: ObjectStore> CachedStore<S> {
+    pub fn new(inner: S, capacity_bytes: usize) -> Self {
+        Self {
+            inner,
+            lru: Mutex::new(Lru::default()),
+            capacity_bytes,
+            ttl: None,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    // Total size of the bodies currently cached
+    pub fn cached_bytes(&self) -> usize {
+        self.lru.lock().unwrap().bytes
+    }
+
+    pub fn invalidate(&self, key: &str) {
+        let mut lru = self.lru.lock().unwrap();
+        lru.remove(key);
+        if let Some((_, written)) = lru.fetching.get_mut(key) {
+            *written = true;
+        }
+    }
+
+    // A cached body and ETag for `key`, revalidating it first if it's stale
+    fn lookup(&self, key: &str) -> Result<Option<(Arc<Vec<u8>>, String)>> {
+        let (data, etag, stale) = {
+            let mut lru = self.lru.lock().unwrap();
+            lru.touch(key);
+            match lru.entries.get(key) {
+                Some(entry) => {
+                    let stale = self.ttl.is_some_and(|ttl| entry.validated_at.elapsed() >= ttl);
+                    (entry.data.clone(), entry.etag.clone(), stale)
+                }
+                None => return Ok(None),
+            }
+        };
+        if !stale {
+            return Ok(Some((data, etag)));
+        }
+
+        // The lock isn't held across the backend call
+        match self.inner.head(key)? {
+            Some(meta) if meta.etag == etag => {
+                if let Some(entry) = self.lru.lock().unwrap().entries.get_mut(key) {
+                    entry.validated_at = Instant::now();
+                }
+                Ok(Some((data, etag)))
+            }
+            _ => {
+                self.invalidate(key);
+                Ok(None)
+            }
+        }
+    }
+
+    fn fetch(&self, key: &str) -> Result<Option<(Arc<Vec<u8>>, String)>> {
+        self.lru.lock().unwrap().begin_fetch(key);
+        let opts = GetOptions {
+            include_metadata: true,
+            ..Default::default()
+        };
+        let result = self.inner.get_opts(key, opts);
+        let mut lru = self.lru.lock().unwrap();
+        let current = lru.end_fetch(key);
+        let Some(GetResult::Body { data, meta }) = result? else {
+            return Ok(None);
+        };
+        let etag = meta
+            .ok_or_else(|| ObjectStoreError::Other("inner store returned no metadata".to_string()))?
+            .etag;
+        let data = Arc::new(data);
+        if current && data.len() <= self.capacity_bytes {
+            lru.insert(key, data.clone(), etag.clone(), self.capacity_bytes);
+        }
+        Ok(Some((data, etag)))
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for CachedStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        if let Some((data, _)) = self.lookup(key)? {
+            return Ok(Some(data.to_vec()));
+        }
+        Ok(self.fetch(key)?.map(|(data, _)| data.to_vec()))
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        // Invalidate even on failure: a failed precondition means our copy may be stale
+        let result = self.inner.put(key, body, cond);
+        self.invalidate(key);
+        result
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        self.inner.list(prefix, continuation)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let result = self.inner.delete(key);
+        self.invalidate(key);
+        result
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        match self.lookup(key)? {
+            Some((data, etag)) => Ok(Some(ObjectMeta {
+                size: data.len() as u64,
+                etag,
+            })),
+            None => self.inner.head(key),
+        }
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        // Ranged reads of uncached objects go straight through without filling the cache
+        match self.lookup(key)? {
+            Some((data, _)) => Ok(Some(slice_range(&data, range).to_vec())),
+            None => self.inner.get_range(key, range),
+        }
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        if let Some((data, etag)) = self.lookup(key)? {
+            return get_from_body(&data, &etag, &opts).map(Some);
+        }
+        // Like get and get_range: whole-object reads fill the cache, ranged ones don't
+        if opts.range.is_some() {
+            return self.inner.get_opts(key, opts);
+        }
+        match self.fetch(key)? {
+            Some((data, etag)) => get_from_body(&data, &etag, &opts).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Tags aren't cached; they don't change the body
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.inner.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.inner.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::chunked::ChunkedStore;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::{run_object_store_tests, CountingStore};
+    use std::sync::mpsc;
+    use std::thread;
+    use uuid::Uuid;
+
+    fn cached(capacity: usize) -> CachedStore<CountingStore<InMemoryStore>> {
+        CachedStore::new(CountingStore::new(InMemoryStore::default()), capacity)
+    }
+
+    // Holds each get_opts between reading and returning until the test
+    // lets it go
+    struct Paused {
+        inner: InMemoryStore,
+        read: mpsc::Sender<()>,
+        resume: Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl ObjectStore for Paused {
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            self.inner.get(key)
+        }
+
+        fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+            self.inner.put(key, body, cond)
+        }
+
+        fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+            self.inner.list(prefix, continuation)
+        }
+
+        fn delete(&self, key: &str) -> Result<()> {
+            self.inner.delete(key)
+        }
+
+        fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+            let result = self.inner.get_opts(key, opts);
+            let _ = self.read.send(());
+            self.resume.lock().unwrap().recv().unwrap();
+            result
+        }
+    }
+
+    #[test]
+    fn test_cached_object_store() {
+        let store = cached(1 << 20);
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_repeated_gets_hit_cache() {
+        let store = cached(1 << 20);
+        store.put("hot.txt", b"hot", IfMatch::Any).unwrap();
+        for _ in 0..5 {
+            assert_eq!(store.get("hot.txt").unwrap(), Some(b"hot".to_vec()));
+        }
+        assert_eq!(store.inner().count("get_opts"), 1);
+
+        let meta = store.head("hot.txt").unwrap().unwrap();
+        assert_eq!(meta.etag, format!("{:x}", md5::compute(b"hot")));
+        assert_eq!(store.get_range("hot.txt", 1..3).unwrap(), Some(b"ot".to_vec()));
+        assert_eq!(store.inner().count("head"), 0);
+        assert_eq!(store.inner().count("get_range"), 0);
+    }
+
+    #[test]
+    fn test_caches_the_backend_etag() {
+        let store = CachedStore::new(ChunkedStore::new(InMemoryStore::default(), 4), 1 << 20);
+        let etag = store.put("big", b"ten bytes!", IfMatch::Any).unwrap();
+        assert_ne!(etag, format!("{:x}", md5::compute(b"ten bytes!")));
+        assert_eq!(store.get("big").unwrap(), Some(b"ten bytes!".to_vec()));
+        assert_eq!(store.head("big").unwrap().unwrap().etag, etag);
+        let fresh = GetOptions { if_none_match: Some(&etag), ..Default::default() };
+        assert_eq!(store.get_opts("big", fresh).unwrap(), Some(GetResult::NotModified));
+    }
+
+    #[test]
+    fn test_read_racing_a_put_is_not_cached() {
+        let (read, reads) = mpsc::channel();
+        let (resume, resumes) = mpsc::channel();
+        let store = CachedStore::new(
+            Paused {
+                inner: InMemoryStore::default(),
+                read,
+                resume: Mutex::new(resumes),
+            },
+            1 << 20,
+        );
+        store.put("k", b"old", IfMatch::Any).unwrap();
+        thread::scope(|scope| {
+            let reader = scope.spawn(|| store.get("k").unwrap());
+            reads.recv().unwrap();
+            store.put("k", b"new", IfMatch::Any).unwrap();
+            resume.send(()).unwrap();
+            assert_eq!(reader.join().unwrap(), Some(b"old".to_vec()));
+        });
+        assert_eq!(store.cached_bytes(), 0);
+        resume.send(()).unwrap();
+        assert_eq!(store.get("k").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(store.cached_bytes(), 3);
+    }
+
+    #[test]
+    fn test_writes_invalidate() {
+        let store = cached(1 << 20);
+        store.put("k", b"one", IfMatch::Any).unwrap();
+        store.get("k").unwrap();
+        store.put("k", b"two", IfMatch::Any).unwrap();
+        assert_eq!(store.get("k").unwrap(), Some(b"two".to_vec()));
+        store.delete("k").unwrap();
+        assert_eq!(store.get("k").unwrap(), None);
+        assert_eq!(store.cached_bytes(), 0);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let store = cached(10);
+        store.put("a", b"aaaa", IfMatch::Any).unwrap();
+        store.put("b", b"bbbb", IfMatch::Any).unwrap();
+        store.put("c", b"cccc", IfMatch::Any).unwrap();
+        store.put("big", b"way too big to cache", IfMatch::Any).unwrap();
+
+        store.get("a").unwrap();
+        store.get("b").unwrap();
+        store.get("a").unwrap();
+        store.get("c").unwrap(); // evicts b, the least recently used
+        assert_eq!(store.cached_bytes(), 8);
+        assert_eq!(store.inner().count("get_opts"), 3);
+
+        store.get("a").unwrap();
+        assert_eq!(store.inner().count("get_opts"), 3);
+        store.get("b").unwrap();
+        assert_eq!(store.inner().count("get_opts"), 4);
+
+        store.get("big").unwrap();
+        store.get("big").unwrap();
+        assert_eq!(store.inner().count("get_opts"), 6);
+        assert!(store.cached_bytes() <= 10);
+    }
+
+    #[test]
+    fn test_ttl_revalidates() {
+        let store = cached(1 << 20).with_ttl(Duration::from_millis(20));
+        store.put("k", b"v1", IfMatch::Any).unwrap();
+        store.get("k").unwrap();
+
+        // Unchanged: revalidated with a head, body still served from memory
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(store.get("k").unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(store.inner().count("head"), 1);
+        assert_eq!(store.inner().count("get_opts"), 1);
+
+        // Changed behind the cache's back: picked up once the TTL expires
+        store.inner().put("k", b"v2", IfMatch::Any).unwrap();
+        assert_eq!(store.get("k").unwrap(), Some(b"v1".to_vec()));
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(store.get("k").unwrap(), Some(b"v2".to_vec()));
+        assert_eq!(store.inner().count("get_opts"), 2);
+    }
+}
@@ -0,0 +1,188 @@
+use super::sync::list_all;
+use super::{IfMatch, ObjectStore, ObjectStoreError, Result};
+use sha2::{Digest as _, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+/// SHA-256 of a blob's content, shown and parsed as 64 lowercase hex digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Digest(pub [u8; 32]);
+
+impl Digest {
+    pub fn of(data: &[u8]) -> Self {
+        Self(Sha256::digest(data).into())
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+impl FromStr for Digest {
+    type Err = ObjectStoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || ObjectStoreError::InvalidArgument(format!("invalid SHA-256 digest {s:?}"));
+        if s.len() != 64 || !s.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+            return Err(invalid());
+        }
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+/// Blobs stored under the SHA-256 of their content on any `ObjectStore`.
+///
+/// A blob's key is `prefix`, then `levels` directories of `width` hex
+/// digits taken from the start of its digest, then the full digest:
+/// `cas/ab/cd/abcd…` with the default two levels of two digits. The fanout
+/// keeps any one directory small on filesystem backends and spreads
+/// requests across key prefixes on S3. Storing content that is already
+/// present costs a `head` and no upload; reads are verified against the
+/// digest, so corruption surfaces as `ObjectStoreError::ChecksumMismatch`.
+pub struct ContentStore<S> {
+    inner: S,
+    prefix: String,
+    levels: usize,
+    width: usize,
+}
+
+impl<S: ObjectStore> ContentStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            prefix: "cas/".to_string(),
+            levels: 2,
+            width: 2,
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    // Directory levels and hex digits per level; levels * width is capped at 64
+    pub fn with_fanout(mut self, levels: usize, width: usize) -> Self {
+        self.width = width.clamp(1, 64);
+        self.levels = levels.min(64 / self.width);
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn key_for(&self, digest: &Digest) -> String {
+        let hex = digest.to_string();
+        let mut key = self.prefix.clone();
+        for level in 0..self.levels {
+            key.push_str(&hex[level * self.width..(level + 1) * self.width]);
+            key.push('/');
+        }
+        key.push_str(&hex);
+        key
+    }
+
+    pub fn put(&self, data: &[u8]) -> Result<Digest> {
+        let digest = Digest::of(data);
+        let key = self.key_for(&digest);
+        if self.inner.head(&key)?.is_some() {
+            return Ok(digest);
+        }
+        match self.inner.put(&key, data, IfMatch::NoneMatch) {
+            Ok(_) => Ok(digest),
+            // Someone stored the same content first
+            Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => Ok(digest),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get(&self, digest: &Digest) -> Result<Option<Vec<u8>>> {
+        let key = self.key_for(digest);
+        match self.inner.get(&key)? {
+            Some(data) if Digest::of(&data) != *digest => Err(ObjectStoreError::ChecksumMismatch(key)),
+            data => Ok(data),
+        }
+    }
+
+    pub fn contains(&self, digest: &Digest) -> Result<bool> {
+        Ok(self.inner.head(&self.key_for(digest))?.is_some())
+    }
+
+    pub fn delete(&self, digest: &Digest) -> Result<()> {
+        self.inner.delete(&self.key_for(digest))
+    }
+
+    // Every stored digest; keys under the prefix that aren't blobs are skipped
+    pub fn digests(&self) -> Result<Vec<Digest>> {
+        Ok(list_all(&self.inner, &self.prefix)?
+            .iter()
+            .filter_map(|key| key.rsplit('/').next()?.parse().ok())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::CountingStore;
+
+    #[test]
+    fn test_digest_round_trip() {
+        let digest = Digest::of(b"hello");
+        let hex = digest.to_string();
+        assert_eq!(hex, "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+        assert_eq!(hex.parse::<Digest>().unwrap(), digest);
+        assert!("2CF24DBA".parse::<Digest>().is_err());
+        assert!(hex.replace('2', "g").parse::<Digest>().is_err());
+    }
+
+    #[test]
+    fn test_put_get_and_dedup() {
+        let store = ContentStore::new(CountingStore::new(InMemoryStore::default()));
+        let digest = store.put(b"layer").unwrap();
+        assert_eq!(store.put(b"layer").unwrap(), digest);
+        assert_eq!(store.inner().count("put"), 1);
+
+        assert_eq!(store.get(&digest).unwrap(), Some(b"layer".to_vec()));
+        assert!(store.contains(&digest).unwrap());
+        assert_eq!(store.get(&Digest::of(b"other")).unwrap(), None);
+
+        let other = store.put(b"other").unwrap();
+        let mut all = store.digests().unwrap();
+        all.sort();
+        let mut expected = vec![digest, other];
+        expected.sort();
+        assert_eq!(all, expected);
+
+        store.delete(&digest).unwrap();
+        assert!(!store.contains(&digest).unwrap());
+    }
+
+    #[test]
+    fn test_fanout_layout() {
+        let digest = Digest::of(b"hello");
+        let store = ContentStore::new(InMemoryStore::default());
+        assert_eq!(store.key_for(&digest), format!("cas/2c/f2/{digest}"));
+        let store = ContentStore::new(InMemoryStore::default()).with_prefix("blobs/").with_fanout(1, 3);
+        assert_eq!(store.key_for(&digest), format!("blobs/2cf/{digest}"));
+        let flat = ContentStore::new(InMemoryStore::default()).with_fanout(0, 2);
+        assert_eq!(flat.key_for(&digest), format!("cas/{digest}"));
+    }
+
+    #[test]
+    fn test_detects_corruption() {
+        let store = ContentStore::new(InMemoryStore::default());
+        let digest = store.put(b"original").unwrap();
+        let key = store.key_for(&digest);
+        store.inner().put(&key, b"tampered", IfMatch::Any).unwrap();
+        assert!(matches!(store.get(&digest), Err(ObjectStoreError::ChecksumMismatch(k)) if k == key));
+    }
+}
@@ -0,0 +1,185 @@
+use super::sync::{for_each_concurrent, list_all};
+use super::{IfMatch, ObjectStore, ObjectStoreError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+// Listing snapshots live in the store itself under this prefix
+pub const SNAPSHOT_PREFIX: &str = ".changes/";
+
+// Objects headed at once while taking a snapshot
+const CONCURRENCY: usize = 8;
+
+/// Keys that changed under a prefix between two `list_changes` calls.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeSet {
+    pub added: Vec<String>,
+    // Present both times with a different ETag
+    pub modified: Vec<String>,
+    pub deleted: Vec<String>,
+    // Pass to the next call to get changes since this one
+    pub token: String,
+}
+
+impl ChangeSet {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.deleted.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    prefix: String,
+    // Key to ETag
+    etags: BTreeMap<String, String>,
+}
+
+/// Reports which keys under `prefix` were added, modified or deleted since
+/// the call that returned `since`, and a new token for the next poll.
+///
+/// Each call lists the prefix, heads every key and saves the key/ETag
+/// snapshot as `.changes/<token>.json` in the store; the diff is against
+/// the snapshot named by `since`. Without a token every key counts as
+/// added. Polling costs a listing plus a head per object, so this suits
+/// prefixes of modest size. Tokens stay valid until `forget_changes`
+/// removes their snapshot, so a poller that crashes before saving the new
+/// token can safely retry with the old one.
+pub fn list_changes(store: &dyn ObjectStore, prefix: &str, since: Option<&str>) -> Result<ChangeSet> {
+    let previous = match since {
+        Some(token) => load(store, token)?,
+        None => Snapshot {
+            prefix: prefix.to_string(),
+            etags: BTreeMap::new(),
+        },
+    };
+    if previous.prefix != prefix {
+        return Err(ObjectStoreError::InvalidArgument(format!(
+            "change token was issued for prefix {:?}, not {prefix:?}",
+            previous.prefix
+        )));
+    }
+
+    let current = snapshot(store, prefix)?;
+    let mut changes = ChangeSet::default();
+    for (key, etag) in &current.etags {
+        match previous.etags.get(key) {
+            None => changes.added.push(key.clone()),
+            Some(old) if old != etag => changes.modified.push(key.clone()),
+            Some(_) => {}
+        }
+    }
+    changes.deleted = previous.etags.keys().filter(|key| !current.etags.contains_key(*key)).cloned().collect();
+
+    changes.token = Uuid::new_v4().to_string();
+    let data = serde_json::to_vec(&current).expect("change snapshot serializes");
+    store.put(&snapshot_key(&changes.token), &data, IfMatch::NoneMatch)?;
+    Ok(changes)
+}
+
+// Deletes the snapshot behind a token once no poller needs it
+pub fn forget_changes(store: &dyn ObjectStore, token: &str) -> Result<()> {
+    store.delete(&snapshot_key(&parse_token(token)?.to_string()))
+}
+
+fn snapshot(store: &dyn ObjectStore, prefix: &str) -> Result<Snapshot> {
+    let keys: Vec<String> = list_all(store, prefix)?
+        .into_iter()
+        .filter(|key| !key.starts_with(SNAPSHOT_PREFIX))
+        .collect();
+    let etags = Mutex::new(BTreeMap::new());
+    let first_err = Mutex::new(None);
+    for_each_concurrent(&keys, CONCURRENCY, |key| match store.head(key) {
+        Ok(Some(meta)) => {
+            etags.lock().unwrap().insert(key.clone(), meta.etag);
+        }
+        // Deleted since it was listed
+        Ok(None) => {}
+        Err(e) => {
+            first_err.lock().unwrap().get_or_insert(e);
+        }
+    });
+    if let Some(e) = first_err.into_inner().unwrap() {
+        return Err(e);
+    }
+    Ok(Snapshot {
+        prefix: prefix.to_string(),
+        etags: etags.into_inner().unwrap(),
+    })
+}
+
+fn load(store: &dyn ObjectStore, token: &str) -> Result<Snapshot> {
+    let key = snapshot_key(&parse_token(token)?.to_string());
+    let data = store
+        .get(&key)?
+        .ok_or_else(|| ObjectStoreError::NotFound(format!("change token {token}")))?;
+    serde_json::from_slice(&data).map_err(|e| ObjectStoreError::Other(format!("corrupt change snapshot {key}: {e}")))
+}
+
+// Tokens name objects in the store, so only accept what we hand out
+fn parse_token(token: &str) -> Result<Uuid> {
+    Uuid::parse_str(token).map_err(|_| ObjectStoreError::InvalidArgument(format!("invalid change token {token:?}")))
+}
+
+fn snapshot_key(token: &str) -> String {
+    format!("{SNAPSHOT_PREFIX}{token}.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+
+    fn put(store: &dyn ObjectStore, key: &str, body: &[u8]) {
+        store.put(key, body, IfMatch::Any).unwrap();
+    }
+
+    #[test]
+    fn test_reports_changes_since_token() {
+        let store = InMemoryStore::default();
+        put(&store, "logs/a", b"1");
+        put(&store, "logs/b", b"1");
+        put(&store, "other/c", b"1");
+
+        let first = list_changes(&store, "logs/", None).unwrap();
+        assert_eq!(first.added, vec!["logs/a", "logs/b"]);
+        assert!(first.modified.is_empty() && first.deleted.is_empty());
+
+        let unchanged = list_changes(&store, "logs/", Some(&first.token)).unwrap();
+        assert!(unchanged.is_empty());
+
+        put(&store, "logs/a", b"2");
+        store.delete("logs/b").unwrap();
+        put(&store, "logs/d", b"1");
+        put(&store, "other/e", b"1");
+        let changes = list_changes(&store, "logs/", Some(&unchanged.token)).unwrap();
+        assert_eq!(changes.added, vec!["logs/d"]);
+        assert_eq!(changes.modified, vec!["logs/a"]);
+        assert_eq!(changes.deleted, vec!["logs/b"]);
+
+        // Old tokens still diff against their own snapshot
+        let again = list_changes(&store, "logs/", Some(&first.token)).unwrap();
+        assert_eq!((again.added, again.deleted), (vec!["logs/d".to_string()], vec!["logs/b".to_string()]));
+    }
+
+    #[test]
+    fn test_snapshots_are_not_reported() {
+        let store = InMemoryStore::default();
+        put(&store, "a", b"1");
+        let first = list_changes(&store, "", None).unwrap();
+        let second = list_changes(&store, "", Some(&first.token)).unwrap();
+        assert!(second.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_bad_tokens() {
+        let store = InMemoryStore::default();
+        let first = list_changes(&store, "logs/", None).unwrap();
+        assert!(list_changes(&store, "other/", Some(&first.token)).is_err());
+        assert!(list_changes(&store, "logs/", Some("../../etc")).is_err());
+        assert!(list_changes(&store, "logs/", Some(&Uuid::new_v4().to_string())).is_err());
+
+        forget_changes(&store, &first.token).unwrap();
+        assert!(list_changes(&store, "logs/", Some(&first.token)).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use super::{get_opts_fallback, slice_range, GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+pub const DEFAULT_SUFFIX: &str = ".sha256";
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Wraps a store so every object carries a SHA-256 checksum that is checked
+/// on every read, turning silent corruption into
+/// `ObjectStoreError::ChecksumMismatch`.
+///
+/// The checksum is kept as hex in a sidecar object, `<key>.sha256` by
+/// default, written after the object itself; sidecars are hidden from
+/// `list` and removed by `delete`, and keys ending in the suffix are
+/// rejected. Objects without a sidecar, such as those written before the
+/// wrapper was introduced, are served unverified. Ranged reads fetch the
+/// whole object so it can be checked. A crash between the object and
+/// sidecar writes leaves a stale checksum that reports as a mismatch until
+/// the object is rewritten.
+pub struct VerifiedStore<S> {
+    inner: S,
+    suffix: String,
+}
+
+impl<S: ObjectStore> VerifiedStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            suffix: DEFAULT_SUFFIX.to_string(),
+        }
+    }
+
+    pub fn with_suffix(mut self, suffix: impl Into<String>) -> Self {
+        self.suffix = suffix.into();
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn sidecar(&self, key: &str) -> String {
+        format!("{key}{}", self.suffix)
+    }
+
+    fn is_sidecar(&self, key: &str) -> bool {
+        key.ends_with(&self.suffix)
+    }
+
+    // Checks `data` against the recorded checksum, if there is one
+    fn verify(&self, key: &str, data: &[u8]) -> Result<()> {
+        let Some(recorded) = self.inner.get(&self.sidecar(key))? else {
+            return Ok(());
+        };
+        if recorded.trim_ascii() == sha256_hex(data).as_bytes() {
+            Ok(())
+        } else {
+            Err(ObjectStoreError::ChecksumMismatch(key.to_string()))
+        }
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for VerifiedStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let Some(data) = self.inner.get(key)? else {
+            return Ok(None);
+        };
+        self.verify(key, &data)?;
+        Ok(Some(data))
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        if self.is_sidecar(key) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        let etag = self.inner.put(key, body, cond)?;
+        self.inner.put(&self.sidecar(key), sha256_hex(body).as_bytes(), IfMatch::Any)?;
+        Ok(etag)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let (keys, next) = self.inner.list(prefix, continuation)?;
+        Ok((keys.into_iter().filter(|key| !self.is_sidecar(key)).collect(), next))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(key)?;
+        self.inner.delete(&self.sidecar(key))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.inner.head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        Ok(self.get(key)?.map(|data| slice_range(&data, range).to_vec()))
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        get_opts_fallback(self, key, &opts)
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        if self.is_sidecar(key) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        self.inner.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.inner.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::local::LocalStore;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use tempfile::TempDir;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_verified_object_store() {
+        let store = VerifiedStore::new(InMemoryStore::default());
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_detects_bit_rot() {
+        let tmp = TempDir::new().unwrap();
+        let store = VerifiedStore::new(LocalStore::new(tmp.path()));
+        store.put("photos/cat.jpg", b"meow meow", IfMatch::Any).unwrap();
+        assert_eq!(store.get("photos/cat.jpg").unwrap(), Some(b"meow meow".to_vec()));
+
+        // Flip a byte behind the store's back
+        std::fs::write(tmp.path().join("photos/cat.jpg"), b"meow meoW").unwrap();
+        let result = store.get("photos/cat.jpg");
+        assert!(matches!(result, Err(ObjectStoreError::ChecksumMismatch(ref key)) if key == "photos/cat.jpg"));
+        assert!(store.get_range("photos/cat.jpg", 0..4).is_err());
+        assert!(store.get_opts("photos/cat.jpg", GetOptions::default()).is_err());
+
+        // Rewriting repairs it
+        store.put("photos/cat.jpg", b"purr", IfMatch::Any).unwrap();
+        assert_eq!(store.get("photos/cat.jpg").unwrap(), Some(b"purr".to_vec()));
+    }
+
+    #[test]
+    fn test_tags_reach_native_tagging() {
+        let tmp = TempDir::new().unwrap();
+        let store = VerifiedStore::new(LocalStore::new(tmp.path()));
+        store.put("report.csv", b"a,b", IfMatch::Any).unwrap();
+        let tags = BTreeMap::from([("class".to_string(), "cold".to_string())]);
+        store.put_tags("report.csv", &tags).unwrap();
+        assert_eq!(store.inner().get_tags("report.csv").unwrap(), Some(tags));
+        assert_eq!(store.inner().list("", None).unwrap().0, vec!["report.csv", "report.csv.sha256"]);
+        assert!(matches!(store.put_tags("report.csv.sha256", &BTreeMap::new()), Err(ObjectStoreError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn test_sidecars_are_managed() {
+        let backend = InMemoryStore::default();
+        backend.put("legacy", b"old", IfMatch::Any).unwrap();
+        let store = VerifiedStore::new(backend);
+
+        // Objects from before the wrapper have no checksum to check
+        assert_eq!(store.get("legacy").unwrap(), Some(b"old".to_vec()));
+
+        store.put("new", b"data", IfMatch::Any).unwrap();
+        let recorded = store.inner().get("new.sha256").unwrap().unwrap();
+        assert_eq!(recorded, sha256_hex(b"data").into_bytes());
+        assert_eq!(store.list("", None).unwrap().0, vec!["legacy", "new"]);
+        assert!(matches!(store.put("x.sha256", b"forged", IfMatch::Any), Err(ObjectStoreError::InvalidKey(_))));
+
+        store.delete("new").unwrap();
+        assert_eq!(store.inner().get("new.sha256").unwrap(), None);
+
+        // A failed conditional put leaves the old checksum alone
+        store.put("new", b"v1", IfMatch::Any).unwrap();
+        assert!(store.put("new", b"v2", IfMatch::NoneMatch).is_err());
+        assert_eq!(store.get("new").unwrap(), Some(b"v1".to_vec()));
+    }
+}
@@ -0,0 +1,417 @@
+use super::sync::for_each_concurrent;
+use super::{check_conditions, GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Mutex;
+
+// Marks an object as a manifest rather than plain content
+const MAGIC: &[u8] = b"BLOBCHUNKS1\n";
+
+// Chunks of every chunked object live under this prefix
+pub const CHUNK_PREFIX: &str = ".chunks/";
+
+/// Where a chunked object's content lives, stored at the object's key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub size: u64,
+    pub chunk_size: u64,
+    pub chunks: u64,
+    // Names the chunk set; derived from the key and content
+    pub id: String,
+}
+
+impl Manifest {
+    fn chunk_key(&self, index: u64) -> String {
+        format!("{CHUNK_PREFIX}{}/{index:08}", self.id)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend(serde_json::to_vec(self).expect("manifest serializes"));
+        data
+    }
+
+    fn decode(key: &str, data: &[u8]) -> Result<Self> {
+        serde_json::from_slice(&data[MAGIC.len()..])
+            .map_err(|e| ObjectStoreError::Other(format!("corrupt chunk manifest at {key}: {e}")))
+    }
+}
+
+enum Stored {
+    Plain,
+    Chunked(Manifest, String),
+}
+
+pub(crate) enum Peeked {
+    Plain,
+    // The whole manifest object and its ETag
+    Manifest(Vec<u8>, String),
+}
+
+// Whether `key` holds a manifest starting with `magic`, reading the rest of
+// it only if so. Retries if the object changes between the two reads.
+pub(crate) fn peek_manifest<S: ObjectStore + ?Sized>(inner: &S, key: &str, magic: &[u8]) -> Result<Option<Peeked>> {
+    let peek = GetOptions {
+        range: Some(0..magic.len() as u64),
+        include_metadata: true,
+        ..Default::default()
+    };
+    for _ in 0..3 {
+        let Some(GetResult::Body { data, meta }) = inner.get_opts(key, peek.clone())? else {
+            return Ok(None);
+        };
+        if data != magic {
+            return Ok(Some(Peeked::Plain));
+        }
+        let etag = meta
+            .ok_or_else(|| ObjectStoreError::Other("inner store returned no metadata".to_string()))?
+            .etag;
+        let pinned = GetOptions {
+            if_match: Some(&etag),
+            ..Default::default()
+        };
+        match inner.get_opts(key, pinned) {
+            Ok(Some(GetResult::Body { data, .. })) => return Ok(Some(Peeked::Manifest(data, etag))),
+            Ok(_) => continue,
+            Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(ObjectStoreError::Other(format!("{key} kept changing while being read")))
+}
+
+/// Wraps a store so objects larger than a threshold are split into
+/// fixed-size chunk objects plus a small manifest at the object's key.
+///
+/// Reads reassemble the chunks; ranged reads fetch only the chunks the
+/// range touches. Chunks live under `.chunks/`, which `list` hides, and
+/// are uploaded `concurrency` at a time. The manifest is written last,
+/// under the caller's condition, so a failed or rejected put leaves the
+/// previous version readable; the chunks it replaced are deleted
+/// afterwards. A chunked object's ETag is its manifest's.
+///
+/// Telling chunked objects from plain ones takes a short peek at the
+/// object, so `head`, `get_range`, `get_opts` and `put` make one more
+/// request than the inner store would. A reader racing an overwrite can
+/// find its chunks gone and fail; retrying reads the new version.
+pub struct ChunkedStore<S> {
+    inner: S,
+    chunk_size: u64,
+    threshold: u64,
+    concurrency: usize,
+}
+
+impl<S: ObjectStore> ChunkedStore<S> {
+    // Objects larger than `chunk_size` are chunked
+    pub fn new(inner: S, chunk_size: u64) -> Self {
+        let chunk_size = chunk_size.max(1);
+        Self {
+            inner,
+            chunk_size,
+            threshold: chunk_size,
+            concurrency: 4,
+        }
+    }
+
+    // Largest object stored as is
+    pub fn with_threshold(mut self, threshold: u64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn lookup(&self, key: &str) -> Result<Option<Stored>> {
+        Ok(match peek_manifest(&self.inner, key, MAGIC)? {
+            Some(Peeked::Manifest(data, etag)) => Some(Stored::Chunked(Manifest::decode(key, &data)?, etag)),
+            Some(Peeked::Plain) => Some(Stored::Plain),
+            None => None,
+        })
+    }
+
+    fn read_range(&self, key: &str, manifest: &Manifest, range: Range<u64>) -> Result<Vec<u8>> {
+        let (start, end) = (range.start.min(manifest.size), range.end.min(manifest.size));
+        if start >= end {
+            return Ok(Vec::new());
+        }
+        let indexes: Vec<u64> = (start / manifest.chunk_size..=(end - 1) / manifest.chunk_size).collect();
+        let chunks = Mutex::new(vec![Vec::new(); indexes.len()]);
+        let first_err = Mutex::new(None);
+        for_each_concurrent(&indexes, self.concurrency, |&index| {
+            let result = self.inner.get(&manifest.chunk_key(index)).and_then(|chunk| {
+                chunk.ok_or_else(|| ObjectStoreError::Other(format!("chunk {index} of {key} is missing")))
+            });
+            match result {
+                Ok(chunk) => chunks.lock().unwrap()[(index - indexes[0]) as usize] = chunk,
+                Err(e) => {
+                    first_err.lock().unwrap().get_or_insert(e);
+                }
+            }
+        });
+        if let Some(e) = first_err.into_inner().unwrap() {
+            return Err(e);
+        }
+
+        let offset = indexes[0] * manifest.chunk_size;
+        let data = chunks.into_inner().unwrap().concat();
+        Ok(data[(start - offset) as usize..(end - offset) as usize].to_vec())
+    }
+
+    fn delete_chunks(&self, manifest: &Manifest) -> Result<()> {
+        (0..manifest.chunks).try_for_each(|index| self.inner.delete(&manifest.chunk_key(index)))
+    }
+
+    // The chunk set behind `key` if it's chunked, for cleaning up after a put
+    fn current_chunks(&self, key: &str) -> Result<Option<Manifest>> {
+        Ok(match self.lookup(key)? {
+            Some(Stored::Chunked(manifest, _)) => Some(manifest),
+            _ => None,
+        })
+    }
+
+    fn put_chunked(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        // The chunk size changes the chunks' boundaries, so it's part of
+        // what identifies them
+        let mut seed = key.as_bytes().to_vec();
+        seed.push(0);
+        seed.extend(format!("{:x}/{}", md5::compute(body), self.chunk_size).as_bytes());
+        let manifest = Manifest {
+            size: body.len() as u64,
+            chunk_size: self.chunk_size,
+            chunks: (body.len() as u64).div_ceil(self.chunk_size),
+            id: format!("{:x}", md5::compute(&seed)),
+        };
+
+        let indexes: Vec<u64> = (0..manifest.chunks).collect();
+        let first_err = Mutex::new(None);
+        for_each_concurrent(&indexes, self.concurrency, |&index| {
+            let start = (index * self.chunk_size) as usize;
+            let end = (start + self.chunk_size as usize).min(body.len());
+            if let Err(e) = self.inner.put(&manifest.chunk_key(index), &body[start..end], IfMatch::Any) {
+                first_err.lock().unwrap().get_or_insert(e);
+            }
+        });
+        let result = match first_err.into_inner().unwrap() {
+            Some(e) => Err(e),
+            None => self.inner.put(key, &manifest.encode(), cond),
+        };
+        if result.is_err() {
+            // Unless the object already is this very content
+            if self.current_chunks(key)?.is_none_or(|current| current.id != manifest.id) {
+                let _ = self.delete_chunks(&manifest);
+            }
+        }
+        result
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for ChunkedStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.inner.get(key)? {
+            Some(data) if data.starts_with(MAGIC) => {
+                let manifest = Manifest::decode(key, &data)?;
+                self.read_range(key, &manifest, 0..manifest.size).map(Some)
+            }
+            data => Ok(data),
+        }
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        let previous = self.current_chunks(key)?;
+        // Plain content that happens to start like a manifest is chunked too
+        let etag = if body.len() as u64 > self.threshold || body.starts_with(MAGIC) {
+            self.put_chunked(key, body, cond)?
+        } else {
+            self.inner.put(key, body, cond)?
+        };
+        if let Some(previous) = previous
+            && self.current_chunks(key)?.is_none_or(|current| current.id != previous.id)
+        {
+            self.delete_chunks(&previous)?;
+        }
+        Ok(etag)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let (keys, next) = self.inner.list(prefix, continuation)?;
+        Ok((keys.into_iter().filter(|key| !key.starts_with(CHUNK_PREFIX)).collect(), next))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let previous = self.current_chunks(key)?;
+        self.inner.delete(key)?;
+        match previous {
+            Some(manifest) => self.delete_chunks(&manifest),
+            None => Ok(()),
+        }
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        match self.lookup(key)? {
+            Some(Stored::Chunked(manifest, etag)) => Ok(Some(ObjectMeta {
+                size: manifest.size,
+                etag,
+            })),
+            Some(Stored::Plain) => self.inner.head(key),
+            None => Ok(None),
+        }
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        match self.lookup(key)? {
+            Some(Stored::Chunked(manifest, _)) => self.read_range(key, &manifest, range).map(Some),
+            Some(Stored::Plain) => self.inner.get_range(key, range),
+            None => Ok(None),
+        }
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        let (manifest, etag) = match self.lookup(key)? {
+            Some(Stored::Chunked(manifest, etag)) => (manifest, etag),
+            Some(Stored::Plain) => return self.inner.get_opts(key, opts),
+            None => return Ok(None),
+        };
+        if let Some(result) = check_conditions(&etag, &opts)? {
+            return Ok(Some(result));
+        }
+        let data = self.read_range(key, &manifest, opts.range.clone().unwrap_or(0..manifest.size))?;
+        let meta = opts.include_metadata.then_some(ObjectMeta {
+            size: manifest.size,
+            etag,
+        });
+        Ok(Some(GetResult::Body { data, meta }))
+    }
+
+    // On the manifest or plain object at the key, which every put replaces
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.inner.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.inner.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::{run_object_store_tests, run_oracle_tests, CountingStore};
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    fn chunk_count(store: &ChunkedStore<impl ObjectStore>) -> usize {
+        store.inner().list(CHUNK_PREFIX, None).unwrap().0.len()
+    }
+
+    #[test]
+    fn test_chunked_object_store() {
+        let store = ChunkedStore::new(InMemoryStore::default(), 4096).with_threshold(8);
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+        // Bodies of a few bytes, split into two or three chunks each
+        let store = ChunkedStore::new(InMemoryStore::default(), 3).with_threshold(4);
+        run_oracle_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_splits_and_reassembles() {
+        let store = ChunkedStore::new(InMemoryStore::default(), 10);
+        let body: Vec<u8> = (0..95).collect();
+        let etag = store.put("big", &body, IfMatch::Any).unwrap();
+        assert_eq!(chunk_count(&store), 10);
+        assert!(store.inner().get("big").unwrap().unwrap().starts_with(MAGIC));
+
+        assert_eq!(store.get("big").unwrap(), Some(body.clone()));
+        assert_eq!(store.head("big").unwrap(), Some(ObjectMeta { size: 95, etag: etag.clone() }));
+        assert_eq!(store.list("", None).unwrap().0, vec!["big"]);
+
+        // Small objects are stored as they are
+        store.put("small", b"0123456789", IfMatch::Any).unwrap();
+        assert_eq!(store.inner().get("small").unwrap(), Some(b"0123456789".to_vec()));
+
+        // Conditional puts are against the manifest's ETag
+        assert!(store.put("big", b"x", IfMatch::Tag("wrong")).is_err());
+        store.put("big", &body[..50], IfMatch::Tag(&etag)).unwrap();
+        assert_eq!(store.get("big").unwrap(), Some(body[..50].to_vec()));
+        assert_eq!(chunk_count(&store), 5);
+    }
+
+    #[test]
+    fn test_ranges_read_only_needed_chunks() {
+        let store = ChunkedStore::new(CountingStore::new(InMemoryStore::default()), 10).with_concurrency(1);
+        let body: Vec<u8> = (0..100).collect();
+        store.put("big", &body, IfMatch::Any).unwrap();
+
+        let gets = store.inner().count("get");
+        assert_eq!(store.get_range("big", 25..42).unwrap(), Some(body[25..42].to_vec()));
+        // Chunks 2, 3 and 4
+        assert_eq!(store.inner().count("get") - gets, 3);
+        assert_eq!(store.get_range("big", 95..200).unwrap(), Some(body[95..].to_vec()));
+        assert_eq!(store.get_range("big", 200..300).unwrap(), Some(Vec::new()));
+
+        let opts = GetOptions {
+            range: Some(10..20),
+            include_metadata: true,
+            ..Default::default()
+        };
+        let Some(GetResult::Body { data, meta }) = store.get_opts("big", opts).unwrap() else {
+            panic!("expected a body");
+        };
+        assert_eq!((data, meta.unwrap().size), (body[10..20].to_vec(), 100));
+    }
+
+    #[test]
+    fn test_cleans_up_chunks() {
+        let store = ChunkedStore::new(InMemoryStore::default(), 10);
+        let body = vec![7u8; 45];
+        store.put("a", &body, IfMatch::Any).unwrap();
+        assert_eq!(chunk_count(&store), 5);
+
+        // A rejected put leaves the old version and no stray chunks
+        assert!(store.put("a", &[1u8; 30], IfMatch::NoneMatch).is_err());
+        assert_eq!(chunk_count(&store), 5);
+        assert_eq!(store.get("a").unwrap(), Some(body.clone()));
+        // Rewriting the same content keeps its chunks
+        assert!(store.put("a", &body, IfMatch::NoneMatch).is_err());
+        store.put("a", &body, IfMatch::Any).unwrap();
+        assert_eq!(store.get("a").unwrap(), Some(body));
+
+        // Shrinking below the threshold drops the chunks, as does delete
+        store.put("a", b"tiny", IfMatch::Any).unwrap();
+        assert_eq!(chunk_count(&store), 0);
+        store.put("b", &[2u8; 25], IfMatch::Any).unwrap();
+        store.delete("b").unwrap();
+        assert_eq!(chunk_count(&store), 0);
+        assert_eq!(store.list("", None).unwrap().0, vec!["a"]);
+    }
+
+    #[test]
+    fn test_rejected_rewrite_with_other_chunk_size() {
+        let backend = Arc::new(InMemoryStore::default());
+        let tens = ChunkedStore::new(backend.clone(), 10);
+        let sevens = ChunkedStore::new(backend.clone(), 7);
+        let body: Vec<u8> = (0..45).collect();
+        tens.put("a", &body, IfMatch::Any).unwrap();
+        // Same content, other boundaries: its chunks must not replace the live ones
+        assert!(sevens.put("a", &body, IfMatch::NoneMatch).is_err());
+        assert_eq!(sevens.get("a").unwrap(), Some(body.clone()));
+        assert_eq!(chunk_count(&tens), 5);
+    }
+
+    #[test]
+    fn test_content_resembling_a_manifest() {
+        let store = ChunkedStore::new(InMemoryStore::default(), 1024);
+        let mut body = MAGIC.to_vec();
+        body.extend(b"not really a manifest");
+        store.put("tricky", &body, IfMatch::Any).unwrap();
+        assert_eq!(store.get("tricky").unwrap(), Some(body));
+    }
+}
@@ -0,0 +1,133 @@
+use super::{ErrorContext, GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, Result};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Wraps a store so every error it returns says where it came from: the
+/// backend name given here, the operation and the key (the prefix for a
+/// listing). Under a `RetryingStore` the error also records the attempt
+/// that failed last.
+///
+/// The errors become `ObjectStoreError::Context`, so code that matches on
+/// failure classes should match on `root()`. Helpers in this crate that
+/// act on `PreconditionFailed`, such as locks and counters, do, so they
+/// work above or below this layer.
+pub struct ContextStore<S> {
+    inner: S,
+    backend: String,
+}
+
+impl<S: ObjectStore> ContextStore<S> {
+    pub fn new(inner: S, backend: impl Into<String>) -> Self {
+        Self { inner, backend: backend.into() }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn wrap<T>(&self, op: &'static str, key: &str, result: Result<T>) -> Result<T> {
+        result.map_err(|e| e.with_context(ErrorContext::new(self.backend.as_str(), op, Some(key))))
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for ContextStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.wrap("get", key, self.inner.get(key))
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        self.wrap("put", key, self.inner.put(key, body, cond))
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        self.wrap("list", prefix, self.inner.list(prefix, continuation))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.wrap("delete", key, self.inner.delete(key))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.wrap("head", key, self.inner.head(key))
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.wrap("get_range", key, self.inner.get_range(key, range))
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.wrap("get_opts", key, self.inner.get_opts(key, opts))
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.wrap("put_tags", key, self.inner.put_tags(key, tags))
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.wrap("get_tags", key, self.inner.get_tags(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::counter::Counters;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::readonly::ReadOnlyStore;
+    use crate::object_store::retry::{is_transient, RetryingStore};
+    use crate::object_store::sim::{FaultSchedule, FaultyStore};
+    use crate::object_store::test_helpers::tests::run_object_store_tests_by_root;
+    use crate::object_store::ObjectStoreError;
+    use std::error::Error;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_context_object_store() {
+        let store = ContextStore::new(InMemoryStore::default(), "memory");
+        run_object_store_tests_by_root(&store, "test/");
+    }
+
+    #[test]
+    fn test_errors_carry_context() {
+        let store = ContextStore::new(ReadOnlyStore::new(InMemoryStore::default()), "archive");
+        let err = store.put("photos/cat.jpg", b"meow", IfMatch::Any).unwrap_err();
+        let context = err.context().unwrap();
+        assert_eq!(context.backend(), "archive");
+        assert_eq!(context.op(), "put");
+        assert_eq!(context.key(), Some("photos/cat.jpg"));
+        assert_eq!(context.attempt(), None);
+        assert!(matches!(err.root(), ObjectStoreError::ReadOnly(_)));
+        assert_eq!(err.kind(), "read_only");
+        assert_eq!(err.to_string(), "archive put \"photos/cat.jpg\": read-only: cannot modify photos/cat.jpg");
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn test_counters_retry_conflicts_through_context() {
+        let store = Arc::new(ContextStore::new(InMemoryStore::default(), "memory"));
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let counters = Counters::new(store.clone());
+                scope.spawn(move || {
+                    for _ in 0..25 {
+                        counters.increment("shared").unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(Counters::new(store).get("shared").unwrap(), 100);
+    }
+
+    #[test]
+    fn test_retry_records_the_last_attempt() {
+        let sim = FaultyStore::new(FaultSchedule::new().outage(0..u64::MAX), 7);
+        let store = RetryingStore::new(ContextStore::new(sim, "flaky"))
+            .with_max_attempts(3)
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(1));
+        let err = store.get("k").unwrap_err();
+        assert!(is_transient(&err));
+        assert_eq!(err.context().unwrap().attempt(), Some(3));
+        assert!(err.to_string().starts_with("flaky get \"k\" (attempt 3): "));
+    }
+}
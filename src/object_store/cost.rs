@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+
+/// Request and transfer prices for one backend, in dollars.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pricing {
+    pub get_per_1k: f64,
+    pub head_per_1k: f64,
+    pub put_per_1k: f64,
+    pub list_per_1k: f64,
+    pub delete_per_1k: f64,
+    // Bytes read out of the backend
+    pub egress_per_gb: f64,
+    // Bytes written into the backend
+    pub ingress_per_gb: f64,
+}
+
+impl Pricing {
+    // Local disk, memory and self-hosted backends
+    pub fn free() -> Self {
+        Self {
+            get_per_1k: 0.0,
+            head_per_1k: 0.0,
+            put_per_1k: 0.0,
+            list_per_1k: 0.0,
+            delete_per_1k: 0.0,
+            egress_per_gb: 0.0,
+            ingress_per_gb: 0.0,
+        }
+    }
+
+    // S3 Standard in us-east-1, transferring out to the internet
+    pub fn s3_standard() -> Self {
+        Self {
+            get_per_1k: 0.0004,
+            head_per_1k: 0.0004,
+            put_per_1k: 0.005,
+            list_per_1k: 0.005,
+            delete_per_1k: 0.0,
+            egress_per_gb: 0.09,
+            ingress_per_gb: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestCounts {
+    pub get: u64,
+    pub head: u64,
+    pub put: u64,
+    pub list: u64,
+    pub delete: u64,
+}
+
+impl RequestCounts {
+    pub fn total(&self) -> u64 {
+        self.get + self.head + self.put + self.list + self.delete
+    }
+
+    fn cost(&self, pricing: &Pricing) -> f64 {
+        (self.get as f64 * pricing.get_per_1k
+            + self.head as f64 * pricing.head_per_1k
+            + self.put as f64 * pricing.put_per_1k
+            + self.list as f64 * pricing.list_per_1k
+            + self.delete as f64 * pricing.delete_per_1k)
+            / 1000.0
+    }
+}
+
+/// Estimated requests, data transfer and price of an operation between two
+/// stores.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CostEstimate {
+    pub src_requests: RequestCounts,
+    pub dst_requests: RequestCounts,
+    // Bytes read from the source and written to the destination
+    pub bytes: u64,
+    pub dollars: f64,
+}
+
+const GB: f64 = (1u64 << 30) as f64;
+
+pub fn estimate(
+    src_requests: RequestCounts,
+    src_pricing: &Pricing,
+    dst_requests: RequestCounts,
+    dst_pricing: &Pricing,
+    bytes: u64,
+) -> CostEstimate {
+    let gb = bytes as f64 / GB;
+    let dollars = src_requests.cost(src_pricing)
+        + dst_requests.cost(dst_pricing)
+        + gb * src_pricing.egress_per_gb
+        + gb * dst_pricing.ingress_per_gb;
+    CostEstimate {
+        src_requests,
+        dst_requests,
+        bytes,
+        dollars,
+    }
+}
+
+// Pages needed to list `keys` keys at the crate's 1000-per-page convention
+pub(crate) fn list_pages(keys: u64) -> u64 {
+    keys.div_ceil(1000).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_s3_to_local() {
+        // 50M objects of 1 MiB each, pulled out of S3
+        let objects = 50_000_000;
+        let src = RequestCounts {
+            get: objects,
+            list: list_pages(objects),
+            ..Default::default()
+        };
+        let dst = RequestCounts {
+            put: objects,
+            ..Default::default()
+        };
+        let est = estimate(src, &Pricing::s3_standard(), dst, &Pricing::free(), objects << 20);
+
+        let requests = 50_000.0 * 0.0004 + 50.0 * 0.005;
+        let transfer = (objects as f64 / 1024.0) * 0.09;
+        assert!((est.dollars - (requests + transfer)).abs() < 1e-6);
+        assert_eq!(est.src_requests.total(), objects + 50_000);
+    }
+
+    #[test]
+    fn test_free_backends_cost_nothing() {
+        let counts = RequestCounts {
+            get: 10,
+            put: 10,
+            ..Default::default()
+        };
+        let est = estimate(counts, &Pricing::free(), counts, &Pricing::free(), 1 << 40);
+        assert_eq!(est.dollars, 0.0);
+        assert_eq!(list_pages(0), 1);
+        assert_eq!(list_pages(1001), 2);
+    }
+}
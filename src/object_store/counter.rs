@@ -0,0 +1,203 @@
+use super::retry::{cas_backoff, MAX_CAS_ATTEMPTS};
+use super::{GetOptions, GetResult, IfMatch, ObjectStore, ObjectStoreError, Result};
+use std::ops::Range;
+use std::sync::Mutex;
+
+/// Named 64-bit counters, each a small object on any store with
+/// conditional puts.
+///
+/// A counter is the object `<prefix><name>` holding its value as decimal
+/// text; a missing counter reads as 0. Every change reads the value and
+/// writes the new one conditional on its ETag (or on its absence for a new
+/// counter), retrying on conflict with a short random backoff, so
+/// concurrent updates from anywhere are never lost; after 32 conflicts in a
+/// row the update fails instead. Under heavy contention, hand out values from a `Sequence`
+/// instead, which reserves them in blocks.
+pub struct Counters<S> {
+    store: S,
+    prefix: String,
+}
+
+impl<S: ObjectStore> Counters<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            prefix: "counters/".to_string(),
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}{name}", self.prefix)
+    }
+
+    // The value and its ETag, if the counter exists
+    fn read(&self, name: &str) -> Result<Option<(i64, String)>> {
+        let key = self.key(name);
+        let opts = GetOptions {
+            include_metadata: true,
+            ..Default::default()
+        };
+        match self.store.get_opts(&key, opts)? {
+            Some(GetResult::Body { data, meta: Some(meta) }) => {
+                let value = std::str::from_utf8(&data)
+                    .ok()
+                    .and_then(|text| text.trim().parse().ok())
+                    .ok_or_else(|| ObjectStoreError::Other(format!("corrupt counter {key}")))?;
+                Ok(Some((value, meta.etag)))
+            }
+            None => Ok(None),
+            Some(_) => Err(ObjectStoreError::Other(format!("no metadata for counter {key}"))),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<i64> {
+        Ok(self.read(name)?.map_or(0, |(value, _)| value))
+    }
+
+    /// Adds `delta` to the counter and returns the new value.
+    pub fn add(&self, name: &str, delta: i64) -> Result<i64> {
+        let key = self.key(name);
+        for attempt in 0..MAX_CAS_ATTEMPTS {
+            let current = self.read(name)?;
+            let (value, cond) = match &current {
+                Some((value, etag)) => (*value, IfMatch::Tag(etag)),
+                None => (0, IfMatch::NoneMatch),
+            };
+            let next = value
+                .checked_add(delta)
+                .ok_or_else(|| ObjectStoreError::Other(format!("counter {key} would overflow")))?;
+            match self.store.put(&key, next.to_string().as_bytes(), cond) {
+                Ok(_) => return Ok(next),
+                // Another update got in first; read its value and go again
+                Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => cas_backoff(attempt),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(ObjectStoreError::Other(format!("counter {key} kept changing while being updated")))
+    }
+
+    pub fn increment(&self, name: &str) -> Result<i64> {
+        self.add(name, 1)
+    }
+
+    pub fn decrement(&self, name: &str) -> Result<i64> {
+        self.add(name, -1)
+    }
+
+    /// Hands out the counter's values `block` at a time; see `Sequence`.
+    pub fn sequence(&self, name: &str, block: u64) -> Sequence<'_, S> {
+        Sequence {
+            counters: self,
+            name: name.to_string(),
+            block: block.clamp(1, i64::MAX as u64) as i64,
+            reserved: Mutex::new(0..0),
+        }
+    }
+}
+
+/// Unique increasing values from a counter, reserving `block` of them with
+/// each update so most calls to `next` don't touch the store.
+///
+/// Values reserved but not handed out are lost when the sequence is
+/// dropped, so with several sequences on one counter the values are unique
+/// but have gaps and aren't handed out in order across sequences.
+pub struct Sequence<'a, S> {
+    counters: &'a Counters<S>,
+    name: String,
+    block: i64,
+    reserved: Mutex<Range<i64>>,
+}
+
+impl<S: ObjectStore> Sequence<'_, S> {
+    pub fn next(&self) -> Result<i64> {
+        let mut reserved = self.reserved.lock().unwrap();
+        if reserved.is_empty() {
+            let end = self.counters.add(&self.name, self.block)?;
+            *reserved = (end - self.block + 1)..(end + 1);
+        }
+        let value = reserved.start;
+        reserved.start += 1;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::ContendedStore;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_add() {
+        let counters = Counters::new(InMemoryStore::default());
+        assert_eq!(counters.get("hits").unwrap(), 0);
+        assert_eq!(counters.increment("hits").unwrap(), 1);
+        assert_eq!(counters.add("hits", 10).unwrap(), 11);
+        assert_eq!(counters.decrement("hits").unwrap(), 10);
+        assert_eq!(counters.decrement("misses").unwrap(), -1);
+        assert_eq!(counters.get("hits").unwrap(), 10);
+        assert_eq!(counters.inner().get("counters/hits").unwrap(), Some(b"10".to_vec()));
+
+        counters.add("big", i64::MAX).unwrap();
+        assert!(counters.increment("big").is_err());
+        assert_eq!(counters.get("big").unwrap(), i64::MAX);
+    }
+
+    #[test]
+    fn test_gives_up_under_endless_contention() {
+        let counters = Counters::new(ContendedStore(InMemoryStore::default()));
+        assert_eq!(counters.increment("hits").unwrap(), 1);
+        assert!(matches!(counters.increment("hits"), Err(ObjectStoreError::Other(_))));
+        assert_eq!(counters.get("hits").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_concurrent_increments() {
+        let store = Arc::new(InMemoryStore::default());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let counters = Counters::new(store.clone());
+                thread::spawn(move || {
+                    for _ in 0..25 {
+                        counters.increment("shared").unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(Counters::new(store).get("shared").unwrap(), 100);
+    }
+
+    #[test]
+    fn test_sequences_hand_out_unique_values() {
+        let store = Arc::new(InMemoryStore::default());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let counters = Counters::new(store.clone());
+                thread::spawn(move || {
+                    let ids = counters.sequence("content-ids", 10);
+                    (0..25).map(|_| ids.next().unwrap()).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let ids: Vec<i64> = handles.into_iter().flat_map(|h| h.join().unwrap()).collect();
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), 100);
+        assert!(ids.iter().all(|&id| id >= 1));
+        // Three blocks of ten per sequence, the last one partly used
+        assert_eq!(Counters::new(store).get("content-ids").unwrap(), 120);
+    }
+}
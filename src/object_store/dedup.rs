@@ -0,0 +1,512 @@
+use super::cas::ContentStore;
+use super::chunked::{peek_manifest, Peeked};
+use super::sim::SimRng;
+use super::sync::{for_each_concurrent, list_all};
+use super::{check_conditions, GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Range;
+use std::sync::Mutex;
+
+// Marks an object as a manifest rather than plain content
+const MAGIC: &[u8] = b"BLOBDEDUP1\n";
+
+// Chunks of every deduplicated object live here, keyed by their SHA-256
+pub const CHUNK_PREFIX: &str = ".dedup/";
+
+// Random words for the gear hash. Chunk boundaries depend on them, so they
+// must never change or existing chunks stop being shared with new uploads.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut rng = SimRng::new(0x6a09_e667_f3bc_c908);
+    let mut i = 0;
+    while i < table.len() {
+        table[i] = rng.next_u64();
+        i += 1;
+    }
+    table
+};
+
+/// Chunk size bounds for content-defined chunking, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CdcOptions {
+    pub min_size: usize,
+    // Rounded down to a power of two
+    pub avg_size: usize,
+    pub max_size: usize,
+}
+
+impl CdcOptions {
+    // A quarter of the average to four times it
+    pub fn with_average(avg_size: usize) -> Self {
+        Self {
+            min_size: avg_size / 4,
+            avg_size,
+            max_size: avg_size * 4,
+        }
+    }
+}
+
+impl Default for CdcOptions {
+    fn default() -> Self {
+        Self::with_average(64 * 1024)
+    }
+}
+
+/// Splits `data` into content-defined chunks, FastCDC style.
+///
+/// A boundary falls where a rolling hash of the preceding 64 bytes has
+/// enough leading zero bits, so it depends only on nearby content: an
+/// insertion or deletion moves the boundaries around it and leaves the
+/// rest where they were. Below the average size the condition is stricter
+/// and above it looser, which keeps chunk sizes close to the average.
+pub fn cdc_chunks(data: &[u8], options: &CdcOptions) -> Vec<Range<usize>> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let len = next_cut(&data[start..], options);
+        chunks.push(start..start + len);
+        start += len;
+    }
+    chunks
+}
+
+fn next_cut(data: &[u8], options: &CdcOptions) -> usize {
+    let min = options.min_size.max(1);
+    if data.len() <= min {
+        return data.len();
+    }
+    let bits = options.avg_size.max(4).ilog2();
+    let (strict, loose) = (64 - (bits + 1), 64 - (bits - 1));
+    let normal = data.len().min(options.avg_size);
+    let max = data.len().min(options.max_size.max(min));
+
+    let mut hash = 0u64;
+    for (i, &byte) in data.iter().enumerate().take(max).skip(min) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        let shift = if i < normal { strict } else { loose };
+        if hash >> shift == 0 {
+            return i + 1;
+        }
+    }
+    max
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkRef {
+    pub digest: String,
+    pub size: u64,
+}
+
+/// The chunks a deduplicated object is made of, in order, stored at the
+/// object's key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub size: u64,
+    pub chunks: Vec<ChunkRef>,
+}
+
+impl Manifest {
+    fn encode(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend(serde_json::to_vec(self).expect("manifest serializes"));
+        data
+    }
+
+    fn decode(key: &str, data: &[u8]) -> Result<Self> {
+        serde_json::from_slice(&data[MAGIC.len()..])
+            .map_err(|e| ObjectStoreError::Other(format!("corrupt dedup manifest at {key}: {e}")))
+    }
+}
+
+/// Logical bytes are what callers stored; physical bytes count each
+/// distinct chunk once.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DedupStats {
+    pub objects: u64,
+    pub logical_bytes: u64,
+    pub physical_bytes: u64,
+}
+
+impl DedupStats {
+    // Logical over physical bytes; 2.0 means half the data was duplicate
+    pub fn ratio(&self) -> f64 {
+        if self.physical_bytes == 0 {
+            1.0
+        } else {
+            self.logical_bytes as f64 / self.physical_bytes as f64
+        }
+    }
+}
+
+enum Stored {
+    Plain,
+    Deduped(Manifest, String),
+}
+
+/// Wraps a store so objects are split with content-defined chunking and
+/// their chunks kept in a `ContentStore` under `.dedup/`, where identical
+/// chunks from any object are stored once.
+///
+/// Near-duplicate large objects, such as successive VM images or database
+/// dumps, then share most of their chunks. Like `ChunkedStore`, the object
+/// at each key is a manifest written last under the caller's condition,
+/// ranged reads fetch only the chunks they need and its ETag is the
+/// manifest's; objects no bigger than the minimum chunk size are stored as
+/// they are. Chunks are verified against their digest on read.
+///
+/// Chunks can be shared, so overwrites and deletes leave them behind for
+/// `collect_garbage` to reclaim.
+pub struct DedupStore<S> {
+    chunks: ContentStore<S>,
+    options: CdcOptions,
+    concurrency: usize,
+}
+
+impl<S: ObjectStore> DedupStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            chunks: ContentStore::new(inner).with_prefix(CHUNK_PREFIX),
+            options: CdcOptions::default(),
+            concurrency: 4,
+        }
+    }
+
+    pub fn with_options(mut self, options: CdcOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        self.chunks.inner()
+    }
+
+    fn lookup(&self, key: &str) -> Result<Option<Stored>> {
+        Ok(match peek_manifest(self.inner(), key, MAGIC)? {
+            Some(Peeked::Manifest(data, etag)) => Some(Stored::Deduped(Manifest::decode(key, &data)?, etag)),
+            Some(Peeked::Plain) => Some(Stored::Plain),
+            None => None,
+        })
+    }
+
+    fn read_range(&self, key: &str, manifest: &Manifest, range: Range<u64>) -> Result<Vec<u8>> {
+        let (start, end) = (range.start.min(manifest.size), range.end.min(manifest.size));
+        // The chunks overlapping the range, with their offsets
+        let mut needed = Vec::new();
+        let mut offset = 0;
+        for chunk in &manifest.chunks {
+            if offset < end && offset + chunk.size > start {
+                needed.push((chunk, offset));
+            }
+            offset += chunk.size;
+        }
+        let Some(&(_, first)) = needed.first() else {
+            return Ok(Vec::new());
+        };
+
+        let parts = Mutex::new(vec![Vec::new(); needed.len()]);
+        let first_err = Mutex::new(None);
+        let indexes: Vec<usize> = (0..needed.len()).collect();
+        for_each_concurrent(&indexes, self.concurrency, |&i| {
+            let chunk = needed[i].0;
+            let result = chunk.digest.parse().and_then(|digest| self.chunks.get(&digest)).and_then(|data| {
+                data.ok_or_else(|| ObjectStoreError::Other(format!("chunk {} of {key} is missing", chunk.digest)))
+            });
+            match result {
+                Ok(data) => parts.lock().unwrap()[i] = data,
+                Err(e) => {
+                    first_err.lock().unwrap().get_or_insert(e);
+                }
+            }
+        });
+        if let Some(e) = first_err.into_inner().unwrap() {
+            return Err(e);
+        }
+
+        let data = parts.into_inner().unwrap().concat();
+        Ok(data[(start - first) as usize..(end - first) as usize].to_vec())
+    }
+
+    fn put_deduped(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        let ranges = cdc_chunks(body, &self.options);
+        let digests = Mutex::new(vec![None; ranges.len()]);
+        let first_err = Mutex::new(None);
+        let indexes: Vec<usize> = (0..ranges.len()).collect();
+        for_each_concurrent(&indexes, self.concurrency, |&i| match self.chunks.put(&body[ranges[i].clone()]) {
+            Ok(digest) => digests.lock().unwrap()[i] = Some(digest),
+            Err(e) => {
+                first_err.lock().unwrap().get_or_insert(e);
+            }
+        });
+        if let Some(e) = first_err.into_inner().unwrap() {
+            return Err(e);
+        }
+
+        let chunks = digests
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .zip(&ranges)
+            .map(|(digest, range)| ChunkRef {
+                digest: digest.expect("every chunk stored").to_string(),
+                size: range.len() as u64,
+            })
+            .collect();
+        let manifest = Manifest {
+            size: body.len() as u64,
+            chunks,
+        };
+        self.inner().put(key, &manifest.encode(), cond)
+    }
+
+    /// Objects, logical and physical bytes under `prefix`.
+    pub fn stats(&self, prefix: &str) -> Result<DedupStats> {
+        let mut stats = DedupStats::default();
+        let mut seen = HashSet::new();
+        for key in list_all(self.inner(), prefix)? {
+            if key.starts_with(CHUNK_PREFIX) {
+                continue;
+            }
+            match self.lookup(&key)? {
+                Some(Stored::Deduped(manifest, _)) => {
+                    stats.logical_bytes += manifest.size;
+                    for chunk in manifest.chunks {
+                        if seen.insert(chunk.digest) {
+                            stats.physical_bytes += chunk.size;
+                        }
+                    }
+                }
+                Some(Stored::Plain) => {
+                    let size = self.inner().head(&key)?.map_or(0, |meta| meta.size);
+                    stats.logical_bytes += size;
+                    stats.physical_bytes += size;
+                }
+                // Deleted since it was listed
+                None => continue,
+            }
+            stats.objects += 1;
+        }
+        Ok(stats)
+    }
+
+    /// Deletes chunks no manifest references any more and returns how many.
+    ///
+    /// A put in progress has stored chunks its manifest doesn't reference
+    /// yet, so run this while writes are paused.
+    pub fn collect_garbage(&self) -> Result<usize> {
+        let mut referenced = HashSet::new();
+        for key in list_all(self.inner(), "")? {
+            if key.starts_with(CHUNK_PREFIX) {
+                continue;
+            }
+            if let Some(Stored::Deduped(manifest, _)) = self.lookup(&key)? {
+                referenced.extend(manifest.chunks.into_iter().map(|chunk| chunk.digest));
+            }
+        }
+
+        let mut deleted = 0;
+        for digest in self.chunks.digests()? {
+            if !referenced.contains(&digest.to_string()) {
+                self.chunks.delete(&digest)?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for DedupStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.inner().get(key)? {
+            Some(data) if data.starts_with(MAGIC) => {
+                let manifest = Manifest::decode(key, &data)?;
+                self.read_range(key, &manifest, 0..manifest.size).map(Some)
+            }
+            data => Ok(data),
+        }
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        if key.starts_with(CHUNK_PREFIX) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        // Plain content that happens to start like a manifest is deduplicated too
+        if body.len() > self.options.min_size || body.starts_with(MAGIC) {
+            self.put_deduped(key, body, cond)
+        } else {
+            self.inner().put(key, body, cond)
+        }
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let (keys, next) = self.inner().list(prefix, continuation)?;
+        Ok((keys.into_iter().filter(|key| !key.starts_with(CHUNK_PREFIX)).collect(), next))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.inner().delete(key)
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        match self.lookup(key)? {
+            Some(Stored::Deduped(manifest, etag)) => Ok(Some(ObjectMeta {
+                size: manifest.size,
+                etag,
+            })),
+            Some(Stored::Plain) => self.inner().head(key),
+            None => Ok(None),
+        }
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        match self.lookup(key)? {
+            Some(Stored::Deduped(manifest, _)) => self.read_range(key, &manifest, range).map(Some),
+            Some(Stored::Plain) => self.inner().get_range(key, range),
+            None => Ok(None),
+        }
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        let (manifest, etag) = match self.lookup(key)? {
+            Some(Stored::Deduped(manifest, etag)) => (manifest, etag),
+            Some(Stored::Plain) => return self.inner().get_opts(key, opts),
+            None => return Ok(None),
+        };
+        if let Some(result) = check_conditions(&etag, &opts)? {
+            return Ok(Some(result));
+        }
+        let data = self.read_range(key, &manifest, opts.range.clone().unwrap_or(0..manifest.size))?;
+        let meta = opts.include_metadata.then_some(ObjectMeta {
+            size: manifest.size,
+            etag,
+        });
+        Ok(Some(GetResult::Body { data, meta }))
+    }
+
+    // On the manifest or plain object at the key, which every put replaces
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        if key.starts_with(CHUNK_PREFIX) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        self.inner().put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.inner().get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::{run_object_store_tests, run_oracle_tests, CountingStore};
+    use uuid::Uuid;
+
+    fn random_bytes(seed: u64, len: usize) -> Vec<u8> {
+        let mut rng = SimRng::new(seed);
+        (0..len).map(|_| rng.next_u64() as u8).collect()
+    }
+
+    // Like an image with a small patch applied in the middle
+    fn near_duplicate(data: &[u8]) -> Vec<u8> {
+        let mut edited = data.to_vec();
+        edited.splice(100_000..100_000, b"inserted bytes".iter().copied());
+        edited
+    }
+
+    #[test]
+    fn test_dedup_object_store() {
+        let options = CdcOptions::with_average(64);
+        let store = DedupStore::new(InMemoryStore::default()).with_options(options);
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+        let store = DedupStore::new(InMemoryStore::default()).with_options(CdcOptions::with_average(4));
+        run_oracle_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_boundaries_survive_edits() {
+        let options = CdcOptions::with_average(4096);
+        let data = random_bytes(1, 256 * 1024);
+        let chunks = cdc_chunks(&data, &options);
+        assert_eq!(chunks, cdc_chunks(&data, &options));
+        assert_eq!(chunks.last().unwrap().end, data.len());
+        assert!(chunks[..chunks.len() - 1].iter().all(|c| (1024..=16384).contains(&c.len())));
+        let avg = data.len() / chunks.len();
+        assert!((2048..8192).contains(&avg), "average chunk size {avg}");
+
+        let edited = near_duplicate(&data);
+        let before: HashSet<_> = chunks.iter().map(|c| &data[c.clone()]).collect();
+        let after = cdc_chunks(&edited, &options);
+        let shared = after.iter().filter(|c| before.contains(&edited[(*c).clone()])).count();
+        assert!(shared + 3 >= after.len(), "only {shared} of {} chunks shared", after.len());
+    }
+
+    #[test]
+    fn test_near_duplicates_share_chunks() {
+        let store = DedupStore::new(InMemoryStore::default()).with_options(CdcOptions::with_average(4096));
+        let image = random_bytes(2, 256 * 1024);
+        let patched = near_duplicate(&image);
+        store.put("images/v1", &image, IfMatch::Any).unwrap();
+        store.put("images/v2", &patched, IfMatch::Any).unwrap();
+        store.put("images/notes", b"small", IfMatch::Any).unwrap();
+        assert_eq!(store.get("images/v2").unwrap(), Some(patched.clone()));
+        assert_eq!(store.list("", None).unwrap().0, vec!["images/notes", "images/v1", "images/v2"]);
+
+        let stats = store.stats("images/").unwrap();
+        assert_eq!(stats.objects, 3);
+        assert_eq!(stats.logical_bytes, (image.len() + patched.len() + 5) as u64);
+        assert!(stats.physical_bytes < image.len() as u64 * 11 / 10, "{stats:?}");
+        assert!(stats.ratio() > 1.8);
+        assert_eq!(store.stats("images/v1").unwrap().physical_bytes, image.len() as u64);
+    }
+
+    #[test]
+    fn test_ranges_read_only_needed_chunks() {
+        let inner = CountingStore::new(InMemoryStore::default());
+        let store = DedupStore::new(inner).with_options(CdcOptions::with_average(4096));
+        let data = random_bytes(3, 64 * 1024);
+        store.put("blob", &data, IfMatch::Any).unwrap();
+
+        let gets = store.inner().count("get");
+        assert_eq!(store.get_range("blob", 30_000..30_100).unwrap(), Some(data[30_000..30_100].to_vec()));
+        // The manifest plus one or two chunks
+        assert!(store.inner().count("get") - gets <= 3);
+        assert_eq!(store.get_range("blob", 60_000..90_000).unwrap(), Some(data[60_000..].to_vec()));
+        assert_eq!(store.head("blob").unwrap().unwrap().size, data.len() as u64);
+    }
+
+    #[test]
+    fn test_garbage_collection() {
+        let store = DedupStore::new(InMemoryStore::default()).with_options(CdcOptions::with_average(4096));
+        let image = random_bytes(4, 256 * 1024);
+        let patched = near_duplicate(&image);
+        store.put("v1", &image, IfMatch::Any).unwrap();
+        store.put("v2", &patched, IfMatch::Any).unwrap();
+        assert_eq!(store.collect_garbage().unwrap(), 0);
+
+        // Only the chunks v1 didn't share with v2 go
+        store.delete("v1").unwrap();
+        let deleted = store.collect_garbage().unwrap();
+        assert!((1..=3).contains(&deleted), "{deleted} chunks deleted");
+        assert_eq!(store.get("v2").unwrap(), Some(patched));
+        store.delete("v2").unwrap();
+        store.collect_garbage().unwrap();
+        assert_eq!(store.inner().list(CHUNK_PREFIX, None).unwrap().0, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_detects_corrupt_chunks() {
+        let store = DedupStore::new(InMemoryStore::default()).with_options(CdcOptions::with_average(64));
+        store.put("doc", &random_bytes(5, 1000), IfMatch::Any).unwrap();
+        let key = store.inner().list(CHUNK_PREFIX, None).unwrap().0.remove(0);
+        store.inner().put(&key, b"flipped", IfMatch::Any).unwrap();
+        assert!(matches!(store.get("doc"), Err(ObjectStoreError::ChecksumMismatch(k)) if k == key));
+        assert!(store.put(&key, b"forged", IfMatch::Any).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use super::sync::list_all;
+use super::{IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::BTreeMap;
+
+// Marker object that keeps an otherwise empty directory alive. A plain name
+// rather than S3's trailing-slash key, which LocalStore can't represent.
+pub const MARKER: &str = ".keep";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DirEntry {
+    File { name: String, meta: ObjectMeta },
+    Dir { name: String },
+}
+
+impl DirEntry {
+    pub fn name(&self) -> &str {
+        match self {
+            DirEntry::File { name, .. } | DirEntry::Dir { name } => name,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        matches!(self, DirEntry::Dir { .. })
+    }
+}
+
+// "a/b", "/a/b/" and "a/b/" all name the directory whose keys start with "a/b/"
+fn dir_prefix(path: &str) -> String {
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        String::new()
+    } else {
+        format!("{path}/")
+    }
+}
+
+/// Creates a directory by writing its marker object. Parents are implied by
+/// key prefixes and don't need to exist. Creating an existing directory is
+/// not an error.
+pub fn mkdir(store: &dyn ObjectStore, path: &str) -> Result<()> {
+    let prefix = dir_prefix(path);
+    if prefix.is_empty() {
+        return Ok(());
+    }
+    match store.put(&format!("{prefix}{MARKER}"), b"", IfMatch::NoneMatch) {
+        Ok(_) => Ok(()),
+        Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Removes a directory. Without `recursive` it must hold nothing but its
+/// marker, otherwise `PreconditionFailed` is returned; with `recursive`
+/// every key under it is deleted. Removing a missing directory is not an
+/// error.
+pub fn rmdir(store: &dyn ObjectStore, path: &str, recursive: bool) -> Result<()> {
+    let prefix = dir_prefix(path);
+    let marker = format!("{prefix}{MARKER}");
+    let keys = list_all(store, &prefix)?;
+    if !recursive && keys.iter().any(|key| *key != marker) {
+        return Err(ObjectStoreError::PreconditionFailed);
+    }
+    // Marker last, so an interrupted rmdir leaves the directory visible
+    for key in keys.iter().filter(|key| **key != marker) {
+        store.delete(key)?;
+    }
+    store.delete(&marker)
+}
+
+/// Immediate children of a directory, sorted by name. Subdirectories appear
+/// once however many keys they hold; marker objects are hidden.
+pub fn list_dir(store: &dyn ObjectStore, path: &str) -> Result<Vec<DirEntry>> {
+    let prefix = dir_prefix(path);
+    let mut entries = BTreeMap::new();
+    for key in list_all(store, &prefix)? {
+        let rest = &key[prefix.len()..];
+        match rest.split_once('/') {
+            Some((dir, _)) => {
+                entries
+                    .entry(dir.to_string())
+                    .or_insert_with(|| DirEntry::Dir { name: dir.to_string() });
+            }
+            None if rest == MARKER => {}
+            None => {
+                // Deleted since it was listed
+                let Some(meta) = store.head(&key)? else {
+                    continue;
+                };
+                entries.insert(rest.to_string(), DirEntry::File {
+                    name: rest.to_string(),
+                    meta,
+                });
+            }
+        }
+    }
+    Ok(entries.into_values().collect())
+}
+
+/// Whether anything, even just a marker, exists under the directory. The
+/// root always exists.
+pub fn dir_exists(store: &dyn ObjectStore, path: &str) -> Result<bool> {
+    let prefix = dir_prefix(path);
+    if prefix.is_empty() {
+        return Ok(true);
+    }
+    let (keys, _) = store.list(&prefix, None)?;
+    Ok(!keys.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::local::LocalStore;
+    use crate::object_store::memory::InMemoryStore;
+    use tempfile::TempDir;
+
+    fn run_dir_tests(store: &dyn ObjectStore) {
+        assert!(dir_exists(store, "/").unwrap());
+        assert!(!dir_exists(store, "docs").unwrap());
+
+        mkdir(store, "docs/").unwrap();
+        mkdir(store, "docs").unwrap();
+        assert!(dir_exists(store, "/docs").unwrap());
+        assert_eq!(list_dir(store, "docs").unwrap(), vec![]);
+
+        store.put("docs/readme.md", b"# hi", IfMatch::Any).unwrap();
+        store.put("docs/img/a.png", b"png", IfMatch::Any).unwrap();
+        store.put("docs/img/b.png", b"png", IfMatch::Any).unwrap();
+        // Implied directory: no marker, but keys underneath
+        store.put("docs/api/v1/index.html", b"<html>", IfMatch::Any).unwrap();
+
+        let entries = list_dir(store, "docs").unwrap();
+        let names: Vec<(&str, bool)> = entries.iter().map(|e| (e.name(), e.is_dir())).collect();
+        assert_eq!(names, vec![("api", true), ("img", true), ("readme.md", false)]);
+        match &entries[2] {
+            DirEntry::File { meta, .. } => assert_eq!(meta.size, 4),
+            other => panic!("expected a file, got {other:?}"),
+        }
+        assert!(dir_exists(store, "docs/api").unwrap());
+        let root: Vec<String> = list_dir(store, "").unwrap().iter().map(|e| e.name().to_string()).collect();
+        assert_eq!(root, vec!["docs"]);
+
+        let result = rmdir(store, "docs/img", false);
+        assert!(matches!(result, Err(ObjectStoreError::PreconditionFailed)));
+        rmdir(store, "docs/img", true).unwrap();
+        assert!(!dir_exists(store, "docs/img").unwrap());
+
+        mkdir(store, "docs/empty").unwrap();
+        rmdir(store, "docs/empty", false).unwrap();
+        assert!(!dir_exists(store, "docs/empty").unwrap());
+        rmdir(store, "docs/never-existed", false).unwrap();
+
+        rmdir(store, "docs", true).unwrap();
+        assert!(!dir_exists(store, "docs").unwrap());
+    }
+
+    #[test]
+    fn test_dirs_in_memory() {
+        run_dir_tests(&InMemoryStore::default());
+    }
+
+    #[test]
+    fn test_dirs_on_local_filesystem() {
+        let tmp = TempDir::new().unwrap();
+        run_dir_tests(&LocalStore::new(tmp.path()));
+    }
+}
@@ -0,0 +1,442 @@
+use super::local::{Attributes, LocalStore};
+use super::retry::is_transient;
+use super::sync::list_all;
+use super::{check_conditions, GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, FileTimes};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+// The user attribute of a cached file holding the backend's ETag for it
+const REMOTE_ETAG: &str = "remote-etag";
+
+// Cached keys by recency; mirrors the files under the cache directory
+#[derive(Default)]
+struct DiskIndex {
+    // key -> (size, tick)
+    entries: HashMap<String, (u64, u64)>,
+    // tick -> key, least recently used first
+    order: BTreeMap<u64, String>,
+    bytes: u64,
+    next_tick: u64,
+}
+
+impl DiskIndex {
+    fn touch(&mut self, key: &str, size: u64) {
+        self.remove(key);
+        self.entries.insert(key.to_string(), (size, self.next_tick));
+        self.order.insert(self.next_tick, key.to_string());
+        self.bytes += size;
+        self.next_tick += 1;
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((size, tick)) = self.entries.remove(key) {
+            self.order.remove(&tick);
+            self.bytes -= size;
+        }
+    }
+}
+
+/// Fronts a (usually remote) store with a cache directory on local disk.
+///
+/// Cached copies are validated against the backend's ETag with a head
+/// request before being served, so only changed objects are downloaded
+/// again. Each copy keeps the backend's ETag in its file's attributes, so
+/// the check works whatever ETags the backend uses, and those are the
+/// ETags reported for cached copies. If the backend fails with a transient
+/// error, cached copies are served as they are, which keeps reads working
+/// offline. Puts are written through and cached. Least recently used files
+/// are evicted once the cache exceeds `max_bytes`; recency survives
+/// restarts through file access times.
+pub struct DiskCachedStore<S> {
+    inner: S,
+    cache: LocalStore,
+    max_bytes: u64,
+    index: Mutex<DiskIndex>,
+}
+
+impl<S: ObjectStore> DiskCachedStore<S> {
+    pub fn new<P: AsRef<Path>>(inner: S, cache_dir: P, max_bytes: u64) -> Result<Self> {
+        let root = cache_dir.as_ref().to_path_buf();
+        fs::create_dir_all(&root).map_err(ObjectStoreError::Io)?;
+
+        // Rebuild recency from access times, oldest first
+        let cache = LocalStore::new(&root);
+        let mut files = Vec::new();
+        for key in list_all(&cache, "")? {
+            let meta = fs::metadata(cache.object_path(&key)?).map_err(ObjectStoreError::Io)?;
+            files.push((meta.accessed().unwrap_or(SystemTime::UNIX_EPOCH), key, meta.len()));
+        }
+        files.sort();
+        let mut index = DiskIndex::default();
+        for (_, key, size) in files {
+            index.touch(&key, size);
+        }
+
+        let store = Self {
+            inner,
+            cache,
+            max_bytes,
+            index: Mutex::new(index),
+        };
+        store.evict()?;
+        Ok(store)
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    // Total size of the cached files
+    pub fn cached_bytes(&self) -> u64 {
+        self.index.lock().unwrap().bytes
+    }
+
+    fn is_cached(&self, key: &str) -> bool {
+        self.index.lock().unwrap().entries.contains_key(key)
+    }
+
+    // The backend's ETag for the cached copy of `key`, if the copy may be
+    // served. Falls back to the cached copy when the backend is unreachable.
+    fn valid_etag(&self, key: &str) -> Result<Option<String>> {
+        if !self.is_cached(key) {
+            return Ok(None);
+        }
+        let remote = match self.inner.head(key) {
+            Ok(remote) => remote,
+            Err(e) if is_transient(e.root()) => return self.cached_etag(key),
+            Err(e) => return Err(e),
+        };
+        match (remote, self.cached_etag(key)?) {
+            (Some(remote), Some(cached)) if remote.etag == cached => {
+                self.mark_used(key);
+                Ok(Some(cached))
+            }
+            (None, _) => {
+                self.forget(key)?;
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // None for copies cached without one, which are never served
+    fn cached_etag(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.cache.attributes(key)?.and_then(|mut attributes| attributes.user.remove(REMOTE_ETAG)))
+    }
+
+    fn mark_used(&self, key: &str) {
+        {
+            let mut index = self.index.lock().unwrap();
+            if let Some(&(size, _)) = index.entries.get(key) {
+                index.touch(key, size);
+            }
+        }
+        // Best effort: only affects eviction order after a restart. Not the
+        // modification time, which would make the file's attributes stale
+        if let Ok(path) = self.cache.object_path(key)
+            && let Ok(file) = File::options().write(true).open(path)
+        {
+            let _ = file.set_times(FileTimes::new().set_accessed(SystemTime::now()));
+        }
+    }
+
+    fn store(&self, key: &str, data: &[u8], etag: &str) -> Result<()> {
+        if data.len() as u64 > self.max_bytes {
+            return self.forget(key);
+        }
+        // Keys the cache directory can't hold are read through uncached
+        if self.cache.object_path(key).is_err() {
+            return Ok(());
+        }
+        let attributes = Attributes {
+            user: BTreeMap::from([(REMOTE_ETAG.to_string(), etag.to_string())]),
+            ..Default::default()
+        };
+        self.cache.put_with_attributes(key, data, IfMatch::Any, &attributes)?;
+        self.index.lock().unwrap().touch(key, data.len() as u64);
+        self.evict()
+    }
+
+    fn forget(&self, key: &str) -> Result<()> {
+        self.index.lock().unwrap().remove(key);
+        if self.cache.object_path(key).is_err() {
+            return Ok(());
+        }
+        self.cache.delete(key)
+    }
+
+    fn evict(&self) -> Result<()> {
+        loop {
+            let victim = {
+                let mut index = self.index.lock().unwrap();
+                if index.bytes <= self.max_bytes {
+                    return Ok(());
+                }
+                let Some((_, key)) = index.order.pop_first() else {
+                    return Ok(());
+                };
+                index.remove(&key);
+                key
+            };
+            self.cache.delete(&victim)?;
+        }
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for DiskCachedStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        if self.valid_etag(key)?.is_some()
+            && let Some(data) = self.cache.get(key)?
+        {
+            return Ok(Some(data));
+        }
+        let opts = GetOptions {
+            include_metadata: true,
+            ..Default::default()
+        };
+        let Some(GetResult::Body { data, meta }) = self.inner.get_opts(key, opts)? else {
+            self.forget(key)?;
+            return Ok(None);
+        };
+        let meta = meta.ok_or_else(|| ObjectStoreError::Other("inner store returned no metadata".to_string()))?;
+        self.store(key, &data, &meta.etag)?;
+        Ok(Some(data))
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        match self.inner.put(key, body, cond) {
+            Ok(etag) => {
+                self.store(key, body, &etag)?;
+                Ok(etag)
+            }
+            Err(e) => {
+                self.forget(key)?;
+                Err(e)
+            }
+        }
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        self.inner.list(prefix, continuation)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(key)?;
+        self.forget(key)
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        match self.inner.head(key) {
+            Err(e) if is_transient(e.root()) && self.is_cached(key) => {
+                match (self.cache.head(key)?, self.cached_etag(key)?) {
+                    (Some(local), Some(etag)) => Ok(Some(ObjectMeta { size: local.size, etag })),
+                    _ => Err(e),
+                }
+            }
+            result => result,
+        }
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        // Uncached objects are read through without filling the cache
+        if self.valid_etag(key)?.is_some()
+            && let Some(data) = self.cache.get_range(key, range.clone())?
+        {
+            return Ok(Some(data));
+        }
+        self.inner.get_range(key, range)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        // The cached copy was just checked against the backend's ETag, so
+        // conditions can be evaluated against that
+        if let Some(etag) = self.valid_etag(key)? {
+            if let Some(result) = check_conditions(&etag, &opts)? {
+                return Ok(Some(result));
+            }
+            let plain = GetOptions {
+                range: opts.range.clone(),
+                include_metadata: opts.include_metadata,
+                ..Default::default()
+            };
+            if let Some(GetResult::Body { data, meta }) = self.cache.get_opts(key, plain)? {
+                let meta = meta.map(|meta| ObjectMeta { size: meta.size, etag });
+                return Ok(Some(GetResult::Body { data, meta }));
+            }
+        }
+        self.inner.get_opts(key, opts)
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.inner.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.inner.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::chunked::ChunkedStore;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::{run_object_store_tests, CountingStore};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tempfile::TempDir;
+    use uuid::Uuid;
+
+    type Remote = CountingStore<InMemoryStore>;
+
+    fn remote() -> Remote {
+        CountingStore::new(InMemoryStore::default())
+    }
+
+    #[test]
+    fn test_disk_cached_object_store() {
+        let tmp = TempDir::new().unwrap();
+        let store = DiskCachedStore::new(remote(), tmp.path(), 1 << 20).unwrap();
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_validated_hits_skip_download() {
+        let tmp = TempDir::new().unwrap();
+        let store = DiskCachedStore::new(remote(), tmp.path(), 1 << 20).unwrap();
+        store.inner().put("data/a.csv", b"1,2,3", IfMatch::Any).unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(store.get("data/a.csv").unwrap(), Some(b"1,2,3".to_vec()));
+        }
+        assert_eq!(store.inner().count("get_opts"), 1);
+        assert_eq!(fs::read(tmp.path().join("data/a.csv")).unwrap(), b"1,2,3");
+
+        // Changed remotely: the ETag check notices and refetches
+        store.inner().put("data/a.csv", b"4,5,6", IfMatch::Any).unwrap();
+        assert_eq!(store.get("data/a.csv").unwrap(), Some(b"4,5,6".to_vec()));
+        assert_eq!(store.inner().count("get_opts"), 2);
+
+        // Deleted remotely: the cached copy goes too
+        store.inner().delete("data/a.csv").unwrap();
+        assert_eq!(store.get("data/a.csv").unwrap(), None);
+        assert!(!tmp.path().join("data/a.csv").exists());
+    }
+
+    #[test]
+    fn test_validates_against_the_backend_etag() {
+        let tmp = TempDir::new().unwrap();
+        let backend = CountingStore::new(ChunkedStore::new(InMemoryStore::default(), 4));
+        let store = DiskCachedStore::new(backend, tmp.path(), 1 << 20).unwrap();
+        let etag = store.put("big", b"ten bytes!", IfMatch::Any).unwrap();
+        assert_ne!(etag, store.cache.head("big").unwrap().unwrap().etag);
+
+        for _ in 0..3 {
+            assert_eq!(store.get("big").unwrap(), Some(b"ten bytes!".to_vec()));
+        }
+        assert_eq!(store.inner().count("get_opts"), 0);
+        let fresh = GetOptions { if_none_match: Some(&etag), ..Default::default() };
+        assert_eq!(store.get_opts("big", fresh).unwrap(), Some(GetResult::NotModified));
+        let with_meta = GetOptions { range: Some(0..3), include_metadata: true, ..Default::default() };
+        let meta = Some(ObjectMeta { size: 10, etag: etag.clone() });
+        assert_eq!(store.get_opts("big", with_meta).unwrap(), Some(GetResult::Body { data: b"ten".to_vec(), meta }));
+        assert_eq!(store.inner().count("get_opts"), 0);
+    }
+
+    #[test]
+    fn test_evicts_by_total_bytes() {
+        let tmp = TempDir::new().unwrap();
+        let store = DiskCachedStore::new(remote(), tmp.path(), 10).unwrap();
+        store.put("a", b"aaaa", IfMatch::Any).unwrap();
+        store.put("b", b"bbbb", IfMatch::Any).unwrap();
+        store.get("a").unwrap();
+        store.put("c", b"cccc", IfMatch::Any).unwrap(); // evicts b
+        assert_eq!(store.cached_bytes(), 8);
+        assert!(!tmp.path().join("b").exists());
+        assert!(tmp.path().join("a").exists());
+
+        store.put("huge", b"more than ten bytes", IfMatch::Any).unwrap();
+        assert!(!tmp.path().join("huge").exists());
+        assert_eq!(store.get("huge").unwrap(), Some(b"more than ten bytes".to_vec()));
+    }
+
+    #[test]
+    fn test_cache_survives_restart() {
+        let tmp = TempDir::new().unwrap();
+        let (cache_dir, remote_dir) = (tmp.path().join("cache"), tmp.path().join("remote"));
+        let backend = || CountingStore::new(LocalStore::new(&remote_dir));
+        backend().put("k", b"persisted", IfMatch::Any).unwrap();
+        {
+            let store = DiskCachedStore::new(backend(), &cache_dir, 1 << 20).unwrap();
+            store.get("k").unwrap();
+        }
+        let store = DiskCachedStore::new(backend(), &cache_dir, 1 << 20).unwrap();
+        assert_eq!(store.cached_bytes(), 9);
+        assert_eq!(store.get("k").unwrap(), Some(b"persisted".to_vec()));
+        assert_eq!(store.inner().count("get_opts"), 0);
+
+        // Reopening with a smaller budget trims the directory
+        drop(store);
+        let store = DiskCachedStore::new(backend(), &cache_dir, 4).unwrap();
+        assert_eq!(store.cached_bytes(), 0);
+        assert!(!cache_dir.join("k").exists());
+    }
+
+    // Backend that can be switched to failing as unreachable, like a laptop going offline
+    struct Flaky {
+        inner: InMemoryStore,
+        offline: AtomicBool,
+    }
+
+    impl Flaky {
+        fn check(&self) -> Result<()> {
+            if self.offline.load(Ordering::SeqCst) {
+                Err(ObjectStoreError::Unavailable("network unreachable".to_string()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl ObjectStore for Flaky {
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            self.check()?;
+            self.inner.get(key)
+        }
+        fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+            self.check()?;
+            self.inner.put(key, body, cond)
+        }
+        fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+            self.check()?;
+            self.inner.list(prefix, continuation)
+        }
+        fn delete(&self, key: &str) -> Result<()> {
+            self.check()?;
+            self.inner.delete(key)
+        }
+    }
+
+    #[test]
+    fn test_serves_cached_copies_offline() {
+        let tmp = TempDir::new().unwrap();
+        let backend = Flaky {
+            inner: InMemoryStore::default(),
+            offline: AtomicBool::new(false),
+        };
+        let store = DiskCachedStore::new(backend, tmp.path(), 1 << 20).unwrap();
+        store.put("notes.md", b"# offline", IfMatch::Any).unwrap();
+        store.inner().inner.put("uncached.md", b"remote only", IfMatch::Any).unwrap();
+
+        store.inner().offline.store(true, Ordering::SeqCst);
+        assert_eq!(store.get("notes.md").unwrap(), Some(b"# offline".to_vec()));
+        assert_eq!(store.head("notes.md").unwrap().unwrap().size, 9);
+        assert_eq!(store.get_range("notes.md", 2..9).unwrap(), Some(b"offline".to_vec()));
+        let etag = format!("{:x}", md5::compute(b"# offline"));
+        assert_eq!(store.head("notes.md").unwrap().unwrap().etag, etag);
+        assert!(matches!(store.get("uncached.md"), Err(ObjectStoreError::Unavailable(_))));
+    }
+}
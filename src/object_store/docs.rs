@@ -0,0 +1,342 @@
+use super::retry::{cas_backoff, MAX_CAS_ATTEMPTS};
+use super::{GetOptions, GetResult, IfMatch, ObjectStore, ObjectStoreError, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+
+// Binary documents start with this byte and then their format's tag; JSON
+// is stored bare, since no JSON text starts with a NUL
+const BINARY_MARKER: u8 = 0;
+
+/// The serialization formats a document can be stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Cbor,
+    Bincode,
+    MessagePack,
+}
+
+impl Format {
+    fn tag(self) -> Option<u8> {
+        match self {
+            Format::Json => None,
+            Format::Cbor => Some(b'c'),
+            Format::Bincode => Some(b'b'),
+            Format::MessagePack => Some(b'm'),
+        }
+    }
+
+    // The format of a stored document, and its serialized body
+    fn detect(data: &[u8]) -> Option<(Format, &[u8])> {
+        match data {
+            [BINARY_MARKER, tag, body @ ..] => {
+                let format = [Format::Cbor, Format::Bincode, Format::MessagePack]
+                    .into_iter()
+                    .find(|format| format.tag() == Some(*tag))?;
+                Some((format, body))
+            }
+            [BINARY_MARKER, ..] => None,
+            _ => Some((Format::Json, data)),
+        }
+    }
+}
+
+/// How a `DocStore` serializes documents. Errors are reported as text.
+pub trait Codec {
+    const FORMAT: Format;
+
+    fn encode<T: Serialize>(doc: &T) -> std::result::Result<Vec<u8>, String>;
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> std::result::Result<T, String>;
+}
+
+pub struct Json;
+
+impl Codec for Json {
+    const FORMAT: Format = Format::Json;
+
+    fn encode<T: Serialize>(doc: &T) -> std::result::Result<Vec<u8>, String> {
+        serde_json::to_vec(doc).map_err(|e| e.to_string())
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> std::result::Result<T, String> {
+        serde_json::from_slice(data).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "cbor")]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    const FORMAT: Format = Format::Cbor;
+
+    fn encode<T: Serialize>(doc: &T) -> std::result::Result<Vec<u8>, String> {
+        let mut data = Vec::new();
+        ciborium::into_writer(doc, &mut data).map_err(|e| e.to_string())?;
+        Ok(data)
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> std::result::Result<T, String> {
+        ciborium::from_reader(data).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    const FORMAT: Format = Format::Bincode;
+
+    fn encode<T: Serialize>(doc: &T) -> std::result::Result<Vec<u8>, String> {
+        bincode::serialize(doc).map_err(|e| e.to_string())
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> std::result::Result<T, String> {
+        bincode::deserialize(data).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "msgpack")]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    const FORMAT: Format = Format::MessagePack;
+
+    // With field names, so fields can be added the way they can in JSON
+    fn encode<T: Serialize>(doc: &T) -> std::result::Result<Vec<u8>, String> {
+        rmp_serde::to_vec_named(doc).map_err(|e| e.to_string())
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> std::result::Result<T, String> {
+        rmp_serde::from_slice(data).map_err(|e| e.to_string())
+    }
+}
+
+// Decodes a document in whichever format it was stored in
+fn decode<T: DeserializeOwned>(key: &str, data: &[u8]) -> Result<T> {
+    let corrupt = |e: String| ObjectStoreError::Other(format!("corrupt document {key}: {e}"));
+    let (format, body) = Format::detect(data).ok_or_else(|| corrupt("unknown format".to_string()))?;
+    match format {
+        Format::Json => Json::decode(body).map_err(corrupt),
+        #[cfg(feature = "cbor")]
+        Format::Cbor => Cbor::decode(body).map_err(corrupt),
+        #[cfg(feature = "bincode")]
+        Format::Bincode => Bincode::decode(body).map_err(corrupt),
+        #[cfg(feature = "msgpack")]
+        Format::MessagePack => MessagePack::decode(body).map_err(corrupt),
+        #[allow(unreachable_patterns)]
+        format => Err(ObjectStoreError::Other(format!(
+            "document {key} is stored as {format:?}, which this build doesn't support"
+        ))),
+    }
+}
+
+fn encode<T: Serialize, C: Codec>(key: &str, doc: &T) -> Result<Vec<u8>> {
+    let body = C::encode(doc).map_err(|e| ObjectStoreError::Other(format!("cannot serialize document {key}: {e}")))?;
+    Ok(match C::FORMAT.tag() {
+        Some(tag) => [&[BINARY_MARKER, tag][..], &body].concat(),
+        None => body,
+    })
+}
+
+/// The version of a document as loaded, which `save` must be given back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version(Option<String>);
+
+impl Version {
+    // For saving a document that must not exist yet
+    pub const NEW: Version = Version(None);
+
+    pub fn etag(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+
+    fn cond(&self) -> IfMatch<'_> {
+        match &self.0 {
+            Some(etag) => IfMatch::Tag(etag),
+            None => IfMatch::NoneMatch,
+        }
+    }
+}
+
+/// Small documents of type `T` with optimistic concurrency, saved as JSON
+/// or with the codec `C`.
+///
+/// `load` returns a document with its `Version`, and `save` writes it back
+/// only if it is still at that version, failing with `PreconditionFailed`
+/// if someone else saved in between. `modify` wraps the load, change and
+/// save in a loop that retries on conflict, so the change must be safe to
+/// apply more than once. Binary formats are marked with a leading NUL and a
+/// tag byte, so documents load whatever format they were saved in and a
+/// store can switch codecs without rewriting them.
+pub struct DocStore<S, T, C = Json> {
+    store: S,
+    _doc: PhantomData<fn() -> (T, C)>,
+}
+
+impl<S: ObjectStore, T: Serialize + DeserializeOwned> DocStore<S, T> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            _doc: PhantomData,
+        }
+    }
+}
+
+impl<S: ObjectStore, T: Serialize + DeserializeOwned, C: Codec> DocStore<S, T, C> {
+    // Used for saving from then on; loading reads any format
+    pub fn with_codec<D: Codec>(self) -> DocStore<S, T, D> {
+        DocStore {
+            store: self.store,
+            _doc: PhantomData,
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    pub fn load(&self, key: &str) -> Result<Option<(T, Version)>> {
+        let opts = GetOptions {
+            include_metadata: true,
+            ..Default::default()
+        };
+        match self.store.get_opts(key, opts)? {
+            Some(GetResult::Body { data, meta: Some(meta) }) => {
+                Ok(Some((decode(key, &data)?, Version(Some(meta.etag)))))
+            }
+            None => Ok(None),
+            Some(_) => Err(ObjectStoreError::Other(format!("no metadata for document {key}"))),
+        }
+    }
+
+    /// Writes `doc` if `key` is still at `version`, returning the new version.
+    pub fn save(&self, key: &str, doc: &T, version: &Version) -> Result<Version> {
+        let data = encode::<T, C>(key, doc)?;
+        let etag = self.store.put(key, &data, version.cond())?;
+        Ok(Version(Some(etag)))
+    }
+
+    /// Applies `f` to the document, or to `T::default()` if there is none,
+    /// and saves the result, retrying from a fresh load on conflict.
+    pub fn modify(&self, key: &str, mut f: impl FnMut(&mut T)) -> Result<(T, Version)>
+    where
+        T: Default,
+    {
+        for attempt in 0..MAX_CAS_ATTEMPTS {
+            let (mut doc, version) = self.load(key)?.unwrap_or_else(|| (T::default(), Version::NEW));
+            f(&mut doc);
+            match self.save(key, &doc, &version) {
+                Ok(version) => return Ok((doc, version)),
+                Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => cas_backoff(attempt),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(ObjectStoreError::Other(format!("document {key} kept changing while being modified")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use serde::Deserialize;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Settings {
+        name: String,
+        retries: u32,
+    }
+
+    #[test]
+    fn test_load_and_save() {
+        let docs: DocStore<_, Settings> = DocStore::new(InMemoryStore::default());
+        assert!(docs.load("settings.json").unwrap().is_none());
+
+        let settings = Settings {
+            name: "prod".to_string(),
+            retries: 3,
+        };
+        let version = docs.save("settings.json", &settings, &Version::NEW).unwrap();
+        assert!(matches!(
+            docs.save("settings.json", &settings, &Version::NEW),
+            Err(ObjectStoreError::PreconditionFailed)
+        ));
+        let (loaded, loaded_version) = docs.load("settings.json").unwrap().unwrap();
+        assert_eq!((&loaded, &loaded_version), (&settings, &version));
+
+        let newer = docs.save("settings.json", &Settings::default(), &version).unwrap();
+        assert_ne!(newer, version);
+        // Saving from a stale version fails
+        assert!(matches!(
+            docs.save("settings.json", &settings, &version),
+            Err(ObjectStoreError::PreconditionFailed)
+        ));
+
+        docs.inner().put("broken.json", b"{", IfMatch::Any).unwrap();
+        assert!(docs.load("broken.json").is_err());
+    }
+
+    #[cfg(all(feature = "cbor", feature = "bincode", feature = "msgpack"))]
+    #[test]
+    fn test_codecs() {
+        let store = Arc::new(InMemoryStore::default());
+        let settings = Settings {
+            name: "edge".to_string(),
+            retries: 7,
+        };
+        let json: DocStore<_, Settings> = DocStore::new(store.clone());
+        json.save("json", &settings, &Version::NEW).unwrap();
+        DocStore::<_, Settings>::new(store.clone())
+            .with_codec::<Cbor>()
+            .save("cbor", &settings, &Version::NEW)
+            .unwrap();
+        DocStore::<_, Settings>::new(store.clone())
+            .with_codec::<Bincode>()
+            .save("bincode", &settings, &Version::NEW)
+            .unwrap();
+        let msgpack = DocStore::<_, Settings>::new(store.clone()).with_codec::<MessagePack>();
+        msgpack.save("msgpack", &settings, &Version::NEW).unwrap();
+
+        assert_eq!(store.get("json").unwrap().unwrap()[0], b'{');
+        assert_eq!(store.get("msgpack").unwrap().unwrap()[..2], [0, b'm']);
+        // Any DocStore reads every format
+        for key in ["json", "cbor", "bincode", "msgpack"] {
+            assert_eq!(json.load(key).unwrap().unwrap().0, settings);
+            assert_eq!(msgpack.load(key).unwrap().unwrap().0, settings);
+        }
+        // Modifying a JSON document with a binary codec converts it
+        msgpack.modify("json", |s| s.retries += 1).unwrap();
+        assert_eq!(store.get("json").unwrap().unwrap()[..2], [0, b'm']);
+        assert_eq!(json.load("json").unwrap().unwrap().0.retries, 8);
+
+        store.put("unknown", &[0, b'?', 1], IfMatch::Any).unwrap();
+        assert!(json.load("unknown").is_err());
+    }
+
+    #[test]
+    fn test_concurrent_modify() {
+        let store = Arc::new(InMemoryStore::default());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let docs: DocStore<_, Settings> = DocStore::new(store.clone());
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        docs.modify("settings.json", |s| s.retries += 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let docs: DocStore<_, Settings> = DocStore::new(store);
+        assert_eq!(docs.load("settings.json").unwrap().unwrap().0.retries, 40);
+    }
+}
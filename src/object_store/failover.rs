@@ -0,0 +1,334 @@
+use super::retry::is_transient;
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+type Op<T> = dyn Fn(&dyn ObjectStore) -> Result<T> + Send + Sync;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Primary,
+    Secondary,
+}
+
+struct Health {
+    active: Backend,
+    probed_at: Instant,
+    // A health probe is in flight
+    probing: bool,
+}
+
+/// Serves from a primary store and falls back to a secondary one when the
+/// primary fails, e.g. a replica bucket in another region.
+///
+/// A call that fails on the primary with a transient error (as classified
+/// by `retry::is_transient`), or doesn't answer within the timeout, is
+/// repeated on the secondary, and later calls go straight to the secondary.
+/// While failed over, the first call after each `probe_interval` first
+/// probes the primary with a `head` of `probe_key` and switches back if it
+/// answers. Errors the caller caused, such as `PreconditionFailed`, are
+/// returned as they are.
+///
+/// Writes made while failed over stay on the secondary; copy them back with
+/// `sync` once the primary has recovered. A write that timed out may still
+/// land on the primary afterwards, and listings continued across a switch
+/// hand one backend's continuation token to the other.
+pub struct FailoverStore {
+    primary: Arc<dyn ObjectStore>,
+    secondary: Arc<dyn ObjectStore>,
+    timeout: Option<Duration>,
+    probe_interval: Duration,
+    probe_key: String,
+    health: Mutex<Health>,
+}
+
+impl FailoverStore {
+    pub fn new(primary: Arc<dyn ObjectStore>, secondary: Arc<dyn ObjectStore>) -> Self {
+        Self {
+            primary,
+            secondary,
+            timeout: None,
+            probe_interval: Duration::from_secs(30),
+            probe_key: ".failover-probe".to_string(),
+            health: Mutex::new(Health {
+                active: Backend::Primary,
+                probed_at: Instant::now(),
+                probing: false,
+            }),
+        }
+    }
+
+    // How long a call to the primary may take before the secondary is tried
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_probe_interval(mut self, interval: Duration) -> Self {
+        self.probe_interval = interval;
+        self
+    }
+
+    // Key the health probe looks up; it needn't exist
+    pub fn with_probe_key(mut self, key: impl Into<String>) -> Self {
+        self.probe_key = key.into();
+        self
+    }
+
+    /// The backend calls currently go to.
+    pub fn active(&self) -> Backend {
+        self.health.lock().unwrap().active
+    }
+
+    /// Checks whether the primary answers, switching back to it if so.
+    pub fn probe(&self) -> bool {
+        let key = self.probe_key.clone();
+        let healthy = match self.on_primary(Arc::new(move |store: &dyn ObjectStore| store.head(&key))) {
+            Err(e) => !is_transient(&e),
+            Ok(_) => true,
+        };
+        let mut health = self.health.lock().unwrap();
+        health.probing = false;
+        health.probed_at = Instant::now();
+        if healthy {
+            health.active = Backend::Primary;
+        }
+        healthy
+    }
+
+    // Claims the next periodic probe while failed over
+    fn probe_due(&self) -> bool {
+        let mut health = self.health.lock().unwrap();
+        let due = health.active == Backend::Secondary
+            && !health.probing
+            && health.probed_at.elapsed() >= self.probe_interval;
+        if due {
+            health.probing = true;
+        }
+        due
+    }
+
+    fn fail_over(&self) {
+        let mut health = self.health.lock().unwrap();
+        if health.active == Backend::Primary {
+            health.active = Backend::Secondary;
+            health.probed_at = Instant::now();
+        }
+    }
+
+    fn on_primary<T: Send + 'static>(&self, op: Arc<Op<T>>) -> Result<T> {
+        let Some(timeout) = self.timeout else {
+            return op(self.primary.as_ref());
+        };
+        // The call can't be cancelled, so it's left to finish on its own thread
+        let (tx, rx) = mpsc::channel();
+        let primary = self.primary.clone();
+        thread::spawn(move || {
+            let _ = tx.send(op(primary.as_ref()));
+        });
+        match rx.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(ObjectStoreError::Io(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("primary did not answer within {timeout:?}"),
+            ))),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(ObjectStoreError::Io(std::io::Error::other("primary call panicked")))
+            }
+        }
+    }
+
+    fn call<T: Send + 'static>(&self, op: impl Fn(&dyn ObjectStore) -> Result<T> + Send + Sync + 'static) -> Result<T> {
+        if self.probe_due() {
+            self.probe();
+        }
+        let op: Arc<Op<T>> = Arc::new(op);
+        if self.active() == Backend::Primary {
+            match self.on_primary(op.clone()) {
+                Err(e) if is_transient(&e) => self.fail_over(),
+                result => return result,
+            }
+        }
+        op(self.secondary.as_ref())
+    }
+}
+
+impl ObjectStore for FailoverStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let key = key.to_string();
+        self.call(move |store| store.get(&key))
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        // The call may outlive this borrow on the primary's thread
+        let (key, body) = (key.to_string(), body.to_vec());
+        let tag = match cond {
+            IfMatch::Tag(tag) => Some(tag.to_string()),
+            _ => None,
+        };
+        let none_match = matches!(cond, IfMatch::NoneMatch);
+        self.call(move |store| {
+            let cond = match &tag {
+                Some(tag) => IfMatch::Tag(tag),
+                None if none_match => IfMatch::NoneMatch,
+                None => IfMatch::Any,
+            };
+            store.put(&key, &body, cond)
+        })
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let prefix = prefix.to_string();
+        self.call(move |store| store.list(&prefix, continuation.clone()))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let key = key.to_string();
+        self.call(move |store| store.delete(&key))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let key = key.to_string();
+        self.call(move |store| store.head(&key))
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        let key = key.to_string();
+        self.call(move |store| store.get_range(&key, range.clone()))
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        let key = key.to_string();
+        let (range, include_metadata) = (opts.range, opts.include_metadata);
+        let if_match = opts.if_match.map(str::to_string);
+        let if_none_match = opts.if_none_match.map(str::to_string);
+        self.call(move |store| {
+            let opts = GetOptions {
+                range: range.clone(),
+                if_match: if_match.as_deref(),
+                if_none_match: if_none_match.as_deref(),
+                include_metadata,
+            };
+            store.get_opts(&key, opts)
+        })
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        let (key, tags) = (key.to_string(), tags.clone());
+        self.call(move |store| store.put_tags(&key, &tags))
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        let key = key.to_string();
+        self.call(move |store| store.get_tags(&key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use std::sync::atomic::{AtomicU8, Ordering};
+    use uuid::Uuid;
+
+    const UP: u8 = 0;
+    const DOWN: u8 = 1;
+    const HUNG: u8 = 2;
+
+    // Backend that can be taken down or made to hang
+    #[derive(Default)]
+    struct Switchable {
+        inner: InMemoryStore,
+        state: AtomicU8,
+    }
+
+    impl Switchable {
+        fn check(&self) -> Result<()> {
+            match self.state.load(Ordering::SeqCst) {
+                DOWN => Err(ObjectStoreError::Io(std::io::Error::other("connection refused"))),
+                HUNG => {
+                    thread::sleep(Duration::from_millis(200));
+                    Ok(())
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+
+    impl ObjectStore for Switchable {
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            self.check()?;
+            self.inner.get(key)
+        }
+        fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+            self.check()?;
+            self.inner.put(key, body, cond)
+        }
+        fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+            self.check()?;
+            self.inner.list(prefix, continuation)
+        }
+        fn delete(&self, key: &str) -> Result<()> {
+            self.check()?;
+            self.inner.delete(key)
+        }
+    }
+
+    fn stores() -> (Arc<Switchable>, Arc<InMemoryStore>) {
+        let (primary, secondary) = (Arc::new(Switchable::default()), Arc::new(InMemoryStore::default()));
+        primary.put("doc", b"primary", IfMatch::Any).unwrap();
+        secondary.put("doc", b"secondary", IfMatch::Any).unwrap();
+        (primary, secondary)
+    }
+
+    #[test]
+    fn test_failover_object_store() {
+        let store = FailoverStore::new(Arc::new(InMemoryStore::default()), Arc::new(InMemoryStore::default()))
+            .with_timeout(Duration::from_secs(5));
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+        assert_eq!(store.active(), Backend::Primary);
+    }
+
+    #[test]
+    fn test_fails_over_and_back() {
+        let (primary, secondary) = stores();
+        let store = FailoverStore::new(primary.clone(), secondary).with_probe_interval(Duration::from_millis(30));
+        assert_eq!(store.get("doc").unwrap(), Some(b"primary".to_vec()));
+
+        primary.state.store(DOWN, Ordering::SeqCst);
+        assert_eq!(store.get("doc").unwrap(), Some(b"secondary".to_vec()));
+        assert_eq!(store.active(), Backend::Secondary);
+        assert!(!store.probe());
+
+        // Recovered, but only noticed once the probe interval has passed
+        primary.state.store(UP, Ordering::SeqCst);
+        assert_eq!(store.get("doc").unwrap(), Some(b"secondary".to_vec()));
+        thread::sleep(Duration::from_millis(40));
+        assert_eq!(store.get("doc").unwrap(), Some(b"primary".to_vec()));
+        assert_eq!(store.active(), Backend::Primary);
+    }
+
+    #[test]
+    fn test_fails_over_on_timeout() {
+        let (primary, secondary) = stores();
+        let store = FailoverStore::new(primary.clone(), secondary).with_timeout(Duration::from_millis(50));
+        primary.state.store(HUNG, Ordering::SeqCst);
+        let started = Instant::now();
+        assert_eq!(store.get("doc").unwrap(), Some(b"secondary".to_vec()));
+        assert!(started.elapsed() < Duration::from_millis(200));
+        assert_eq!(store.active(), Backend::Secondary);
+    }
+
+    #[test]
+    fn test_caller_errors_do_not_fail_over() {
+        let (primary, secondary) = stores();
+        let store = FailoverStore::new(primary, secondary);
+        let result = store.put("doc", b"v", IfMatch::NoneMatch);
+        assert!(matches!(result, Err(ObjectStoreError::PreconditionFailed)));
+        assert_eq!(store.active(), Backend::Primary);
+    }
+}
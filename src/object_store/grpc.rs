@@ -0,0 +1,586 @@
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use proto::blob_store_client::BlobStoreClient;
+use proto::blob_store_server::{BlobStore, BlobStoreServer};
+use proto::{condition, put_request, Chunk, Condition, GetRangeRequest, GetRequest, HeadRequest, HeadResponse};
+use proto::{DeleteRequest, DeleteResponse, ListRequest, ListResponse, PutHeader, PutRequest, PutResponse};
+use proto::{get_opts_response, ByteRange, GetOptsHeader, GetOptsRequest, GetOptsResponse};
+use proto::{GetTagsRequest, GetTagsResponse, PutTagsRequest, PutTagsResponse};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use tonic::transport::{Channel, Server};
+use tonic::{Code, Request, Response, Status, Streaming};
+
+pub mod proto {
+    tonic::include_proto!("blob_store");
+}
+
+// Bodies are streamed in slices of this size in both directions
+const CHUNK_SIZE: usize = 64 * 1024;
+
+type ChunkStream = tokio_stream::Iter<std::vec::IntoIter<std::result::Result<Chunk, Status>>>;
+type GetOptsStream = tokio_stream::Iter<std::vec::IntoIter<std::result::Result<GetOptsResponse, Status>>>;
+
+/// gRPC service exposing any store over the network.
+///
+/// Store calls are blocking, so each request runs on tokio's blocking pool.
+pub struct BlobStoreService {
+    store: Arc<dyn ObjectStore>,
+}
+
+pub fn service(store: Arc<dyn ObjectStore>) -> BlobStoreServer<BlobStoreService> {
+    BlobStoreServer::new(BlobStoreService { store })
+}
+
+// Serves `store` on `addr` until the future is dropped
+pub async fn serve(store: Arc<dyn ObjectStore>, addr: SocketAddr) -> Result<()> {
+    Server::builder()
+        .add_service(service(store))
+        .serve(addr)
+        .await
+        .map_err(|e| ObjectStoreError::Other(format!("gRPC server error: {e}")))
+}
+
+// Tell a read-only rejection and refused credentials from a blocked upload;
+// all three are PermissionDenied
+const READ_ONLY_PREFIX: &str = "read-only: ";
+const DENIED_PREFIX: &str = "denied: ";
+// Tells a NotFound error from the NotFound status for a missing object
+const NOT_FOUND_PREFIX: &str = "not found: ";
+// Tells throttling from an exceeded quota; both are ResourceExhausted
+const THROTTLED_PREFIX: &str = "throttled: ";
+// Tells a bad argument from a bad key; both are InvalidArgument
+const INVALID_ARGUMENT_PREFIX: &str = "invalid argument: ";
+
+fn to_status(e: ObjectStoreError) -> Status {
+    match e.into_root() {
+        ObjectStoreError::NotFound(what) => Status::not_found(format!("{NOT_FOUND_PREFIX}{what}")),
+        ObjectStoreError::PreconditionFailed => Status::failed_precondition("precondition failed"),
+        ObjectStoreError::PermissionDenied(msg) => Status::permission_denied(format!("{DENIED_PREFIX}{msg}")),
+        ObjectStoreError::Throttled(msg) => Status::resource_exhausted(format!("{THROTTLED_PREFIX}{msg}")),
+        ObjectStoreError::Timeout(msg) => Status::deadline_exceeded(msg),
+        ObjectStoreError::Unavailable(msg) => Status::unavailable(msg),
+        ObjectStoreError::Blocked(reason) => Status::permission_denied(reason),
+        ObjectStoreError::Unsupported(what) => Status::unimplemented(what),
+        ObjectStoreError::ChecksumMismatch(key) => Status::data_loss(key),
+        ObjectStoreError::QuotaExceeded(limit) => Status::resource_exhausted(limit),
+        ObjectStoreError::ReadOnly(key) => Status::permission_denied(format!("{READ_ONLY_PREFIX}{key}")),
+        ObjectStoreError::InvalidKey(key) => Status::invalid_argument(key),
+        ObjectStoreError::InvalidArgument(what) => Status::invalid_argument(format!("{INVALID_ARGUMENT_PREFIX}{what}")),
+        ObjectStoreError::Io(e) => Status::internal(format!("io error: {e}")),
+        e @ (ObjectStoreError::Backend { .. } | ObjectStoreError::Context { .. }) => Status::internal(e.to_string()),
+        ObjectStoreError::Other(msg) => Status::internal(msg),
+    }
+}
+
+fn from_status(status: Status) -> ObjectStoreError {
+    let message = status.message();
+    match status.code() {
+        Code::NotFound => ObjectStoreError::NotFound(message.strip_prefix(NOT_FOUND_PREFIX).unwrap_or(message).to_string()),
+        Code::FailedPrecondition => ObjectStoreError::PreconditionFailed,
+        Code::PermissionDenied => {
+            if let Some(key) = message.strip_prefix(READ_ONLY_PREFIX) {
+                ObjectStoreError::ReadOnly(key.to_string())
+            } else if let Some(msg) = message.strip_prefix(DENIED_PREFIX) {
+                ObjectStoreError::PermissionDenied(msg.to_string())
+            } else {
+                ObjectStoreError::Blocked(message.to_string())
+            }
+        }
+        Code::Unimplemented => ObjectStoreError::Unsupported(message.to_string()),
+        Code::DataLoss => ObjectStoreError::ChecksumMismatch(message.to_string()),
+        Code::ResourceExhausted => match message.strip_prefix(THROTTLED_PREFIX) {
+            Some(msg) => ObjectStoreError::Throttled(msg.to_string()),
+            None => ObjectStoreError::QuotaExceeded(message.to_string()),
+        },
+        Code::DeadlineExceeded => ObjectStoreError::Timeout(message.to_string()),
+        Code::Unavailable => ObjectStoreError::Unavailable(format!("gRPC error: {status}")),
+        Code::InvalidArgument => match message.strip_prefix(INVALID_ARGUMENT_PREFIX) {
+            Some(what) => ObjectStoreError::InvalidArgument(what.to_string()),
+            None => ObjectStoreError::InvalidKey(message.to_string()),
+        },
+        _ => ObjectStoreError::Other(format!("gRPC error: {status}")),
+    }
+}
+
+// Whether a status only says the requested object does not exist
+fn is_missing(status: &Status) -> bool {
+    status.code() == Code::NotFound && !status.message().starts_with(NOT_FOUND_PREFIX)
+}
+
+fn chunk_stream(data: Vec<u8>) -> ChunkStream {
+    let chunks: Vec<_> = data
+        .chunks(CHUNK_SIZE)
+        .map(|c| Ok(Chunk { data: c.to_vec() }))
+        .collect();
+    tokio_stream::iter(chunks)
+}
+
+impl BlobStoreService {
+    async fn blocking<T, F>(&self, f: F) -> std::result::Result<T, Status>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn ObjectStore) -> Result<T> + Send + 'static,
+    {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || f(store.as_ref()))
+            .await
+            .map_err(|e| Status::internal(format!("store task failed: {e}")))?
+            .map_err(to_status)
+    }
+}
+
+#[tonic::async_trait]
+impl BlobStore for BlobStoreService {
+    type GetStream = ChunkStream;
+    type GetRangeStream = ChunkStream;
+    type GetOptsStream = GetOptsStream;
+
+    async fn get(&self, request: Request<GetRequest>) -> std::result::Result<Response<ChunkStream>, Status> {
+        let key = request.into_inner().key;
+        match self.blocking(move |store| store.get(&key)).await? {
+            Some(data) => Ok(Response::new(chunk_stream(data))),
+            None => Err(Status::not_found("no such key")),
+        }
+    }
+
+    async fn get_range(
+        &self,
+        request: Request<GetRangeRequest>,
+    ) -> std::result::Result<Response<ChunkStream>, Status> {
+        let req = request.into_inner();
+        match self.blocking(move |store| store.get_range(&req.key, req.start..req.end)).await? {
+            Some(data) => Ok(Response::new(chunk_stream(data))),
+            None => Err(Status::not_found("no such key")),
+        }
+    }
+
+    async fn get_opts(
+        &self,
+        request: Request<GetOptsRequest>,
+    ) -> std::result::Result<Response<GetOptsStream>, Status> {
+        let req = request.into_inner();
+        let result = self
+            .blocking(move |store| {
+                let opts = GetOptions {
+                    range: req.range.map(|range| range.start..range.end),
+                    if_match: req.if_match.as_deref(),
+                    if_none_match: req.if_none_match.as_deref(),
+                    include_metadata: req.include_metadata,
+                };
+                store.get_opts(&req.key, opts)
+            })
+            .await?;
+        let header = |not_modified, meta: Option<ObjectMeta>| GetOptsResponse {
+            part: Some(get_opts_response::Part::Header(GetOptsHeader {
+                not_modified,
+                meta: meta.map(|meta| HeadResponse {
+                    size: meta.size,
+                    etag: meta.etag,
+                }),
+            })),
+        };
+        let messages = match result {
+            Some(GetResult::NotModified) => vec![Ok(header(true, None))],
+            Some(GetResult::Body { data, meta }) => std::iter::once(Ok(header(false, meta)))
+                .chain(data.chunks(CHUNK_SIZE).map(|c| {
+                    Ok(GetOptsResponse {
+                        part: Some(get_opts_response::Part::Data(c.to_vec())),
+                    })
+                }))
+                .collect(),
+            None => return Err(Status::not_found("no such key")),
+        };
+        Ok(Response::new(tokio_stream::iter(messages)))
+    }
+
+    async fn head(&self, request: Request<HeadRequest>) -> std::result::Result<Response<HeadResponse>, Status> {
+        let key = request.into_inner().key;
+        match self.blocking(move |store| store.head(&key)).await? {
+            Some(meta) => Ok(Response::new(HeadResponse {
+                size: meta.size,
+                etag: meta.etag,
+            })),
+            None => Err(Status::not_found("no such key")),
+        }
+    }
+
+    async fn put(
+        &self,
+        request: Request<Streaming<PutRequest>>,
+    ) -> std::result::Result<Response<PutResponse>, Status> {
+        let mut stream = request.into_inner();
+        let header = match stream.message().await?.and_then(|m| m.part) {
+            Some(put_request::Part::Header(header)) => header,
+            _ => return Err(Status::invalid_argument("put must start with a header")),
+        };
+
+        let mut body = Vec::new();
+        while let Some(msg) = stream.message().await? {
+            match msg.part {
+                Some(put_request::Part::Data(data)) => body.extend_from_slice(&data),
+                _ => return Err(Status::invalid_argument("unexpected header after put body")),
+            }
+        }
+
+        let key = header.key;
+        let kind = header.condition.and_then(|c| c.kind);
+        let etag = self
+            .blocking(move |store| {
+                let cond = match &kind {
+                    None => IfMatch::Any,
+                    Some(condition::Kind::IfMatch(tag)) => IfMatch::Tag(tag),
+                    Some(condition::Kind::IfNoneMatch(_)) => IfMatch::NoneMatch,
+                };
+                store.put(&key, &body, cond)
+            })
+            .await?;
+        Ok(Response::new(PutResponse { etag }))
+    }
+
+    async fn list(&self, request: Request<ListRequest>) -> std::result::Result<Response<ListResponse>, Status> {
+        let req = request.into_inner();
+        let (keys, next_continuation) = self
+            .blocking(move |store| store.list(&req.prefix, req.continuation))
+            .await?;
+        Ok(Response::new(ListResponse { keys, next_continuation }))
+    }
+
+    async fn delete(&self, request: Request<DeleteRequest>) -> std::result::Result<Response<DeleteResponse>, Status> {
+        let key = request.into_inner().key;
+        self.blocking(move |store| store.delete(&key)).await?;
+        Ok(Response::new(DeleteResponse {}))
+    }
+
+    async fn put_tags(&self, request: Request<PutTagsRequest>) -> std::result::Result<Response<PutTagsResponse>, Status> {
+        let req = request.into_inner();
+        let tags: BTreeMap<String, String> = req.tags.into_iter().collect();
+        self.blocking(move |store| store.put_tags(&req.key, &tags)).await?;
+        Ok(Response::new(PutTagsResponse {}))
+    }
+
+    async fn get_tags(&self, request: Request<GetTagsRequest>) -> std::result::Result<Response<GetTagsResponse>, Status> {
+        let key = request.into_inner().key;
+        match self.blocking(move |store| store.get_tags(&key)).await? {
+            Some(tags) => Ok(Response::new(GetTagsResponse {
+                tags: tags.into_iter().collect(),
+            })),
+            None => Err(Status::not_found("no such key")),
+        }
+    }
+}
+
+/// Client backend talking to a remote `BlobStoreService`.
+pub struct GrpcStore {
+    client: BlobStoreClient<Channel>,
+    rt: Arc<Runtime>,
+}
+
+impl GrpcStore {
+    // `endpoint` is a URI such as "http://127.0.0.1:50051"
+    pub fn connect(endpoint: impl Into<String>) -> Result<Self> {
+        let rt = Arc::new(Runtime::new().expect("Failed to create Tokio runtime"));
+        let endpoint = Channel::from_shared(endpoint.into())
+            .map_err(|e| ObjectStoreError::Other(format!("invalid gRPC endpoint: {e}")))?;
+        let channel = rt
+            .block_on(endpoint.connect())
+            .map_err(|e| ObjectStoreError::Unavailable(format!("gRPC connect error: {e}")))?;
+        Ok(Self {
+            client: BlobStoreClient::new(channel),
+            rt,
+        })
+    }
+
+    fn read_chunks(
+        &self,
+        response: std::result::Result<Response<Streaming<Chunk>>, Status>,
+    ) -> Result<Option<Vec<u8>>> {
+        self.rt.block_on(async move {
+            let mut stream = match response {
+                Ok(resp) => resp.into_inner(),
+                Err(status) if is_missing(&status) => return Ok(None),
+                Err(status) => return Err(from_status(status)),
+            };
+            let mut data = Vec::new();
+            while let Some(chunk) = stream.message().await.map_err(from_status)? {
+                data.extend_from_slice(&chunk.data);
+            }
+            Ok(Some(data))
+        })
+    }
+}
+
+impl ObjectStore for GrpcStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let mut client = self.client.clone();
+        let request = GetRequest { key: key.to_string() };
+        let response = self.rt.block_on(client.get(request));
+        self.read_chunks(response)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        let condition = match cond {
+            IfMatch::Any => None,
+            IfMatch::Tag(tag) => Some(Condition {
+                kind: Some(condition::Kind::IfMatch(tag.to_string())),
+            }),
+            IfMatch::NoneMatch => Some(Condition {
+                kind: Some(condition::Kind::IfNoneMatch(true)),
+            }),
+        };
+
+        let mut messages = vec![PutRequest {
+            part: Some(put_request::Part::Header(PutHeader {
+                key: key.to_string(),
+                condition,
+            })),
+        }];
+        messages.extend(body.chunks(CHUNK_SIZE).map(|c| PutRequest {
+            part: Some(put_request::Part::Data(c.to_vec())),
+        }));
+
+        let mut client = self.client.clone();
+        self.rt.block_on(async move {
+            let resp = client
+                .put(tokio_stream::iter(messages))
+                .await
+                .map_err(from_status)?;
+            Ok(resp.into_inner().etag)
+        })
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let mut client = self.client.clone();
+        let request = ListRequest {
+            prefix: prefix.to_string(),
+            continuation,
+        };
+        self.rt.block_on(async move {
+            let resp = client.list(request).await.map_err(from_status)?.into_inner();
+            Ok((resp.keys, resp.next_continuation))
+        })
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let mut client = self.client.clone();
+        let request = DeleteRequest { key: key.to_string() };
+        self.rt.block_on(async move {
+            client.delete(request).await.map_err(from_status)?;
+            Ok(())
+        })
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let mut client = self.client.clone();
+        let request = HeadRequest { key: key.to_string() };
+        self.rt.block_on(async move {
+            match client.head(request).await {
+                Ok(resp) => {
+                    let resp = resp.into_inner();
+                    Ok(Some(ObjectMeta {
+                        size: resp.size,
+                        etag: resp.etag,
+                    }))
+                }
+                Err(status) if is_missing(&status) => Ok(None),
+                Err(status) => Err(from_status(status)),
+            }
+        })
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        let mut client = self.client.clone();
+        let request = GetRangeRequest {
+            key: key.to_string(),
+            start: range.start,
+            end: range.end,
+        };
+        let response = self.rt.block_on(client.get_range(request));
+        self.read_chunks(response)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        let mut client = self.client.clone();
+        let request = GetOptsRequest {
+            key: key.to_string(),
+            range: opts.range.map(|range| ByteRange {
+                start: range.start,
+                end: range.end,
+            }),
+            if_match: opts.if_match.map(str::to_string),
+            if_none_match: opts.if_none_match.map(str::to_string),
+            include_metadata: opts.include_metadata,
+        };
+        self.rt.block_on(async move {
+            let mut stream = match client.get_opts(request).await {
+                Ok(resp) => resp.into_inner(),
+                Err(status) if is_missing(&status) => return Ok(None),
+                Err(status) => return Err(from_status(status)),
+            };
+            let header = match stream.message().await.map_err(from_status)?.and_then(|m| m.part) {
+                Some(get_opts_response::Part::Header(header)) => header,
+                _ => return Err(ObjectStoreError::Other("get_opts response must start with a header".to_string())),
+            };
+            if header.not_modified {
+                return Ok(Some(GetResult::NotModified));
+            }
+            let mut data = Vec::new();
+            while let Some(msg) = stream.message().await.map_err(from_status)? {
+                match msg.part {
+                    Some(get_opts_response::Part::Data(chunk)) => data.extend_from_slice(&chunk),
+                    _ => return Err(ObjectStoreError::Other("unexpected header after get_opts body".to_string())),
+                }
+            }
+            let meta = header.meta.map(|meta| ObjectMeta {
+                size: meta.size,
+                etag: meta.etag,
+            });
+            Ok(Some(GetResult::Body { data, meta }))
+        })
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        let mut client = self.client.clone();
+        let request = PutTagsRequest {
+            key: key.to_string(),
+            tags: tags.clone().into_iter().collect(),
+        };
+        self.rt.block_on(async move {
+            client.put_tags(request).await.map_err(from_status)?;
+            Ok(())
+        })
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        let mut client = self.client.clone();
+        let request = GetTagsRequest { key: key.to_string() };
+        self.rt.block_on(async move {
+            match client.get_tags(request).await {
+                Ok(resp) => Ok(Some(resp.into_inner().tags.into_iter().collect())),
+                Err(status) if is_missing(&status) => Ok(None),
+                Err(status) => Err(from_status(status)),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::readonly::ReadOnlyStore;
+    use crate::object_store::scan::{ScanVerdict, ScanningStore};
+    use crate::object_store::strict::StrictMemoryStore;
+    use crate::object_store::tags::TaggedStore;
+    use crate::object_store::test_helpers::tests::{run_object_store_tests, run_oracle_tests, CountingStore};
+    use std::thread;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use uuid::Uuid;
+
+    // Runs the service on its own runtime and returns the endpoint URI
+    fn spawn_server(store: Arc<dyn ObjectStore>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        listener.set_nonblocking(true).unwrap();
+
+        thread::spawn(move || {
+            let rt = Runtime::new().unwrap();
+            rt.block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                Server::builder()
+                    .add_service(service(store))
+                    .serve_with_incoming(TcpListenerStream::new(listener))
+                    .await
+                    .unwrap();
+            });
+        });
+
+        format!("http://{addr}")
+    }
+
+    #[test]
+    fn test_grpc_object_store() {
+        let endpoint = spawn_server(Arc::new(InMemoryStore::default()));
+        let store = GrpcStore::connect(endpoint).unwrap();
+        let prefix = format!("test/{}/", Uuid::new_v4());
+        run_object_store_tests(&store, &prefix);
+        run_oracle_tests(&store, &format!("oracle/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_large_body_is_streamed() {
+        let endpoint = spawn_server(Arc::new(InMemoryStore::default()));
+        let store = GrpcStore::connect(endpoint).unwrap();
+
+        // Larger than tonic's default 4 MiB message limit
+        let big: Vec<u8> = (0..6 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        store.put("big.bin", &big, IfMatch::Any).unwrap();
+        assert_eq!(store.get("big.bin").unwrap(), Some(big));
+    }
+
+    #[test]
+    fn test_get_opts_is_forwarded() {
+        let backend = Arc::new(CountingStore::new(InMemoryStore::default()));
+        let store = GrpcStore::connect(spawn_server(backend.clone())).unwrap();
+        let etag = store.put("k", b"0123456789", IfMatch::Any).unwrap();
+
+        let opts = GetOptions {
+            range: Some(2..5),
+            if_match: Some(&etag),
+            include_metadata: true,
+            ..Default::default()
+        };
+        let meta = ObjectMeta { size: 10, etag: etag.clone() };
+        let result = store.get_opts("k", opts).unwrap();
+        assert_eq!(result, Some(GetResult::Body { data: b"234".to_vec(), meta: Some(meta) }));
+        let opts = GetOptions {
+            if_none_match: Some(&etag),
+            ..Default::default()
+        };
+        assert_eq!(store.get_opts("k", opts).unwrap(), Some(GetResult::NotModified));
+        let opts = GetOptions {
+            if_match: Some("stale"),
+            ..Default::default()
+        };
+        assert!(matches!(store.get_opts("k", opts), Err(ObjectStoreError::PreconditionFailed)));
+        assert_eq!(store.get_opts("missing", GetOptions::default()).unwrap(), None);
+        // Answered by the backend's own get_opts, not a whole-object get
+        assert_eq!(backend.count("get_opts"), 4);
+        assert_eq!(backend.count("get"), 0);
+    }
+
+    #[test]
+    fn test_error_codes_round_trip() {
+        let scanner = |_: &str, body: &[u8]| {
+            if body == b"virus" {
+                Ok(ScanVerdict::Infected("test-signature".to_string()))
+            } else {
+                Ok(ScanVerdict::Clean)
+            }
+        };
+        let backend = ScanningStore::new(InMemoryStore::default(), scanner);
+        let store = GrpcStore::connect(spawn_server(Arc::new(backend))).unwrap();
+
+        let result = store.put("bad", b"virus", IfMatch::Any);
+        assert!(matches!(result, Err(ObjectStoreError::Blocked(ref r)) if r == "test-signature"));
+
+        store.put("key", b"v1", IfMatch::Any).unwrap();
+        let result = store.put("key", b"v2", IfMatch::NoneMatch);
+        assert!(matches!(result, Err(ObjectStoreError::PreconditionFailed)));
+
+        assert_eq!(store.get("missing").unwrap(), None);
+        assert_eq!(store.head("missing").unwrap(), None);
+
+        let store = GrpcStore::connect(spawn_server(Arc::new(ReadOnlyStore::new(InMemoryStore::default())))).unwrap();
+        let result = store.delete("key");
+        assert!(matches!(result, Err(ObjectStoreError::ReadOnly(ref k)) if k == "key"));
+
+        let store = GrpcStore::connect(spawn_server(Arc::new(TaggedStore::new(StrictMemoryStore::default())))).unwrap();
+        let result = store.put(".tags/key", b"v", IfMatch::Any);
+        assert!(matches!(result, Err(ObjectStoreError::InvalidKey(ref k)) if k == ".tags/key"));
+        let result = store.list("", Some("bogus".to_string()));
+        assert!(matches!(result, Err(ObjectStoreError::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,404 @@
+use super::{
+    check_conditions, get_opts_fallback, slice_range, GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore,
+    ObjectStoreError, Result,
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::ops::Range;
+use ureq::Agent;
+
+// Everything but unreserved characters and the path separator gets escaped
+const KEY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+
+/// Read-only store over a static HTTP server or CDN.
+///
+/// Keys map to `<base_url>/<key>`. Reads use `ETag`, `Content-Length` and
+/// `Range` headers; put, list and delete return `ObjectStoreError::Unsupported`.
+pub struct HttpStore {
+    base_url: String,
+    agent: Agent,
+}
+
+impl HttpStore {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        let agent = Agent::config_builder()
+            .http_status_as_error(false)
+            .build()
+            .into();
+        Self::with_agent(base_url, agent)
+    }
+
+    // The agent must be configured with `http_status_as_error(false)`
+    pub fn with_agent(base_url: impl Into<String>, agent: Agent) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            agent,
+        }
+    }
+
+    fn object_url(&self, key: &str) -> String {
+        format!("{}/{}", self.base_url, utf8_percent_encode(key, KEY_ENCODE_SET))
+    }
+
+    fn map_err(e: ureq::Error) -> ObjectStoreError {
+        match e {
+            ureq::Error::Io(e) => ObjectStoreError::Io(e),
+            ureq::Error::Timeout(_) => ObjectStoreError::Timeout(format!("HTTP error: {e}")),
+            ureq::Error::HostNotFound | ureq::Error::ConnectionFailed => {
+                ObjectStoreError::Unavailable(format!("HTTP error: {e}"))
+            }
+            e => ObjectStoreError::backend(format!("HTTP error: {e}"), e),
+        }
+    }
+
+    fn status_err(status: u16, url: &str) -> ObjectStoreError {
+        let message = format!("HTTP {status} for {url}");
+        match status {
+            401 | 403 => ObjectStoreError::PermissionDenied(message),
+            408 | 504 => ObjectStoreError::Timeout(message),
+            429 => ObjectStoreError::Throttled(message),
+            500..=599 => ObjectStoreError::Unavailable(message),
+            _ => ObjectStoreError::Other(message),
+        }
+    }
+}
+
+fn is_missing(status: u16) -> bool {
+    status == 404 || status == 410
+}
+
+// Conditional headers want quoted ETags, except for the "*" wildcard
+fn quote_etag(etag: &str) -> String {
+    if etag == "*" { etag.to_string() } else { format!("\"{etag}\"") }
+}
+
+impl ObjectStore for HttpStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let url = self.object_url(key);
+        let mut resp = self.agent.get(&url).call().map_err(Self::map_err)?;
+        match resp.status().as_u16() {
+            200 => {
+                let data = resp
+                    .body_mut()
+                    .with_config()
+                    .limit(u64::MAX)
+                    .read_to_vec()
+                    .map_err(Self::map_err)?;
+                Ok(Some(data))
+            }
+            status if is_missing(status) => Ok(None),
+            status => Err(Self::status_err(status, &url)),
+        }
+    }
+
+    fn put(&self, _key: &str, _body: &[u8], _cond: IfMatch) -> Result<String> {
+        Err(ObjectStoreError::Unsupported("put on HttpStore".to_string()))
+    }
+
+    fn list(&self, _prefix: &str, _continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        Err(ObjectStoreError::Unsupported("list on HttpStore".to_string()))
+    }
+
+    fn delete(&self, _key: &str) -> Result<()> {
+        Err(ObjectStoreError::Unsupported("delete on HttpStore".to_string()))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let url = self.object_url(key);
+        let resp = self.agent.head(&url).call().map_err(Self::map_err)?;
+        match resp.status().as_u16() {
+            200 => {
+                let header = |name: &str| {
+                    resp.headers()
+                        .get(name)
+                        .and_then(|v| v.to_str().ok())
+                        .map(|v| v.to_string())
+                };
+                let size = header("content-length")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0);
+                // Weak validators still identify the representation for our purposes
+                let etag = header("etag")
+                    .map(|v| v.trim_start_matches("W/").trim_matches('"').to_string())
+                    .unwrap_or_default();
+                Ok(Some(ObjectMeta { size, etag }))
+            }
+            status if is_missing(status) => Ok(None),
+            status => Err(Self::status_err(status, &url)),
+        }
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        // HTTP ranges are inclusive and can't be empty, so answer those locally
+        if range.start >= range.end {
+            return Ok(self.head(key)?.map(|_| Vec::new()));
+        }
+
+        let url = self.object_url(key);
+        let mut resp = self
+            .agent
+            .get(&url)
+            .header("Range", &format!("bytes={}-{}", range.start, range.end - 1))
+            .call()
+            .map_err(Self::map_err)?;
+        match resp.status().as_u16() {
+            status @ (200 | 206) => {
+                let data = resp
+                    .body_mut()
+                    .with_config()
+                    .limit(u64::MAX)
+                    .read_to_vec()
+                    .map_err(Self::map_err)?;
+                // A 200 means the server ignored the Range header
+                if status == 200 {
+                    Ok(Some(slice_range(&data, range).to_vec()))
+                } else {
+                    Ok(Some(data))
+                }
+            }
+            // Range starts past the end of an existing object
+            416 => Ok(Some(Vec::new())),
+            status if is_missing(status) => Ok(None),
+            status => Err(Self::status_err(status, &url)),
+        }
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        // HTTP ranges can't be empty; the fallback answers those with a head
+        if opts.range.as_ref().is_some_and(|range| range.start >= range.end) {
+            return get_opts_fallback(self, key, &opts);
+        }
+
+        let url = self.object_url(key);
+        let mut req = self.agent.get(&url);
+        if let Some(range) = &opts.range {
+            req = req.header("Range", &format!("bytes={}-{}", range.start, range.end - 1));
+        }
+        if let Some(etag) = opts.if_match {
+            req = req.header("If-Match", &quote_etag(etag));
+        }
+        if let Some(etag) = opts.if_none_match {
+            req = req.header("If-None-Match", &quote_etag(etag));
+        }
+        let mut resp = req.call().map_err(Self::map_err)?;
+
+        match resp.status().as_u16() {
+            status @ (200 | 206) => {
+                let header = |name: &str| {
+                    resp.headers()
+                        .get(name)
+                        .and_then(|v| v.to_str().ok())
+                        .map(|v| v.to_string())
+                };
+                let etag = header("etag")
+                    .map(|v| v.trim_start_matches("W/").trim_matches('"').to_string())
+                    .unwrap_or_default();
+                // Ranged responses carry the full size after the slash in Content-Range
+                let size = header("content-range")
+                    .and_then(|v| v.rsplit_once('/').and_then(|(_, total)| total.parse().ok()))
+                    .or_else(|| header("content-length").and_then(|v| v.parse().ok()))
+                    .unwrap_or(0);
+                // Not every static server honours conditional headers, so check them here too
+                if !etag.is_empty()
+                    && let Some(result) = check_conditions(&etag, &opts)?
+                {
+                    return Ok(Some(result));
+                }
+
+                let data = resp
+                    .body_mut()
+                    .with_config()
+                    .limit(u64::MAX)
+                    .read_to_vec()
+                    .map_err(Self::map_err)?;
+                // A 200 means the server ignored the Range header
+                let data = match &opts.range {
+                    Some(range) if status == 200 => slice_range(&data, range.clone()).to_vec(),
+                    _ => data,
+                };
+                let meta = opts.include_metadata.then_some(ObjectMeta { size, etag });
+                Ok(Some(GetResult::Body { data, meta }))
+            }
+            304 => Ok(Some(GetResult::NotModified)),
+            412 => Err(ObjectStoreError::PreconditionFailed),
+            // Range starts past the end of an existing object; preconditions come first, so they passed
+            416 => {
+                if !opts.include_metadata {
+                    return Ok(Some(GetResult::Body { data: Vec::new(), meta: None }));
+                }
+                Ok(self.head(key)?.map(|meta| GetResult::Body {
+                    data: Vec::new(),
+                    meta: Some(meta),
+                }))
+            }
+            status if is_missing(status) => Ok(None),
+            status => Err(Self::status_err(status, &url)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    // Minimal static file server: GET/HEAD with ETag, Content-Length and
+    // single byte ranges. Each connection serves one request.
+    fn serve(files: HashMap<String, Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let files = Arc::new(files);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let files = files.clone();
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    let mut parts = request_line.split_whitespace();
+                    let method = parts.next().unwrap_or_default().to_string();
+                    let path = parts.next().unwrap_or_default().to_string();
+
+                    let mut range = None;
+                    let mut if_match = None;
+                    let mut if_none_match = None;
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        if let Some(value) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                            let (start, end) = value.trim().split_once('-').unwrap();
+                            range = Some((start.parse::<usize>().unwrap(), end.parse::<usize>().unwrap()));
+                        }
+                        if let Some(value) = line.to_ascii_lowercase().strip_prefix("if-match: ") {
+                            if_match = Some(value.trim().trim_matches('"').to_string());
+                        }
+                        if let Some(value) = line.to_ascii_lowercase().strip_prefix("if-none-match: ") {
+                            if_none_match = Some(value.trim().trim_matches('"').to_string());
+                        }
+                    }
+
+                    let (status, body, extra) = match files.get(&path) {
+                        None => ("404 Not Found", Vec::new(), String::new()),
+                        Some(data) => {
+                            let tag = format!("{:x}", md5::compute(data));
+                            let etag = format!("ETag: \"{tag}\"\r\n");
+                            let matches = |expected: &Option<String>| {
+                                expected.as_deref().map(|e| e == "*" || e == tag)
+                            };
+                            match range {
+                                _ if matches(&if_match) == Some(false) => {
+                                    ("412 Precondition Failed", Vec::new(), String::new())
+                                }
+                                _ if matches(&if_none_match) == Some(true) => ("304 Not Modified", Vec::new(), etag),
+                                Some((start, _)) if start >= data.len() => {
+                                    ("416 Range Not Satisfiable", Vec::new(), String::new())
+                                }
+                                Some((start, end)) => {
+                                    let end = end.min(data.len() - 1);
+                                    let etag = format!("{etag}Content-Range: bytes {start}-{end}/{}\r\n", data.len());
+                                    ("206 Partial Content", data[start..=end].to_vec(), etag)
+                                }
+                                None => ("200 OK", data.clone(), etag),
+                            }
+                        }
+                    };
+
+                    let head = format!(
+                        "HTTP/1.1 {status}\r\nContent-Length: {}\r\n{extra}Connection: close\r\n\r\n",
+                        body.len()
+                    );
+                    stream.write_all(head.as_bytes()).unwrap();
+                    if method != "HEAD" {
+                        stream.write_all(&body).unwrap();
+                    }
+                });
+            }
+        });
+
+        format!("http://{addr}")
+    }
+
+    fn setup_store() -> HttpStore {
+        let mut files = HashMap::new();
+        files.insert("/assets/hello.txt".to_string(), b"Hello, world!".to_vec());
+        files.insert("/assets/with%20space.txt".to_string(), b"spaced".to_vec());
+        HttpStore::new(format!("{}/", serve(files)))
+    }
+
+    #[test]
+    fn test_get() {
+        let store = setup_store();
+        assert_eq!(store.get("assets/hello.txt").unwrap(), Some(b"Hello, world!".to_vec()));
+        assert_eq!(store.get("assets/with space.txt").unwrap(), Some(b"spaced".to_vec()));
+    }
+
+    #[test]
+    fn test_get_nonexistent() {
+        let store = setup_store();
+        assert_eq!(store.get("assets/nope.txt").unwrap(), None);
+        assert_eq!(store.head("assets/nope.txt").unwrap(), None);
+        assert_eq!(store.get_range("assets/nope.txt", 0..4).unwrap(), None);
+    }
+
+    #[test]
+    fn test_head() {
+        let store = setup_store();
+        let meta = store.head("assets/hello.txt").unwrap().unwrap();
+        assert_eq!(meta.size, 13);
+        assert_eq!(meta.etag, format!("{:x}", md5::compute(b"Hello, world!")));
+    }
+
+    #[test]
+    fn test_get_range() {
+        let store = setup_store();
+        assert_eq!(store.get_range("assets/hello.txt", 0..5).unwrap(), Some(b"Hello".to_vec()));
+        assert_eq!(store.get_range("assets/hello.txt", 7..100).unwrap(), Some(b"world!".to_vec()));
+        assert_eq!(store.get_range("assets/hello.txt", 50..60).unwrap(), Some(Vec::new()));
+        assert_eq!(store.get_range("assets/hello.txt", 3..3).unwrap(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_get_opts() {
+        let store = setup_store();
+        let etag = format!("{:x}", md5::compute(b"Hello, world!"));
+        let opts = GetOptions {
+            range: Some(7..12),
+            include_metadata: true,
+            ..Default::default()
+        };
+        let result = store.get_opts("assets/hello.txt", opts).unwrap().unwrap();
+        let meta = ObjectMeta { size: 13, etag: etag.clone() };
+        assert_eq!(result, GetResult::Body { data: b"world".to_vec(), meta: Some(meta) });
+
+        let fresh = GetOptions { if_none_match: Some(&etag), ..Default::default() };
+        assert_eq!(store.get_opts("assets/hello.txt", fresh).unwrap(), Some(GetResult::NotModified));
+        let stale = GetOptions { if_match: Some("0123"), ..Default::default() };
+        let result = store.get_opts("assets/hello.txt", stale);
+        assert!(matches!(result, Err(ObjectStoreError::PreconditionFailed)));
+        assert_eq!(store.get_opts("assets/nope.txt", GetOptions::default()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_writes_and_listing_unsupported() {
+        let store = setup_store();
+        let result = store.put("assets/new.txt", b"data", IfMatch::Any);
+        assert!(matches!(result, Err(ObjectStoreError::Unsupported(_))));
+        let result = store.list("assets/", None);
+        assert!(matches!(result, Err(ObjectStoreError::Unsupported(_))));
+        let result = store.delete("assets/hello.txt");
+        assert!(matches!(result, Err(ObjectStoreError::Unsupported(_))));
+    }
+}
@@ -0,0 +1,195 @@
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, Result};
+use metrics::{counter, histogram, Label};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::time::Instant;
+
+pub const REQUESTS_TOTAL: &str = "blob_store_requests_total";
+pub const ERRORS_TOTAL: &str = "blob_store_errors_total";
+pub const BYTES_READ_TOTAL: &str = "blob_store_bytes_read_total";
+pub const BYTES_WRITTEN_TOTAL: &str = "blob_store_bytes_written_total";
+pub const REQUEST_DURATION_SECONDS: &str = "blob_store_request_duration_seconds";
+
+/// Wraps a store and reports every call through the `metrics` facade.
+///
+/// All metrics carry `store` (the name given to `new`) and `op` labels:
+/// a request counter, a latency histogram in seconds, counters of bytes
+/// read and written, and an error counter with an extra `error` label
+/// naming the `ObjectStoreError` variant; `with_label` adds fixed labels of
+/// its own, such as a tenant. Nothing is exported until the application
+/// installs a recorder, e.g. with `install_prometheus_recorder`.
+pub struct InstrumentedStore<S> {
+    inner: S,
+    name: String,
+    labels: Vec<Label>,
+}
+
+impl<S: ObjectStore> InstrumentedStore<S> {
+    pub fn new(inner: S, name: impl Into<String>) -> Self {
+        Self {
+            inner,
+            name: name.into(),
+            labels: Vec::new(),
+        }
+    }
+
+    pub fn with_label(mut self, key: &'static str, value: impl Into<String>) -> Self {
+        self.labels.push(Label::new(key, value.into()));
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn labels(&self, op: &'static str) -> Vec<Label> {
+        let mut labels = vec![Label::new("store", self.name.clone()), Label::new("op", op)];
+        labels.extend(self.labels.iter().cloned());
+        labels
+    }
+
+    fn record<T>(&self, op: &'static str, call: impl FnOnce() -> Result<T>) -> Result<T> {
+        let started = Instant::now();
+        let result = call();
+        let labels = self.labels(op);
+        histogram!(REQUEST_DURATION_SECONDS, labels.clone()).record(started.elapsed());
+        counter!(REQUESTS_TOTAL, labels.clone()).increment(1);
+        if let Err(e) = &result {
+            let mut labels = labels;
+            labels.push(Label::new("error", e.kind()));
+            counter!(ERRORS_TOTAL, labels).increment(1);
+        }
+        result
+    }
+
+    fn read(&self, op: &'static str, bytes: usize) {
+        counter!(BYTES_READ_TOTAL, self.labels(op)).increment(bytes as u64);
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for InstrumentedStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let data = self.record("get", || self.inner.get(key))?;
+        self.read("get", data.as_ref().map_or(0, Vec::len));
+        Ok(data)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        let etag = self.record("put", || self.inner.put(key, body, cond))?;
+        counter!(BYTES_WRITTEN_TOTAL, self.labels("put")).increment(body.len() as u64);
+        Ok(etag)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        self.record("list", || self.inner.list(prefix, continuation))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.record("delete", || self.inner.delete(key))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.record("head", || self.inner.head(key))
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        let data = self.record("get_range", || self.inner.get_range(key, range))?;
+        self.read("get_range", data.as_ref().map_or(0, Vec::len));
+        Ok(data)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        let result = self.record("get_opts", || self.inner.get_opts(key, opts))?;
+        if let Some(GetResult::Body { data, .. }) = &result {
+            self.read("get_opts", data.len());
+        }
+        Ok(result)
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.record("put_tags", || self.inner.put_tags(key, tags))
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.record("get_tags", || self.inner.get_tags(key))
+    }
+}
+
+/// Installs a global Prometheus recorder and returns the handle whose
+/// `render()` output should be served from the application's `/metrics`
+/// endpoint. Latencies are exported as histograms rather than summaries.
+#[cfg(feature = "prometheus")]
+pub fn install_prometheus_recorder() -> Result<metrics_exporter_prometheus::PrometheusHandle> {
+    use super::ObjectStoreError;
+    use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+
+    const BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Full(REQUEST_DURATION_SECONDS.to_string()), BUCKETS)
+        .and_then(|builder| builder.install_recorder())
+        .map_err(|e| ObjectStoreError::Other(format!("prometheus recorder: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use metrics::{SharedString, Unit};
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use metrics_util::CompositeKey;
+    use uuid::Uuid;
+
+    type Snapshot = Vec<(CompositeKey, Option<Unit>, Option<SharedString>, DebugValue)>;
+
+    // Sum of a metric's values across label sets carrying all of `labels`;
+    // histograms count their samples
+    fn total(snapshot: &Snapshot, name: &str, labels: &[(&str, &str)]) -> u64 {
+        snapshot
+            .iter()
+            .filter(|(key, ..)| key.key().name() == name)
+            .filter(|(key, ..)| {
+                labels.iter().all(|(k, v)| key.key().labels().any(|l| l.key() == *k && l.value() == *v))
+            })
+            .map(|(.., value)| match value {
+                DebugValue::Counter(n) => *n,
+                DebugValue::Histogram(samples) => samples.len() as u64,
+                DebugValue::Gauge(_) => 0,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_instrumented_object_store() {
+        let store = InstrumentedStore::new(InMemoryStore::default(), "memory");
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_records_requests_bytes_and_errors() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let store = InstrumentedStore::new(InMemoryStore::default(), "primary");
+        let labelled = InstrumentedStore::new(InMemoryStore::default(), "primary").with_label("tenant", "acme");
+
+        metrics::with_local_recorder(&recorder, || {
+            labelled.put("k", b"abc", IfMatch::Any).unwrap();
+            store.put("k", b"hello", IfMatch::Any).unwrap();
+            store.get("k").unwrap();
+            store.get_range("k", 0..2).unwrap();
+            store.put("k", b"x", IfMatch::NoneMatch).unwrap_err();
+        });
+
+        // Taking a snapshot drains the recorder, so take just the one
+        let snapshot = snapshotter.snapshot().into_vec();
+        let primary = ("store", "primary");
+        assert_eq!(total(&snapshot, REQUESTS_TOTAL, &[primary, ("op", "put")]), 3);
+        assert_eq!(total(&snapshot, REQUESTS_TOTAL, &[primary, ("op", "get")]), 1);
+        assert_eq!(total(&snapshot, BYTES_WRITTEN_TOTAL, &[primary]), 8);
+        assert_eq!(total(&snapshot, BYTES_WRITTEN_TOTAL, &[primary, ("tenant", "acme")]), 3);
+        assert_eq!(total(&snapshot, BYTES_READ_TOTAL, &[primary]), 7);
+        let failed = [primary, ("op", "put"), ("error", "precondition_failed")];
+        assert_eq!(total(&snapshot, ERRORS_TOTAL, &failed), 1);
+        assert_eq!(total(&snapshot, REQUEST_DURATION_SECONDS, &[primary]), 5);
+    }
+}
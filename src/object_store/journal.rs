@@ -0,0 +1,302 @@
+use super::sync::list_all;
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where the journal lives in the backend: one object per entry.
+pub const JOURNAL_PREFIX: &str = ".journal/";
+
+// Records the first sequence number `truncate` kept
+const TRUNCATED_KEY: &str = ".journal/truncated.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalOp {
+    Put,
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub seq: u64,
+    pub op: JournalOp,
+    pub key: String,
+    // The ETag a put returned
+    pub etag: Option<String>,
+    // Milliseconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Truncated {
+    first: u64,
+}
+
+fn entry_key(seq: u64) -> String {
+    format!("{JOURNAL_PREFIX}{seq:020}.json")
+}
+
+fn parse_seq(key: &str) -> Option<u64> {
+    key.strip_prefix(JOURNAL_PREFIX)?.strip_suffix(".json")?.parse().ok()
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// Sequence numbers of the entries still in the journal, oldest first
+fn entry_seqs(store: &dyn ObjectStore) -> Result<Vec<u64>> {
+    let mut seqs: Vec<u64> = list_all(store, JOURNAL_PREFIX)?.iter().filter_map(|key| parse_seq(key)).collect();
+    seqs.sort();
+    Ok(seqs)
+}
+
+/// Up to `limit` journal entries after sequence number `since`, in order.
+/// Start from 0 to read the whole journal, then pass the last `seq` seen.
+/// Fails if `truncate` already removed entries after `since`.
+pub fn tail(store: &dyn ObjectStore, since: u64, limit: usize) -> Result<Vec<JournalEntry>> {
+    let mut entries = Vec::new();
+    let mut seq = since + 1;
+    while entries.len() < limit {
+        let Some(data) = store.get(&entry_key(seq))? else {
+            break;
+        };
+        let entry = serde_json::from_slice(&data)
+            .map_err(|e| ObjectStoreError::Other(format!("corrupt journal entry {seq}: {e}")))?;
+        entries.push(entry);
+        seq += 1;
+    }
+    if entries.is_empty()
+        && let Some(data) = store.get(TRUNCATED_KEY)?
+    {
+        let truncated: Truncated = serde_json::from_slice(&data)
+            .map_err(|e| ObjectStoreError::Other(format!("corrupt {TRUNCATED_KEY}: {e}")))?;
+        if truncated.first > since + 1 {
+            return Err(ObjectStoreError::Other(format!(
+                "journal was truncated to start at {}, after {since}",
+                truncated.first
+            )));
+        }
+    }
+    Ok(entries)
+}
+
+/// Deletes entries before sequence number `before`, always keeping the
+/// newest so writers know where to continue. Returns how many went.
+pub fn truncate(store: &dyn ObjectStore, before: u64) -> Result<usize> {
+    let seqs = entry_seqs(store)?;
+    let Some(&newest) = seqs.last() else {
+        return Ok(0);
+    };
+    let first = before.min(newest);
+    let marker = serde_json::to_vec(&Truncated { first }).expect("marker serializes");
+    store.put(TRUNCATED_KEY, &marker, IfMatch::Any)?;
+    let doomed: Vec<u64> = seqs.into_iter().filter(|&seq| seq < first).collect();
+    for &seq in &doomed {
+        store.delete(&entry_key(seq))?;
+    }
+    Ok(doomed.len())
+}
+
+/// Wraps a store so every put and delete is recorded in an append-only
+/// journal kept in the store itself, for incremental replication and cache
+/// invalidation: consumers `tail` it from the last sequence number they
+/// handled.
+///
+/// Each entry is its own object, `.journal/<seq>.json`, created with
+/// `IfMatch::NoneMatch` at the number after the last one this writer has
+/// seen, so several writers share one gapless sequence. A writer finds
+/// where the journal ends with a listing on its first write; `truncate`
+/// keeps that listing short. Entries are appended after the change is
+/// made, so two writes racing on a key may be journaled in either order;
+/// consumers should read the key's current state rather than trust the
+/// order. If appending fails the call fails too, although the change was
+/// made. The journal is hidden from `list` and can't be written through
+/// the wrapper.
+pub struct JournaledStore<S> {
+    inner: S,
+    // The next sequence number to try, once known
+    next: Mutex<Option<u64>>,
+}
+
+impl<S: ObjectStore> JournaledStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            next: Mutex::new(None),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn tail(&self, since: u64, limit: usize) -> Result<Vec<JournalEntry>> {
+        tail(&self.inner, since, limit)
+    }
+
+    fn append(&self, op: JournalOp, key: &str, etag: Option<String>) -> Result<u64> {
+        let mut next = self.next.lock().unwrap();
+        let mut seq = match *next {
+            Some(seq) => seq,
+            None => entry_seqs(&self.inner)?.last().map_or(1, |newest| newest + 1),
+        };
+        let mut entry = JournalEntry {
+            seq,
+            op,
+            key: key.to_string(),
+            etag,
+            timestamp: now_millis(),
+        };
+        loop {
+            entry.seq = seq;
+            let data = serde_json::to_vec(&entry).expect("journal entry serializes");
+            match self.inner.put(&entry_key(seq), &data, IfMatch::NoneMatch) {
+                Ok(_) => break,
+                // Another writer took this number
+                Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => seq += 1,
+                Err(e) => {
+                    *next = Some(seq);
+                    return Err(e);
+                }
+            }
+        }
+        *next = Some(seq + 1);
+        Ok(seq)
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for JournaledStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        if key.starts_with(JOURNAL_PREFIX) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        let etag = self.inner.put(key, body, cond)?;
+        self.append(JournalOp::Put, key, Some(etag.clone()))?;
+        Ok(etag)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let (keys, next) = self.inner.list(prefix, continuation)?;
+        Ok((keys.into_iter().filter(|key| !key.starts_with(JOURNAL_PREFIX)).collect(), next))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(key)?;
+        self.append(JournalOp::Delete, key, None)?;
+        Ok(())
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.inner.head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.inner.get_range(key, range)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.inner.get_opts(key, opts)
+    }
+
+    // Tags leave the body alone, so they aren't journaled
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        if key.starts_with(JOURNAL_PREFIX) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        self.inner.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.inner.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use std::sync::Arc;
+    use std::thread;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_journaled_object_store() {
+        let store = JournaledStore::new(InMemoryStore::default());
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+        assert!(!store.tail(0, 1000).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tail_from_sequence() {
+        let store = JournaledStore::new(InMemoryStore::default());
+        let etag = store.put("a", b"1", IfMatch::Any).unwrap();
+        assert!(store.put("a", b"2", IfMatch::NoneMatch).is_err());
+        store.delete("a").unwrap();
+        store.put("b", b"3", IfMatch::Any).unwrap();
+
+        let entries = store.tail(0, 10).unwrap();
+        let summary: Vec<_> = entries.iter().map(|e| (e.seq, e.op, e.key.as_str())).collect();
+        assert_eq!(summary, vec![(1, JournalOp::Put, "a"), (2, JournalOp::Delete, "a"), (3, JournalOp::Put, "b")]);
+        assert_eq!(entries[0].etag, Some(etag));
+        assert_eq!(store.tail(1, 1).unwrap()[0].seq, 2);
+        assert!(store.tail(3, 10).unwrap().is_empty());
+
+        assert!(store.list("", None).unwrap().0.iter().all(|key| !key.starts_with(JOURNAL_PREFIX)));
+        assert!(matches!(store.put(".journal/4.json", b"{}", IfMatch::Any), Err(ObjectStoreError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn test_writers_share_one_sequence() {
+        let backend = Arc::new(InMemoryStore::default());
+        backend.put("seed", b"", IfMatch::Any).unwrap();
+        let handles: Vec<_> = (0..4)
+            .map(|writer| {
+                let store = JournaledStore::new(backend.clone());
+                thread::spawn(move || {
+                    for i in 0..10 {
+                        store.put(&format!("w{writer}/{i}"), b"x", IfMatch::Any).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // A writer started later carries on after the others
+        let late = JournaledStore::new(backend.clone());
+        late.delete("seed").unwrap();
+        let seqs: Vec<u64> = tail(backend.as_ref(), 0, 100).unwrap().iter().map(|e| e.seq).collect();
+        assert_eq!(seqs, (1..=41).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_truncate() {
+        let backend = Arc::new(InMemoryStore::default());
+        let store = JournaledStore::new(backend.clone());
+        for i in 0..5 {
+            store.put(&format!("k{i}"), b"x", IfMatch::Any).unwrap();
+        }
+        assert_eq!(truncate(backend.as_ref(), 4).unwrap(), 3);
+        assert_eq!(store.tail(3, 10).unwrap().len(), 2);
+        assert!(store.tail(1, 10).is_err());
+
+        // The newest entry survives, so numbering carries on
+        assert_eq!(truncate(backend.as_ref(), 100).unwrap(), 1);
+        let fresh = JournaledStore::new(backend);
+        fresh.put("k5", b"x", IfMatch::Any).unwrap();
+        assert_eq!(fresh.tail(5, 10).unwrap()[0].seq, 6);
+    }
+}
@@ -0,0 +1,273 @@
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+// Where `KeyPolicy::Hash` puts keys that aren't safe paths
+pub const HASHED_PREFIX: &str = ".hashed/";
+
+/// Fails with `ObjectStoreError::InvalidKey` unless `key` can be used as a
+/// relative file path as it is: non-empty, without NUL or other control
+/// characters, and made of `/`-separated segments none of which is empty,
+/// `.` or `..`. So no key can name an absolute path, climb out of the
+/// store's root, or alias another key.
+pub fn validate_key(key: &str) -> Result<()> {
+    let safe = !key.chars().any(|c| c.is_control())
+        && key.split('/').all(|segment| !segment.is_empty() && segment != "." && segment != "..");
+    if safe {
+        Ok(())
+    } else {
+        Err(ObjectStoreError::InvalidKey(key.to_string()))
+    }
+}
+
+/// What a store does with keys that `validate_key` rejects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyPolicy {
+    /// Fail with `ObjectStoreError::InvalidKey`.
+    #[default]
+    Reject,
+    /// Store every key percent-encoded: `%` and control characters always,
+    /// and `/` and `.` where they would make an unsafe path. Safe keys
+    /// without a `%` are stored as they are, and listing decodes them all.
+    PercentEncode,
+    /// Store unsafe keys as `.hashed/<SHA-256 of the key>`, and refuse
+    /// keys under `.hashed/` so none can reach those objects by their
+    /// hashed names. Hashing is one way, so a `KeyPolicyStore` leaves them
+    /// out of listings; a `LocalStore` lists them under their own names.
+    Hash,
+}
+
+impl KeyPolicy {
+    /// The safe relative path `key` is stored at.
+    pub fn encode(&self, key: &str) -> Result<String> {
+        match self {
+            KeyPolicy::Reject => validate_key(key).map(|_| key.to_string()),
+            // Nothing encodes to an empty path
+            KeyPolicy::PercentEncode if key.is_empty() => Err(ObjectStoreError::InvalidKey(String::new())),
+            KeyPolicy::PercentEncode => Ok(percent_encode(key)),
+            KeyPolicy::Hash if key.starts_with(HASHED_PREFIX) => Err(ObjectStoreError::InvalidKey(key.to_string())),
+            KeyPolicy::Hash if validate_key(key).is_ok() => Ok(key.to_string()),
+            KeyPolicy::Hash => Ok(format!("{HASHED_PREFIX}{:x}", Sha256::digest(key.as_bytes()))),
+        }
+    }
+
+    /// The key stored at `path`; `None` if no key encodes to it.
+    pub fn decode(&self, path: &str) -> Option<String> {
+        match self {
+            KeyPolicy::Reject | KeyPolicy::Hash => Some(path.to_string()),
+            KeyPolicy::PercentEncode => percent_decode(path),
+        }
+    }
+}
+
+fn push_escaped(out: &mut String, c: char) {
+    let mut buf = [0; 4];
+    for byte in c.encode_utf8(&mut buf).bytes() {
+        out.push_str(&format!("%{byte:02X}"));
+    }
+}
+
+fn percent_encode(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    let mut chars = key.chars().peekable();
+    // Whether `out` ends with a separator, so another would make an empty
+    // segment
+    let mut after_separator = true;
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if !after_separator && chars.peek().is_some() => {
+                out.push('/');
+                after_separator = true;
+                continue;
+            }
+            '/' | '%' => push_escaped(&mut out, c),
+            c if c.is_control() => push_escaped(&mut out, c),
+            c => out.push(c),
+        }
+        after_separator = false;
+    }
+    // Segments of nothing but dots are left; `.` and `..` are escaped
+    out.split('/')
+        .map(|segment| match segment {
+            "." => "%2E".to_string(),
+            ".." => "%2E%2E".to_string(),
+            segment => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn percent_decode(path: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Applies a `KeyPolicy` in front of any store, so every backend accepts
+/// and refuses the same keys a `LocalStore` does.
+pub struct KeyPolicyStore<S> {
+    inner: S,
+    policy: KeyPolicy,
+}
+
+impl<S: ObjectStore> KeyPolicyStore<S> {
+    pub fn new(inner: S, policy: KeyPolicy) -> Self {
+        Self { inner, policy }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    // The inner prefix every encoded key under `prefix` starts with: the
+    // encoding of its directory part when that is safe, else everything
+    fn inner_prefix(&self, prefix: &str) -> String {
+        match prefix.rsplit_once('/') {
+            Some((dir, _)) if validate_key(dir).is_ok() => self.policy.encode(dir).unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for KeyPolicyStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.get(&self.policy.encode(key)?)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        self.inner.put(&self.policy.encode(key)?, body, cond)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        match self.policy {
+            KeyPolicy::Reject => return self.inner.list(prefix, continuation),
+            KeyPolicy::Hash => {
+                let (paths, next) = self.inner.list(prefix, continuation)?;
+                let keys = paths.into_iter().filter(|path| !path.starts_with(HASHED_PREFIX)).collect();
+                return Ok((keys, next));
+            }
+            KeyPolicy::PercentEncode => {}
+        }
+        // Pages may come back short, since the inner listing is wider
+        let (paths, next) = self.inner.list(&self.inner_prefix(prefix), continuation)?;
+        let keys = paths
+            .iter()
+            .filter_map(|path| self.policy.decode(path))
+            .filter(|key| key.starts_with(prefix))
+            .collect();
+        Ok((keys, next))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(&self.policy.encode(key)?)
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.inner.head(&self.policy.encode(key)?)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.inner.get_range(&self.policy.encode(key)?, range)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.inner.get_opts(&self.policy.encode(key)?, opts)
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.inner.put_tags(&self.policy.encode(key)?, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.inner.get_tags(&self.policy.encode(key)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_validate_key() {
+        for key in ["a", "a/b.txt", "dir/.hidden", "a/.../b", "with space/ünïcode", "100%"] {
+            assert!(validate_key(key).is_ok(), "{key}");
+        }
+        for key in ["", "../../etc/cron.d/x", "/etc/passwd", "a//b", "a/", "a/./b", "..", "nul\0byte", "tab\there"] {
+            assert!(matches!(validate_key(key), Err(ObjectStoreError::InvalidKey(ref k)) if k == key), "{key:?}");
+        }
+    }
+
+    #[test]
+    fn test_percent_encoding_round_trips() {
+        let policy = KeyPolicy::PercentEncode;
+        for (key, path) in [
+            ("a/b.txt", "a/b.txt"),
+            ("../../etc/cron.d/x", "%2E%2E/%2E%2E/etc/cron.d/x"),
+            ("/etc/passwd", "%2Fetc/passwd"),
+            ("a//b/", "a/%2Fb%2F"),
+            ("100%", "100%25"),
+            ("nul\0byte", "nul%00byte"),
+        ] {
+            assert_eq!(policy.encode(key).unwrap(), path);
+            assert_eq!(policy.decode(path).as_deref(), Some(key));
+        }
+        assert_eq!(policy.decode("bad%zz"), None);
+        assert!(policy.encode("").is_err());
+        assert!(validate_key(&policy.encode("./x/..//y").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_hash_policy() {
+        let policy = KeyPolicy::Hash;
+        assert_eq!(policy.encode("a/b").unwrap(), "a/b");
+        let hashed = policy.encode("../x").unwrap();
+        assert!(hashed.starts_with(HASHED_PREFIX));
+        assert_eq!(hashed.len(), HASHED_PREFIX.len() + 64);
+        assert_ne!(policy.encode("../y").unwrap(), hashed);
+        // A safe key can't pose as a hashed one
+        assert!(matches!(policy.encode(&hashed), Err(ObjectStoreError::InvalidKey(_))));
+
+        let store = KeyPolicyStore::new(InMemoryStore::default(), policy);
+        store.put("../x", b"unsafe", IfMatch::Any).unwrap();
+        store.put("a/b", b"safe", IfMatch::Any).unwrap();
+        assert!(matches!(store.put(&hashed, b"forged", IfMatch::Any), Err(ObjectStoreError::InvalidKey(_))));
+        assert!(matches!(store.get(&hashed), Err(ObjectStoreError::InvalidKey(_))));
+        assert_eq!(store.get("../x").unwrap(), Some(b"unsafe".to_vec()));
+        assert_eq!(store.list("", None).unwrap().0, vec!["a/b"]);
+    }
+
+    #[test]
+    fn test_policy_store() {
+        let strict = KeyPolicyStore::new(InMemoryStore::default(), KeyPolicy::Reject);
+        assert!(matches!(strict.put("../escape", b"x", IfMatch::Any), Err(ObjectStoreError::InvalidKey(_))));
+        assert!(matches!(strict.get("/abs"), Err(ObjectStoreError::InvalidKey(_))));
+
+        let encoded = KeyPolicyStore::new(InMemoryStore::default(), KeyPolicy::PercentEncode);
+        encoded.put("docs//a", b"1", IfMatch::Any).unwrap();
+        encoded.put("docs/../b", b"2", IfMatch::Any).unwrap();
+        encoded.put("docs/", b"3", IfMatch::Any).unwrap();
+        encoded.put("other", b"4", IfMatch::Any).unwrap();
+        assert_eq!(encoded.get("docs/../b").unwrap(), Some(b"2".to_vec()));
+        let mut keys = encoded.list("docs/", None).unwrap().0;
+        keys.sort();
+        assert_eq!(keys, vec!["docs/", "docs/../b", "docs//a"]);
+        assert!(encoded.inner().get("docs/%2E%2E/b").unwrap().is_some());
+
+        let store = KeyPolicyStore::new(InMemoryStore::default(), KeyPolicy::PercentEncode);
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+}
@@ -0,0 +1,289 @@
+use super::{get_from_body, slice_range, GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::BTreeMap;
+use std::ops::{Bound, Range};
+use std::path::Path;
+
+/// Embedded persistent store backed by a sled tree.
+///
+/// Each object is a single tree entry keyed by the object key, with the ETag
+/// and body packed into the value. Conditional puts use sled's
+/// compare-and-swap, so they are atomic even with concurrent writers.
+/// Tags live in a separate tree, packed the same way with the ETag of the
+/// content they were set on, so a put racing `put_tags` can't leave them
+/// on new content; puts and deletes remove them.
+pub struct KvStore {
+    // Objects live in the database's default tree
+    db: sled::Db,
+    tags: sled::Tree,
+}
+
+impl KvStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let db = sled::open(path).map_err(map_sled_err)?;
+        Self::from_db(db)
+    }
+
+    pub fn from_db(db: sled::Db) -> Result<Self> {
+        let tags = db.open_tree("tags").map_err(map_sled_err)?;
+        Ok(Self { db, tags })
+    }
+
+    fn compute_etag(data: &[u8]) -> String {
+        format!("{:x}", md5::compute(data))
+    }
+
+    // Value layout: [etag length: u8][etag][body]
+    fn encode(etag: &str, data: &[u8]) -> Vec<u8> {
+        let mut value = Vec::with_capacity(1 + etag.len() + data.len());
+        value.push(etag.len() as u8);
+        value.extend_from_slice(etag.as_bytes());
+        value.extend_from_slice(data);
+        value
+    }
+
+    fn decode(value: &[u8]) -> Result<(&str, &[u8])> {
+        let corrupt = || ObjectStoreError::Other("corrupt kv entry".to_string());
+        let (&etag_len, rest) = value.split_first().ok_or_else(corrupt)?;
+        if rest.len() < etag_len as usize {
+            return Err(corrupt());
+        }
+        let (etag, data) = rest.split_at(etag_len as usize);
+        let etag = std::str::from_utf8(etag).map_err(|_| corrupt())?;
+        Ok((etag, data))
+    }
+}
+
+fn map_sled_err(e: sled::Error) -> ObjectStoreError {
+    match e {
+        sled::Error::Io(e) => ObjectStoreError::Io(e),
+        e => ObjectStoreError::backend(format!("sled error: {e}"), e),
+    }
+}
+
+impl ObjectStore for KvStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.db.get(key).map_err(map_sled_err)? {
+            Some(value) => Ok(Some(Self::decode(&value)?.1.to_vec())),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        let new_etag = Self::compute_etag(body);
+        let new_value = Self::encode(&new_etag, body);
+
+        match cond {
+            IfMatch::Any => {
+                self.db.insert(key, new_value).map_err(map_sled_err)?;
+            }
+            IfMatch::Tag(expected_etag) => loop {
+                let current = self.db.get(key).map_err(map_sled_err)?;
+                let Some(current) = current else {
+                    return Err(ObjectStoreError::PreconditionFailed);
+                };
+                if Self::decode(&current)?.0 != expected_etag {
+                    return Err(ObjectStoreError::PreconditionFailed);
+                }
+                // A concurrent writer may have replaced the value with one
+                // carrying the same ETag, so re-check rather than fail outright
+                let swapped = self
+                    .db
+                    .compare_and_swap(key, Some(current), Some(new_value.clone()))
+                    .map_err(map_sled_err)?;
+                if swapped.is_ok() {
+                    break;
+                }
+            },
+            IfMatch::NoneMatch => {
+                let swapped = self
+                    .db
+                    .compare_and_swap(key, None as Option<&[u8]>, Some(new_value))
+                    .map_err(map_sled_err)?;
+                if swapped.is_err() {
+                    return Err(ObjectStoreError::PreconditionFailed);
+                }
+            }
+        }
+
+        self.tags.remove(key).map_err(map_sled_err)?;
+        Ok(new_etag)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        // Simple pagination: 1000 per page, resuming after the last key returned
+        let page_size = 1000;
+        let start = match &continuation {
+            Some(token) if token.as_str() >= prefix => Bound::Excluded(token.as_bytes()),
+            _ => Bound::Included(prefix.as_bytes()),
+        };
+
+        let mut keys = Vec::new();
+        let mut next_token = None;
+        for entry in self.db.range::<&[u8], _>((start, Bound::Unbounded)) {
+            let (key, _) = entry.map_err(map_sled_err)?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            if keys.len() == page_size {
+                next_token = keys.last().cloned();
+                break;
+            }
+            keys.push(String::from_utf8_lossy(&key).to_string());
+        }
+
+        Ok((keys, next_token))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.db.remove(key).map_err(map_sled_err)?;
+        self.tags.remove(key).map_err(map_sled_err)?;
+        Ok(())
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        match self.db.get(key).map_err(map_sled_err)? {
+            Some(value) => {
+                let (etag, data) = Self::decode(&value)?;
+                Ok(Some(ObjectMeta {
+                    size: data.len() as u64,
+                    etag: etag.to_string(),
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        match self.db.get(key).map_err(map_sled_err)? {
+            Some(value) => Ok(Some(slice_range(Self::decode(&value)?.1, range).to_vec())),
+            None => Ok(None),
+        }
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        match self.db.get(key).map_err(map_sled_err)? {
+            Some(value) => {
+                let (etag, data) = Self::decode(&value)?;
+                get_from_body(data, etag, &opts).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        let Some(meta) = self.head(key)? else {
+            return Err(ObjectStoreError::NotFound(key.to_string()));
+        };
+        let json = serde_json::to_vec(tags).expect("tags serialize");
+        self.tags
+            .insert(key, Self::encode(&meta.etag, &json))
+            .map_err(map_sled_err)?;
+        Ok(())
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        let Some(meta) = self.head(key)? else {
+            return Ok(None);
+        };
+        let Some(value) = self.tags.get(key).map_err(map_sled_err)? else {
+            return Ok(Some(BTreeMap::new()));
+        };
+        let (etag, json) = Self::decode(&value)?;
+        // Set on content a later put replaced
+        if etag != meta.etag {
+            return Ok(Some(BTreeMap::new()));
+        }
+        let tags = serde_json::from_slice(json).map_err(|e| ObjectStoreError::Other(format!("corrupt kv tags: {e}")))?;
+        Ok(Some(tags))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::test_helpers::tests::{run_object_store_tests, run_oracle_tests};
+    use std::sync::Arc;
+    use std::thread;
+    use tempfile::TempDir;
+    use uuid::Uuid;
+
+    fn setup_store() -> (KvStore, TempDir) {
+        let tmp = TempDir::new().unwrap();
+        let store = KvStore::open(tmp.path().join("db")).unwrap();
+        (store, tmp)
+    }
+
+    #[test]
+    fn test_put_and_get() {
+        let (store, _tmp) = setup_store();
+        let etag = store.put("foo.txt", b"hello", IfMatch::Any).unwrap();
+        assert!(!etag.is_empty());
+        assert_eq!(store.get("foo.txt").unwrap(), Some(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn test_conditional_put() {
+        let (store, _tmp) = setup_store();
+        let etag1 = store.put("bar.txt", b"one", IfMatch::NoneMatch).unwrap();
+        let result = store.put("bar.txt", b"two", IfMatch::NoneMatch);
+        assert!(matches!(result, Err(ObjectStoreError::PreconditionFailed)));
+
+        let result = store.put("bar.txt", b"two", IfMatch::Tag("wrong-etag"));
+        assert!(matches!(result, Err(ObjectStoreError::PreconditionFailed)));
+        store.put("bar.txt", b"two", IfMatch::Tag(&etag1)).unwrap();
+        assert_eq!(store.get("bar.txt").unwrap(), Some(b"two".to_vec()));
+    }
+
+    #[test]
+    fn test_persists_across_reopen() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("db");
+        {
+            let store = KvStore::open(&path).unwrap();
+            store.put("kept.txt", b"still here", IfMatch::Any).unwrap();
+        }
+        let store = KvStore::open(&path).unwrap();
+        assert_eq!(store.get("kept.txt").unwrap(), Some(b"still here".to_vec()));
+    }
+
+    #[test]
+    fn test_list_pagination() {
+        let (store, _tmp) = setup_store();
+        for i in 0..1005 {
+            store.put(&format!("page/{i:04}"), b"x", IfMatch::Any).unwrap();
+        }
+        store.put("pagf", b"x", IfMatch::Any).unwrap();
+
+        let (first, token) = store.list("page/", None).unwrap();
+        assert_eq!(first.len(), 1000);
+        let (second, token) = store.list("page/", token).unwrap();
+        assert_eq!(second, vec!["page/1000", "page/1001", "page/1002", "page/1003", "page/1004"]);
+        assert!(token.is_none());
+    }
+
+    #[test]
+    fn test_concurrent_none_match_has_one_winner() {
+        let (store, _tmp) = setup_store();
+        let store = Arc::new(store);
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let store = store.clone();
+                thread::spawn(move || store.put("race", format!("{i}").as_bytes(), IfMatch::NoneMatch).is_ok())
+            })
+            .collect();
+        let winners = handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .filter(|won| *won)
+            .count();
+        assert_eq!(winners, 1);
+    }
+
+    #[test]
+    fn test_kv_object_store() {
+        let (store, _tmp) = setup_store();
+        let prefix = format!("test/{}/", Uuid::new_v4());
+        run_object_store_tests(&store, &prefix);
+        run_oracle_tests(&store, &format!("oracle/{}/", Uuid::new_v4()));
+    }
+}
@@ -0,0 +1,191 @@
+use super::lock::{Lease, Locks};
+use super::{ObjectStore, ObjectStoreError};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeadershipEvent {
+    Elected { term: u64 },
+    Deposed { term: u64 },
+}
+
+struct Shared {
+    lease: Mutex<Option<Lease>>,
+    subscribers: Mutex<Vec<Sender<LeadershipEvent>>>,
+    stopping: Mutex<bool>,
+    wake: Condvar,
+}
+
+impl Shared {
+    fn notify(&self, event: LeadershipEvent) {
+        // Drop subscribers whose receiver is gone
+        self.subscribers.lock().unwrap().retain(|tx| tx.send(event).is_ok());
+    }
+
+    fn set_lease(&self, lease: Option<Lease>) {
+        let previous = std::mem::replace(&mut *self.lease.lock().unwrap(), lease.clone());
+        match (previous, lease) {
+            (Some(old), Some(new)) if old.generation == new.generation => {}
+            (old, new) => {
+                if let Some(old) = old {
+                    self.notify(LeadershipEvent::Deposed { term: old.generation });
+                }
+                if let Some(new) = new {
+                    self.notify(LeadershipEvent::Elected { term: new.generation });
+                }
+            }
+        }
+    }
+
+    // Sleeps for `timeout` or until stopped; true once stopped
+    fn wait(&self, timeout: Duration) -> bool {
+        let stopping = self.stopping.lock().unwrap();
+        let (stopping, _) = self.wake.wait_timeout_while(stopping, timeout, |stopping| !*stopping).unwrap();
+        *stopping
+    }
+}
+
+/// Campaigns for a named leadership on a background thread, holding it as
+/// a `Locks` lease.
+///
+/// Every third of the TTL the thread renews the lease while leading, or
+/// tries to acquire it otherwise. The lease's generation is the leader's
+/// term: it goes up with every change of leader and is stored through the
+/// lock's conditional puts, so work done on behalf of a term can be fenced
+/// by checking it against `Locks::generation`. `is_leader` also turns false
+/// as soon as the lease expires, even if the thread hasn't noticed yet.
+/// Dropping the elector stops campaigning and releases leadership.
+pub struct LeaderElector {
+    shared: Arc<Shared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl LeaderElector {
+    pub fn start<S: ObjectStore + 'static>(locks: Locks<S>, name: impl Into<String>, ttl: Duration) -> Self {
+        let shared = Arc::new(Shared {
+            lease: Mutex::new(None),
+            subscribers: Mutex::new(Vec::new()),
+            stopping: Mutex::new(false),
+            wake: Condvar::new(),
+        });
+        let name = name.into();
+        let campaign = shared.clone();
+        let handle = thread::spawn(move || run(&locks, &name, ttl, &campaign));
+        Self {
+            shared,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.term().is_some()
+    }
+
+    /// The current term, while leading.
+    pub fn term(&self) -> Option<u64> {
+        let lease = self.shared.lease.lock().unwrap();
+        lease.as_ref().filter(|lease| !lease.is_expired()).map(|lease| lease.generation)
+    }
+
+    /// Events for every gain and loss of leadership from now on.
+    pub fn subscribe(&self) -> Receiver<LeadershipEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.shared.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+}
+
+impl Drop for LeaderElector {
+    fn drop(&mut self) {
+        *self.shared.stopping.lock().unwrap() = true;
+        self.shared.wake.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run<S: ObjectStore>(locks: &Locks<S>, name: &str, ttl: Duration, shared: &Shared) {
+    let interval = ttl / 3;
+    loop {
+        let current = shared.lease.lock().unwrap().clone();
+        let next = match current {
+            Some(mut lease) => match locks.renew(&mut lease, ttl) {
+                Ok(()) => Some(lease),
+                // Someone else holds it now
+                Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => None,
+                // Keep trying to renew while the lease lasts
+                Err(_) => Some(lease).filter(|lease| !lease.is_expired()),
+            },
+            None => locks.acquire(name, ttl).ok(),
+        };
+        shared.set_lease(next);
+        if shared.wait(interval) {
+            break;
+        }
+    }
+    let lease = shared.lease.lock().unwrap().clone();
+    if let Some(lease) = lease {
+        let _ = locks.release(lease);
+        shared.set_lease(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use std::time::Instant;
+
+    fn elector(store: &Arc<InMemoryStore>) -> LeaderElector {
+        LeaderElector::start(Locks::new(store.clone()), "scheduler", Duration::from_millis(150))
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let started = Instant::now();
+        while !condition() {
+            assert!(started.elapsed() < Duration::from_secs(5), "timed out");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_single_leader_and_handover() {
+        let store = Arc::new(InMemoryStore::default());
+        let first = elector(&store);
+        let events = first.subscribe();
+        wait_for(|| first.is_leader());
+        let second = elector(&store);
+        let second_events = second.subscribe();
+
+        // Renewals keep the first leader in place
+        thread::sleep(Duration::from_millis(400));
+        assert!(first.is_leader());
+        assert!(!second.is_leader());
+        let term = first.term().unwrap();
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), vec![LeadershipEvent::Elected { term }]);
+
+        drop(first);
+        assert_eq!(events.recv().unwrap(), LeadershipEvent::Deposed { term });
+        wait_for(|| second.is_leader());
+        assert_eq!(second.term(), Some(term + 1));
+        assert_eq!(second_events.recv().unwrap(), LeadershipEvent::Elected { term: term + 1 });
+        assert_eq!(Locks::new(store.clone()).generation("scheduler").unwrap(), Some(term + 1));
+    }
+
+    #[test]
+    fn test_leadership_lapses_with_the_lease() {
+        let store = Arc::new(InMemoryStore::default());
+        let leader = elector(&store);
+        let events = leader.subscribe();
+        wait_for(|| leader.is_leader());
+        // The lock is cleared and taken over behind the leader's back
+        store.delete("locks/scheduler").unwrap();
+        let usurper = Locks::new(store.clone()).acquire("scheduler", Duration::from_secs(60)).unwrap();
+        wait_for(|| !leader.is_leader());
+        assert!(matches!(events.try_iter().last(), Some(LeadershipEvent::Deposed { .. })));
+        assert!(!usurper.is_expired());
+    }
+}
@@ -1,123 +1,1040 @@
-use super::{IfMatch, ObjectStore, ObjectStoreError, Result};
+use super::keys::{KeyPolicy, HASHED_PREFIX};
+use super::{check_conditions, get_from_body, GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{self, File};
-use std::io::{Write};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use uuid::Uuid;
 
 use md5;
 
+// Keys whose path doesn't spell them out are recorded under this directory
+pub const KEYMAP_DIR: &str = ".keymap/";
+
+// Files are written here first and renamed into place once complete
+pub const TMP_DIR: &str = ".tmp/";
+
+// Lock files serializing writes, shared by every process using the root
+pub const LOCK_DIR: &str = ".locks/";
+
+// Object metadata, where it can't go in an extended attribute
+pub const META_DIR: &str = ".meta/";
+
+// The store's own directories, for key mappings, partial writes, write
+// locks, object metadata and keys hashed by `KeyPolicy::Hash`; keys can't
+// be under them
+const INTERNAL_DIRS: [&str; 5] = [KEYMAP_DIR, TMP_DIR, LOCK_DIR, META_DIR, HASHED_PREFIX];
+
+// The extended attribute holding an object's metadata
+#[cfg(unix)]
+const META_XATTR: &str = "user.blob_store.meta";
+
+// Keys are spread over this many lock files
+const LOCK_STRIPES: usize = 256;
+
+// The longest file name most filesystems allow, in bytes
+const MAX_SEGMENT_BYTES: usize = 255;
+
+// How much of a mapped segment is kept readable in its file name
+const MAPPED_HEAD_BYTES: usize = 100;
+
+// Hex digits of the segment's hash ending a mapped file name
+const MAPPED_HASH_DIGITS: usize = 32;
+
+// Characters Windows doesn't allow in file names, besides control characters
+const WINDOWS_INVALID_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*', '\\'];
+
+// Device names Windows reserves, with or without an extension
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1",
+    "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Which filesystem's file name rules a `LocalStore` lays out its files by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathRules {
+    /// Only length limits; anything else but NUL and `/` is a valid name.
+    Posix,
+    /// Also maps names with `<>:"|?*\`, names ending in a dot or space,
+    /// and reserved device names such as `CON` or `lpt1.txt`.
+    Windows,
+}
+
+impl Default for PathRules {
+    // The rules of the platform the store runs on
+    fn default() -> Self {
+        if cfg!(windows) { PathRules::Windows } else { PathRules::Posix }
+    }
+}
+
+impl PathRules {
+    fn is_invalid_char(self, c: char) -> bool {
+        match self {
+            PathRules::Posix => false,
+            PathRules::Windows => c.is_control() || WINDOWS_INVALID_CHARS.contains(&c),
+        }
+    }
+
+    // Whether the filesystem takes `segment` as a file name as it is
+    fn allows(self, segment: &str) -> bool {
+        if segment.len() > MAX_SEGMENT_BYTES || segment.chars().any(|c| self.is_invalid_char(c)) {
+            return false;
+        }
+        match self {
+            PathRules::Posix => true,
+            PathRules::Windows => {
+                let stem = segment.split('.').next().unwrap_or_default().trim_end_matches(' ');
+                !segment.ends_with(['.', ' '])
+                    && !WINDOWS_RESERVED_NAMES.iter().any(|name| name.eq_ignore_ascii_case(stem))
+            }
+        }
+    }
+}
+
+// Whether a segment looks like the file name `map_segment` makes, which a
+// segment of a key must not be as it is, or it could alias a mapped one
+fn looks_mapped(segment: &str) -> bool {
+    segment.len() > MAPPED_HASH_DIGITS
+        && segment.is_char_boundary(segment.len() - MAPPED_HASH_DIGITS - 1)
+        && segment[..segment.len() - MAPPED_HASH_DIGITS].ends_with('~')
+        && segment[segment.len() - MAPPED_HASH_DIGITS..].bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+// The file name for one path segment: the segment itself if the filesystem
+// takes it, else a readable head and a hash of the whole segment, or just
+// the hash if the head would still make a reserved name
+fn map_segment(segment: &str, rules: PathRules) -> String {
+    if rules.allows(segment) && !looks_mapped(segment) {
+        return segment.to_string();
+    }
+    let mut head: String = segment.chars().filter(|&c| !rules.is_invalid_char(c)).collect();
+    let mut end = head.len().min(MAPPED_HEAD_BYTES);
+    while !head.is_char_boundary(end) {
+        end -= 1;
+    }
+    head.truncate(end);
+    let hash = format!("{:x}", Sha256::digest(segment.as_bytes()));
+    let mapped = format!("{head}~{}", &hash[..MAPPED_HASH_DIGITS]);
+    if rules.allows(&mapped) {
+        mapped
+    } else {
+        format!("~{}", &hash[..MAPPED_HASH_DIGITS])
+    }
+}
+
+/// How far `LocalStore::put` goes to make a write survive a crash or power
+/// loss before returning. Writes are atomic in every mode: a file is
+/// written in full under `.tmp/` and then renamed over the object.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Leave flushing to the OS; a crash may lose recent writes.
+    #[default]
+    None,
+    /// fsync the file before renaming it into place, so its content is on
+    /// disk, though the rename itself may still be lost.
+    Data,
+    /// Also fsync the directory after the rename, and any directories the
+    /// write created, so the object is there after a power loss.
+    DataAndDir,
+}
+
+/// How a `LocalStore` arranges object files under its root.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// Each object at its key's path, so `a/b.txt` is `<root>/a/b.txt`.
+    #[default]
+    Flat,
+    /// Each object two directories further down, named by the first two
+    /// bytes of a SHA-256 of its key in hex, so `a/b.txt` is
+    /// `<root>/3f/9c/a/b.txt`. No directory then holds more than 256 fanout
+    /// entries however many keys share a prefix, but `list` has to walk
+    /// the whole root whatever the prefix.
+    Fanout,
+}
+
+/// Where a `LocalStore` keeps each object's ETag and `Attributes`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MetadataStorage {
+    /// In an extended attribute of the object's file where the platform and
+    /// filesystem have them, else in a sidecar file under `.meta/`.
+    #[default]
+    Auto,
+    /// Always in a sidecar file under `.meta/`, for roots copied by tools
+    /// that drop extended attributes.
+    Sidecar,
+}
+
+/// What a `LocalStore` keeps about an object besides its content.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attributes {
+    pub content_type: Option<String>,
+    pub user: BTreeMap<String, String>,
+}
+
+// Identifies one file an object was written to. Every put writes a new
+// file, so metadata recorded for an earlier one is recognized as stale.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct FileStamp {
+    size: u64,
+    // Nanoseconds since the Unix epoch
+    modified: u64,
+    // Zero where std doesn't expose a file id
+    inode: u64,
+}
+
+impl FileStamp {
+    fn of(meta: &fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(meta);
+        #[cfg(not(unix))]
+        let inode = 0;
+        Self {
+            size: meta.len(),
+            modified: meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0),
+            inode,
+        }
+    }
+}
+
+// An object's metadata and the file it was recorded for
+#[derive(Serialize, Deserialize)]
+struct MetaRecord {
+    etag: String,
+    stamp: FileStamp,
+    #[serde(flatten)]
+    attributes: Attributes,
+    // Set by `put_tags`, so absent from records written before it
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    tags: BTreeMap<String, String>,
+}
+
+// Directory entries only reach the disk with an fsync of the directory on
+// Unix; NTFS journals them, and Windows can't open a directory to sync it
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+// `fs::create_dir_all`, retried when a delete pruning empty directories
+// removes one of them while they're being created
+fn create_dirs(dir: &Path) -> std::io::Result<()> {
+    let mut attempts = 0;
+    loop {
+        match fs::create_dir_all(dir) {
+            Err(e)
+                if matches!(e.kind(), std::io::ErrorKind::NotFound | std::io::ErrorKind::AlreadyExists)
+                    && attempts < 3 =>
+            {
+                attempts += 1
+            }
+            result => return result,
+        }
+    }
+}
+
+/// An object's content mapped into memory by `LocalStore::get_mapped`,
+/// read through `Deref<Target = [u8]>` without copying it.
+#[cfg(feature = "mmap")]
+pub struct MappedObject {
+    // Empty files can't be mapped
+    map: Option<memmap2::Mmap>,
+}
+
+#[cfg(feature = "mmap")]
+impl std::ops::Deref for MappedObject {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.map.as_deref().unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl AsRef<[u8]> for MappedObject {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+// One page of a `list` call, which is after the keys under `prefix` that
+// sort after the continuation token
+struct Listing<'a> {
+    prefix: &'a str,
+    after: Option<&'a str>,
+    keymap: HashMap<String, String>,
+    // Keys found in order, up to one more than a page
+    keys: Vec<String>,
+    limit: usize,
+    // Keys found out of order, every one there is
+    unordered: Vec<String>,
+}
+
+impl Listing<'_> {
+    fn wants(&self, key: &str) -> bool {
+        key.starts_with(self.prefix) && self.after.is_none_or(|after| key > after)
+    }
+
+    // Whether any key starting with `start` can be wanted. If `start`
+    // sorts before the token without being the start of it, every key
+    // starting with it does too.
+    fn may_want_under(&self, start: &str) -> bool {
+        (start.starts_with(self.prefix) || self.prefix.starts_with(start))
+            && self.after.is_none_or(|after| start > after || after.starts_with(start))
+    }
+}
+
+// Records which key a mapped path holds
+#[derive(Serialize, Deserialize)]
+struct KeymapEntry {
+    path: String,
+    key: String,
+}
+
+/// Objects as files under a root directory, at their key's path.
+///
+/// Keys that aren't safe relative paths (see `keys::validate_key`) are
+/// handled by the store's `KeyPolicy`; by default they are rejected with
+/// `ObjectStoreError::InvalidKey`, so no key can reach outside the root.
+/// Path segments the filesystem can't take as file names under the store's
+/// `PathRules`, such as over-long ones or, on Windows, `CON` or `a:b`, are
+/// stored under a shortened name ending in a hash of the segment. The
+/// original key of such files, and of keys hashed by `KeyPolicy::Hash`, is
+/// recorded in `.keymap/`, filed by directory, so `list` still returns it
+/// without reading every mapping. With `Layout::Fanout` objects are spread
+/// over hashed directories instead of sitting at their key's path. Each
+/// object's ETag and `Attributes` are kept in an extended attribute of its
+/// file, or under `.meta/` where there are none, so `head` doesn't read
+/// the object. `list` walks only the directory the prefix names, in key
+/// order, and stops once it has a page. Writes take an advisory lock under
+/// `.locks/`, so conditional puts hold across every process sharing the
+/// root. Keys under `.keymap/`, `.tmp/`, `.locks/`, `.meta/` and `.hashed/`
+/// are reserved.
 pub struct LocalStore {
     root: PathBuf,
+    key_policy: KeyPolicy,
+    path_rules: PathRules,
+    durability: Durability,
+    layout: Layout,
+    metadata_storage: MetadataStorage,
+    prune_empty_dirs: bool,
+    page_size: usize,
 }
 
 impl LocalStore {
     pub fn new<P: AsRef<Path>>(root: P) -> Self {
         Self {
             root: root.as_ref().to_path_buf(),
+            key_policy: KeyPolicy::default(),
+            path_rules: PathRules::default(),
+            durability: Durability::default(),
+            layout: Layout::default(),
+            metadata_storage: MetadataStorage::default(),
+            prune_empty_dirs: false,
+            page_size: 1000,
         }
     }
 
-    fn object_path(&self, key: &str) -> PathBuf {
-        self.root.join(key)
+    // Every process sharing a root must use the same policy
+    pub fn with_key_policy(mut self, policy: KeyPolicy) -> Self {
+        self.key_policy = policy;
+        self
+    }
+
+    // `PathRules::Windows` on every platform gives a root that can be
+    // copied between platforms and read back with the same keys. Every
+    // process sharing a root must use the same rules.
+    pub fn with_path_rules(mut self, rules: PathRules) -> Self {
+        self.path_rules = rules;
+        self
+    }
+
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    // Every process sharing a root must use the same layout, and a root
+    // written with one layout can't be read with the other
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn with_metadata_storage(mut self, storage: MetadataStorage) -> Self {
+        self.metadata_storage = storage;
+        self
+    }
+
+    // Off by default, leaving the directories a delete empties in place
+    pub fn with_prune_empty_dirs(mut self, prune: bool) -> Self {
+        self.prune_empty_dirs = prune;
+        self
+    }
+
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    // The path of `key` relative to the root, and whether it needs a
+    // keymap entry to be listed as `key`
+    fn relative_path(&self, key: &str) -> Result<(String, bool)> {
+        if INTERNAL_DIRS.iter().any(|dir| key.starts_with(dir)) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        let path = self
+            .key_policy
+            .encode(key)?
+            .split('/')
+            .map(|segment| map_segment(segment, self.path_rules))
+            .collect::<Vec<_>>()
+            .join("/");
+        let mapped = self.key_policy.decode(&path).as_deref() != Some(key);
+        match self.layout {
+            Layout::Flat => Ok((path, mapped)),
+            Layout::Fanout => {
+                let hash = Sha256::digest(key.as_bytes());
+                Ok((format!("{:02x}/{:02x}/{path}", hash[0], hash[1]), mapped))
+            }
+        }
+    }
+
+    // The path the key of the file at `relative` encodes to, without the
+    // layout's directories; `None` if the file isn't where objects go
+    fn unlaid_path<'a>(&self, relative: &'a str) -> Option<&'a str> {
+        match self.layout {
+            Layout::Flat => Some(relative),
+            Layout::Fanout => {
+                let mut parts = relative.splitn(3, '/');
+                let is_fanout = |part: &str| part.len() == 2 && part.bytes().all(|b| b.is_ascii_hexdigit());
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(first), Some(second), Some(rest)) if is_fanout(first) && is_fanout(second) => Some(rest),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    pub(crate) fn object_path(&self, key: &str) -> Result<PathBuf> {
+        Ok(self.root.join(self.relative_path(key)?.0))
+    }
+
+    // Entries sit under the directory of the path they map, like sidecars,
+    // so a listing reads only those of the directories it can reach
+    fn keymap_path(&self, path: &str) -> PathBuf {
+        let name = format!("{:x}", Sha256::digest(path.as_bytes()));
+        match path.rsplit_once('/') {
+            Some((dir, _)) => self.root.join(KEYMAP_DIR).join(dir).join(name),
+            None => self.flat_keymap_path(path),
+        }
+    }
+
+    // Where every entry went before they were kept by directory
+    fn flat_keymap_path(&self, path: &str) -> PathBuf {
+        self.root.join(KEYMAP_DIR).join(format!("{:x}", Sha256::digest(path.as_bytes())))
+    }
+
+    // The mapped paths that may hold keys under `prefix`, and those keys:
+    // the entries of the directory the prefix names, everything under it,
+    // and the directory beside which a key ending in `/` sits. Entries
+    // directly in `.keymap/` are always read, being those of the root and
+    // any written before entries were kept by directory.
+    fn keymap(&self, prefix: &str) -> Result<HashMap<String, String>> {
+        let mut keymap = HashMap::new();
+        let dir = match (self.layout, prefix.rsplit_once('/')) {
+            // Empty segments encode differently on their own than inside a
+            // longer key, so their directory says nothing
+            (Layout::Flat, Some((dir, _))) if !dir.split('/').any(str::is_empty) => {
+                self.relative_path(dir).map(|(path, _)| path).unwrap_or_default()
+            }
+            _ => String::new(),
+        };
+        self.read_keymap("", dir.is_empty(), &mut keymap)?;
+        if !dir.is_empty() {
+            self.read_keymap(&dir, true, &mut keymap)?;
+            if let Some((parent, _)) = dir.rsplit_once('/') {
+                self.read_keymap(parent, false, &mut keymap)?;
+            }
+        }
+        // Hashed keys may start with anything
+        if self.key_policy == KeyPolicy::Hash && !dir.is_empty() {
+            self.read_keymap(HASHED_PREFIX.trim_end_matches('/'), false, &mut keymap)?;
+        }
+        Ok(keymap)
+    }
+
+    // Adds the entries in `dir` under `.keymap/`, and with `recursive` those
+    // in its subdirectories, to `keymap`
+    fn read_keymap(&self, dir: &str, recursive: bool, keymap: &mut HashMap<String, String>) -> Result<()> {
+        let walk = walkdir::WalkDir::new(self.root.join(KEYMAP_DIR).join(dir)).min_depth(1);
+        let walk = if recursive { walk } else { walk.max_depth(1) };
+        for entry in walk {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) if e.io_error().is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) => continue,
+                Err(e) => return Err(ObjectStoreError::Io(e.into())),
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let data = fs::read(entry.path()).map_err(ObjectStoreError::Io)?;
+            let mapping: KeymapEntry = serde_json::from_slice(&data).map_err(|e| {
+                ObjectStoreError::Other(format!("corrupt key mapping {}: {e}", entry.path().display()))
+            })?;
+            keymap.insert(mapping.path, mapping.key);
+        }
+        Ok(())
+    }
+
+    // Takes the advisory lock covering the object at `relative`, held until
+    // the returned file is dropped. Locks are taken per open file, so this
+    // excludes other threads as well as other processes.
+    fn lock(&self, relative: &str) -> Result<File> {
+        let stripe = usize::from(Sha256::digest(relative.as_bytes())[0]) % LOCK_STRIPES;
+        let dir = self.root.join(LOCK_DIR);
+        fs::create_dir_all(&dir).map_err(ObjectStoreError::Io)?;
+        let file = File::options()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(dir.join(format!("{stripe:02x}")))
+            .map_err(ObjectStoreError::Io)?;
+        file.lock().map_err(ObjectStoreError::Io)?;
+        Ok(file)
+    }
+
+    fn sidecar_path(&self, relative: &str) -> PathBuf {
+        self.root.join(META_DIR).join(relative)
+    }
+
+    // Writes `data` to `path` atomically, as durably as configured.
+    // `prepare` sees the new file before it's renamed into place.
+    fn write_file(&self, path: &Path, data: &[u8], prepare: impl FnOnce(&File) -> std::io::Result<()>) -> Result<()> {
+        let parent = path.parent().expect("object paths are under the root");
+        // The directories the write creates need syncing too
+        let mut existing = parent;
+        while !existing.exists() && existing != self.root {
+            existing = existing.parent().unwrap_or(&self.root);
+        }
+        let tmp_dir = self.root.join(TMP_DIR);
+        create_dirs(parent).and_then(|_| create_dirs(&tmp_dir)).map_err(ObjectStoreError::Io)?;
+
+        let tmp = tmp_dir.join(Uuid::new_v4().simple().to_string());
+        let result = (|| {
+            let mut file = File::create(&tmp)?;
+            file.write_all(data)?;
+            prepare(&file)?;
+            if self.durability != Durability::None {
+                file.sync_all()?;
+            }
+            // A delete pruning empty directories can remove `parent` again
+            // before the rename; the file is kept, so just put it back
+            let mut attempts = 0;
+            while let Err(e) = fs::rename(&tmp, path) {
+                attempts += 1;
+                if e.kind() != std::io::ErrorKind::NotFound || attempts > 3 || !tmp.exists() {
+                    return Err(e);
+                }
+                create_dirs(parent)?;
+            }
+            if self.durability == Durability::DataAndDir {
+                for dir in parent.ancestors() {
+                    sync_dir(dir)?;
+                    if dir == existing {
+                        break;
+                    }
+                }
+            }
+            Ok(())
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        result.map_err(ObjectStoreError::Io)
+    }
+
+    // Removes the directories above `path` that are left empty, up to but
+    // not including `top`. Stops at the first that isn't empty, which a
+    // concurrent write may make any of them.
+    fn prune_dirs(&self, path: &Path, top: &Path) {
+        for dir in path.ancestors().skip(1).take_while(|dir| *dir != top && dir.starts_with(top)) {
+            if fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+    }
+
+    // The directory every key under `prefix` is in, relative to the root,
+    // and the start of the keys in it. `None` if the directory is mapped,
+    // so all of its keys are in the keymap.
+    fn list_start(&self, prefix: &str) -> Option<(String, String)> {
+        let Some((dir, _)) = prefix.rsplit_once('/') else {
+            return Some((String::new(), String::new()));
+        };
+        match self.relative_path(dir) {
+            Ok((path, false)) => Some((path, format!("{dir}/"))),
+            Ok((_, true)) => None,
+            // No key is in a directory by that name; any that match are
+            // somewhere else
+            Err(_) => Some((String::new(), String::new())),
+        }
+    }
+
+    // Adds the keys under `dir`, relative to the root, to the listing in
+    // order, until it has a page. Every key there starts with `dir_key`.
+    // Entries are visited by the keys they hold: a file by its key and a
+    // directory by its key followed by `/`, which all of its keys start
+    // with, so no key can come between them out of order. Keys that can't
+    // be placed that way are added as unordered.
+    fn walk_ordered(&self, dir: &str, dir_key: &str, listing: &mut Listing) -> Result<()> {
+        let entries = match fs::read_dir(self.root.join(dir)) {
+            Ok(entries) => entries,
+            // Nothing under the prefix, or pruned since
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(ObjectStoreError::Io(e)),
+        };
+        let mut children = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(ObjectStoreError::Io)?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = if dir.is_empty() { name.clone() } else { format!("{dir}/{name}") };
+            let file_type = entry.file_type().map_err(ObjectStoreError::Io)?;
+            let is_dir = file_type.is_dir();
+            let internal = dir.is_empty() && INTERNAL_DIRS.iter().any(|internal| internal[..internal.len() - 1] == name);
+            // Skips the store's own directories, those holding only mapped
+            // keys, and mapped files, which are all listed from the keymap
+            if is_dir && (internal || looks_mapped(&name)) || !is_dir && (!file_type.is_file() || listing.keymap.contains_key(&path)) {
+                continue;
+            }
+            let Some(segment) = self.key_policy.decode(&name) else {
+                continue;
+            };
+            let key = format!("{dir_key}{segment}");
+            if segment.contains('/') {
+                // An escaped `/`, so the keys here don't sort by the name
+                if is_dir {
+                    self.walk_unordered(&self.root.join(&path), listing);
+                } else if listing.wants(&key) {
+                    listing.unordered.push(key);
+                }
+            } else if is_dir {
+                children.push((format!("{key}/"), Some(path)));
+            } else {
+                children.push((key, None));
+            }
+        }
+        children.sort();
+        for (key, path) in children {
+            if listing.keys.len() >= listing.limit {
+                break;
+            }
+            match path {
+                Some(path) if listing.may_want_under(&key) => self.walk_ordered(&path, &key, listing)?,
+                None if listing.wants(&key) => listing.keys.push(key),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Adds every wanted key of the files under `dir` as unordered
+    fn walk_unordered(&self, dir: &Path, listing: &mut Listing) {
+        let internal = INTERNAL_DIRS.map(|dir| self.root.join(dir));
+        for entry in walkdir::WalkDir::new(dir)
+            .into_iter()
+            .filter_entry(|e| !internal.iter().any(|dir| e.path() == dir))
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+        {
+            // Joined with `/` whatever the platform's separator
+            let rel_path = entry
+                .path()
+                .strip_prefix(&self.root)
+                .unwrap()
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if listing.keymap.contains_key(&rel_path) {
+                continue;
+            }
+            if let Some(key) = self.unlaid_path(&rel_path).and_then(|path| self.key_policy.decode(path))
+                && listing.wants(&key)
+            {
+                listing.unordered.push(key);
+            }
+        }
     }
 
     fn compute_etag(data: &[u8]) -> String {
         format!("{:x}", md5::compute(data))
     }
-}
 
-impl ObjectStore for LocalStore {
-    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
-        let path = self.object_path(key);
-        match fs::read(&path) {
-            Ok(data) => Ok(Some(data)),
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
-            Err(e) => Err(ObjectStoreError::Io(e)),
-        }
+    // Records `record` in an extended attribute of `file`, if there are any
+    #[cfg(unix)]
+    fn set_meta_xattr(&self, file: &File, record: &MetaRecord) -> bool {
+        let data = serde_json::to_vec(record).expect("metadata serializes");
+        self.metadata_storage == MetadataStorage::Auto && xattr::FileExt::set_xattr(file, META_XATTR, &data).is_ok()
     }
 
-    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
-        let path = self.object_path(key);
+    #[cfg(not(unix))]
+    fn set_meta_xattr(&self, _file: &File, _record: &MetaRecord) -> bool {
+        false
+    }
+
+    // The metadata recorded for the object at `relative`, open as `file`;
+    // `None` if there is none, or only for an earlier file
+    fn meta_record(&self, relative: &str, file: &File) -> Result<Option<MetaRecord>> {
+        let stamp = FileStamp::of(&file.metadata().map_err(ObjectStoreError::Io)?);
+        #[cfg(unix)]
+        let data = match self.metadata_storage {
+            MetadataStorage::Auto => xattr::FileExt::get_xattr(file, META_XATTR).ok().flatten(),
+            MetadataStorage::Sidecar => None,
+        };
+        #[cfg(not(unix))]
+        let data = None;
+        let data = match data {
+            Some(data) => data,
+            None => match fs::read(self.sidecar_path(relative)) {
+                Ok(data) => data,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(ObjectStoreError::Io(e)),
+            },
+        };
+        // A corrupt record is as good as none: the ETag can be recomputed
+        Ok(serde_json::from_slice::<MetaRecord>(&data)
+            .ok()
+            .filter(|record| record.stamp == stamp))
+    }
+
+    /// The `Attributes` stored with the object; the defaults if its file
+    /// was replaced other than through the store.
+    pub fn attributes(&self, key: &str) -> Result<Option<Attributes>> {
+        let relative = self.relative_path(key)?.0;
+        let file = match File::open(self.root.join(&relative)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(ObjectStoreError::Io(e)),
+        };
+        Ok(Some(self.meta_record(&relative, &file)?.map(|record| record.attributes).unwrap_or_default()))
+    }
+
+    /// `put`, storing `attributes` along with the object.
+    pub fn put_with_attributes(&self, key: &str, body: &[u8], cond: IfMatch, attributes: &Attributes) -> Result<String> {
+        let (relative, mapped) = self.relative_path(key)?;
+        let path = self.root.join(&relative);
+        // Held across the check and the write, so conditions hold against
+        // writers in other processes too
+        let _lock = self.lock(&relative)?;
 
         // Check preconditions
         match cond {
             IfMatch::Any => { /* always write */ }
-            IfMatch::Tag(expected_etag) => {
-                let existing = self.get(key)?;
-                match existing {
-                    Some(data) => {
-                        let etag = Self::compute_etag(&data);
-                        if etag != expected_etag {
-                            return Err(ObjectStoreError::PreconditionFailed);
-                        }
-                    }
-                    None => return Err(ObjectStoreError::PreconditionFailed),
-                }
-            }
+            IfMatch::Tag(expected_etag) => match self.head(key)? {
+                Some(meta) if meta.etag == expected_etag => {}
+                _ => return Err(ObjectStoreError::PreconditionFailed),
+            },
             IfMatch::NoneMatch => {
-                if self.get(key)?.is_some() {
+                if self.head(key)?.is_some() {
                     return Err(ObjectStoreError::PreconditionFailed);
                 }
             }
         }
 
-        // Ensure parent directories exist
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent).map_err(ObjectStoreError::Io)?;
+        // Recorded first, so the file is never there without its key
+        if mapped {
+            let entry = KeymapEntry {
+                path: relative.clone(),
+                key: key.to_string(),
+            };
+            let data = serde_json::to_vec(&entry).expect("key mapping serializes");
+            self.write_file(&self.keymap_path(&relative), &data, |_| Ok(()))?;
         }
 
-        // Write the file
-        let mut file = File::create(&path).map_err(ObjectStoreError::Io)?;
-        file.write_all(body).map_err(ObjectStoreError::Io)?;
-
         let etag = Self::compute_etag(body);
+        // Set on the new file before it's in place where there are extended
+        // attributes, else written once it is. Until then the sidecar still
+        // describes the old file, so readers see it's stale and ignore it.
+        let mut record = None;
+        let mut in_xattr = false;
+        self.write_file(&path, body, |file| {
+            let new = MetaRecord {
+                etag: etag.clone(),
+                stamp: FileStamp::of(&file.metadata()?),
+                attributes: attributes.clone(),
+                tags: BTreeMap::new(),
+            };
+            in_xattr = self.set_meta_xattr(file, &new);
+            record = Some(new);
+            Ok(())
+        })?;
+        if !in_xattr && let Some(record) = record {
+            let data = serde_json::to_vec(&record).expect("metadata serializes");
+            self.write_file(&self.sidecar_path(&relative), &data, |_| Ok(()))?;
+        }
         Ok(etag)
     }
 
+    /// Maps the object into memory instead of reading it, so large objects
+    /// are paged in as they are read rather than copied up front. Windows
+    /// won't replace a mapped file, so there puts to the key fail while the
+    /// mapping is alive.
+    #[cfg(feature = "mmap")]
+    pub fn get_mapped(&self, key: &str) -> Result<Option<MappedObject>> {
+        let file = match File::open(self.object_path(key)?) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(ObjectStoreError::Io(e)),
+        };
+        if file.metadata().map_err(ObjectStoreError::Io)?.len() == 0 {
+            return Ok(Some(MappedObject { map: None }));
+        }
+        // SAFETY: the store never writes to an object's file in place; puts
+        // rename a new file over it, so the mapping keeps the old content
+        // for as long as it lives. Only writing to the file behind the
+        // store's back could change the mapped bytes.
+        let map = unsafe { memmap2::Mmap::map(&file) }.map_err(ObjectStoreError::Io)?;
+        Ok(Some(MappedObject { map: Some(map) }))
+    }
+}
+
+impl ObjectStore for LocalStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.object_path(key)?;
+        match fs::read(&path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(ObjectStoreError::Io(e)),
+        }
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        self.put_with_attributes(key, body, cond, &Attributes::default())
+    }
+
     fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
-        let mut keys = Vec::new();
-
-        // Recursively walk the directory tree
-        if self.root.exists() {
-            for entry in walkdir::WalkDir::new(&self.root)
-                .into_iter()
-                .filter_map(|e| e.ok())
-                .filter(|e| e.file_type().is_file())
-            {
-                let rel_path = entry.path().strip_prefix(&self.root).unwrap().to_string_lossy().to_string();
-                if rel_path.starts_with(prefix) {
-                    keys.push(rel_path);
+        let mut listing = Listing {
+            prefix,
+            after: continuation.as_deref(),
+            keymap: self.keymap(prefix)?,
+            keys: Vec::new(),
+            limit: self.page_size + 1,
+            unordered: Vec::new(),
+        };
+        // Mapped keys don't sort by their paths, so they're listed from the
+        // keymap
+        listing.unordered = listing
+            .keymap
+            .iter()
+            .filter(|(path, key)| listing.wants(key) && self.root.join(path).is_file())
+            .map(|(_, key)| key.clone())
+            .collect();
+        match self.layout {
+            Layout::Flat => {
+                if let Some((dir, dir_key)) = self.list_start(prefix) {
+                    self.walk_ordered(&dir, &dir_key, &mut listing)?;
+                }
+                // Percent-encoded, a key ending in `/` sits beside its directory
+                if let Some((dir, _)) = prefix.rsplit_once('/')
+                    && let key = format!("{dir}/")
+                    && listing.wants(&key)
+                    && let Ok((path, false)) = self.relative_path(&key)
+                    && self.root.join(path).is_file()
+                {
+                    listing.unordered.push(key);
                 }
             }
+            // Hashed directories say nothing about the keys under them
+            Layout::Fanout => self.walk_unordered(&self.root, &mut listing),
         }
 
+        // The walk stopped after one key more than a page, so every key
+        // before that one is here
+        let mut keys = listing.keys;
+        keys.extend(listing.unordered);
         keys.sort();
-
-        // Simple pagination: 1000 per page
-        let page_size = 1000;
-        let start = continuation
-            .and_then(|token| keys.iter().position(|k| k > &token))
-            .unwrap_or(0);
-        let end = (start + page_size).min(keys.len());
-        let next_token = if end < keys.len() {
-            Some(keys[end - 1].clone())
+        let next_token = if keys.len() > self.page_size {
+            keys.truncate(self.page_size);
+            keys.last().cloned()
         } else {
             None
         };
+        Ok((keys, next_token))
+    }
 
-        Ok((keys[start..end].to_vec(), next_token))
+    fn delete(&self, key: &str) -> Result<()> {
+        let (relative, mapped) = self.relative_path(key)?;
+        let _lock = self.lock(&relative)?;
+        let path = self.root.join(&relative);
+        let sidecar = self.sidecar_path(&relative);
+        let mut paths = vec![&path, &sidecar];
+        let keymap_path = self.keymap_path(&relative);
+        let flat_keymap_path = self.flat_keymap_path(&relative);
+        if mapped {
+            paths.push(&keymap_path);
+            paths.push(&flat_keymap_path);
+        }
+        for path in paths {
+            match fs::remove_file(path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(ObjectStoreError::Io(e)),
+            }
+        }
+        if self.prune_empty_dirs {
+            self.prune_dirs(&path, &self.root);
+            self.prune_dirs(&sidecar, &self.root.join(META_DIR));
+            if mapped {
+                self.prune_dirs(&keymap_path, &self.root.join(KEYMAP_DIR));
+            }
+        }
+        Ok(())
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let relative = self.relative_path(key)?.0;
+        let file = match File::open(self.root.join(&relative)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(ObjectStoreError::Io(e)),
+        };
+        if let Some(record) = self.meta_record(&relative, &file)? {
+            return Ok(Some(ObjectMeta {
+                size: record.stamp.size,
+                etag: record.etag,
+            }));
+        }
+        // Nothing recorded for this file, so the ETag has to be computed
+        // from its contents
+        let mut data = Vec::new();
+        (&file).read_to_end(&mut data).map_err(ObjectStoreError::Io)?;
+        Ok(Some(ObjectMeta {
+            size: data.len() as u64,
+            etag: Self::compute_etag(&data),
+        }))
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        let path = self.object_path(key)?;
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(ObjectStoreError::Io(e)),
+        };
+        read_range(file, range).map(Some)
+    }
+
+    // The ETag and the bytes come from the same open file, so a put
+    // replacing the object meanwhile can't pair one with the other
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        let relative = self.relative_path(key)?.0;
+        let mut file = match File::open(self.root.join(&relative)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(ObjectStoreError::Io(e)),
+        };
+        let mut meta = None;
+        if opts.if_match.is_some() || opts.if_none_match.is_some() || opts.include_metadata {
+            let Some(record) = self.meta_record(&relative, &file)? else {
+                // Nothing recorded for this file, so the ETag has to be
+                // computed from all of it
+                let mut data = Vec::new();
+                file.read_to_end(&mut data).map_err(ObjectStoreError::Io)?;
+                return get_from_body(&data, &Self::compute_etag(&data), &opts).map(Some);
+            };
+            if let Some(result) = check_conditions(&record.etag, &opts)? {
+                return Ok(Some(result));
+            }
+            meta = opts.include_metadata.then_some(ObjectMeta {
+                size: record.stamp.size,
+                etag: record.etag,
+            });
+        }
+        let data = match opts.range {
+            Some(range) => read_range(file, range)?,
+            None => {
+                let mut data = Vec::new();
+                file.read_to_end(&mut data).map_err(ObjectStoreError::Io)?;
+                data
+            }
+        };
+        Ok(Some(GetResult::Body { data, meta }))
     }
-}
 
+    // Kept with the rest of the object's metadata, like its ETag
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        let relative = self.relative_path(key)?.0;
+        let file = match File::open(self.root.join(&relative)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(ObjectStoreError::Io(e)),
+        };
+        Ok(Some(self.meta_record(&relative, &file)?.map(|record| record.tags).unwrap_or_default()))
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        let relative = self.relative_path(key)?.0;
+        let _lock = self.lock(&relative)?;
+        let file = match File::open(self.root.join(&relative)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(ObjectStoreError::NotFound(key.to_string())),
+            Err(e) => return Err(ObjectStoreError::Io(e)),
+        };
+        let mut record = match self.meta_record(&relative, &file)? {
+            Some(record) => record,
+            // Nothing recorded for this file yet, so start a record for it
+            None => {
+                let stamp = FileStamp::of(&file.metadata().map_err(ObjectStoreError::Io)?);
+                let mut data = Vec::new();
+                (&file).read_to_end(&mut data).map_err(ObjectStoreError::Io)?;
+                MetaRecord {
+                    etag: Self::compute_etag(&data),
+                    stamp,
+                    attributes: Attributes::default(),
+                    tags: BTreeMap::new(),
+                }
+            }
+        };
+        record.tags = tags.clone();
+        if !self.set_meta_xattr(&file, &record) {
+            let data = serde_json::to_vec(&record).expect("metadata serializes");
+            self.write_file(&self.sidecar_path(&relative), &data, |_| Ok(()))?;
+        }
+        Ok(())
+    }
+}
 
+// The bytes of `range` in `file`, short or empty past its end
+fn read_range(mut file: File, range: Range<u64>) -> Result<Vec<u8>> {
+    let len = range.end.saturating_sub(range.start);
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(range.start)).map_err(ObjectStoreError::Io)?;
+    file.take(len).read_to_end(&mut data).map_err(ObjectStoreError::Io)?;
+    Ok(data)
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::object_store::{IfMatch, ObjectStore};
-    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use crate::object_store::test_helpers::tests::{run_object_store_tests, run_oracle_tests};
     use tempfile::TempDir;
     use std::fs;
-    use std::panic;
+    use std::sync::Arc;
     use uuid::Uuid;
 
     fn setup_store() -> (LocalStore, TempDir) {
@@ -196,6 +1113,43 @@ mod tests {
         assert!(keys.contains(&"folder/c.txt".to_string()));
     }
 
+    #[test]
+    fn test_list_pages_in_key_order() {
+        let long = format!("a/{}", "l".repeat(300));
+        let mut keys = vec!["a.txt", "a-b", "a/b.txt", "a/b/c", "a/b/c.d/e", "a/bb", "ab", "b/x", &long];
+        for policy in [KeyPolicy::Reject, KeyPolicy::PercentEncode] {
+            if policy == KeyPolicy::PercentEncode {
+                // Escaped separators, whose files don't sort by name
+                keys.extend(["a/", "a//x", "a/b/", "x//y/z", "../up", "100%"]);
+            }
+            let tmp = TempDir::new().unwrap();
+            let store = LocalStore::new(tmp.path()).with_key_policy(policy).with_page_size(2);
+            for key in &keys {
+                store.put(key, b"x", IfMatch::NoneMatch).unwrap();
+            }
+            for prefix in ["", "a", "a/", "a/b", "a/b/", "b/", "x/", "z"] {
+                let mut expected: Vec<_> = keys.iter().copied().filter(|key| key.starts_with(prefix)).collect();
+                expected.sort();
+                let mut listed = Vec::new();
+                let mut token = None;
+                loop {
+                    let (page, next) = store.list(prefix, token).unwrap();
+                    assert!(page.len() <= 2);
+                    listed.extend(page);
+                    match next {
+                        Some(next) => token = Some(next),
+                        None => break,
+                    }
+                }
+                assert_eq!(listed, expected, "{policy:?} {prefix:?}");
+            }
+        }
+
+        let tmp = TempDir::new().unwrap();
+        let store = LocalStore::new(tmp.path()).with_page_size(2);
+        run_oracle_tests(&store, &format!("oracle/{}/", Uuid::new_v4()));
+    }
+
     #[test]
     fn test_parent_dirs_created() {
         let (store, tmp) = setup_store();
@@ -209,13 +1163,384 @@ mod tests {
         let on_disk = fs::read(tmp.path().join(nested_path)).unwrap();
         assert_eq!(on_disk, b"deep");
 
-        let long_key_result = panic::catch_unwind(|| {
-            // The code that is expected to panic
-            let long_key = format!("{}{}", "local_test", "a".repeat(512));
-            let _ = store.put(&long_key, b"long", IfMatch::Any).unwrap();
-        });
+    }
+
+    #[test]
+    fn test_long_keys() {
+        let (store, tmp) = setup_store();
+        let long_key = format!("{}{}", "local_test", "a".repeat(512));
+        let nested = format!("dir/{}/{}.txt", "b".repeat(300), "é".repeat(200));
+        let lookalike = format!("short~{}", "0".repeat(32));
+        for key in [&long_key, &nested, &lookalike] {
+            store.put(key, key.as_bytes(), IfMatch::Any).unwrap();
+            assert_eq!(store.get(key).unwrap(), Some(key.as_bytes().to_vec()));
+            assert_eq!(store.head(key).unwrap().unwrap().size, key.len() as u64);
+        }
+        // Every file name on disk fits
+        for entry in walkdir::WalkDir::new(tmp.path()) {
+            assert!(entry.unwrap().file_name().len() <= 255);
+        }
+
+        let mut keys = store.list("", None).unwrap().0;
+        keys.sort();
+        let mut expected = vec![long_key.clone(), nested.clone(), lookalike.clone()];
+        expected.sort();
+        assert_eq!(keys, expected);
+        assert_eq!(store.list("dir/", None).unwrap().0, vec![nested.clone()]);
+
+        store.delete(&long_key).unwrap();
+        assert_eq!(store.get(&long_key).unwrap(), None);
+        assert_eq!(store.list("", None).unwrap().0.len(), 2);
+        assert_eq!(fs::read_dir(tmp.path().join(KEYMAP_DIR)).unwrap().count(), 2);
+        assert!(matches!(store.put(".keymap/x", b"x", IfMatch::Any), Err(ObjectStoreError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn test_listing_reads_only_reachable_key_mappings() {
+        let (store, tmp) = setup_store();
+        let long = "x".repeat(300);
+        let keys = [format!("a/{long}"), format!("a/{long}d/inner"), format!("a/b/{long}"), format!("c/{long}")];
+        for key in &keys {
+            store.put(key, b"v", IfMatch::Any).unwrap();
+        }
+        let mapped = |prefix: &str| {
+            let mut keys: Vec<_> = store.keymap(prefix).unwrap().into_values().collect();
+            keys.sort();
+            keys
+        };
+        // Those in `a/` too, which a key `a/b/` would be beside
+        assert_eq!(mapped("a/b/"), vec![keys[2].clone(), keys[0].clone()]);
+        assert_eq!(mapped("c/"), vec![keys[3].clone()]);
+        assert_eq!(mapped("").len(), 4);
+        assert_eq!(store.list("a/", None).unwrap().0, [keys[2].clone(), keys[0].clone(), keys[1].clone()]);
+        assert_eq!(store.list(&format!("a/{long}d/"), None).unwrap().0, vec![keys[1].clone()]);
+
+        // Entries from before they were kept by directory are still read,
+        // and go with their object
+        let relative = store.relative_path(&keys[3]).unwrap().0;
+        fs::rename(store.keymap_path(&relative), store.flat_keymap_path(&relative)).unwrap();
+        assert_eq!(store.list("c/", None).unwrap().0, vec![keys[3].clone()]);
+        store.delete(&keys[3]).unwrap();
+        assert!(!store.flat_keymap_path(&relative).exists());
+        assert!(tmp.path().join(KEYMAP_DIR).join("c").read_dir().unwrap().next().is_none());
+    }
+
+    #[test]
+    fn test_unsafe_keys_are_rejected() {
+        let (store, tmp) = setup_store();
+        for key in ["../../etc/cron.d/x", "/etc/passwd", "a/../../b", "nul\0byte", "a//b", ""] {
+            assert!(matches!(store.put(key, b"x", IfMatch::Any), Err(ObjectStoreError::InvalidKey(_))), "{key:?}");
+            assert!(matches!(store.get(key), Err(ObjectStoreError::InvalidKey(_))), "{key:?}");
+            assert!(matches!(store.delete(key), Err(ObjectStoreError::InvalidKey(_))), "{key:?}");
+        }
+        assert!(fs::read_dir(tmp.path()).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn test_percent_encoded_keys_stay_inside_root() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("root");
+        let store = LocalStore::new(&root).with_key_policy(KeyPolicy::PercentEncode);
+        let keys = ["../../escape", "/etc/passwd", "a//b", "nul\0byte", "plain/key"];
+        for key in keys {
+            store.put(key, key.as_bytes(), IfMatch::Any).unwrap();
+            assert_eq!(store.get(key).unwrap(), Some(key.as_bytes().to_vec()));
+        }
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+        assert!(root.join("%2E%2E/%2E%2E/escape").exists());
+
+        let mut listed = store.list("", None).unwrap().0;
+        listed.sort();
+        let mut expected = keys.map(String::from).to_vec();
+        expected.sort();
+        assert_eq!(listed, expected);
+        assert_eq!(store.list("/etc", None).unwrap().0, vec!["/etc/passwd"]);
+    }
+
+    #[test]
+    fn test_hashed_keys_stay_inside_root() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("root");
+        let store = LocalStore::new(&root).with_key_policy(KeyPolicy::Hash);
+        store.put("../../escape", b"x", IfMatch::Any).unwrap();
+        store.put("safe/key", b"y", IfMatch::Any).unwrap();
+        assert_eq!(store.get("../../escape").unwrap(), Some(b"x".to_vec()));
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+        assert!(root.join("safe/key").exists());
+        assert!(root.join(".hashed").is_dir());
+        // Listed under the original key through the keymap
+        let mut keys = store.list("", None).unwrap().0;
+        keys.sort();
+        assert_eq!(keys, vec!["../../escape", "safe/key"]);
+
+        // The hashed name is no way around the policy
+        let hashed = KeyPolicy::Hash.encode("../../escape").unwrap();
+        assert!(matches!(store.put(&hashed, b"z", IfMatch::Any), Err(ObjectStoreError::InvalidKey(_))));
+        assert!(matches!(store.get(&hashed), Err(ObjectStoreError::InvalidKey(_))));
+        let store = LocalStore::new(&root);
+        assert!(matches!(store.put(&hashed, b"z", IfMatch::Any), Err(ObjectStoreError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn test_windows_path_rules() {
+        let rules = PathRules::Windows;
+        for name in ["a.txt", "CONFIG", "con_", "lpt10", ".hidden", "a b"] {
+            assert!(rules.allows(name), "{name}");
+        }
+        for name in ["a:b", "what?", "x*", "back\\slash", "CON", "con.txt", "Lpt1.tar.gz", "nul .x", "dot.", "space "] {
+            assert!(!rules.allows(name), "{name}");
+            assert!(PathRules::Posix.allows(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_windows_layout_round_trips() {
+        let tmp = TempDir::new().unwrap();
+        let store = LocalStore::new(tmp.path()).with_path_rules(PathRules::Windows);
+        let keys = ["c:/temp", "what?/why*", "CON", "dir/aux.log", "trailing.", "trailing /x", "plain/key.txt"];
+        for key in keys {
+            store.put(key, key.as_bytes(), IfMatch::Any).unwrap();
+        }
+        for entry in walkdir::WalkDir::new(tmp.path()).min_depth(1) {
+            let entry = entry.unwrap();
+            let name = entry.file_name().to_str().unwrap();
+            assert!(PathRules::Windows.allows(name), "{name}");
+        }
+        assert!(tmp.path().join("plain/key.txt").exists());
+
+        // Another store on the same root reads it back, whatever the platform
+        let reopened = LocalStore::new(tmp.path()).with_path_rules(PathRules::Windows);
+        for key in keys {
+            assert_eq!(reopened.get(key).unwrap(), Some(key.as_bytes().to_vec()));
+        }
+        let mut listed = reopened.list("", None).unwrap().0;
+        listed.sort();
+        let mut expected = keys.map(String::from).to_vec();
+        expected.sort();
+        assert_eq!(listed, expected);
+    }
+
+    #[test]
+    fn test_durability_modes() {
+        for durability in [Durability::None, Durability::Data, Durability::DataAndDir] {
+            let (store, tmp) = setup_store();
+            let store = store.with_durability(durability);
+            let etag = store.put("deep/new/dirs/file", b"one", IfMatch::NoneMatch).unwrap();
+            store.put("deep/new/dirs/file", b"two", IfMatch::Tag(&etag)).unwrap();
+            assert_eq!(store.get("deep/new/dirs/file").unwrap(), Some(b"two".to_vec()));
+            // Nothing is left behind in the staging directory, nor listed from it
+            assert_eq!(fs::read_dir(tmp.path().join(TMP_DIR)).unwrap().count(), 0);
+            fs::write(tmp.path().join(TMP_DIR).join("partial"), b"x").unwrap();
+            assert_eq!(store.list("", None).unwrap().0, vec!["deep/new/dirs/file"]);
+            assert!(store.put(".tmp/x", b"x", IfMatch::Any).is_err());
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_get_mapped() {
+        let (store, _tmp) = setup_store();
+        let data: Vec<u8> = (0..1_000_000u32).map(|i| i as u8).collect();
+        store.put("big.bin", &data, IfMatch::Any).unwrap();
+        store.put("empty", b"", IfMatch::Any).unwrap();
+
+        let mapped = store.get_mapped("big.bin").unwrap().unwrap();
+        assert_eq!(&mapped[..], &data[..]);
+        assert!(store.get_mapped("empty").unwrap().unwrap().is_empty());
+        assert!(store.get_mapped("missing").unwrap().is_none());
+
+        // Overwriting leaves an existing mapping as it was
+        store.put("big.bin", b"replaced", IfMatch::Any).unwrap();
+        assert_eq!(mapped.len(), data.len());
+        assert_eq!(mapped[999_999], data[999_999]);
+        assert_eq!(&store.get_mapped("big.bin").unwrap().unwrap()[..], b"replaced");
+    }
+
+    #[test]
+    fn test_fanout_layout() {
+        let (store, tmp) = setup_store();
+        let store = store.with_layout(Layout::Fanout);
+        let long_key = format!("docs/{}", "x".repeat(300));
+        for key in ["a.txt", "docs/readme.md", &long_key] {
+            store.put(key, key.as_bytes(), IfMatch::NoneMatch).unwrap();
+            assert_eq!(store.get(key).unwrap(), Some(key.as_bytes().to_vec()));
+        }
+        // Objects sit two hashed directories down, and nothing else is at the top
+        let hash = Sha256::digest(b"docs/readme.md");
+        let path = tmp.path().join(format!("{:02x}/{:02x}/docs/readme.md", hash[0], hash[1]));
+        assert_eq!(fs::read(path).unwrap(), b"docs/readme.md");
+        for entry in fs::read_dir(tmp.path()).unwrap() {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            assert!(name.len() == 2 || name.starts_with('.'), "{name}");
+        }
+
+        assert_eq!(store.list("docs/", None).unwrap().0, vec!["docs/readme.md".to_string(), long_key.clone()]);
+        assert_eq!(store.list("", None).unwrap().0.len(), 3);
+        // A flat store over the same root finds none of them by key
+        assert_eq!(LocalStore::new(tmp.path()).get("a.txt").unwrap(), None);
+
+        store.delete(&long_key).unwrap();
+        assert_eq!(store.list("docs/", None).unwrap().0, vec!["docs/readme.md"]);
+
+        let tmp = TempDir::new().unwrap();
+        let store = LocalStore::new(tmp.path()).with_layout(Layout::Fanout);
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_attributes() {
+        for storage in [MetadataStorage::Auto, MetadataStorage::Sidecar] {
+            let (store, tmp) = setup_store();
+            let store = store.with_metadata_storage(storage);
+            let attributes = Attributes {
+                content_type: Some("text/html".to_string()),
+                user: BTreeMap::from([("owner".to_string(), "web".to_string())]),
+            };
+            let etag = store.put_with_attributes("site/index.html", b"<p>hi</p>", IfMatch::NoneMatch, &attributes).unwrap();
+            assert_eq!(store.attributes("site/index.html").unwrap(), Some(attributes));
+            let meta = store.head("site/index.html").unwrap().unwrap();
+            assert_eq!((meta.size, meta.etag.as_str()), (9, etag.as_str()));
+            if storage == MetadataStorage::Sidecar {
+                assert!(tmp.path().join(META_DIR).join("site/index.html").exists());
+            }
+            assert_eq!(store.list("", None).unwrap().0, vec!["site/index.html"]);
+
+            // A plain put replaces them
+            store.put("site/index.html", b"<p>bye</p>", IfMatch::Tag(&etag)).unwrap();
+            assert_eq!(store.attributes("site/index.html").unwrap(), Some(Attributes::default()));
+            assert_eq!(store.attributes("missing").unwrap(), None);
+
+            store.delete("site/index.html").unwrap();
+            assert_eq!(store.head("site/index.html").unwrap(), None);
+            assert!(!tmp.path().join(META_DIR).join("site/index.html").exists());
+            assert!(matches!(store.put(".meta/x", b"x", IfMatch::Any), Err(ObjectStoreError::InvalidKey(_))));
+        }
+    }
+
+    #[test]
+    fn test_tags() {
+        for storage in [MetadataStorage::Auto, MetadataStorage::Sidecar] {
+            let (store, tmp) = setup_store();
+            let store = store.with_metadata_storage(storage);
+            let attributes = Attributes {
+                content_type: Some("text/csv".to_string()),
+                ..Default::default()
+            };
+            let etag = store.put_with_attributes("logs/day.csv", b"a,b", IfMatch::Any, &attributes).unwrap();
+            assert_eq!(store.get_tags("logs/day.csv").unwrap(), Some(BTreeMap::new()));
+            let tags = BTreeMap::from([("retention".to_string(), "30d".to_string())]);
+            store.put_tags("logs/day.csv", &tags).unwrap();
+            assert_eq!(store.get_tags("logs/day.csv").unwrap(), Some(tags.clone()));
+            // The object, its ETag and its attributes are untouched
+            assert_eq!(store.head("logs/day.csv").unwrap().unwrap().etag, etag);
+            assert_eq!(store.attributes("logs/day.csv").unwrap(), Some(attributes));
+            assert_eq!(store.list("", None).unwrap().0, vec!["logs/day.csv"]);
+
+            // A file replaced behind the store's back gets a fresh record
+            fs::write(tmp.path().join("logs/day.csv"), b"c,d,e").unwrap();
+            store.put_tags("logs/day.csv", &tags).unwrap();
+            assert_eq!(store.get_tags("logs/day.csv").unwrap(), Some(tags));
+            let meta = store.head("logs/day.csv").unwrap().unwrap();
+            assert_eq!(meta.etag, format!("{:x}", md5::compute(b"c,d,e")));
+
+            // A put drops them
+            store.put("logs/day.csv", b"f", IfMatch::Tag(&meta.etag)).unwrap();
+            assert_eq!(store.get_tags("logs/day.csv").unwrap(), Some(BTreeMap::new()));
+            assert_eq!(store.get_tags("missing").unwrap(), None);
+            let result = store.put_tags("missing", &BTreeMap::new());
+            assert!(matches!(result, Err(ObjectStoreError::NotFound(key)) if key == "missing"));
+        }
+    }
 
-        assert!(long_key_result.is_err(), "Expected a panic for long file name");
+    #[test]
+    fn test_get_opts_uses_recorded_etag() {
+        let (store, tmp) = setup_store();
+        let store = store.with_metadata_storage(MetadataStorage::Sidecar);
+        let etag = store.put("data.bin", b"0123456789", IfMatch::Any).unwrap();
+        // Only the recorded ETag can match, so nothing was recomputed
+        let sidecar = tmp.path().join(META_DIR).join("data.bin");
+        let record = fs::read_to_string(&sidecar).unwrap().replace(&etag, "recorded");
+        fs::write(&sidecar, record).unwrap();
+
+        let opts = |if_match, if_none_match| GetOptions {
+            range: Some(2..5),
+            if_match,
+            if_none_match,
+            include_metadata: true,
+        };
+        let result = store.get_opts("data.bin", opts(Some("recorded"), None)).unwrap();
+        let meta = ObjectMeta {
+            size: 10,
+            etag: "recorded".to_string(),
+        };
+        assert_eq!(result, Some(GetResult::Body { data: b"234".to_vec(), meta: Some(meta) }));
+        assert_eq!(store.get_opts("data.bin", opts(None, Some("recorded"))).unwrap(), Some(GetResult::NotModified));
+        assert!(matches!(store.get_opts("data.bin", opts(Some(&etag), None)), Err(ObjectStoreError::PreconditionFailed)));
+        assert_eq!(store.get_opts("missing", opts(None, None)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_stale_metadata_is_ignored() {
+        let (store, tmp) = setup_store();
+        let store = store.with_metadata_storage(MetadataStorage::Sidecar);
+        let attributes = Attributes {
+            content_type: Some("text/plain".to_string()),
+            ..Default::default()
+        };
+        store.put_with_attributes("notes.txt", b"first", IfMatch::Any, &attributes).unwrap();
+        // Rewritten behind the store's back, the same length as before, and
+        // late enough for a coarse clock to give it a new modification time
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        fs::write(tmp.path().join("notes.txt"), b"later").unwrap();
+        let meta = store.head("notes.txt").unwrap().unwrap();
+        assert_eq!(meta.etag, format!("{:x}", md5::compute(b"later")));
+        assert_eq!(store.attributes("notes.txt").unwrap(), Some(Attributes::default()));
+        // A corrupt record is recomputed too
+        fs::write(tmp.path().join(META_DIR).join("notes.txt"), b"{").unwrap();
+        assert_eq!(store.head("notes.txt").unwrap().unwrap().etag, meta.etag);
+    }
+
+    #[test]
+    fn test_prune_empty_dirs() {
+        let (store, tmp) = setup_store();
+        store.put("a/b/c/file", b"x", IfMatch::Any).unwrap();
+        store.delete("a/b/c/file").unwrap();
+        // Kept by default
+        assert!(tmp.path().join("a/b/c").is_dir());
+
+        let store = store.with_prune_empty_dirs(true);
+        store.put("a/b/c/file", b"x", IfMatch::Any).unwrap();
+        store.put("a/keep", b"x", IfMatch::Any).unwrap();
+        store.delete("a/b/c/file").unwrap();
+        assert!(!tmp.path().join("a/b").exists());
+        assert!(tmp.path().join("a/keep").is_file());
+        store.delete("a/keep").unwrap();
+        assert!(!tmp.path().join("a").exists());
+        assert!(tmp.path().is_dir());
+        assert!(store.list("", None).unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn test_prune_races_with_puts() {
+        let tmp = TempDir::new().unwrap();
+        let store = Arc::new(LocalStore::new(tmp.path()).with_prune_empty_dirs(true));
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    for i in 0..50 {
+                        let key = format!("shared/dir/{t}-{i}");
+                        store.put(&key, b"x", IfMatch::NoneMatch).unwrap();
+                        assert_eq!(store.get(&key).unwrap(), Some(b"x".to_vec()));
+                        store.delete(&key).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(!tmp.path().join("shared").exists());
     }
 
     // this is more of a test of genericness of
@@ -226,6 +1551,7 @@ mod tests {
         let store = LocalStore::new(tmp.path());
         let prefix = format!("test/{}/", Uuid::new_v4());
         run_object_store_tests(&store, &prefix);
+        run_oracle_tests(&store, &format!("oracle/{}/", Uuid::new_v4()));
     }
 
 }
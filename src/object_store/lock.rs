@@ -0,0 +1,240 @@
+use super::retry::{cas_backoff, MAX_CAS_ATTEMPTS};
+use super::{GetOptions, GetResult, IfMatch, ObjectStore, ObjectStoreError, Result};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LockRecord {
+    owner: String,
+    generation: u64,
+    // Milliseconds since the Unix epoch; 0 once released
+    expires_at: u64,
+}
+
+/// A lock held until `expires_at` unless renewed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub name: String,
+    pub owner: String,
+    // Goes up by one with every acquisition of the lock, so it can fence
+    // out writes from holders whose lease has since expired
+    pub generation: u64,
+    // Milliseconds since the Unix epoch
+    pub expires_at: u64,
+    etag: String,
+}
+
+impl Lease {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= now_millis()
+    }
+}
+
+/// Named locks with expiring leases, on any store with conditional puts.
+///
+/// A lock is the object `<prefix><name>` recording its holder, generation
+/// and expiry. `acquire` creates it with `IfMatch::NoneMatch`, or takes it
+/// over with `IfMatch::Tag` once the current lease has expired or been
+/// released; `renew` and `release` are also conditional on the lease's
+/// ETag, so they fail with `PreconditionFailed` if the lock has changed
+/// hands. Expiry is judged by each client's clock, so clocks must agree to
+/// well within the TTL, and a holder must stop relying on its lease before
+/// it runs out.
+pub struct Locks<S> {
+    store: S,
+    prefix: String,
+    owner: String,
+}
+
+impl<S: ObjectStore> Locks<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            prefix: "locks/".to_string(),
+            owner: Uuid::new_v4().to_string(),
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    // Recorded in the locks this instance takes; a random id by default
+    pub fn with_owner(mut self, owner: impl Into<String>) -> Self {
+        self.owner = owner.into();
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}{name}", self.prefix)
+    }
+
+    fn read(&self, name: &str) -> Result<Option<(LockRecord, String)>> {
+        let key = self.key(name);
+        let opts = GetOptions {
+            include_metadata: true,
+            ..Default::default()
+        };
+        match self.store.get_opts(&key, opts)? {
+            Some(GetResult::Body { data, meta: Some(meta) }) => {
+                let record = serde_json::from_slice(&data)
+                    .map_err(|e| ObjectStoreError::Other(format!("corrupt lock {key}: {e}")))?;
+                Ok(Some((record, meta.etag)))
+            }
+            None => Ok(None),
+            Some(_) => Err(ObjectStoreError::Other(format!("no metadata for lock {key}"))),
+        }
+    }
+
+    fn write(&self, name: &str, record: &LockRecord, cond: IfMatch) -> Result<Lease> {
+        let data = serde_json::to_vec(record).expect("lock record serializes");
+        let etag = self.store.put(&self.key(name), &data, cond)?;
+        Ok(Lease {
+            name: name.to_string(),
+            owner: record.owner.clone(),
+            generation: record.generation,
+            expires_at: record.expires_at,
+            etag,
+        })
+    }
+
+    /// Takes the lock for `ttl`. Fails with `PreconditionFailed` while
+    /// someone else's lease on it is live.
+    pub fn acquire(&self, name: &str, ttl: Duration) -> Result<Lease> {
+        for attempt in 0..MAX_CAS_ATTEMPTS {
+            let current = self.read(name)?;
+            let (generation, cond) = match &current {
+                None => (1, IfMatch::NoneMatch),
+                Some((record, _)) if record.expires_at > now_millis() => {
+                    return Err(ObjectStoreError::PreconditionFailed);
+                }
+                Some((record, etag)) => (record.generation + 1, IfMatch::Tag(etag)),
+            };
+            let record = LockRecord {
+                owner: self.owner.clone(),
+                generation,
+                expires_at: now_millis() + ttl.as_millis() as u64,
+            };
+            match self.write(name, &record, cond) {
+                // Someone else got there first; see whether their lease is live
+                Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => cas_backoff(attempt),
+                result => return result,
+            }
+        }
+        Err(ObjectStoreError::Other(format!("lock {name} kept changing while being acquired")))
+    }
+
+    /// The lock's latest generation, for checking a fencing token against.
+    pub fn generation(&self, name: &str) -> Result<Option<u64>> {
+        Ok(self.read(name)?.map(|(record, _)| record.generation))
+    }
+
+    /// Extends a lease to `ttl` from now, if the lock hasn't changed hands.
+    pub fn renew(&self, lease: &mut Lease, ttl: Duration) -> Result<()> {
+        let record = LockRecord {
+            owner: lease.owner.clone(),
+            generation: lease.generation,
+            expires_at: now_millis() + ttl.as_millis() as u64,
+        };
+        *lease = self.write(&lease.name, &record, IfMatch::Tag(&lease.etag))?;
+        Ok(())
+    }
+
+    /// Gives the lock up so the next `acquire` gets it at once.
+    pub fn release(&self, lease: Lease) -> Result<()> {
+        let record = LockRecord {
+            owner: lease.owner.clone(),
+            generation: lease.generation,
+            expires_at: 0,
+        };
+        self.write(&lease.name, &record, IfMatch::Tag(&lease.etag)).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::ContendedStore;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_acquire_renew_release() {
+        let store = Arc::new(InMemoryStore::default());
+        let (a, b) = (Locks::new(store.clone()).with_owner("a"), Locks::new(store.clone()).with_owner("b"));
+
+        let mut lease = a.acquire("compaction", Duration::from_secs(60)).unwrap();
+        assert_eq!((lease.owner.as_str(), lease.generation), ("a", 1));
+        assert!(!lease.is_expired());
+        assert!(matches!(b.acquire("compaction", Duration::from_secs(60)), Err(ObjectStoreError::PreconditionFailed)));
+        assert!(store.get("locks/compaction").unwrap().is_some());
+
+        let before = lease.expires_at;
+        thread::sleep(Duration::from_millis(5));
+        a.renew(&mut lease, Duration::from_secs(120)).unwrap();
+        assert!(lease.expires_at > before);
+
+        a.release(lease).unwrap();
+        let taken = b.acquire("compaction", Duration::from_secs(60)).unwrap();
+        assert_eq!((taken.owner.as_str(), taken.generation), ("b", 2));
+    }
+
+    #[test]
+    fn test_expired_leases_are_stolen() {
+        let store = Arc::new(InMemoryStore::default());
+        let (a, b) = (Locks::new(store.clone()), Locks::new(store));
+        let mut stale = a.acquire("job", Duration::from_millis(20)).unwrap();
+        thread::sleep(Duration::from_millis(30));
+        assert!(stale.is_expired());
+
+        let fresh = b.acquire("job", Duration::from_secs(60)).unwrap();
+        assert_eq!(fresh.generation, 2);
+        // The old holder finds out it lost the lock
+        assert!(matches!(a.renew(&mut stale, Duration::from_secs(60)), Err(ObjectStoreError::PreconditionFailed)));
+        assert!(matches!(a.release(stale), Err(ObjectStoreError::PreconditionFailed)));
+        assert!(b.acquire("job", Duration::from_secs(60)).is_err());
+    }
+
+    #[test]
+    fn test_acquire_gives_up_under_endless_contention() {
+        let locks = Locks::new(ContendedStore(InMemoryStore::default()));
+        locks.acquire("job", Duration::ZERO).unwrap();
+        // Stealing the expired lease needs a conditional put, which never lands
+        assert!(matches!(locks.acquire("job", Duration::from_secs(60)), Err(ObjectStoreError::Other(_))));
+    }
+
+    #[test]
+    fn test_one_winner() {
+        let store = Arc::new(InMemoryStore::default());
+        let winners = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (locks, winners) = (Locks::new(store.clone()), winners.clone());
+                thread::spawn(move || {
+                    if locks.acquire("singleton", Duration::from_secs(60)).is_ok() {
+                        winners.fetch_add(1, Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(winners.load(Ordering::SeqCst), 1);
+    }
+}
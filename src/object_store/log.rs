@@ -0,0 +1,249 @@
+use super::retry::{cas_backoff, MAX_CAS_ATTEMPTS};
+use super::sync::list_all;
+use super::{GetOptions, GetResult, IfMatch, ObjectStore, ObjectStoreError, Result};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    pub seq: u64,
+    pub data: Vec<u8>,
+}
+
+fn validate_topic(topic: &str) -> Result<()> {
+    if topic.is_empty() || topic.contains('/') {
+        return Err(ObjectStoreError::InvalidArgument(format!("invalid log topic {topic:?}")));
+    }
+    Ok(())
+}
+
+// Segments are records back to back, each preceded by its length as a u32
+fn decode(key: &str, mut data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let corrupt = || ObjectStoreError::Other(format!("corrupt log segment {key}"));
+    let mut records = Vec::new();
+    while !data.is_empty() {
+        let (len, rest) = data.split_first_chunk::<4>().ok_or_else(corrupt)?;
+        let len = u32::from_be_bytes(*len) as usize;
+        if rest.len() < len {
+            return Err(corrupt());
+        }
+        records.push(rest[..len].to_vec());
+        data = &rest[len..];
+    }
+    Ok(records)
+}
+
+fn encode(records: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(records.iter().map(|record| record.len() + 4).sum());
+    for record in records {
+        data.extend_from_slice(&(record.len() as u32).to_be_bytes());
+        data.extend_from_slice(record);
+    }
+    data
+}
+
+/// Durable append-only logs, one per topic, on any store.
+///
+/// A topic's records are numbered from 0 and kept in segment objects of
+/// `segment_size` records at `<prefix><topic>/<segment>`. Appending reads
+/// the last segment and writes it back with one more record, conditional
+/// on its ETag (or on its absence when starting a segment), and retries on
+/// conflict; so any number of writers in any number of processes share
+/// one gapless sequence per topic. Each append rewrites its segment, so
+/// smaller segments make appends cheaper and reads chattier.
+pub struct Log<S> {
+    store: S,
+    prefix: String,
+    segment_size: u64,
+    // The last segment seen of each topic, where appends start looking
+    tails: Mutex<HashMap<String, u64>>,
+}
+
+impl<S: ObjectStore> Log<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            prefix: "logs/".to_string(),
+            segment_size: 1000,
+            tails: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    // Every process sharing a topic must use the same size
+    pub fn with_segment_size(mut self, records: u64) -> Self {
+        self.segment_size = records.max(1);
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    fn segment_key(&self, topic: &str, segment: u64) -> String {
+        format!("{}{topic}/{segment:020}", self.prefix)
+    }
+
+    // The segment and its ETag, if it exists
+    fn read_segment(&self, topic: &str, segment: u64) -> Result<Option<(Vec<Vec<u8>>, String)>> {
+        let key = self.segment_key(topic, segment);
+        let opts = GetOptions {
+            include_metadata: true,
+            ..Default::default()
+        };
+        match self.store.get_opts(&key, opts)? {
+            Some(GetResult::Body { data, meta: Some(meta) }) => Ok(Some((decode(&key, &data)?, meta.etag))),
+            None => Ok(None),
+            Some(_) => Err(ObjectStoreError::Other(format!("no metadata for log segment {key}"))),
+        }
+    }
+
+    // Where appends to `topic` start looking, listing the topic the first time
+    fn tail_segment(&self, topic: &str) -> Result<u64> {
+        if let Some(&segment) = self.tails.lock().unwrap().get(topic) {
+            return Ok(segment);
+        }
+        let topic_prefix = format!("{}{topic}/", self.prefix);
+        let last = list_all(&self.store, &topic_prefix)?
+            .iter()
+            .filter_map(|key| key.strip_prefix(&topic_prefix)?.parse::<u64>().ok())
+            .max()
+            .unwrap_or(0);
+        Ok(last)
+    }
+
+    /// Appends `record` to `topic` and returns its sequence number.
+    pub fn append(&self, topic: &str, record: &[u8]) -> Result<u64> {
+        validate_topic(topic)?;
+        if u32::try_from(record.len()).is_err() {
+            return Err(ObjectStoreError::InvalidArgument(format!("log record of {} bytes is too large", record.len())));
+        }
+        let mut segment = self.tail_segment(topic)?;
+        let mut conflicts = 0;
+        while conflicts < MAX_CAS_ATTEMPTS {
+            let (mut records, cond_etag) = match self.read_segment(topic, segment)? {
+                Some((records, etag)) => (records, Some(etag)),
+                None => (Vec::new(), None),
+            };
+            if records.len() as u64 >= self.segment_size {
+                segment += 1;
+                continue;
+            }
+            let seq = segment * self.segment_size + records.len() as u64;
+            records.push(record.to_vec());
+            let data = encode(&records);
+
+            let cond = match &cond_etag {
+                Some(etag) => IfMatch::Tag(etag),
+                None => IfMatch::NoneMatch,
+            };
+            match self.store.put(&self.segment_key(topic, segment), &data, cond) {
+                Ok(_) => {
+                    self.tails.lock().unwrap().insert(topic.to_string(), segment);
+                    return Ok(seq);
+                }
+                // Another writer appended first; read its version and go again
+                Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => {
+                    cas_backoff(conflicts);
+                    conflicts += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(ObjectStoreError::Other(format!("log topic {topic} kept changing while being appended to")))
+    }
+
+    /// Up to `limit` records of `topic` from sequence number `seq` on, in
+    /// order. Returns fewer at the end of the log.
+    pub fn read_from(&self, topic: &str, seq: u64, limit: usize) -> Result<Vec<LogRecord>> {
+        validate_topic(topic)?;
+        let mut records = Vec::new();
+        let mut segment = seq / self.segment_size;
+        let mut skip = (seq % self.segment_size) as usize;
+        while records.len() < limit {
+            let Some((segment_records, _)) = self.read_segment(topic, segment)? else {
+                break;
+            };
+            let full = segment_records.len() as u64 == self.segment_size;
+            let first_seq = segment * self.segment_size;
+            for (offset, data) in segment_records.into_iter().enumerate().skip(skip).take(limit - records.len()) {
+                records.push(LogRecord {
+                    seq: first_seq + offset as u64,
+                    data,
+                });
+            }
+            if !full {
+                break;
+            }
+            segment += 1;
+            skip = 0;
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::ContendedStore;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_append_and_read() {
+        let store = Arc::new(InMemoryStore::default());
+        let log = Log::new(store.clone()).with_segment_size(3);
+        for i in 0..7u64 {
+            assert_eq!(log.append("events", format!("e{i}").as_bytes()).unwrap(), i);
+        }
+        log.append("other", b"x").unwrap();
+
+        let all = log.read_from("events", 0, 100).unwrap();
+        let data: Vec<_> = all.iter().map(|r| String::from_utf8(r.data.clone()).unwrap()).collect();
+        assert_eq!(data, vec!["e0", "e1", "e2", "e3", "e4", "e5", "e6"]);
+        assert_eq!(all.iter().map(|r| r.seq).collect::<Vec<_>>(), (0..7).collect::<Vec<_>>());
+
+        let page = log.read_from("events", 2, 3).unwrap();
+        assert_eq!(page.iter().map(|r| r.seq).collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert!(log.read_from("events", 7, 10).unwrap().is_empty());
+        assert!(log.read_from("missing", 0, 10).unwrap().is_empty());
+        assert_eq!(list_all(log.inner(), "logs/events/").unwrap().len(), 3);
+        assert!(log.append("a/b", b"x").is_err());
+
+        // A fresh handle finds the end of the log
+        let again = Log::new(store).with_segment_size(3);
+        assert_eq!(again.append("events", b"e7").unwrap(), 7);
+    }
+
+    #[test]
+    fn test_append_gives_up_under_endless_contention() {
+        let log = Log::new(ContendedStore(InMemoryStore::default()));
+        assert_eq!(log.append("events", b"e0").unwrap(), 0);
+        assert!(matches!(log.append("events", b"e1"), Err(ObjectStoreError::Other(_))));
+    }
+
+    #[test]
+    fn test_concurrent_writers() {
+        let store = Arc::new(InMemoryStore::default());
+        let handles: Vec<_> = (0..4)
+            .map(|writer| {
+                let log = Log::new(store.clone()).with_segment_size(5);
+                thread::spawn(move || {
+                    (0..10).map(|i| log.append("shared", format!("{writer}-{i}").as_bytes()).unwrap()).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut seqs: Vec<u64> = handles.into_iter().flat_map(|h| h.join().unwrap()).collect();
+        seqs.sort();
+        assert_eq!(seqs, (0..40).collect::<Vec<_>>());
+
+        let late = Log::new(store.clone()).with_segment_size(5);
+        assert_eq!(late.append("shared", b"last").unwrap(), 40);
+        assert_eq!(late.read_from("shared", 0, 100).unwrap().len(), 41);
+    }
+}
@@ -1,16 +1,240 @@
-use super::{IfMatch, ObjectStore, ObjectStoreError, Result};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use super::{get_from_body, slice_range, GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
 
+// Key: object key, Value: (data, etag, tags), in key order for listing by
+// range. Bodies are shared with forks until either side replaces them.
+type ObjectMap = BTreeMap<String, (Arc<[u8]>, String, Tags)>;
+
+type Tags = BTreeMap<String, String>;
+
+// Snapshots and journals are this magic followed by a sequence of records:
+//   put:    b'P' [key len: u32][key] [etag len: u8][etag] [data len: u64][data]
+//   delete: b'D' [key len: u32][key]
+//   tags:   b'T' [key len: u32][key] [tags len: u32][tags as JSON]
+// with lengths little-endian. A snapshot holds only puts, each followed by
+// its tags if it has any.
+const MAGIC: &[u8; 8] = b"BLOBMEM1";
+const PUT: u8 = b'P';
+const DELETE: u8 = b'D';
+const TAGS: u8 = b'T';
+
+enum Record {
+    Put { key: String, etag: String, data: Vec<u8> },
+    Delete { key: String },
+    Tags { key: String, tags: Tags },
+}
+
+fn encode_put(key: &str, etag: &str, data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(14 + key.len() + etag.len() + data.len());
+    buf.push(PUT);
+    buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    buf.extend_from_slice(key.as_bytes());
+    buf.push(etag.len() as u8);
+    buf.extend_from_slice(etag.as_bytes());
+    buf.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    buf.extend_from_slice(data);
+    buf
+}
+
+fn encode_delete(key: &str) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(5 + key.len());
+    buf.push(DELETE);
+    buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    buf.extend_from_slice(key.as_bytes());
+    buf
+}
+
+fn encode_tags(key: &str, tags: &Tags) -> Vec<u8> {
+    let json = serde_json::to_vec(tags).expect("tags serialize");
+    let mut buf = Vec::with_capacity(9 + key.len() + json.len());
+    buf.push(TAGS);
+    buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    buf.extend_from_slice(key.as_bytes());
+    buf.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&json);
+    buf
+}
+
+fn read_bytes(reader: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_string(reader: &mut impl Read, len: usize) -> io::Result<String> {
+    String::from_utf8(read_bytes(reader, len)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// The next record and its encoded length, or None at a clean end of input
+fn read_record(reader: &mut impl Read) -> io::Result<Option<(Record, u64)>> {
+    let mut tag = [0u8; 1];
+    if reader.read(&mut tag)? == 0 {
+        return Ok(None);
+    }
+    let mut len32 = [0u8; 4];
+    reader.read_exact(&mut len32)?;
+    let key = read_string(reader, u32::from_le_bytes(len32) as usize)?;
+    match tag[0] {
+        PUT => {
+            let mut len8 = [0u8; 1];
+            reader.read_exact(&mut len8)?;
+            let etag = read_string(reader, len8[0] as usize)?;
+            let mut len64 = [0u8; 8];
+            reader.read_exact(&mut len64)?;
+            let data = read_bytes(reader, u64::from_le_bytes(len64) as usize)?;
+            let len = 14 + key.len() + etag.len() + data.len();
+            Ok(Some((Record::Put { key, etag, data }, len as u64)))
+        }
+        DELETE => {
+            let len = 5 + key.len();
+            Ok(Some((Record::Delete { key }, len as u64)))
+        }
+        TAGS => {
+            reader.read_exact(&mut len32)?;
+            let json = read_bytes(reader, u32::from_le_bytes(len32) as usize)?;
+            let tags = serde_json::from_slice(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let len = 9 + key.len() + json.len();
+            Ok(Some((Record::Tags { key, tags }, len as u64)))
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "unknown record type")),
+    }
+}
+
+// Applies every record in `reader` to `map` and returns the length of the
+// input that was valid. A record cut short by a crash mid-write is dropped
+// when `torn_tail_ok` is set; otherwise it makes the input corrupt.
+fn replay(reader: &mut impl Read, map: &mut ObjectMap, torn_tail_ok: bool) -> Result<u64> {
+    let corrupt = |what: &str| ObjectStoreError::Other(format!("corrupt InMemoryStore snapshot: {what}"));
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic).map_err(|_| corrupt("missing header"))?;
+    if &magic != MAGIC {
+        return Err(corrupt("bad header"));
+    }
+
+    let mut valid = MAGIC.len() as u64;
+    loop {
+        match read_record(reader) {
+            Ok(None) => return Ok(valid),
+            Ok(Some((record, len))) => {
+                match record {
+                    Record::Put { key, etag, data } => {
+                        map.insert(key, (data.into(), etag, Tags::new()));
+                    }
+                    Record::Delete { key } => {
+                        map.remove(&key);
+                    }
+                    Record::Tags { key, tags } => {
+                        if let Some(entry) = map.get_mut(&key) {
+                            entry.2 = tags;
+                        }
+                    }
+                }
+                valid += len;
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && torn_tail_ok => return Ok(valid),
+            Err(e) if matches!(e.kind(), io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData) => {
+                return Err(corrupt(&e.to_string()));
+            }
+            Err(e) => return Err(ObjectStoreError::Io(e)),
+        }
+    }
+}
+
+/// Which object a bounded `InMemoryStore` evicts first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Eviction {
+    /// The one read or written longest ago.
+    #[default]
+    Lru,
+    /// The one written longest ago.
+    Fifo,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EvictionStats {
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+// The limits of a bounded store, and what it needs to enforce them
+#[derive(Default, Clone)]
+struct Bounds {
+    max_bytes: Option<u64>,
+    max_objects: Option<usize>,
+    policy: Eviction,
+    // Keys by when they were last used, oldest first
+    order: BTreeMap<u64, String>,
+    // When each key was last used
+    used: HashMap<String, u64>,
+    next_use: u64,
+    bytes: u64,
+    evicted: EvictionStats,
+}
+
+impl Bounds {
+    // Starts tracking `objects` afresh, as if written in key order
+    fn track(&mut self, objects: &ObjectMap) {
+        self.order.clear();
+        self.used.clear();
+        self.bytes = 0;
+        for (key, (data, _, _)) in objects {
+            self.put(key, None, data.len() as u64);
+        }
+    }
+
+    fn touch(&mut self, key: &str) {
+        if let Some(used) = self.used.get_mut(key) {
+            self.order.remove(used);
+            *used = self.next_use;
+            self.order.insert(self.next_use, key.to_string());
+            self.next_use += 1;
+        }
+    }
+
+    fn put(&mut self, key: &str, old_size: Option<u64>, size: u64) {
+        self.bytes = self.bytes - old_size.unwrap_or(0) + size;
+        if let Some(used) = self.used.insert(key.to_string(), self.next_use) {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.next_use, key.to_string());
+        self.next_use += 1;
+    }
+
+    fn remove(&mut self, key: &str, size: u64) {
+        if let Some(used) = self.used.remove(key) {
+            self.order.remove(&used);
+            self.bytes -= size;
+        }
+    }
+
+    fn over(&self) -> bool {
+        self.max_bytes.is_some_and(|max| self.bytes > max) || self.max_objects.is_some_and(|max| self.used.len() > max)
+    }
+}
+
+/// Objects in memory, behind a read-write lock: reads run concurrently, and
+/// each write, conditional puts included, takes the store exclusively.
 pub struct InMemoryStore {
-    // Key: object key, Value: (data, etag)
-    map: Arc<Mutex<HashMap<String, (Vec<u8>, String)>>>,
+    map: Arc<RwLock<ObjectMap>>,
+    // Append-only log of writes, see `with_journal`
+    journal: Option<Mutex<File>>,
+    // Locked after `map`, and only while it is held. Reads under LRU
+    // share it, so they aren't wholly concurrent.
+    bounds: Option<Mutex<Bounds>>,
 }
 
 impl Default for InMemoryStore {
     fn default() -> Self {
         InMemoryStore {
-            map: Arc::new(Mutex::new(HashMap::new())),
+            map: Arc::new(RwLock::new(BTreeMap::new())),
+            journal: None,
+            bounds: None,
         }
     }
 }
@@ -19,27 +243,199 @@ impl InMemoryStore {
     fn compute_etag(data: &[u8]) -> String {
         format!("{:x}", md5::compute(data))
     }
+
+    /// Writes every object, with its ETag, to a snapshot file at `path`.
+    /// The file is replaced atomically, so a crash leaves the old snapshot.
+    pub fn save_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut tmp_name = path.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+
+        let map = self.map.read().unwrap();
+        let mut out = BufWriter::new(File::create(&tmp_name).map_err(ObjectStoreError::Io)?);
+        out.write_all(MAGIC).map_err(ObjectStoreError::Io)?;
+        for (key, (data, etag, tags)) in map.iter() {
+            out.write_all(&encode_put(key, etag, data)).map_err(ObjectStoreError::Io)?;
+            if !tags.is_empty() {
+                out.write_all(&encode_tags(key, tags)).map_err(ObjectStoreError::Io)?;
+            }
+        }
+        let file = out.into_inner().map_err(|e| ObjectStoreError::Io(e.into_error()))?;
+        file.sync_all().map_err(ObjectStoreError::Io)?;
+        fs::rename(&tmp_name, path).map_err(ObjectStoreError::Io)
+    }
+
+    /// A store holding the objects saved by `save_to`.
+    pub fn load_from(path: impl AsRef<Path>) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path).map_err(ObjectStoreError::Io)?);
+        let mut map = BTreeMap::new();
+        replay(&mut reader, &mut map, false)?;
+        Ok(InMemoryStore {
+            map: Arc::new(RwLock::new(map)),
+            journal: None,
+            bounds: None,
+        })
+    }
+
+    // The bounds, set up for the objects already stored if there were none
+    fn bounds_mut(&mut self) -> &mut Bounds {
+        let map = &self.map;
+        self.bounds
+            .get_or_insert_with(|| {
+                let mut bounds = Bounds::default();
+                bounds.track(&map.read().unwrap());
+                Mutex::new(bounds)
+            })
+            .get_mut()
+            .unwrap()
+    }
+
+    /// Evicts objects once their bodies total more than `max` bytes. A put
+    /// of a larger object fails with `ObjectStoreError::QuotaExceeded`.
+    /// Limits are enforced on each put, so setting them doesn't evict
+    /// anything by itself.
+    pub fn with_max_bytes(mut self, max: u64) -> Self {
+        self.bounds_mut().max_bytes = Some(max);
+        self
+    }
+
+    /// Evicts objects once there are more than `max` of them.
+    pub fn with_max_objects(mut self, max: usize) -> Self {
+        self.bounds_mut().max_objects = Some(max.max(1));
+        self
+    }
+
+    /// Picks what the limits evict first; least recently used by default.
+    pub fn with_eviction(mut self, policy: Eviction) -> Self {
+        self.bounds_mut().policy = policy;
+        self
+    }
+
+    /// An independent copy of the store as it is now, for branching a
+    /// shared fixture: writes to either don't show in the other. Bodies
+    /// are shared rather than copied, so forking costs a clone of the keys.
+    /// The fork keeps the limits but not the journal, and counts its own
+    /// evictions.
+    pub fn fork(&self) -> Self {
+        let map = self.map.read().unwrap();
+        let bounds = self.bounds.as_ref().map(|bounds| {
+            let mut bounds = bounds.lock().unwrap().clone();
+            bounds.evicted = EvictionStats::default();
+            Mutex::new(bounds)
+        });
+        InMemoryStore {
+            map: Arc::new(RwLock::new(map.clone())),
+            journal: None,
+            bounds,
+        }
+    }
+
+    /// Objects evicted to stay within the limits so far, and their bytes.
+    pub fn evicted(&self) -> EvictionStats {
+        self.bounds.as_ref().map(|bounds| bounds.lock().unwrap().evicted).unwrap_or_default()
+    }
+
+    // Counts a read of `key` under LRU
+    fn touch(&self, key: &str) {
+        if let Some(bounds) = &self.bounds {
+            let mut bounds = bounds.lock().unwrap();
+            if bounds.policy == Eviction::Lru {
+                bounds.touch(key);
+            }
+        }
+    }
+
+    /// Appends every later put and delete to a journal file at `path`,
+    /// after first replaying the writes an existing journal holds. Records
+    /// reach the OS before the write returns, so they survive the process
+    /// crashing; a record torn by the crash is dropped on the next open.
+    /// To carry state across runs without the journal growing forever,
+    /// `save_to` a snapshot now and then, start a fresh journal, and open
+    /// with `load_from(snapshot)?.with_journal(journal)`.
+    pub fn with_journal(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(ObjectStoreError::Io)?;
+        if file.metadata().map_err(ObjectStoreError::Io)?.len() == 0 {
+            file.write_all(MAGIC).map_err(ObjectStoreError::Io)?;
+        } else {
+            let mut map = self.map.write().unwrap();
+            let valid = replay(&mut BufReader::new(&file), &mut map, true)?;
+            file.set_len(valid).map_err(ObjectStoreError::Io)?;
+            if let Some(bounds) = &self.bounds {
+                bounds.lock().unwrap().track(&map);
+            }
+        }
+        file.seek(SeekFrom::End(0)).map_err(ObjectStoreError::Io)?;
+        self.journal = Some(Mutex::new(file));
+        Ok(self)
+    }
+
+    // Called with the map write-locked, so journal order matches the order writes took effect
+    fn log(&self, record: &[u8]) -> Result<()> {
+        match &self.journal {
+            Some(journal) => journal.lock().unwrap().write_all(record).map_err(ObjectStoreError::Io),
+            None => Ok(()),
+        }
+    }
+
+    fn insert(&self, map: &mut ObjectMap, key: &str, body: &[u8], etag: &str) -> Result<()> {
+        let Some(bounds) = &self.bounds else {
+            self.log(&encode_put(key, etag, body))?;
+            map.insert(key.to_string(), (body.into(), etag.to_string(), Tags::new()));
+            return Ok(());
+        };
+        let mut bounds = bounds.lock().unwrap();
+        let size = body.len() as u64;
+        if let Some(max) = bounds.max_bytes
+            && size > max
+        {
+            return Err(ObjectStoreError::QuotaExceeded(format!("{size} of {max} bytes")));
+        }
+        self.log(&encode_put(key, etag, body))?;
+        let old = map.insert(key.to_string(), (body.into(), etag.to_string(), Tags::new()));
+        bounds.put(key, old.map(|(data, _, _)| data.len() as u64), size);
+        // The new object is the last to go, and fits alone
+        while bounds.over()
+            && let Some((_, victim)) = bounds.order.pop_first()
+        {
+            // Journaled, so a replay evicts the same objects
+            self.log(&encode_delete(&victim))?;
+            let size = map.remove(&victim).map(|(data, _, _)| data.len() as u64).unwrap_or(0);
+            bounds.used.remove(&victim);
+            bounds.bytes -= size;
+            bounds.evicted.objects += 1;
+            bounds.evicted.bytes += size;
+        }
+        Ok(())
+    }
 }
 
 impl ObjectStore for InMemoryStore {
     fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
-        let map = self.map.lock().unwrap();
-        Ok(map.get(key).map(|(data, _)| data.clone()))
+        let map = self.map.read().unwrap();
+        self.touch(key);
+        Ok(map.get(key).map(|(data, _, _)| data.to_vec()))
     }
 
     fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
-        let mut map = self.map.lock().unwrap();
+        // Hashed before locking, to hold the lock no longer than needed
         let new_etag = Self::compute_etag(body);
+        let mut map = self.map.write().unwrap();
 
         match cond {
             IfMatch::Any => {
-                map.insert(key.to_string(), (body.to_vec(), new_etag.clone()));
+                self.insert(&mut map, key, body, &new_etag)?;
                 Ok(new_etag)
             }
             IfMatch::Tag(expected_etag) => {
-                if let Some((_, etag)) = map.get(key) {
+                if let Some((_, etag, _)) = map.get(key) {
                     if etag == expected_etag {
-                        map.insert(key.to_string(), (body.to_vec(), new_etag.clone()));
+                        self.insert(&mut map, key, body, &new_etag)?;
                         Ok(new_etag)
                     } else {
                         Err(ObjectStoreError::PreconditionFailed)
@@ -52,7 +448,7 @@ impl ObjectStore for InMemoryStore {
                 if map.contains_key(key) {
                     Err(ObjectStoreError::PreconditionFailed)
                 } else {
-                    map.insert(key.to_string(), (body.to_vec(), new_etag.clone()));
+                    self.insert(&mut map, key, body, &new_etag)?;
                     Ok(new_etag)
                 }
             }
@@ -60,28 +456,85 @@ impl ObjectStore for InMemoryStore {
     }
 
     fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
-        let map = self.map.lock().unwrap();
-        let mut keys: Vec<String> = map
-            .keys()
-            .filter(|k| k.starts_with(prefix))
-            .cloned()
-            .collect();
-        keys.sort();
+        let map = self.map.read().unwrap();
+        // Resumes just after the token, or at the first key the prefix
+        // could start
+        let start = match continuation {
+            Some(token) if token.as_str() >= prefix => Bound::Excluded(token),
+            _ => Bound::Included(prefix.to_string()),
+        };
 
         // Simple pagination: 1000 per page
         let page_size = 1000;
-        let start = continuation
-            .and_then(|token| keys.iter().position(|k| k > &token))
-            .unwrap_or(0);
-        let end = (start + page_size).min(keys.len());
+        let mut keys: Vec<String> = map
+            .range((start, Bound::Unbounded))
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .take(page_size + 1)
+            .cloned()
+            .collect();
 
-        let next_token = if end < keys.len() {
-            Some(keys[end - 1].clone())
+        let next_token = if keys.len() > page_size {
+            keys.truncate(page_size);
+            keys.last().cloned()
         } else {
             None
         };
 
-        Ok((keys[start..end].to_vec(), next_token))
+        Ok((keys, next_token))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let mut map = self.map.write().unwrap();
+        if map.contains_key(key) {
+            self.log(&encode_delete(key))?;
+            if let Some((data, _, _)) = map.remove(key)
+                && let Some(bounds) = &self.bounds
+            {
+                bounds.lock().unwrap().remove(key, data.len() as u64);
+            }
+        }
+        Ok(())
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let map = self.map.read().unwrap();
+        Ok(map.get(key).map(|(data, etag, _)| ObjectMeta {
+            size: data.len() as u64,
+            etag: etag.clone(),
+        }))
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        let map = self.map.read().unwrap();
+        self.touch(key);
+        Ok(map.get(key).map(|(data, _, _)| slice_range(data, range).to_vec()))
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        let map = self.map.read().unwrap();
+        self.touch(key);
+        match map.get(key) {
+            Some((data, etag, _)) => get_from_body(data, etag, &opts).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Kept with the object, so a put replaces them and a delete or an
+    // eviction drops them; journaled and saved like puts
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        let mut map = self.map.write().unwrap();
+        let Some(entry) = map.get_mut(key) else {
+            return Err(ObjectStoreError::NotFound(key.to_string()));
+        };
+        self.log(&encode_tags(key, tags))?;
+        entry.2 = tags.clone();
+        Ok(())
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        let map = self.map.read().unwrap();
+        Ok(map.get(key).map(|(_, _, tags)| tags.clone()))
     }
 }
 
@@ -89,7 +542,7 @@ impl ObjectStore for InMemoryStore {
 mod tests {
     use super::*;
     use crate::object_store::{IfMatch, ObjectStore};
-    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use crate::object_store::test_helpers::tests::{run_object_store_tests, run_oracle_tests};
     use uuid::Uuid;
 
     #[test]
@@ -162,10 +615,215 @@ mod tests {
         assert!(keys.contains(&"folder/c.txt".to_string()));
     }
 
+    #[test]
+    fn test_list_pages_by_range() {
+        let store = InMemoryStore::default();
+        for i in 0..2500 {
+            store.put(&format!("logs/{i:04}"), b"", IfMatch::Any).unwrap();
+        }
+        store.put("logs", b"", IfMatch::Any).unwrap();
+        store.put("logt/0", b"", IfMatch::Any).unwrap();
+
+        let (first, token) = store.list("logs/", None).unwrap();
+        assert_eq!((first.len(), first[0].as_str()), (1000, "logs/0000"));
+        assert_eq!(token.as_deref(), Some("logs/0999"));
+        let (second, token) = store.list("logs/", token).unwrap();
+        assert_eq!(second[0], "logs/1000");
+        let (third, token) = store.list("logs/", token).unwrap();
+        assert_eq!((third.len(), token), (500, None));
+        // A token from before the prefix starts at its first key
+        assert_eq!(store.list("logt/", Some("a".into())).unwrap().0, vec!["logt/0"]);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.snap");
+        let store = InMemoryStore::default();
+        let etag = store.put("a.txt", b"alpha", IfMatch::Any).unwrap();
+        store.put("bin", &[0, 255, 7], IfMatch::Any).unwrap();
+        store.put("empty", b"", IfMatch::Any).unwrap();
+        let tags = BTreeMap::from([("class".to_string(), "cold".to_string())]);
+        store.put_tags("bin", &tags).unwrap();
+        store.save_to(&path).unwrap();
+
+        let loaded = InMemoryStore::load_from(&path).unwrap();
+        assert_eq!(loaded.get("a.txt").unwrap(), Some(b"alpha".to_vec()));
+        assert_eq!(loaded.get("bin").unwrap(), Some(vec![0, 255, 7]));
+        assert_eq!(loaded.get("empty").unwrap(), Some(Vec::new()));
+        assert_eq!(loaded.head("a.txt").unwrap().unwrap().etag, etag);
+        assert_eq!(loaded.get_tags("bin").unwrap(), Some(tags));
+        assert_eq!(loaded.get_tags("a.txt").unwrap(), Some(BTreeMap::new()));
+        loaded.put("a.txt", b"beta", IfMatch::Tag(&etag)).unwrap();
+
+        std::fs::write(&path, b"not a snapshot").unwrap();
+        assert!(matches!(InMemoryStore::load_from(&path), Err(ObjectStoreError::Other(_))));
+    }
+
+    #[test]
+    fn test_journal_replays_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.journal");
+        {
+            let store = InMemoryStore::default().with_journal(&path).unwrap();
+            store.put("keep", b"1", IfMatch::Any).unwrap();
+            store.put("keep", b"2", IfMatch::Any).unwrap();
+            store.put_tags("keep", &BTreeMap::from([("v".to_string(), "2".to_string())])).unwrap();
+            store.put("gone", b"x", IfMatch::Any).unwrap();
+            store.delete("gone").unwrap();
+            store.put("keep", b"3", IfMatch::NoneMatch).unwrap_err();
+        }
+
+        let store = InMemoryStore::default().with_journal(&path).unwrap();
+        assert_eq!(store.get("keep").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(store.get_tags("keep").unwrap().unwrap()["v"], "2");
+        assert_eq!(store.get("gone").unwrap(), None);
+        store.put("more", b"m", IfMatch::Any).unwrap();
+        drop(store);
+
+        let store = InMemoryStore::default().with_journal(&path).unwrap();
+        assert_eq!(store.list("", None).unwrap().0, vec!["keep".to_string(), "more".to_string()]);
+    }
+
+    #[test]
+    fn test_journal_drops_torn_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.journal");
+        let store = InMemoryStore::default().with_journal(&path).unwrap();
+        store.put("whole", b"ok", IfMatch::Any).unwrap();
+        drop(store);
+
+        // Simulate a crash halfway through appending a record
+        let mut file = File::options().append(true).open(&path).unwrap();
+        file.write_all(&encode_put("torn", "etag", b"data")[..10]).unwrap();
+        drop(file);
+
+        let store = InMemoryStore::default().with_journal(&path).unwrap();
+        assert_eq!(store.get("torn").unwrap(), None);
+        store.put("after", b"fine", IfMatch::Any).unwrap();
+        drop(store);
+
+        let store = InMemoryStore::default().with_journal(&path).unwrap();
+        assert_eq!(store.get("whole").unwrap(), Some(b"ok".to_vec()));
+        assert_eq!(store.get("after").unwrap(), Some(b"fine".to_vec()));
+    }
+
+    #[test]
+    fn test_lru_and_fifo_eviction() {
+        for policy in [Eviction::Lru, Eviction::Fifo] {
+            let store = InMemoryStore::default().with_max_objects(2).with_eviction(policy);
+            store.put("a", b"1", IfMatch::Any).unwrap();
+            store.put("b", b"2", IfMatch::Any).unwrap();
+            store.get("a").unwrap();
+            store.put("c", b"3", IfMatch::Any).unwrap();
+            // Reading `a` saved it from LRU eviction only
+            let expected = match policy {
+                Eviction::Lru => vec!["a", "c"],
+                Eviction::Fifo => vec!["b", "c"],
+            };
+            assert_eq!(store.list("", None).unwrap().0, expected);
+            assert_eq!(store.evicted(), EvictionStats { objects: 1, bytes: 1 });
+        }
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let store = InMemoryStore::default().with_max_bytes(10);
+        store.put("a", &[0; 4], IfMatch::Any).unwrap();
+        store.put("b", &[0; 4], IfMatch::Any).unwrap();
+        // Overwriting counts only the new size
+        store.put("b", &[0; 5], IfMatch::Any).unwrap();
+        assert_eq!(store.evicted().objects, 0);
+        store.put("c", &[0; 3], IfMatch::Any).unwrap();
+        assert_eq!(store.list("", None).unwrap().0, vec!["b", "c"]);
+        assert_eq!(store.evicted(), EvictionStats { objects: 1, bytes: 4 });
+
+        assert!(matches!(store.put("huge", &[0; 11], IfMatch::Any), Err(ObjectStoreError::QuotaExceeded(_))));
+        assert!(store.get("b").unwrap().is_some());
+        store.delete("b").unwrap();
+        store.put("d", &[0; 7], IfMatch::Any).unwrap();
+        assert_eq!(store.evicted().objects, 1);
+        assert_eq!(InMemoryStore::default().evicted(), EvictionStats::default());
+    }
+
+    #[test]
+    fn test_journal_replays_evictions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.journal");
+        {
+            let store = InMemoryStore::default().with_max_objects(2).with_journal(&path).unwrap();
+            for key in ["a", "b", "c", "d"] {
+                store.put(key, b"x", IfMatch::Any).unwrap();
+            }
+        }
+        // Replayed without bounds, the evictions still happen
+        let store = InMemoryStore::default().with_journal(&path).unwrap();
+        assert_eq!(store.list("", None).unwrap().0, vec!["c", "d"]);
+        let store = store.with_max_objects(1);
+        store.put("e", b"x", IfMatch::Any).unwrap();
+        assert_eq!(store.list("", None).unwrap().0, vec!["e"]);
+    }
+
+    #[test]
+    fn test_conditional_puts_stay_atomic_across_threads() {
+        let store = InMemoryStore::default();
+        store.put("counter", b"0", IfMatch::Any).unwrap();
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        loop {
+                            let etag = store.head("counter").unwrap().unwrap().etag;
+                            let value: u32 = String::from_utf8(store.get("counter").unwrap().unwrap()).unwrap().parse().unwrap();
+                            let next = (value + 1).to_string();
+                            match store.put("counter", next.as_bytes(), IfMatch::Tag(&etag)) {
+                                Ok(_) => break,
+                                Err(ObjectStoreError::PreconditionFailed) => continue,
+                                Err(e) => panic!("{e:?}"),
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(store.get("counter").unwrap(), Some(b"800".to_vec()));
+    }
+
+    #[test]
+    fn test_fork_is_independent() {
+        let fixture = InMemoryStore::default().with_max_objects(3);
+        fixture.put("shared", &[7; 1024], IfMatch::Any).unwrap();
+        fixture.put("edited", b"base", IfMatch::Any).unwrap();
+
+        let fork = fixture.fork();
+        // The body is shared, not copied
+        {
+            let (ours, theirs) = (fixture.map.read().unwrap(), fork.map.read().unwrap());
+            assert!(Arc::ptr_eq(&ours["shared"].0, &theirs["shared"].0));
+        }
+        let etag = fork.head("edited").unwrap().unwrap().etag;
+        fork.put("edited", b"forked", IfMatch::Tag(&etag)).unwrap();
+        fork.delete("shared").unwrap();
+        fork.put("new", b"x", IfMatch::NoneMatch).unwrap();
+        fixture.put("later", b"y", IfMatch::Any).unwrap();
+
+        assert_eq!(fixture.get("edited").unwrap(), Some(b"base".to_vec()));
+        assert_eq!(fixture.get("shared").unwrap(), Some(vec![7; 1024]));
+        assert_eq!(fixture.list("", None).unwrap().0, vec!["edited", "later", "shared"]);
+        assert_eq!(fork.list("", None).unwrap().0, vec!["edited", "new"]);
+
+        // Limits carry over to the fork
+        fork.put("a", b"", IfMatch::Any).unwrap();
+        fork.put("b", b"", IfMatch::Any).unwrap();
+        assert_eq!(fork.evicted().objects, 1);
+        assert_eq!(fixture.evicted().objects, 0);
+    }
+
     #[test]
     fn test_in_memory_object_store() {
         let store = InMemoryStore::default();
         let prefix = format!("test/{}/", Uuid::new_v4());
         run_object_store_tests(&store, &prefix);
+        run_oracle_tests(&store, &format!("oracle/{}/", Uuid::new_v4()));
     }
 }
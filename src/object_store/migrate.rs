@@ -0,0 +1,380 @@
+use super::cost::{estimate, list_pages, CostEstimate, Pricing, RequestCounts};
+use super::sync::{for_each_concurrent, list_all};
+use super::{GetOptions, GetResult, IfMatch, ObjectStore, ObjectStoreError, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+// Migration state lives in the destination under this prefix
+pub const STATE_PREFIX: &str = ".migrations/";
+
+#[derive(Debug, Clone)]
+pub struct MigrateOptions {
+    // Only keys under this prefix are migrated
+    pub prefix: String,
+    // Number of objects copied at once
+    pub concurrency: usize,
+    // Objects copied between state saves
+    pub checkpoint_every: usize,
+}
+
+impl Default for MigrateOptions {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            concurrency: 8,
+            checkpoint_every: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationError {
+    pub key: String,
+    pub message: String,
+}
+
+/// Progress of a migration, as persisted in the destination store.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationState {
+    // Source listing token for the page being worked through (None = first page)
+    pub continuation: Option<String>,
+    // Last key of that page already handled
+    pub last_key: Option<String>,
+    pub copied: u64,
+    pub errors: Vec<MigrationError>,
+    pub done: bool,
+}
+
+/// Size of a migration and the requests it would make, from `Migration::plan`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationPlan {
+    pub objects: u64,
+    pub bytes: u64,
+    pub src_requests: RequestCounts,
+    pub dst_requests: RequestCounts,
+}
+
+impl MigrationPlan {
+    pub fn estimate(&self, src_pricing: &Pricing, dst_pricing: &Pricing) -> CostEstimate {
+        estimate(self.src_requests, src_pricing, self.dst_requests, dst_pricing, self.bytes)
+    }
+}
+
+/// A long-running copy of one store into another that survives restarts.
+///
+/// The job pages through the source listing and copies objects in batches
+/// of `checkpoint_every`. After each batch its state (listing token, last
+/// key handled, counters and failed keys) is written to
+/// `.migrations/<id>.json` in the destination, so calling `run` again after
+/// an interruption continues from the last saved batch. State writes are
+/// conditional on the previous state's ETag; if another process runs the
+/// same job concurrently, one of them fails with `PreconditionFailed`.
+pub struct Migration<'a> {
+    src: &'a dyn ObjectStore,
+    dst: &'a dyn ObjectStore,
+    state_key: String,
+    options: MigrateOptions,
+}
+
+impl<'a> Migration<'a> {
+    pub fn new(src: &'a dyn ObjectStore, dst: &'a dyn ObjectStore, id: &str, options: MigrateOptions) -> Self {
+        Self {
+            src,
+            dst,
+            state_key: format!("{STATE_PREFIX}{id}.json"),
+            options,
+        }
+    }
+
+    // Saved progress, or a fresh state if the job has never run
+    pub fn state(&self) -> Result<MigrationState> {
+        Ok(self.load()?.map(|(state, _)| state).unwrap_or_default())
+    }
+
+    // Forgets saved progress so the next run starts over
+    pub fn reset(&self) -> Result<()> {
+        self.dst.delete(&self.state_key)
+    }
+
+    // Dry run over the whole source. Heads every object to total the bytes,
+    // which on a large bucket is itself a sizeable number of requests.
+    pub fn plan(&self) -> Result<MigrationPlan> {
+        let keys: Vec<String> = list_all(self.src, &self.options.prefix)?
+            .into_iter()
+            .filter(|key| !key.starts_with(STATE_PREFIX))
+            .collect();
+        let bytes = Mutex::new(0u64);
+        let first_err = Mutex::new(None);
+        for_each_concurrent(&keys, self.options.concurrency, |key| match self.src.head(key) {
+            Ok(meta) => *bytes.lock().unwrap() += meta.map_or(0, |m| m.size),
+            Err(e) => {
+                first_err.lock().unwrap().get_or_insert(e);
+            }
+        });
+        if let Some(e) = first_err.into_inner().unwrap() {
+            return Err(e);
+        }
+        let bytes = bytes.into_inner().unwrap();
+
+        let objects = keys.len() as u64;
+        let batches = objects.div_ceil(self.options.checkpoint_every.max(1) as u64);
+        let pages = list_pages(objects);
+        Ok(MigrationPlan {
+            objects,
+            bytes,
+            src_requests: RequestCounts {
+                list: pages,
+                get: objects,
+                ..Default::default()
+            },
+            // Plus one state save per batch and per listing page
+            dst_requests: RequestCounts {
+                put: objects + batches + pages,
+                get: 1,
+                ..Default::default()
+            },
+        })
+    }
+
+    pub fn run(&self) -> Result<MigrationState> {
+        let (mut state, mut etag) = match self.load()? {
+            Some((state, etag)) => (state, Some(etag)),
+            None => (MigrationState::default(), None),
+        };
+
+        while !state.done {
+            let (keys, next) = self.src.list(&self.options.prefix, state.continuation.clone())?;
+            let pending: Vec<String> = keys
+                .into_iter()
+                .filter(|key| state.last_key.as_ref().is_none_or(|last| key > last))
+                .filter(|key| !key.starts_with(STATE_PREFIX))
+                .collect();
+
+            for batch in pending.chunks(self.options.checkpoint_every.max(1)) {
+                let errors = Mutex::new(Vec::new());
+                for_each_concurrent(batch, self.options.concurrency, |key| {
+                    if let Err(e) = self.copy(key) {
+                        errors.lock().unwrap().push(MigrationError {
+                            key: key.clone(),
+                            message: format!("{e:?}"),
+                        });
+                    }
+                });
+
+                let mut errors = errors.into_inner().unwrap();
+                errors.sort_by(|a, b| a.key.cmp(&b.key));
+                state.copied += (batch.len() - errors.len()) as u64;
+                state.errors.extend(errors);
+                state.last_key = batch.last().cloned();
+                etag = Some(self.save(&state, etag.as_deref())?);
+            }
+
+            match next {
+                Some(token) => {
+                    state.continuation = Some(token);
+                    state.last_key = None;
+                }
+                None => state.done = true,
+            }
+            etag = Some(self.save(&state, etag.as_deref())?);
+        }
+
+        Ok(state)
+    }
+
+    fn copy(&self, key: &str) -> Result<()> {
+        // Deleted from the source since it was listed; nothing to copy
+        if let Some(data) = self.src.get(key)? {
+            self.dst.put(key, &data, IfMatch::Any)?;
+        }
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Option<(MigrationState, String)>> {
+        let opts = GetOptions {
+            include_metadata: true,
+            ..Default::default()
+        };
+        let Some(GetResult::Body { data, meta }) = self.dst.get_opts(&self.state_key, opts)? else {
+            return Ok(None);
+        };
+        let etag = meta
+            .ok_or_else(|| ObjectStoreError::Other("destination returned no metadata".to_string()))?
+            .etag;
+        let state = serde_json::from_slice(&data)
+            .map_err(|e| ObjectStoreError::Other(format!("corrupt migration state {}: {e}", self.state_key)))?;
+        Ok(Some((state, etag)))
+    }
+
+    fn save(&self, state: &MigrationState, etag: Option<&str>) -> Result<String> {
+        let data = serde_json::to_vec(state).expect("migration state serializes");
+        let cond = match etag {
+            Some(etag) => IfMatch::Tag(etag),
+            None => IfMatch::NoneMatch,
+        };
+        self.dst.put(&self.state_key, &data, cond)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::chunked::ChunkedStore;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::scan::{ScanVerdict, ScanningStore};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn populate(store: &dyn ObjectStore, count: usize) {
+        for i in 0..count {
+            let key = format!("obj/{i:04}");
+            store.put(&key, key.as_bytes(), IfMatch::Any).unwrap();
+        }
+    }
+
+    fn object_count(store: &dyn ObjectStore) -> usize {
+        crate::object_store::sync::list_all(store, "obj/").unwrap().len()
+    }
+
+    #[test]
+    fn test_migrates_everything() {
+        let src = InMemoryStore::default();
+        let dst = InMemoryStore::default();
+        populate(&src, 2500);
+
+        let migration = Migration::new(&src, &dst, "full", MigrateOptions::default());
+        let state = migration.run().unwrap();
+        assert!(state.done);
+        assert_eq!(state.copied, 2500);
+        assert!(state.errors.is_empty());
+        assert_eq!(object_count(&dst), 2500);
+        assert_eq!(dst.get("obj/2499").unwrap(), Some(b"obj/2499".to_vec()));
+        assert_eq!(migration.state().unwrap(), state);
+
+        // Finished jobs do nothing on rerun
+        let again = migration.run().unwrap();
+        assert_eq!(again.copied, 2500);
+    }
+
+    // Panics on the nth get, simulating a process dying mid-migration
+    struct CrashingStore {
+        inner: InMemoryStore,
+        gets_left: AtomicUsize,
+    }
+
+    impl ObjectStore for CrashingStore {
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            if self.gets_left.fetch_sub(1, Ordering::SeqCst) == 0 {
+                panic!("simulated crash");
+            }
+            self.inner.get(key)
+        }
+        fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+            self.inner.put(key, body, cond)
+        }
+        fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+            self.inner.list(prefix, continuation)
+        }
+        fn delete(&self, key: &str) -> Result<()> {
+            self.inner.delete(key)
+        }
+    }
+
+    #[test]
+    fn test_resumes_after_interruption() {
+        let inner = InMemoryStore::default();
+        populate(&inner, 1500);
+        let src = CrashingStore {
+            inner,
+            gets_left: AtomicUsize::new(1234),
+        };
+        let dst = InMemoryStore::default();
+        let options = MigrateOptions {
+            concurrency: 1,
+            ..Default::default()
+        };
+
+        let migration = Migration::new(&src, &dst, "resumable", options);
+        let crashed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| migration.run()));
+        assert!(crashed.is_err());
+
+        let saved = migration.state().unwrap();
+        assert!(!saved.done);
+        assert_eq!(saved.copied, 1200);
+        assert_eq!(saved.continuation.as_deref(), Some("obj/0999"));
+        assert_eq!(saved.last_key.as_deref(), Some("obj/1199"));
+
+        src.gets_left.store(usize::MAX, Ordering::SeqCst);
+        let state = migration.run().unwrap();
+        assert!(state.done);
+        assert_eq!(state.copied, 1500);
+        // Only the unfinished batch was fetched again
+        assert_eq!(usize::MAX - src.gets_left.load(Ordering::SeqCst), 300);
+    }
+
+    #[test]
+    fn test_resumes_on_a_store_with_other_etags() {
+        let src = InMemoryStore::default();
+        populate(&src, 10);
+        // A chunked state record's ETag is its manifest's, not the JSON's MD5
+        let dst = ChunkedStore::new(InMemoryStore::default(), 16);
+        let migration = Migration::new(&src, &dst, "chunked", MigrateOptions::default());
+        migration.save(&MigrationState::default(), None).unwrap();
+
+        let state = migration.run().unwrap();
+        assert!(state.done);
+        assert_eq!(state.copied, 10);
+    }
+
+    #[test]
+    fn test_records_failed_keys() {
+        let src = InMemoryStore::default();
+        populate(&src, 10);
+        let scanner = |key: &str, _body: &[u8]| {
+            if key == "obj/0003" {
+                Ok(ScanVerdict::Infected("nope".to_string()))
+            } else {
+                Ok(ScanVerdict::Clean)
+            }
+        };
+        let dst = ScanningStore::new(InMemoryStore::default(), scanner);
+
+        let state = Migration::new(&src, &dst, "errors", MigrateOptions::default()).run().unwrap();
+        assert_eq!(state.copied, 9);
+        assert_eq!(state.errors.len(), 1);
+        assert_eq!(state.errors[0].key, "obj/0003");
+    }
+
+    #[test]
+    fn test_concurrent_runner_detected() {
+        let src = InMemoryStore::default();
+        let dst = InMemoryStore::default();
+        let migration = Migration::new(&src, &dst, "shared", MigrateOptions::default());
+
+        // Two runners both start from "no saved state"; only the first save wins
+        migration.save(&MigrationState::default(), None).unwrap();
+        let result = migration.save(&MigrationState::default(), None);
+        assert!(matches!(result, Err(ObjectStoreError::PreconditionFailed)));
+
+        migration.reset().unwrap();
+        assert_eq!(migration.state().unwrap(), MigrationState::default());
+    }
+
+    #[test]
+    fn test_plan() {
+        let src = InMemoryStore::default();
+        let dst = InMemoryStore::default();
+        populate(&src, 1500);
+        let migration = Migration::new(&src, &dst, "planned", MigrateOptions::default());
+
+        let plan = migration.plan().unwrap();
+        assert_eq!(plan.objects, 1500);
+        assert_eq!(plan.bytes, 1500 * 8);
+        assert_eq!(plan.src_requests.list, 2);
+        assert_eq!(plan.src_requests.get, 1500);
+        assert_eq!(plan.dst_requests.put, 1500 + 15 + 2);
+        assert_eq!(migration.state().unwrap(), MigrationState::default());
+
+        let est = plan.estimate(&Pricing::free(), &Pricing::s3_standard());
+        assert!((est.dollars - (1517.0 * 0.005 + 0.0004) / 1000.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,356 @@
+use super::sync::for_each_concurrent;
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Counted as a failed read's latency, so a failing replica drops to the back
+const ERROR_PENALTY: Duration = Duration::from_secs(1);
+
+/// A replica that missed a write or was found out of date by a read.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Divergence {
+    pub key: String,
+    pub replica: String,
+}
+
+/// Writes every object to several stores, for durability across
+/// providers, and reads it back from whichever has been answering fastest.
+///
+/// Puts and deletes go to all replicas at once and succeed once
+/// `write_quorum` of them have, a majority by default. Reads try replicas
+/// in order of their recent latency and move on to the next on an error
+/// or a missing object. Every replica that failed a write the others
+/// accepted, and every one a read found missing an object another had, is
+/// recorded as a divergence that `repair` later brings back in line from an
+/// up-to-date replica. The record lives in memory only; after a restart,
+/// `verify::compare` finds the same differences.
+///
+/// The returned ETag is the first replica's to accept the write, and
+/// `IfMatch::Tag` is checked by each replica against its own ETag, so
+/// conditional puts need replicas that compute ETags the same way.
+pub struct MirroredStore {
+    replicas: Vec<(String, Arc<dyn ObjectStore>)>,
+    write_quorum: Option<usize>,
+    // Moving average of each replica's read latency
+    latency: Mutex<Vec<Duration>>,
+    divergence: Mutex<BTreeSet<Divergence>>,
+}
+
+impl Default for MirroredStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MirroredStore {
+    pub fn new() -> Self {
+        Self {
+            replicas: Vec::new(),
+            write_quorum: None,
+            latency: Mutex::new(Vec::new()),
+            divergence: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    pub fn with_replica(mut self, name: impl Into<String>, store: Arc<dyn ObjectStore>) -> Self {
+        self.replicas.push((name.into(), store));
+        self.latency.get_mut().unwrap().push(Duration::ZERO);
+        self
+    }
+
+    // Replicas that must accept a write for it to succeed
+    pub fn with_write_quorum(mut self, quorum: usize) -> Self {
+        self.write_quorum = Some(quorum.max(1));
+        self
+    }
+
+    fn quorum(&self) -> usize {
+        self.write_quorum.unwrap_or(self.replicas.len() / 2 + 1).min(self.replicas.len())
+    }
+
+    /// Replicas known to be out of date, by key.
+    pub fn divergence(&self) -> Vec<Divergence> {
+        self.divergence.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn diverged(&self, key: &str, replica: usize) {
+        self.divergence.lock().unwrap().insert(Divergence {
+            key: key.to_string(),
+            replica: self.replicas[replica].0.clone(),
+        });
+    }
+
+    fn observe(&self, replica: usize, elapsed: Duration) {
+        let mut latency = self.latency.lock().unwrap();
+        latency[replica] = (latency[replica] * 4 + elapsed) / 5;
+    }
+
+    // Replica indexes, fastest first
+    fn by_latency(&self) -> Vec<usize> {
+        let latency = self.latency.lock().unwrap();
+        let mut order: Vec<usize> = (0..self.replicas.len()).collect();
+        order.sort_by_key(|&replica| latency[replica]);
+        order
+    }
+
+    // Runs `call` on every replica at once, requiring the write quorum
+    fn write<T: Send>(&self, key: &str, call: impl Fn(&dyn ObjectStore) -> Result<T> + Sync) -> Result<T> {
+        if self.replicas.is_empty() {
+            return Err(ObjectStoreError::Other("MirroredStore has no replicas".to_string()));
+        }
+        let indexes: Vec<usize> = (0..self.replicas.len()).collect();
+        let results = Mutex::new((0..self.replicas.len()).map(|_| None).collect::<Vec<_>>());
+        for_each_concurrent(&indexes, indexes.len(), |&replica| {
+            let result = call(self.replicas[replica].1.as_ref());
+            results.lock().unwrap()[replica] = Some(result);
+        });
+
+        let mut first_ok = None;
+        let mut first_err = None;
+        let mut failed = Vec::new();
+        for (replica, result) in results.into_inner().unwrap().into_iter().enumerate() {
+            match result.expect("every replica called") {
+                Ok(value) => {
+                    first_ok.get_or_insert(value);
+                }
+                Err(e) => {
+                    failed.push(replica);
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        // If every replica refused, e.g. a failed condition, they still agree
+        if first_ok.is_some() {
+            for &replica in &failed {
+                self.diverged(key, replica);
+            }
+        }
+        let succeeded = self.replicas.len() - failed.len();
+        match (first_ok, first_err) {
+            (Some(value), _) if succeeded >= self.quorum() => Ok(value),
+            (_, Some(e)) => Err(e),
+            (None, None) => unreachable!("at least one replica"),
+            (Some(_), None) => unreachable!("quorum is at most the replica count"),
+        }
+    }
+
+    // Tries replicas fastest first until one has the object
+    fn read<T>(&self, key: &str, call: impl Fn(&dyn ObjectStore) -> Result<Option<T>>) -> Result<Option<T>> {
+        let mut missing = Vec::new();
+        let mut first_err = None;
+        for replica in self.by_latency() {
+            let started = Instant::now();
+            match call(self.replicas[replica].1.as_ref()) {
+                Ok(Some(value)) => {
+                    self.observe(replica, started.elapsed());
+                    for stale in missing {
+                        self.diverged(key, stale);
+                    }
+                    return Ok(Some(value));
+                }
+                Ok(None) => {
+                    self.observe(replica, started.elapsed());
+                    missing.push(replica);
+                }
+                // An answer about the object, not a fault of the replica
+                Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => {
+                    self.observe(replica, started.elapsed());
+                    return Err(e);
+                }
+                Err(e) => {
+                    self.observe(replica, ERROR_PENALTY);
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        match first_err {
+            Some(e) if missing.is_empty() => Err(e),
+            _ => Ok(None),
+        }
+    }
+
+    /// Brings every divergent replica in line with one that isn't, copying
+    /// or deleting the object as needed. Returns how many were repaired;
+    /// those that fail stay recorded for the next attempt.
+    pub fn repair(&self) -> Result<usize> {
+        let mut repaired = 0;
+        for divergence in self.divergence() {
+            let stale: BTreeSet<String> = self
+                .divergence()
+                .into_iter()
+                .filter(|d| d.key == divergence.key)
+                .map(|d| d.replica)
+                .collect();
+            let Some((_, source)) = self.replicas.iter().find(|(name, _)| !stale.contains(name)) else {
+                continue;
+            };
+            let Some((_, target)) = self.replicas.iter().find(|(name, _)| *name == divergence.replica) else {
+                continue;
+            };
+            let result = match source.get(&divergence.key) {
+                Ok(Some(data)) => target.put(&divergence.key, &data, IfMatch::Any).map(|_| ()),
+                Ok(None) => target.delete(&divergence.key),
+                Err(e) => Err(e),
+            };
+            if result.is_ok() {
+                self.divergence.lock().unwrap().remove(&divergence);
+                repaired += 1;
+            }
+        }
+        Ok(repaired)
+    }
+}
+
+impl ObjectStore for MirroredStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.read(key, |store| store.get(key))
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        self.write(key, |store| store.put(key, body, cond.clone()))
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        // Continuation tokens are only meaningful to the replica that made
+        // them, so listings stick to the first replica that answers
+        let mut first_err = None;
+        for (_, store) in &self.replicas {
+            match store.list(prefix, continuation.clone()) {
+                Ok(page) => return Ok(page),
+                Err(e) => {
+                    first_err.get_or_insert(e);
+                }
+            }
+            if continuation.is_some() {
+                break;
+            }
+        }
+        Err(first_err.unwrap_or_else(|| ObjectStoreError::Other("MirroredStore has no replicas".to_string())))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.write(key, |store| store.delete(key))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.read(key, |store| store.head(key))
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.read(key, |store| store.get_range(key, range.clone()))
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.read(key, |store| store.get_opts(key, opts.clone()))
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.write(key, |store| store.put_tags(key, tags))
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.read(key, |store| store.get_tags(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::readonly::ReadOnlyStore;
+    use crate::object_store::test_helpers::tests::{run_object_store_tests, CountingStore};
+    use std::thread;
+    use uuid::Uuid;
+
+    struct SlowStore(InMemoryStore);
+
+    impl ObjectStore for SlowStore {
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            thread::sleep(Duration::from_millis(20));
+            self.0.get(key)
+        }
+
+        fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+            self.0.put(key, body, cond)
+        }
+
+        fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+            self.0.list(prefix, continuation)
+        }
+
+        fn delete(&self, key: &str) -> Result<()> {
+            self.0.delete(key)
+        }
+    }
+
+    fn memory() -> Arc<InMemoryStore> {
+        Arc::new(InMemoryStore::default())
+    }
+
+    #[test]
+    fn test_mirrored_object_store() {
+        let store = MirroredStore::new()
+            .with_replica("a", memory())
+            .with_replica("b", memory())
+            .with_replica("c", memory());
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+        assert!(store.divergence().is_empty());
+    }
+
+    #[test]
+    fn test_write_quorum() {
+        let (a, b, c) = (memory(), memory(), memory());
+        let store = MirroredStore::new()
+            .with_replica("a", a.clone())
+            .with_replica("b", b.clone())
+            .with_replica("c", Arc::new(ReadOnlyStore::new(c.clone())));
+
+        // Two of three is a majority
+        store.put("doc", b"v1", IfMatch::Any).unwrap();
+        let missed = Divergence {
+            key: "doc".to_string(),
+            replica: "c".to_string(),
+        };
+        assert_eq!(store.divergence(), vec![missed]);
+        assert_eq!(b.get("doc").unwrap(), Some(b"v1".to_vec()));
+
+        let strict = MirroredStore::new()
+            .with_replica("a", a.clone())
+            .with_replica("c", Arc::new(ReadOnlyStore::new(c.clone())))
+            .with_write_quorum(2);
+        assert!(matches!(strict.put("doc", b"v2", IfMatch::Any), Err(ObjectStoreError::ReadOnly(_))));
+        assert!(matches!(strict.put("doc", b"v3", IfMatch::NoneMatch), Err(ObjectStoreError::PreconditionFailed)));
+    }
+
+    #[test]
+    fn test_reads_find_and_repair_stale_replicas() {
+        let (a, b) = (memory(), memory());
+        let store = MirroredStore::new().with_replica("a", a.clone()).with_replica("b", b.clone());
+        // Written behind the mirror's back, as if a write to "a" had been lost
+        b.put("doc", b"v1", IfMatch::Any).unwrap();
+        a.put("gone", b"old", IfMatch::Any).unwrap();
+        store.delete("gone").unwrap();
+
+        assert_eq!(store.get("doc").unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(store.divergence().len(), 1);
+        assert_eq!(store.repair().unwrap(), 1);
+        assert_eq!(a.get("doc").unwrap(), Some(b"v1".to_vec()));
+        assert!(store.divergence().is_empty());
+        assert_eq!(store.get("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_reads_prefer_the_fastest_replica() {
+        let fast = Arc::new(CountingStore::new(InMemoryStore::default()));
+        let store = MirroredStore::new()
+            .with_replica("slow", Arc::new(SlowStore(InMemoryStore::default())))
+            .with_replica("fast", fast.clone());
+        store.put("doc", b"data", IfMatch::Any).unwrap();
+        for _ in 0..5 {
+            assert_eq!(store.get("doc").unwrap(), Some(b"data".to_vec()));
+        }
+        assert!(fast.count("get") >= 4);
+    }
+}
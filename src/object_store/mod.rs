@@ -1,34 +1,491 @@
 pub mod memory;
+pub mod bounded;
+pub mod breaker;
+pub mod cache;
+pub mod cas;
+pub mod changes;
+pub mod checksum;
+pub mod chunked;
+pub mod context;
+pub mod cost;
+pub mod counter;
+pub mod dedup;
+pub mod dir;
+pub mod disk_cache;
+pub mod docs;
+pub mod failover;
+pub mod journal;
+pub mod keys;
+pub mod leader;
 pub mod local;
+pub mod lock;
+pub mod log;
+pub mod migrate;
+pub mod mirrored;
+pub mod observed;
+pub mod prefix;
+pub mod queue;
+pub mod quota;
+pub mod readonly;
+pub mod retry;
 pub mod s3;
+pub mod sample;
+pub mod scan;
+pub mod shard;
+pub mod sharded;
+pub mod sim;
+pub mod snapshots;
+pub mod strict;
+pub mod sync;
+pub mod tags;
+pub mod tenancy;
+pub mod throttle;
+pub mod tiered;
+pub mod trash;
+pub mod union;
+pub mod verify;
+pub mod versioned;
+#[cfg(feature = "archive")]
+pub mod archive;
+#[cfg(feature = "archive")]
+pub mod backup;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "metrics")]
+pub mod instrument;
+#[cfg(feature = "kv")]
+pub mod kv;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(feature = "tracing")]
+pub mod trace;
 pub mod test_helpers;
 
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
 
-#[derive(Debug)]
+/// Why a store operation failed. Callers match on the variant rather than
+/// the message to tell failure classes apart.
+#[derive(Debug, thiserror::Error)]
 pub enum ObjectStoreError {
-    Io(io::Error),
+    /// Local or network I/O failed
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    /// Something the operation needed does not exist, e.g. a version or a
+    /// snapshot; carries what was missing. A missing object is `Ok(None)`.
+    #[error("not found: {0}")]
+    NotFound(String),
+    /// An `IfMatch` or `GetOptions` condition didn't hold
+    #[error("precondition failed")]
     PreconditionFailed,
+    /// Backend refused the caller's credentials for this operation
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+    /// Backend asked the caller to slow down
+    #[error("throttled: {0}")]
+    Throttled(String),
+    /// Backend did not answer in time
+    #[error("timed out: {0}")]
+    Timeout(String),
+    /// Backend could not be reached or failed on its side, e.g. a 5xx
+    /// response or an open circuit breaker
+    #[error("unavailable: {0}")]
+    Unavailable(String),
+    /// Upload rejected by a content scanner; carries the scanner's reason
+    #[error("blocked: {0}")]
+    Blocked(String),
+    /// Operation not available on this backend, e.g. put on a read-only source
+    #[error("unsupported: {0}")]
+    Unsupported(String),
+    /// Stored bytes no longer match the checksum recorded when they were
+    /// written; carries the key
+    #[error("checksum mismatch: {0} is corrupt")]
+    ChecksumMismatch(String),
+    /// Write would take the store past a configured size or object limit;
+    /// carries which limit
+    #[error("quota exceeded: {0}")]
+    QuotaExceeded(String),
+    /// Write through a handle that only allows reads; carries the key
+    #[error("read-only: cannot modify {0}")]
+    ReadOnly(String),
+    /// Key the store's key policy refuses, e.g. one that would escape a
+    /// LocalStore's root; carries the key
+    #[error("invalid key: {0:?}")]
+    InvalidKey(String),
+    /// An argument other than a key the operation can't use, e.g. a
+    /// continuation token or version id this store didn't hand out, or a
+    /// record over a size limit; carries what was wrong
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    /// Failure from a backend's client library that fits no other variant;
+    /// keeps the original error as the source
+    #[error("{message}")]
+    Backend {
+        message: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// Anything else, described by the message
+    #[error("{0}")]
     Other(String),
+    /// Another error with where it happened attached; see `ContextStore`
+    #[error("{context}: {source}")]
+    Context {
+        context: ErrorContext,
+        #[source]
+        source: Box<ObjectStoreError>,
+    },
+}
+
+impl ObjectStoreError {
+    /// A `Backend` error whose message is `message` and whose source is `source`.
+    pub fn backend(message: impl Into<String>, source: impl std::error::Error + Send + Sync + 'static) -> Self {
+        ObjectStoreError::Backend { message: message.into(), source: Box::new(source) }
+    }
+
+    // Short snake_case name of the variant, for logs and metric labels
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ObjectStoreError::Io(_) => "io",
+            ObjectStoreError::NotFound(_) => "not_found",
+            ObjectStoreError::PreconditionFailed => "precondition_failed",
+            ObjectStoreError::PermissionDenied(_) => "permission_denied",
+            ObjectStoreError::Throttled(_) => "throttled",
+            ObjectStoreError::Timeout(_) => "timeout",
+            ObjectStoreError::Unavailable(_) => "unavailable",
+            ObjectStoreError::Blocked(_) => "blocked",
+            ObjectStoreError::Unsupported(_) => "unsupported",
+            ObjectStoreError::ChecksumMismatch(_) => "checksum_mismatch",
+            ObjectStoreError::QuotaExceeded(_) => "quota_exceeded",
+            ObjectStoreError::ReadOnly(_) => "read_only",
+            ObjectStoreError::InvalidKey(_) => "invalid_key",
+            ObjectStoreError::InvalidArgument(_) => "invalid_argument",
+            ObjectStoreError::Backend { .. } => "backend",
+            ObjectStoreError::Other(_) => "other",
+            ObjectStoreError::Context { source, .. } => source.kind(),
+        }
+    }
+
+    /// Attaches `context`, keeping this error as the source.
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        ObjectStoreError::Context { context, source: Box::new(self) }
+    }
+
+    /// Records which attempt failed on the outermost context, if there is one.
+    pub fn with_attempt(mut self, attempt: u32) -> Self {
+        if let ObjectStoreError::Context { context, .. } = &mut self {
+            context.attempt = Some(attempt);
+        }
+        self
+    }
+
+    /// The outermost context attached to this error.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            ObjectStoreError::Context { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error underneath any contexts; match on this to tell failure
+    /// classes apart when a store may attach context.
+    pub fn root(&self) -> &ObjectStoreError {
+        match self {
+            ObjectStoreError::Context { source, .. } => source.root(),
+            e => e,
+        }
+    }
+
+    pub fn into_root(self) -> ObjectStoreError {
+        match self {
+            ObjectStoreError::Context { source, .. } => source.into_root(),
+            e => e,
+        }
+    }
+}
+
+/// Where an error happened: which backend, which operation, which key and,
+/// once a `RetryingStore` gave up, which attempt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    backend: String,
+    op: &'static str,
+    key: Option<String>,
+    attempt: Option<u32>,
+}
+
+impl ErrorContext {
+    pub fn new(backend: impl Into<String>, op: &'static str, key: Option<&str>) -> Self {
+        Self { backend: backend.into(), op, key: key.map(str::to_string), attempt: None }
+    }
+
+    pub fn backend(&self) -> &str {
+        &self.backend
+    }
+
+    // The method that failed, e.g. "put" or "get_range"
+    pub fn op(&self) -> &'static str {
+        self.op
+    }
+
+    // The key, or the prefix for a listing
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    // 1 for the first try; None unless a RetryingStore returned the error
+    pub fn attempt(&self) -> Option<u32> {
+        self.attempt
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.backend, self.op)?;
+        if let Some(key) = &self.key {
+            write!(f, " {key:?}")?;
+        }
+        if let Some(attempt) = self.attempt {
+            write!(f, " (attempt {attempt})")?;
+        }
+        Ok(())
+    }
 }
 
 pub type Result<T> = std::result::Result<T, ObjectStoreError>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum IfMatch<'a> {
+    #[default]
     Any,
     Tag(&'a str),
     NoneMatch,
 }
 
-impl<'a> Default for IfMatch<'a> {
-    fn default() -> Self {
-        IfMatch::Any
-    }
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectMeta {
+    pub size: u64,
+    pub etag: String,
 }
 
-pub trait ObjectStore {
+/// Options for `ObjectStore::get_opts`. The default is a plain `get`.
+#[derive(Debug, Clone, Default)]
+pub struct GetOptions<'a> {
+    // Only these bytes, clamped to the object's length like `get_range`
+    pub range: Option<Range<u64>>,
+    // Fail with PreconditionFailed unless the object's ETag is this; "*" matches any
+    pub if_match: Option<&'a str>,
+    // Answer NotModified if the object's ETag is this; "*" matches any
+    pub if_none_match: Option<&'a str>,
+    // Also report the size and ETag of the whole object
+    pub include_metadata: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GetResult {
+    // The body, or the requested range of it
+    Body { data: Vec<u8>, meta: Option<ObjectMeta> },
+    // The object's ETag matched `if_none_match`
+    NotModified,
+}
+
+/// How urgent a store handle's calls are, for stores that share a
+/// connection limit between handles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    #[default]
+    Normal,
+    // Never holds more than half the connections, so batch work can't
+    // starve requests on the same store
+    Background,
+}
+
+/// Overrides for one code path's copy of a store handle, taken by the
+/// `with_options` methods of `S3Store` and `RetryingStore`. Fields left
+/// at None keep the handle's own setting.
+#[derive(Debug, Clone, Default)]
+pub struct StoreOptions {
+    // Caps how long a whole call may take, retries included
+    pub timeout: Option<Duration>,
+    // Attempts per call, the first included
+    pub max_attempts: Option<u32>,
+    // Wait before the first retry; later ones back off from it
+    pub initial_backoff: Option<Duration>,
+    pub priority: Option<Priority>,
+}
+
+// Send + Sync so a store can be shared as `Arc<dyn ObjectStore>` across threads
+pub trait ObjectStore: Send + Sync {
     fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
     fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String>;
     fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)>;
+    // Removing a key that doesn't exist is not an error
+    fn delete(&self, key: &str) -> Result<()>;
+
+    // Size and ETag without the body. The default fetches the whole object,
+    // so backends that can do better should override it.
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        Ok(self.get(key)?.map(|data| ObjectMeta {
+            size: data.len() as u64,
+            etag: format!("{:x}", md5::compute(&data)),
+        }))
+    }
+
+    // Bytes in `range`, clamped to the object's length. The default fetches
+    // the whole object and slices it.
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        Ok(self.get(key)?.map(|data| slice_range(&data, range).to_vec()))
+    }
+
+    // Ranged, conditional and metadata-inclusive reads in one call; `get`
+    // and `get_range` are the unconditional special cases. None means the
+    // object doesn't exist. The default reads the whole object whenever it
+    // needs the ETag, so backends with native conditional reads should
+    // override it.
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        get_opts_fallback(self, key, &opts)
+    }
+
+    // Replaces the object's tags without touching the object or its ETag;
+    // a put to the key drops them. NotFound if the object doesn't exist.
+    // Stores without native tagging return Unsupported; wrap them in a
+    // `tags::TaggedStore` to keep tags in objects beside the ones they
+    // describe.
+    fn put_tags(&self, key: &str, _tags: &BTreeMap<String, String>) -> Result<()> {
+        Err(ObjectStoreError::Unsupported(format!("tags on {key}")))
+    }
+
+    // The object's tags, empty if it has none; None if it doesn't exist
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        Err(ObjectStoreError::Unsupported(format!("tags on {key}")))
+    }
+}
+
+// A shared handle is a store too, so one stack of layers can be wrapped
+// differently at each call site, e.g. `RetryingStore::new(shared.clone())`
+impl<T: ObjectStore + ?Sized> ObjectStore for Arc<T> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        (**self).get(key)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        (**self).put(key, body, cond)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        (**self).list(prefix, continuation)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        (**self).delete(key)
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        (**self).head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        (**self).get_range(key, range)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        (**self).get_opts(key, opts)
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        (**self).put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        (**self).get_tags(key)
+    }
+}
+
+fn tag_matches(expected: &str, etag: &str) -> bool {
+    expected == "*" || expected == etag
+}
+
+// Checks the conditions in `opts` against the object's ETag: PreconditionFailed
+// for an If-Match miss, Some(NotModified) for an If-None-Match hit, None to go ahead
+pub(crate) fn check_conditions(etag: &str, opts: &GetOptions) -> Result<Option<GetResult>> {
+    if let Some(expected) = opts.if_match
+        && !tag_matches(expected, etag)
+    {
+        return Err(ObjectStoreError::PreconditionFailed);
+    }
+    if let Some(expected) = opts.if_none_match
+        && tag_matches(expected, etag)
+    {
+        return Ok(Some(GetResult::NotModified));
+    }
+    Ok(None)
+}
+
+// Answers `opts` from a whole object body and its ETag
+pub(crate) fn get_from_body(data: &[u8], etag: &str, opts: &GetOptions) -> Result<GetResult> {
+    if let Some(result) = check_conditions(etag, opts)? {
+        return Ok(result);
+    }
+    let meta = opts.include_metadata.then(|| ObjectMeta {
+        size: data.len() as u64,
+        etag: etag.to_string(),
+    });
+    let data = match &opts.range {
+        Some(range) => slice_range(data, range.clone()).to_vec(),
+        None => data.to_vec(),
+    };
+    Ok(GetResult::Body { data, meta })
+}
+
+// `get_opts` in terms of the other methods, for backends without native
+// conditional reads and for the cases native ones can't express
+pub(crate) fn get_opts_fallback<S: ObjectStore + ?Sized>(
+    store: &S,
+    key: &str,
+    opts: &GetOptions,
+) -> Result<Option<GetResult>> {
+    let needs_etag = opts.if_match.is_some() || opts.if_none_match.is_some() || opts.include_metadata;
+    if !needs_etag {
+        let data = match &opts.range {
+            Some(range) => store.get_range(key, range.clone())?,
+            None => store.get(key)?,
+        };
+        return Ok(data.map(|data| GetResult::Body { data, meta: None }));
+    }
+
+    // An empty range needs no bytes, so the metadata alone can answer it
+    if let Some(range) = &opts.range
+        && range.start >= range.end
+    {
+        let Some(meta) = store.head(key)? else {
+            return Ok(None);
+        };
+        if let Some(result) = check_conditions(&meta.etag, opts)? {
+            return Ok(Some(result));
+        }
+        let meta = opts.include_metadata.then_some(meta);
+        return Ok(Some(GetResult::Body { data: Vec::new(), meta }));
+    }
+
+    // Otherwise read the whole object so the ETag matches the bytes returned
+    let Some(data) = store.get(key)? else {
+        return Ok(None);
+    };
+    let etag = format!("{:x}", md5::compute(&data));
+    get_from_body(&data, &etag, opts).map(Some)
+}
+
+// Clamps `range` to `data` so out-of-bounds requests yield a short or empty slice
+pub(crate) fn slice_range(data: &[u8], range: Range<u64>) -> &[u8] {
+    let len = data.len() as u64;
+    let start = range.start.min(len) as usize;
+    let end = range.end.clamp(range.start.min(len), len) as usize;
+    &data[start..end]
 }
@@ -0,0 +1,151 @@
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, Result};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::mpsc::Sender;
+
+/// A mutation that went through an `ObservedStore`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreEvent {
+    PutCompleted { key: String, etag: String, size: u64 },
+    Deleted { key: String },
+}
+
+type Observer = Box<dyn Fn(&StoreEvent) + Send + Sync>;
+
+/// Wraps a store so observers hear about every successful put and delete,
+/// for indexers that need to react to writes without polling `list`.
+///
+/// Observers run on the writing thread, in registration order, after the
+/// inner store has accepted the change and before the call returns, so a
+/// slow observer slows every write; hand events to a channel with
+/// `with_sender` to process them elsewhere. Failed writes produce no event,
+/// and neither do changes made to the inner store directly.
+pub struct ObservedStore<S> {
+    inner: S,
+    observers: Vec<Observer>,
+}
+
+impl<S: ObjectStore> ObservedStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            observers: Vec::new(),
+        }
+    }
+
+    pub fn with_observer(mut self, observer: impl Fn(&StoreEvent) + Send + Sync + 'static) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    // Events sent after the receiver is dropped are discarded
+    pub fn with_sender(self, sender: Sender<StoreEvent>) -> Self {
+        self.with_observer(move |event| {
+            let _ = sender.send(event.clone());
+        })
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn emit(&self, event: StoreEvent) {
+        for observer in &self.observers {
+            observer(&event);
+        }
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for ObservedStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        let etag = self.inner.put(key, body, cond)?;
+        self.emit(StoreEvent::PutCompleted {
+            key: key.to_string(),
+            etag: etag.clone(),
+            size: body.len() as u64,
+        });
+        Ok(etag)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        self.inner.list(prefix, continuation)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(key)?;
+        self.emit(StoreEvent::Deleted { key: key.to_string() });
+        Ok(())
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.inner.head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.inner.get_range(key, range)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.inner.get_opts(key, opts)
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.inner.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.inner.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_observed_object_store() {
+        let store = ObservedStore::new(InMemoryStore::default()).with_observer(|_| {});
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_events_after_successful_writes() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        let (tx, rx) = mpsc::channel();
+        let store = ObservedStore::new(InMemoryStore::default())
+            .with_observer(move |event| recorded.lock().unwrap().push(event.clone()))
+            .with_sender(tx);
+
+        let etag = store.put("a.txt", b"hello", IfMatch::Any).unwrap();
+        assert!(store.put("a.txt", b"again", IfMatch::NoneMatch).is_err());
+        store.delete("a.txt").unwrap();
+
+        let expected = vec![
+            StoreEvent::PutCompleted {
+                key: "a.txt".to_string(),
+                etag,
+                size: 5,
+            },
+            StoreEvent::Deleted {
+                key: "a.txt".to_string(),
+            },
+        ];
+        assert_eq!(*seen.lock().unwrap(), expected);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), expected);
+
+        // A dropped receiver doesn't break writes
+        drop(rx);
+        store.put("b.txt", b"x", IfMatch::Any).unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,148 @@
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// A scoped view of a store: every key is stored under a fixed prefix,
+/// which `list` strips again, so code handed a `PrefixedStore` sees only
+/// its own namespace and can't reach outside it.
+///
+/// A non-empty prefix without a trailing `/` gets one, so `tenants/a`
+/// doesn't also see `tenants/ab/`. Continuation tokens are passed through
+/// untouched, and corruption errors name the key as the caller knows it.
+pub struct PrefixedStore<S> {
+    inner: S,
+    prefix: String,
+}
+
+impl<S: ObjectStore> PrefixedStore<S> {
+    pub fn new(inner: S, prefix: impl Into<String>) -> Self {
+        let mut prefix = prefix.into();
+        if !prefix.is_empty() && !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        Self { inner, prefix }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    fn full_key(&self, key: &str) -> String {
+        format!("{}{key}", self.prefix)
+    }
+
+    fn scoped_err(&self, e: ObjectStoreError) -> ObjectStoreError {
+        match e {
+            ObjectStoreError::ChecksumMismatch(key) => match key.strip_prefix(&self.prefix) {
+                Some(scoped) => ObjectStoreError::ChecksumMismatch(scoped.to_string()),
+                None => ObjectStoreError::ChecksumMismatch(key),
+            },
+            e => e,
+        }
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for PrefixedStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.get(&self.full_key(key)).map_err(|e| self.scoped_err(e))
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        self.inner.put(&self.full_key(key), body, cond)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let (keys, next) = self.inner.list(&self.full_key(prefix), continuation)?;
+        let keys = keys
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(&self.prefix).map(str::to_string))
+            .collect();
+        Ok((keys, next))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(&self.full_key(key))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.inner.head(&self.full_key(key))
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.inner.get_range(&self.full_key(key), range).map_err(|e| self.scoped_err(e))
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.inner.get_opts(&self.full_key(key), opts).map_err(|e| self.scoped_err(e))
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.inner.put_tags(&self.full_key(key), tags).map_err(|e| self.scoped_err(e))
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.inner.get_tags(&self.full_key(key)).map_err(|e| self.scoped_err(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::checksum::VerifiedStore;
+    use crate::object_store::local::LocalStore;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::{run_object_store_tests, run_oracle_tests};
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_prefixed_object_store() {
+        let store = PrefixedStore::new(InMemoryStore::default(), "scope");
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+        run_oracle_tests(&store, &format!("oracle/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_namespaces_are_isolated() {
+        let backend = Arc::new(InMemoryStore::default());
+        let a = PrefixedStore::new(backend.clone(), "tenants/a");
+        let ab = PrefixedStore::new(backend.clone(), "tenants/ab/");
+        assert_eq!(a.prefix(), "tenants/a/");
+
+        a.put("notes.txt", b"from a", IfMatch::Any).unwrap();
+        ab.put("notes.txt", b"from ab", IfMatch::Any).unwrap();
+        ab.put("docs/x", b"x", IfMatch::Any).unwrap();
+        assert_eq!(a.get("notes.txt").unwrap(), Some(b"from a".to_vec()));
+        assert_eq!(a.list("", None).unwrap().0, vec!["notes.txt"]);
+        assert_eq!(ab.list("do", None).unwrap().0, vec!["docs/x"]);
+        assert_eq!(backend.list("tenants/", None).unwrap().0.len(), 3);
+
+        a.delete("notes.txt").unwrap();
+        assert_eq!(ab.get("notes.txt").unwrap(), Some(b"from ab".to_vec()));
+    }
+
+    #[test]
+    fn test_tags_reach_native_tagging() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = PrefixedStore::new(LocalStore::new(tmp.path()), "app");
+        store.put("report.csv", b"a,b", IfMatch::Any).unwrap();
+        let tags = BTreeMap::from([("class".to_string(), "cold".to_string())]);
+        store.put_tags("report.csv", &tags).unwrap();
+        assert_eq!(store.inner().get_tags("app/report.csv").unwrap(), Some(tags));
+        // Kept by the backend itself, not in a sidecar object
+        assert_eq!(store.inner().list("", None).unwrap().0, vec!["app/report.csv"]);
+    }
+
+    #[test]
+    fn test_errors_use_scoped_keys() {
+        let backend = Arc::new(InMemoryStore::default());
+        let store = PrefixedStore::new(VerifiedStore::new(backend.clone()), "app");
+        store.put("data.bin", b"good", IfMatch::Any).unwrap();
+        backend.put("app/data.bin", b"evil", IfMatch::Any).unwrap();
+        assert!(matches!(store.get("data.bin"), Err(ObjectStoreError::ChecksumMismatch(k)) if k == "data.bin"));
+    }
+}
@@ -0,0 +1,293 @@
+use super::sync::list_all;
+use super::versioned::monotonic_micros;
+use super::{GetOptions, GetResult, IfMatch, ObjectStore, ObjectStoreError, Result};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+// Claimed tasks move under this, below the queue's prefix
+const IN_PROGRESS: &str = "in-progress/";
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A claimed task, invisible to other consumers until `expires_at`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Task {
+    pub id: String,
+    pub body: Vec<u8>,
+    // How many times the task has been claimed, this claim included
+    pub attempts: u32,
+    // Milliseconds since the Unix epoch
+    pub expires_at: u64,
+    etag: String,
+}
+
+// A claim is the expiry and attempt count, both big-endian, then the body
+fn encode_claim(expires_at: u64, attempts: u32, body: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(12 + body.len());
+    data.extend_from_slice(&expires_at.to_be_bytes());
+    data.extend_from_slice(&attempts.to_be_bytes());
+    data.extend_from_slice(body);
+    data
+}
+
+fn decode_claim(key: &str, data: &[u8]) -> Result<(u64, u32, Vec<u8>)> {
+    let corrupt = || ObjectStoreError::Other(format!("corrupt task claim {key}"));
+    let (expires_at, rest) = data.split_first_chunk::<8>().ok_or_else(corrupt)?;
+    let (attempts, body) = rest.split_first_chunk::<4>().ok_or_else(corrupt)?;
+    Ok((u64::from_be_bytes(*expires_at), u32::from_be_bytes(*attempts), body.to_vec()))
+}
+
+/// An at-least-once task queue on any store with conditional puts.
+///
+/// `push` writes a task as `<prefix><micros>-<uuid>`, so listing order is
+/// roughly arrival order. `claim` moves the oldest task to
+/// `<prefix>in-progress/<id>` by creating the claim with
+/// `IfMatch::NoneMatch` and then deleting the original, so only one
+/// consumer wins it; the claim hides the task for the visibility timeout.
+/// A consumer that finishes calls `complete`, or `extend` to keep working.
+/// Claims that expire are taken over by the next `claim`, so a task whose
+/// consumer died is retried, and a slow consumer may see its task handed
+/// to someone else: task handlers must be idempotent. Each claim lists the
+/// queue, which suits queues of thousands of tasks rather than millions.
+pub struct Queue<S> {
+    store: S,
+    prefix: String,
+    visibility_timeout: Duration,
+}
+
+impl<S: ObjectStore> Queue<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            prefix: "queue/".to_string(),
+            visibility_timeout: Duration::from_secs(30),
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn with_visibility_timeout(mut self, timeout: Duration) -> Self {
+        self.visibility_timeout = timeout;
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    fn pending_key(&self, id: &str) -> String {
+        format!("{}{id}", self.prefix)
+    }
+
+    fn claim_key(&self, id: &str) -> String {
+        format!("{}{IN_PROGRESS}{id}", self.prefix)
+    }
+
+    fn expiry(&self) -> u64 {
+        now_millis() + self.visibility_timeout.as_millis() as u64
+    }
+
+    /// Adds a task and returns its id.
+    pub fn push(&self, body: &[u8]) -> Result<String> {
+        let id = format!("{:016}-{}", monotonic_micros(), Uuid::new_v4().simple());
+        self.store.put(&self.pending_key(&id), body, IfMatch::NoneMatch)?;
+        Ok(id)
+    }
+
+    /// Ids of the tasks waiting to be claimed, oldest first.
+    pub fn pending(&self) -> Result<Vec<String>> {
+        let mut ids: Vec<String> = list_all(&self.store, &self.prefix)?
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(&self.prefix).map(str::to_string))
+            .filter(|id| !id.contains('/'))
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Claims the next task: an expired claim if there is one, else the
+    /// oldest pending task. `None` when there is nothing to do.
+    pub fn claim(&self) -> Result<Option<Task>> {
+        let claims_prefix = self.claim_key("");
+        let mut claimed = list_all(&self.store, &claims_prefix)?;
+        claimed.sort();
+        for key in claimed {
+            if let Some(task) = self.rescue(&key[claims_prefix.len()..])? {
+                return Ok(Some(task));
+            }
+        }
+        for id in self.pending()? {
+            if let Some(task) = self.take(&id)? {
+                return Ok(Some(task));
+            }
+        }
+        Ok(None)
+    }
+
+    // Takes over `id`'s claim if it has expired
+    fn rescue(&self, id: &str) -> Result<Option<Task>> {
+        let key = self.claim_key(id);
+        let opts = GetOptions {
+            include_metadata: true,
+            ..Default::default()
+        };
+        let (data, etag) = match self.store.get_opts(&key, opts)? {
+            Some(GetResult::Body { data, meta: Some(meta) }) => (data, meta.etag),
+            None => return Ok(None),
+            Some(_) => return Err(ObjectStoreError::Other(format!("no metadata for task claim {key}"))),
+        };
+        let (expires_at, attempts, body) = decode_claim(&key, &data)?;
+        if expires_at > now_millis() {
+            return Ok(None);
+        }
+        let expires_at = self.expiry();
+        let attempts = attempts.saturating_add(1);
+        match self.store.put(&key, &encode_claim(expires_at, attempts, &body), IfMatch::Tag(&etag)) {
+            Ok(etag) => Ok(Some(Task {
+                id: id.to_string(),
+                body,
+                attempts,
+                expires_at,
+                etag,
+            })),
+            // Another consumer rescued it first
+            Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Moves pending task `id` to a claim of our own
+    fn take(&self, id: &str) -> Result<Option<Task>> {
+        let pending = self.pending_key(id);
+        // Claimed and removed since the listing
+        let Some(body) = self.store.get(&pending)? else {
+            return Ok(None);
+        };
+        let key = self.claim_key(id);
+        let expires_at = self.expiry();
+        let etag = match self.store.put(&key, &encode_claim(expires_at, 1, &body), IfMatch::NoneMatch) {
+            Ok(etag) => etag,
+            Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        // If the task is gone, its earlier claim already finished it and
+        // ours came too late
+        if self.store.head(&pending)?.is_none() {
+            self.store.delete(&key)?;
+            return Ok(None);
+        }
+        self.store.delete(&pending)?;
+        Ok(Some(Task {
+            id: id.to_string(),
+            body,
+            attempts: 1,
+            expires_at,
+            etag,
+        }))
+    }
+
+    /// Keeps a task hidden for another visibility timeout from now. Fails
+    /// with `PreconditionFailed` if the claim expired and was taken over.
+    pub fn extend(&self, task: &mut Task) -> Result<()> {
+        let expires_at = self.expiry();
+        let data = encode_claim(expires_at, task.attempts, &task.body);
+        task.etag = self.store.put(&self.claim_key(&task.id), &data, IfMatch::Tag(&task.etag))?;
+        task.expires_at = expires_at;
+        Ok(())
+    }
+
+    /// Removes a finished task. Fails with `PreconditionFailed` if the claim
+    /// expired and was taken over, in which case the task will run again.
+    pub fn complete(&self, task: Task) -> Result<()> {
+        let key = self.claim_key(&task.id);
+        match self.store.head(&key)? {
+            Some(meta) if meta.etag == task.etag => self.store.delete(&key),
+            _ => Err(ObjectStoreError::PreconditionFailed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    #[test]
+    fn test_push_claim_complete() {
+        let queue = Queue::new(InMemoryStore::default());
+        let first = queue.push(b"resize 1.png").unwrap();
+        let second = queue.push(b"resize 2.png").unwrap();
+        assert_eq!(queue.pending().unwrap(), vec![first.clone(), second.clone()]);
+
+        let task = queue.claim().unwrap().unwrap();
+        assert_eq!((task.id.as_str(), task.body.as_slice(), task.attempts), (first.as_str(), &b"resize 1.png"[..], 1));
+        assert_eq!(queue.pending().unwrap(), vec![second.clone()]);
+        assert!(queue.inner().get(&format!("queue/in-progress/{first}")).unwrap().is_some());
+
+        let other = queue.claim().unwrap().unwrap();
+        assert_eq!(other.id, second);
+        assert!(queue.claim().unwrap().is_none());
+
+        queue.complete(task).unwrap();
+        queue.complete(other).unwrap();
+        assert!(queue.inner().list("", None).unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn test_expired_claims_are_rescued() {
+        let queue = Queue::new(InMemoryStore::default()).with_visibility_timeout(Duration::from_millis(20));
+        queue.push(b"flaky").unwrap();
+        let mut stale = queue.claim().unwrap().unwrap();
+        assert!(queue.claim().unwrap().is_none());
+
+        thread::sleep(Duration::from_millis(30));
+        let rescued = queue.claim().unwrap().unwrap();
+        assert_eq!((rescued.id.as_str(), rescued.attempts), (stale.id.as_str(), 2));
+        // The slow consumer finds out it lost the task
+        assert!(matches!(queue.extend(&mut stale), Err(ObjectStoreError::PreconditionFailed)));
+        assert!(matches!(queue.complete(stale), Err(ObjectStoreError::PreconditionFailed)));
+
+        let mut rescued = rescued;
+        queue.extend(&mut rescued).unwrap();
+        queue.complete(rescued).unwrap();
+        assert!(queue.claim().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_concurrent_consumers() {
+        let store = Arc::new(InMemoryStore::default());
+        let producer = Queue::new(store.clone());
+        for i in 0..40 {
+            producer.push(format!("task {i}").as_bytes()).unwrap();
+        }
+        let done = Arc::new(Mutex::new(Vec::new()));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let (queue, done) = (Queue::new(store.clone()), done.clone());
+                thread::spawn(move || {
+                    while let Some(task) = queue.claim().unwrap() {
+                        done.lock().unwrap().push(task.body.clone());
+                        queue.complete(task).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let done = done.lock().unwrap();
+        assert_eq!(done.len(), 40);
+        assert_eq!(done.iter().collect::<HashSet<_>>().len(), 40);
+    }
+}
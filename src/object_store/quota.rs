@@ -0,0 +1,290 @@
+use super::retry::{cas_backoff, MAX_CAS_ATTEMPTS};
+use super::sync::list_all;
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+pub const DEFAULT_USAGE_KEY: &str = ".quota/usage.json";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub bytes: u64,
+    pub objects: u64,
+}
+
+/// Caps for a `QuotaStore`; None means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaLimits {
+    pub max_bytes: Option<u64>,
+    pub max_objects: Option<u64>,
+}
+
+/// Wraps a store so its total size and object count stay within limits,
+/// failing puts that would exceed them with
+/// `ObjectStoreError::QuotaExceeded`.
+///
+/// Usage is kept as JSON in a metadata object, `.quota/usage.json` by
+/// default, and updated with conditional puts, so any number of processes
+/// can share one quota. A put reserves its growth before writing and
+/// releases it if the write fails, so concurrent puts can't overshoot
+/// together; overwrites only count the difference in size and shrinking
+/// is never refused. The object's old size is read before the write, so
+/// racing writes to one key or writes that bypass the wrapper make the
+/// count drift; `recalculate` rebuilds it from a listing. Tags go straight
+/// to the inner store and don't count.
+pub struct QuotaStore<S> {
+    inner: S,
+    limits: QuotaLimits,
+    usage_key: String,
+}
+
+impl<S: ObjectStore> QuotaStore<S> {
+    pub fn new(inner: S, limits: QuotaLimits) -> Self {
+        Self {
+            inner,
+            limits,
+            usage_key: DEFAULT_USAGE_KEY.to_string(),
+        }
+    }
+
+    pub fn with_usage_key(mut self, key: impl Into<String>) -> Self {
+        self.usage_key = key.into();
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn limits(&self) -> QuotaLimits {
+        self.limits
+    }
+
+    // The usage object is the wrapper's own; callers may not touch it
+    fn check_key(&self, key: &str) -> Result<()> {
+        if key == self.usage_key {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        Ok(())
+    }
+
+    fn read_usage(&self) -> Result<Option<(Usage, String)>> {
+        let opts = GetOptions {
+            include_metadata: true,
+            ..Default::default()
+        };
+        let Some(GetResult::Body { data, meta: Some(meta) }) = self.inner.get_opts(&self.usage_key, opts)? else {
+            return Ok(None);
+        };
+        let usage = serde_json::from_slice(&data)
+            .map_err(|e| ObjectStoreError::Other(format!("corrupt quota usage at {}: {e}", self.usage_key)))?;
+        Ok(Some((usage, meta.etag)))
+    }
+
+    pub fn usage(&self) -> Result<Usage> {
+        Ok(self.read_usage()?.map(|(usage, _)| usage).unwrap_or_default())
+    }
+
+    // Applies `update` to the stored usage with a compare-and-swap, retrying on conflicts
+    fn update_usage(&self, update: impl Fn(Usage) -> Result<Usage>) -> Result<Usage> {
+        for attempt in 0..MAX_CAS_ATTEMPTS {
+            let current = self.read_usage()?;
+            let usage = update(current.as_ref().map(|(usage, _)| *usage).unwrap_or_default())?;
+            let json = serde_json::to_vec(&usage).expect("usage serializes");
+            let cond = match &current {
+                Some((_, etag)) => IfMatch::Tag(etag),
+                None => IfMatch::NoneMatch,
+            };
+            match self.inner.put(&self.usage_key, &json, cond) {
+                Ok(_) => return Ok(usage),
+                Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => cas_backoff(attempt),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(ObjectStoreError::Other(format!("{} kept changing while being updated", self.usage_key)))
+    }
+
+    // Adds `bytes` and `objects` to the usage, refusing growth past a limit
+    fn reserve(&self, bytes: i64, objects: i64) -> Result<Usage> {
+        let limits = self.limits;
+        self.update_usage(|usage| {
+            let next = Usage {
+                bytes: usage.bytes.saturating_add_signed(bytes),
+                objects: usage.objects.saturating_add_signed(objects),
+            };
+            if let Some(max) = limits.max_bytes
+                && bytes > 0
+                && next.bytes > max
+            {
+                return Err(ObjectStoreError::QuotaExceeded(format!("{} of {max} bytes", next.bytes)));
+            }
+            if let Some(max) = limits.max_objects
+                && objects > 0
+                && next.objects > max
+            {
+                return Err(ObjectStoreError::QuotaExceeded(format!("{} of {max} objects", next.objects)));
+            }
+            Ok(next)
+        })
+    }
+
+    /// Recounts usage from a full listing and stores it.
+    pub fn recalculate(&self) -> Result<Usage> {
+        let mut usage = Usage::default();
+        for key in list_all(&self.inner, "")? {
+            if key == self.usage_key {
+                continue;
+            }
+            if let Some(meta) = self.inner.head(&key)? {
+                usage.bytes += meta.size;
+                usage.objects += 1;
+            }
+        }
+        self.update_usage(|_| Ok(usage))
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for QuotaStore<S> {
+    // The usage object reads as missing, as it is hidden from listings
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        if key == self.usage_key {
+            return Ok(None);
+        }
+        self.inner.get(key)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        self.check_key(key)?;
+        let (bytes, objects) = match self.inner.head(key)? {
+            Some(old) => (body.len() as i64 - old.size as i64, 0),
+            None => (body.len() as i64, 1),
+        };
+        self.reserve(bytes, objects)?;
+        self.inner.put(key, body, cond).inspect_err(|_| {
+            let _ = self.reserve(-bytes, -objects);
+        })
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let (keys, next) = self.inner.list(prefix, continuation)?;
+        Ok((keys.into_iter().filter(|key| *key != self.usage_key).collect(), next))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.check_key(key)?;
+        let Some(old) = self.inner.head(key)? else {
+            return Ok(());
+        };
+        self.inner.delete(key)?;
+        self.reserve(-(old.size as i64), -1).map(|_| ())
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        if key == self.usage_key {
+            return Ok(None);
+        }
+        self.inner.head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        if key == self.usage_key {
+            return Ok(None);
+        }
+        self.inner.get_range(key, range)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        if key == self.usage_key {
+            return Ok(None);
+        }
+        self.inner.get_opts(key, opts)
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.check_key(key)?;
+        self.inner.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        if key == self.usage_key {
+            return Ok(None);
+        }
+        self.inner.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_quota_object_store() {
+        let store = QuotaStore::new(InMemoryStore::default(), QuotaLimits::default());
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+        assert_eq!(store.usage().unwrap(), store.recalculate().unwrap());
+    }
+
+    #[test]
+    fn test_enforces_limits() {
+        let limits = QuotaLimits {
+            max_bytes: Some(10),
+            max_objects: Some(2),
+        };
+        let store = QuotaStore::new(InMemoryStore::default(), limits);
+        store.put("a", b"123456", IfMatch::Any).unwrap();
+        let result = store.put("b", b"12345", IfMatch::Any);
+        assert!(matches!(result, Err(ObjectStoreError::QuotaExceeded(_))));
+        assert_eq!(store.get("b").unwrap(), None);
+
+        // Overwrites only count the difference
+        store.put("a", b"123456789", IfMatch::Any).unwrap();
+        store.put("b", b"1", IfMatch::Any).unwrap();
+        assert_eq!(store.usage().unwrap(), Usage { bytes: 10, objects: 2 });
+        assert!(matches!(store.put("c", b"", IfMatch::Any), Err(ObjectStoreError::QuotaExceeded(_))));
+
+        // Shrinking and deleting free space
+        store.put("a", b"1", IfMatch::Any).unwrap();
+        store.delete("b").unwrap();
+        assert_eq!(store.usage().unwrap(), Usage { bytes: 1, objects: 1 });
+        assert_eq!(store.list("", None).unwrap().0, vec!["a"]);
+        assert!(matches!(store.put(DEFAULT_USAGE_KEY, b"{}", IfMatch::Any), Err(ObjectStoreError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn test_usage_key_is_protected() {
+        let store = QuotaStore::new(InMemoryStore::default(), QuotaLimits::default());
+        store.put("a", b"123", IfMatch::Any).unwrap();
+        assert!(matches!(store.delete(DEFAULT_USAGE_KEY), Err(ObjectStoreError::InvalidKey(_))));
+        assert_eq!(store.usage().unwrap(), Usage { bytes: 3, objects: 1 });
+        assert_eq!(store.get(DEFAULT_USAGE_KEY).unwrap(), None);
+        assert_eq!(store.head(DEFAULT_USAGE_KEY).unwrap(), None);
+        assert_eq!(store.get_range(DEFAULT_USAGE_KEY, 0..2).unwrap(), None);
+        assert_eq!(store.get_opts(DEFAULT_USAGE_KEY, GetOptions::default()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_usage_is_shared_and_repairable() {
+        let backend = Arc::new(InMemoryStore::default());
+        let limits = QuotaLimits {
+            max_bytes: Some(100),
+            max_objects: None,
+        };
+        let first = QuotaStore::new(backend.clone(), limits);
+        let second = QuotaStore::new(backend.clone(), limits);
+        first.put("x", &[0; 60], IfMatch::Any).unwrap();
+        assert!(matches!(second.put("y", &[0; 60], IfMatch::Any), Err(ObjectStoreError::QuotaExceeded(_))));
+
+        // A rejected write gives its reservation back
+        assert!(second.put("x", &[0; 10], IfMatch::NoneMatch).is_err());
+        assert_eq!(first.usage().unwrap(), Usage { bytes: 60, objects: 1 });
+
+        // Writes behind the wrapper's back are picked up by a recount
+        backend.put("z", &[0; 30], IfMatch::Any).unwrap();
+        assert_eq!(first.recalculate().unwrap(), Usage { bytes: 90, objects: 2 });
+    }
+}
@@ -0,0 +1,84 @@
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Wraps a store so it can only be read: reads and listings are forwarded,
+/// while `put` and `delete` fail with `ObjectStoreError::ReadOnly` without
+/// reaching the inner store. For handing production data to jobs that
+/// must not change it.
+pub struct ReadOnlyStore<S> {
+    inner: S,
+}
+
+impl<S: ObjectStore> ReadOnlyStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for ReadOnlyStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn put(&self, key: &str, _body: &[u8], _cond: IfMatch) -> Result<String> {
+        Err(ObjectStoreError::ReadOnly(key.to_string()))
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        self.inner.list(prefix, continuation)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        Err(ObjectStoreError::ReadOnly(key.to_string()))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.inner.head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.inner.get_range(key, range)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.inner.get_opts(key, opts)
+    }
+
+    fn put_tags(&self, key: &str, _tags: &BTreeMap<String, String>) -> Result<()> {
+        Err(ObjectStoreError::ReadOnly(key.to_string()))
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.inner.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::retry::is_transient;
+
+    #[test]
+    fn test_rejects_writes() {
+        let backend = InMemoryStore::default();
+        backend.put("report.csv", b"a,b", IfMatch::Any).unwrap();
+        let store = ReadOnlyStore::new(backend);
+
+        assert_eq!(store.get("report.csv").unwrap(), Some(b"a,b".to_vec()));
+        assert_eq!(store.get_range("report.csv", 0..1).unwrap(), Some(b"a".to_vec()));
+        assert_eq!(store.head("report.csv").unwrap().unwrap().size, 3);
+        assert_eq!(store.list("", None).unwrap().0, vec!["report.csv"]);
+
+        let result = store.put("report.csv", b"x", IfMatch::Any);
+        assert!(matches!(result, Err(ObjectStoreError::ReadOnly(ref key)) if key == "report.csv"));
+        assert!(!is_transient(&result.unwrap_err()));
+        assert!(matches!(store.delete("report.csv"), Err(ObjectStoreError::ReadOnly(_))));
+        assert_eq!(store.inner().get("report.csv").unwrap(), Some(b"a,b".to_vec()));
+    }
+}
@@ -0,0 +1,445 @@
+use super::tags::TAGS_PREFIX;
+use super::{check_conditions, slice_range, GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use ::redis::{Client, Connection, RedisError, Script};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ops::Range;
+use std::sync::Mutex;
+
+// Listings in progress whose remaining keys are kept before the oldest is dropped
+const MAX_LISTINGS: usize = 64;
+
+// Sets KEYS[1] to ARGV[3] and drops its tags, KEYS[2], unless the condition
+// fails: ARGV[1] is 'any', 'none' (only if absent) or 'tag' (only if the
+// stored ETag is ARGV[2]). Values are laid out as [etag length byte][etag]
+// [body], see `encode`.
+const PUT_SCRIPT: &str = r"
+if ARGV[1] == 'none' then
+  if redis.call('EXISTS', KEYS[1]) == 1 then return 0 end
+elseif ARGV[1] == 'tag' then
+  local head = redis.call('GETRANGE', KEYS[1], 0, 255)
+  if head == '' then return 0 end
+  local n = string.byte(head, 1)
+  if string.sub(head, 2, 1 + n) ~= ARGV[2] then return 0 end
+end
+redis.call('SET', KEYS[1], ARGV[3])
+redis.call('DEL', KEYS[2])
+return 1
+";
+
+// Sets the tags of KEYS[1], kept at KEYS[2], to ARGV[1] if the object exists
+const TAGS_SCRIPT: &str = r"
+if redis.call('EXISTS', KEYS[1]) == 0 then return 0 end
+redis.call('SET', KEYS[2], ARGV[1])
+return 1
+";
+
+// Reads the stored ETag, the body's size and the bytes ARGV[3] up to ARGV[4]
+// of the body (to its end if ARGV[4] is empty) in one step, as GETRANGEs so
+// the rest of the value stays on the server. The body is left out (false)
+// when ARGV[1], an If-Match tag, misses or ARGV[2], an If-None-Match tag,
+// hits; the client checks both again.
+const READ_SCRIPT: &str = r"
+local head = redis.call('GETRANGE', KEYS[1], 0, 255)
+if head == '' then return false end
+local n = string.byte(head, 1)
+local etag = string.sub(head, 2, 1 + n)
+local size = redis.call('STRLEN', KEYS[1]) - 1 - n
+local function matches(tag) return tag == '*' or tag == etag end
+if (ARGV[1] ~= '' and not matches(ARGV[1])) or (ARGV[2] ~= '' and matches(ARGV[2])) then
+  return {etag, size, false}
+end
+local first = tonumber(ARGV[3])
+local last = size - 1
+if ARGV[4] ~= '' then last = math.min(last, tonumber(ARGV[4]) - 1) end
+local body = ''
+if first <= last then body = redis.call('GETRANGE', KEYS[1], 1 + n + first, 1 + n + last) end
+return {etag, size, body}
+";
+
+/// Store over Redis strings.
+///
+/// Every object is one string value holding its ETag and body, under
+/// `<namespace><key>`. The first page of a listing SCANs with a MATCH
+/// pattern and sorts the keys; the rest of them are kept for the next
+/// page, for up to 64 listings in progress, so a listing is a snapshot of
+/// the keys when it started. A token this store didn't hand out, or whose
+/// keys were dropped, scans again and resumes after it. Puts run as
+/// a Lua script that checks the condition and writes in one step, so
+/// conditional puts are atomic on the server. `get_opts` reads only the
+/// ETag and the requested range, also in one script. Tags are JSON under
+/// `<namespace>.tags/<key>`, set only while the object exists and dropped
+/// by the same script or command that replaces or deletes it; `.tags/` is
+/// hidden from `list` and reserved.
+pub struct RedisStore {
+    conn: Mutex<Connection>,
+    namespace: String,
+    put: Script,
+    read: Script,
+    tag: Script,
+    listings: Mutex<Listings>,
+}
+
+// Keys not yet returned by listings in progress, by prefix and the token
+// their last page handed out
+#[derive(Default)]
+struct Listings {
+    remaining: HashMap<(String, String), Vec<String>>,
+    // Oldest first
+    order: VecDeque<(String, String)>,
+}
+
+impl Listings {
+    fn insert(&mut self, prefix: &str, token: &str, keys: Vec<String>) {
+        let id = (prefix.to_string(), token.to_string());
+        self.remaining.insert(id.clone(), keys);
+        self.order.push_back(id);
+        if self.order.len() > MAX_LISTINGS
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.remaining.remove(&oldest);
+        }
+    }
+
+    fn take(&mut self, prefix: &str, token: &str) -> Option<Vec<String>> {
+        let id = (prefix.to_string(), token.to_string());
+        let keys = self.remaining.remove(&id)?;
+        self.order.retain(|other| *other != id);
+        Some(keys)
+    }
+}
+
+impl RedisStore {
+    pub fn new(url: &str) -> Result<Self> {
+        Self::with_namespace(url, "")
+    }
+
+    // Keeps this store's keys apart from anything else in the same database
+    pub fn with_namespace(url: &str, namespace: impl Into<String>) -> Result<Self> {
+        let client = Client::open(url).map_err(map_redis_err)?;
+        let conn = client.get_connection().map_err(map_redis_err)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            namespace: namespace.into(),
+            put: Script::new(PUT_SCRIPT),
+            read: Script::new(READ_SCRIPT),
+            tag: Script::new(TAGS_SCRIPT),
+            listings: Mutex::default(),
+        })
+    }
+
+    fn redis_key(&self, key: &str) -> String {
+        format!("{}{}", self.namespace, key)
+    }
+
+    // The Redis key of an object, refusing the reserved tags keys
+    fn object_key(&self, key: &str) -> Result<String> {
+        if key.starts_with(TAGS_PREFIX) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        Ok(self.redis_key(key))
+    }
+
+    fn tags_key(&self, key: &str) -> String {
+        format!("{}{TAGS_PREFIX}{}", self.namespace, key)
+    }
+
+    fn compute_etag(data: &[u8]) -> String {
+        format!("{:x}", md5::compute(data))
+    }
+
+    fn fetch(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let redis_key = self.object_key(key)?;
+        let mut conn = self.conn.lock().unwrap();
+        ::redis::cmd("GET")
+            .arg(redis_key)
+            .query(&mut *conn)
+            .map_err(map_redis_err)
+    }
+
+    // All keys under the prefix, sorted
+    fn scan(&self, prefix: &str) -> Result<Vec<String>> {
+        let pattern = format!("{}*", glob_escape(&self.redis_key(prefix)));
+        let mut keys = Vec::new();
+        {
+            let mut conn = self.conn.lock().unwrap();
+            let mut cursor = 0u64;
+            loop {
+                let (next, batch): (u64, Vec<String>) = ::redis::cmd("SCAN")
+                    .arg(cursor)
+                    .arg("MATCH")
+                    .arg(&pattern)
+                    .arg("COUNT")
+                    .arg(1000)
+                    .query(&mut *conn)
+                    .map_err(map_redis_err)?;
+                keys.extend(
+                    batch
+                        .into_iter()
+                        .map(|k| k[self.namespace.len()..].to_string())
+                        .filter(|k| !k.starts_with(TAGS_PREFIX)),
+                );
+                if next == 0 {
+                    break;
+                }
+                cursor = next;
+            }
+        }
+        // SCAN may return a key more than once and in no particular order
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+}
+
+fn map_redis_err(e: RedisError) -> ObjectStoreError {
+    if e.is_timeout() {
+        ObjectStoreError::Timeout(format!("Redis error: {e}"))
+    } else if e.is_connection_refusal() {
+        ObjectStoreError::Unavailable(format!("Redis error: {e}"))
+    } else if e.is_io_error() {
+        ObjectStoreError::Io(std::io::Error::other(e))
+    } else {
+        ObjectStoreError::backend(format!("Redis error: {e}"), e)
+    }
+}
+
+fn encode(etag: &str, data: &[u8]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(1 + etag.len() + data.len());
+    value.push(etag.len() as u8);
+    value.extend_from_slice(etag.as_bytes());
+    value.extend_from_slice(data);
+    value
+}
+
+fn decode(value: &[u8]) -> Result<(&str, &[u8])> {
+    let corrupt = || ObjectStoreError::Other("corrupt redis value".to_string());
+    let (&etag_len, rest) = value.split_first().ok_or_else(corrupt)?;
+    if rest.len() < etag_len as usize {
+        return Err(corrupt());
+    }
+    let (etag, data) = rest.split_at(etag_len as usize);
+    let etag = std::str::from_utf8(etag).map_err(|_| corrupt())?;
+    Ok((etag, data))
+}
+
+// Escapes glob metacharacters so a key prefix can be used in SCAN MATCH
+fn glob_escape(prefix: &str) -> String {
+    let mut escaped = String::with_capacity(prefix.len());
+    for c in prefix.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl ObjectStore for RedisStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.fetch(key)? {
+            Some(value) => Ok(Some(decode(&value)?.1.to_vec())),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        let new_etag = Self::compute_etag(body);
+        let value = encode(&new_etag, body);
+        let redis_key = self.object_key(key)?;
+        let (mode, expected_etag) = match cond {
+            IfMatch::Any => ("any", ""),
+            IfMatch::Tag(expected_etag) => ("tag", expected_etag),
+            IfMatch::NoneMatch => ("none", ""),
+        };
+        let mut conn = self.conn.lock().unwrap();
+        let written: i64 = self
+            .put
+            .key(&redis_key)
+            .key(self.tags_key(key))
+            .arg(mode)
+            .arg(expected_etag)
+            .arg(value)
+            .invoke(&mut *conn)
+            .map_err(map_redis_err)?;
+
+        if written == 1 {
+            Ok(new_etag)
+        } else {
+            Err(ObjectStoreError::PreconditionFailed)
+        }
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let cached = continuation
+            .as_ref()
+            .and_then(|token| self.listings.lock().unwrap().take(prefix, token));
+        let mut keys = match cached {
+            Some(keys) => keys,
+            None => {
+                let mut keys = self.scan(prefix)?;
+                if let Some(token) = &continuation {
+                    // Empty if nothing sorts after the token, e.g. once the
+                    // last key of the previous page is deleted
+                    keys.drain(..keys.partition_point(|k| k <= token));
+                }
+                keys
+            }
+        };
+
+        // Simple pagination: 1000 per page
+        let page_size = 1000;
+        if keys.len() <= page_size {
+            return Ok((keys, None));
+        }
+        let rest = keys.split_off(page_size);
+        let token = keys[page_size - 1].clone();
+        self.listings.lock().unwrap().insert(prefix, &token, rest);
+        Ok((keys, Some(token)))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let redis_key = self.object_key(key)?;
+        let mut conn = self.conn.lock().unwrap();
+        ::redis::cmd("DEL")
+            .arg(redis_key)
+            .arg(self.tags_key(key))
+            .exec(&mut *conn)
+            .map_err(map_redis_err)
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        match self.fetch(key)? {
+            Some(value) => {
+                let (etag, data) = decode(&value)?;
+                Ok(Some(ObjectMeta {
+                    size: data.len() as u64,
+                    etag: etag.to_string(),
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        match self.fetch(key)? {
+            Some(value) => Ok(Some(slice_range(decode(&value)?.1, range).to_vec())),
+            None => Ok(None),
+        }
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        let (start, end) = match &opts.range {
+            Some(range) => (range.start, range.end.to_string()),
+            None => (0, String::new()),
+        };
+        let redis_key = self.object_key(key)?;
+        let reply: Option<(String, u64, Option<Vec<u8>>)> = {
+            let mut conn = self.conn.lock().unwrap();
+            self.read
+                .key(redis_key)
+                .arg(opts.if_match.unwrap_or(""))
+                .arg(opts.if_none_match.unwrap_or(""))
+                .arg(start)
+                .arg(end)
+                .invoke(&mut *conn)
+                .map_err(map_redis_err)?
+        };
+        let Some((etag, size, data)) = reply else {
+            return Ok(None);
+        };
+        if let Some(result) = check_conditions(&etag, &opts)? {
+            return Ok(Some(result));
+        }
+        // The script leaves the body out only when a condition fails
+        let data = data.ok_or_else(|| ObjectStoreError::Other("redis read script skipped the body".to_string()))?;
+        let meta = opts.include_metadata.then_some(ObjectMeta { size, etag });
+        Ok(Some(GetResult::Body { data, meta }))
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        let redis_key = self.object_key(key)?;
+        let json = serde_json::to_vec(tags).expect("tags serialize");
+        let mut conn = self.conn.lock().unwrap();
+        let written: i64 = self
+            .tag
+            .key(redis_key)
+            .key(self.tags_key(key))
+            .arg(json)
+            .invoke(&mut *conn)
+            .map_err(map_redis_err)?;
+        if written == 1 {
+            Ok(())
+        } else {
+            Err(ObjectStoreError::NotFound(key.to_string()))
+        }
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        let redis_key = self.object_key(key)?;
+        let (exists, json): (bool, Option<Vec<u8>>) = {
+            let mut conn = self.conn.lock().unwrap();
+            ::redis::pipe()
+                .atomic()
+                .cmd("EXISTS")
+                .arg(redis_key)
+                .cmd("GET")
+                .arg(self.tags_key(key))
+                .query(&mut *conn)
+                .map_err(map_redis_err)?
+        };
+        if !exists {
+            return Ok(None);
+        }
+        match json {
+            Some(json) => serde_json::from_slice(&json)
+                .map(Some)
+                .map_err(|e| ObjectStoreError::Other(format!("corrupt redis tags for {key}: {e}"))),
+            None => Ok(Some(BTreeMap::new())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let value = encode("abc123", b"body bytes");
+        let (etag, data) = decode(&value).unwrap();
+        assert_eq!(etag, "abc123");
+        assert_eq!(data, b"body bytes");
+
+        let empty = encode("e", b"");
+        let (etag, data) = decode(&empty).unwrap();
+        assert_eq!(etag, "e");
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_decode_rejects_truncated_value() {
+        assert!(decode(b"").is_err());
+        assert!(decode(&[10, b'a', b'b']).is_err());
+    }
+
+    #[test]
+    fn test_glob_escape() {
+        assert_eq!(glob_escape("plain/prefix/"), "plain/prefix/");
+        assert_eq!(glob_escape("a*b?[c]\\"), "a\\*b\\?\\[c\\]\\\\");
+    }
+
+    #[test]
+    fn test_listings_are_taken_once_and_bounded() {
+        let mut listings = Listings::default();
+        listings.insert("p/", "p/a", vec!["p/b".to_string()]);
+        assert_eq!(listings.take("q/", "p/a"), None);
+        assert_eq!(listings.take("p/", "p/a"), Some(vec!["p/b".to_string()]));
+        assert_eq!(listings.take("p/", "p/a"), None);
+
+        for i in 0..=MAX_LISTINGS {
+            listings.insert("", &i.to_string(), Vec::new());
+        }
+        assert_eq!(listings.take("", "0"), None);
+        assert_eq!(listings.take("", &MAX_LISTINGS.to_string()), Some(Vec::new()));
+    }
+}
@@ -0,0 +1,349 @@
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result, StoreOptions};
+use std::collections::BTreeMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Range;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Whether an error is worth retrying: I/O failures, throttling, timeouts
+/// and an unavailable backend. Everything else, including backend errors
+/// nobody classified, is permanent.
+pub fn is_transient(e: &ObjectStoreError) -> bool {
+    match e {
+        ObjectStoreError::Io(_)
+        | ObjectStoreError::Throttled(_)
+        | ObjectStoreError::Timeout(_)
+        | ObjectStoreError::Unavailable(_) => true,
+        ObjectStoreError::NotFound(_)
+        | ObjectStoreError::PreconditionFailed
+        | ObjectStoreError::PermissionDenied(_)
+        | ObjectStoreError::Blocked(_)
+        | ObjectStoreError::Unsupported(_)
+        | ObjectStoreError::ChecksumMismatch(_)
+        | ObjectStoreError::QuotaExceeded(_)
+        | ObjectStoreError::ReadOnly(_)
+        | ObjectStoreError::InvalidKey(_)
+        | ObjectStoreError::InvalidArgument(_)
+        | ObjectStoreError::Backend { .. }
+        | ObjectStoreError::Other(_) => false,
+        ObjectStoreError::Context { source, .. } => is_transient(source),
+    }
+}
+
+// Attempts a compare-and-swap update makes before giving up under contention
+pub(crate) const MAX_CAS_ATTEMPTS: u32 = 32;
+
+// A random time up to `ceiling`
+fn jitter(ceiling: Duration) -> Duration {
+    // RandomState is randomly seeded, which is all the jitter needs
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_nanos(random % (ceiling.as_nanos() as u64 + 1))
+}
+
+// Waits after the nth failed attempt of a compare-and-swap update: a random
+// time up to 1ms doubling per attempt, capped at 100ms, so writers racing
+// for one object spread out instead of colliding again
+pub(crate) fn cas_backoff(attempt: u32) {
+    let ceiling = Duration::from_millis(1).saturating_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX));
+    thread::sleep(jitter(ceiling.min(Duration::from_millis(100))));
+}
+
+/// Wraps a store so transient failures are retried with exponential
+/// backoff and full jitter.
+///
+/// The nth retry waits a random time up to `initial * 2^(n-1)`, capped at
+/// `max`. Retrying stops after `max_attempts` calls in total, or when the
+/// next wait would pass the deadline, whichever comes first. The last error
+/// is returned. A conditional put whose response was lost may come back as
+/// `PreconditionFailed` on retry even though the first attempt succeeded.
+///
+/// Over a shared `Arc` store, cloning is cheap, and a clone's builder
+/// methods change only that clone's retry policy. `with_options` does both
+/// at once for one code path.
+#[derive(Clone)]
+pub struct RetryingStore<S> {
+    inner: S,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    deadline: Option<Duration>,
+}
+
+impl<S: ObjectStore> RetryingStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            deadline: None,
+        }
+    }
+
+    pub fn with_max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    // Upper bound on the total time spent on one call, including retries
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// A copy of this store whose retry policy takes the timeout (as the
+    /// deadline), attempts and initial backoff set in `options`. The inner
+    /// store is shared as is; priority means nothing here.
+    pub fn with_options(&self, options: &StoreOptions) -> Self
+    where
+        S: Clone,
+    {
+        let mut store = self.clone();
+        if let Some(timeout) = options.timeout {
+            store = store.with_deadline(timeout);
+        }
+        if let Some(attempts) = options.max_attempts {
+            store = store.with_max_attempts(attempts);
+        }
+        if let Some(initial) = options.initial_backoff {
+            let max = store.max_backoff;
+            store = store.with_backoff(initial, max);
+        }
+        store
+    }
+
+    fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self
+            .initial_backoff
+            .saturating_mul(1u32.checked_shl(retry).unwrap_or(u32::MAX))
+            .min(self.max_backoff);
+        jitter(ceiling)
+    }
+
+    fn retry<T>(&self, mut op: impl FnMut() -> Result<T>) -> Result<T> {
+        let started = Instant::now();
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Err(e) if is_transient(&e) && attempt < self.max_attempts => {
+                    let wait = self.backoff(attempt - 1);
+                    if self.deadline.is_some_and(|deadline| started.elapsed() + wait > deadline) {
+                        return Err(e.with_attempt(attempt));
+                    }
+                    thread::sleep(wait);
+                    attempt += 1;
+                }
+                result => return result.map_err(|e| e.with_attempt(attempt)),
+            }
+        }
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for RetryingStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.retry(|| self.inner.get(key))
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        self.retry(|| self.inner.put(key, body, cond.clone()))
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        self.retry(|| self.inner.list(prefix, continuation.clone()))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.retry(|| self.inner.delete(key))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.retry(|| self.inner.head(key))
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.retry(|| self.inner.get_range(key, range.clone()))
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.retry(|| self.inner.get_opts(key, opts.clone()))
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.retry(|| self.inner.put_tags(key, tags))
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.retry(|| self.inner.get_tags(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use std::sync::{Arc, Mutex};
+    use uuid::Uuid;
+
+    // Fails calls with the queued errors first, then behaves normally
+    struct FailingStore {
+        inner: InMemoryStore,
+        failures: Mutex<Vec<ObjectStoreError>>,
+        calls: Mutex<u32>,
+    }
+
+    impl FailingStore {
+        fn new(failures: Vec<ObjectStoreError>) -> Self {
+            Self {
+                inner: InMemoryStore::default(),
+                failures: Mutex::new(failures),
+                calls: Mutex::new(0),
+            }
+        }
+
+        fn next(&self) -> Result<()> {
+            *self.calls.lock().unwrap() += 1;
+            let mut failures = self.failures.lock().unwrap();
+            if failures.is_empty() { Ok(()) } else { Err(failures.remove(0)) }
+        }
+
+        fn calls(&self) -> u32 {
+            *self.calls.lock().unwrap()
+        }
+    }
+
+    impl ObjectStore for FailingStore {
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            self.next()?;
+            self.inner.get(key)
+        }
+        fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+            self.next()?;
+            self.inner.put(key, body, cond)
+        }
+        fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+            self.next()?;
+            self.inner.list(prefix, continuation)
+        }
+        fn delete(&self, key: &str) -> Result<()> {
+            self.next()?;
+            self.inner.delete(key)
+        }
+        fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+            self.next()?;
+            self.inner.put_tags(key, tags)
+        }
+        fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+            self.next()?;
+            self.inner.get_tags(key)
+        }
+    }
+
+    fn io_error() -> ObjectStoreError {
+        ObjectStoreError::Io(std::io::Error::other("connection reset"))
+    }
+
+    fn fast<S: ObjectStore>(inner: S) -> RetryingStore<S> {
+        RetryingStore::new(inner).with_backoff(Duration::from_millis(1), Duration::from_millis(5))
+    }
+
+    #[test]
+    fn test_retrying_object_store() {
+        let store = fast(FailingStore::new(Vec::new()));
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_retries_transient_errors() {
+        let store = fast(FailingStore::new(vec![
+            io_error(),
+            ObjectStoreError::Throttled("SlowDown".to_string()),
+        ]));
+        store.put("k", b"v", IfMatch::Any).unwrap();
+        assert_eq!(store.inner().calls(), 3);
+        assert_eq!(store.get("k").unwrap(), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    fn test_gives_up_after_max_attempts() {
+        let store = fast(FailingStore::new((0..10).map(|_| io_error()).collect())).with_max_attempts(3);
+        assert!(matches!(store.get("k"), Err(ObjectStoreError::Io(_))));
+        assert_eq!(store.inner().calls(), 3);
+    }
+
+    #[test]
+    fn test_never_retries_permanent_errors() {
+        for error in [
+            ObjectStoreError::PreconditionFailed,
+            ObjectStoreError::Blocked("virus".to_string()),
+            ObjectStoreError::PermissionDenied("AccessDenied".to_string()),
+        ] {
+            let store = fast(FailingStore::new(vec![error]));
+            assert!(store.put("k", b"v", IfMatch::Any).is_err());
+            assert_eq!(store.inner().calls(), 1);
+        }
+    }
+
+    #[test]
+    fn test_deadline_stops_retrying() {
+        let store = RetryingStore::new(FailingStore::new((0..100).map(|_| io_error()).collect()))
+            .with_max_attempts(100)
+            .with_backoff(Duration::from_millis(20), Duration::from_millis(20))
+            .with_deadline(Duration::from_millis(50));
+        let started = Instant::now();
+        assert!(store.get("k").is_err());
+        // Sleeps may overshoot, so only a gross overrun fails
+        assert!(started.elapsed() < Duration::from_millis(150));
+        assert!(store.inner().calls() < 100);
+    }
+
+    #[test]
+    fn test_clones_override_policy() {
+        let shared = Arc::new(FailingStore::new((0..10).map(|_| io_error()).collect()));
+        let patient = fast(shared.clone()).with_max_attempts(4);
+        let impatient = patient.clone().with_max_attempts(1);
+
+        assert!(impatient.get("k").is_err());
+        assert_eq!(shared.calls(), 1);
+        assert!(patient.get("k").is_err());
+        assert_eq!(shared.calls(), 5);
+    }
+
+    #[test]
+    fn test_with_options() {
+        let shared = Arc::new(FailingStore::new((0..20).map(|_| io_error()).collect()));
+        let store = fast(shared.clone()).with_max_attempts(5);
+        let options = StoreOptions {
+            max_attempts: Some(2),
+            ..StoreOptions::default()
+        };
+        assert!(store.with_options(&options).get("k").is_err());
+        assert_eq!(shared.calls(), 2);
+        // Unset fields and the original handle keep their policy
+        assert!(store.with_options(&StoreOptions::default()).get("k").is_err());
+        assert_eq!(shared.calls(), 7);
+        assert!(store.get("k").is_err());
+        assert_eq!(shared.calls(), 12);
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let store = RetryingStore::new(InMemoryStore::default())
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(300));
+        for retry in 0..40 {
+            assert!(store.backoff(retry) <= Duration::from_millis(300));
+        }
+        assert!(store.backoff(0) <= Duration::from_millis(100));
+    }
+}
@@ -1,30 +1,653 @@
-use super::{IfMatch, ObjectStore, ObjectStoreError, Result};
+use super::{
+    get_opts_fallback, GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Priority, Result,
+    StoreOptions,
+};
 use aws_sdk_s3::{Client};
-use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::config::retry::{RetryConfig, RetryMode};
+use aws_sdk_s3::config::timeout::{TimeoutConfig, TimeoutConfigBuilder};
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_s3::operation::head_object::HeadObjectOutput;
+use aws_sdk_s3::primitives::{ByteStream, ByteStreamError};
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart, ServerSideEncryption, Tag, Tagging};
+use aws_smithy_types::base64;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::runtime::Runtime;
+use std::time::{Duration, SystemTime};
+use tokio::runtime::{Handle, Runtime, RuntimeFlavor};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::{JoinError, JoinSet};
 
+/// How `put` enforces `IfMatch` conditions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Preconditions {
+    // If-Match / If-None-Match on the PutObject request, checked atomically
+    // by S3. An endpoint that answers 501 Not Implemented is switched to
+    // emulation for the rest of the store's life.
+    #[default]
+    Native,
+    // A head before the put. Another writer can slip in between, so only
+    // for endpoints that ignore the headers instead of rejecting them.
+    Emulated,
+}
+
+/// A multipart upload that was started and not yet completed or aborted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultipartUpload {
+    pub key: String,
+    pub upload_id: String,
+    pub initiated: SystemTime,
+}
+
+/// Server-side encryption for the objects a store writes.
+#[derive(Clone, PartialEq, Eq, Default)]
+pub enum Encryption {
+    // Whatever the bucket's default encryption says
+    #[default]
+    BucketDefault,
+    // SSE-S3: AES-256 with keys S3 manages
+    S3Managed,
+    // SSE-KMS with this key ID, alias or ARN, or the account's aws/s3 key
+    // if None. An S3 Bucket Key cuts the number of KMS requests.
+    Kms { key_id: Option<String>, bucket_key: bool },
+    // SSE-C: S3 encrypts with this AES-256 key and does not keep it, so
+    // every read needs it too and a lost key means lost data
+    CustomerKey([u8; 32]),
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Encryption::BucketDefault => f.write_str("BucketDefault"),
+            Encryption::S3Managed => f.write_str("S3Managed"),
+            Encryption::Kms { key_id, bucket_key } => {
+                f.debug_struct("Kms").field("key_id", key_id).field("bucket_key", bucket_key).finish()
+            }
+            // Never print the key itself
+            Encryption::CustomerKey(_) => f.write_str("CustomerKey(..)"),
+        }
+    }
+}
+
+// SSE-C request headers; all None unless the store has a customer key
+#[derive(Clone, Default)]
+struct CustomerKeyHeaders {
+    algorithm: Option<String>,
+    key: Option<String>,
+    md5: Option<String>,
+}
+
+/// Clones share the client, runtime and connection limit, so handing a
+/// code path its own `with_options` copy is cheap.
+#[derive(Clone)]
 pub struct S3Store {
     client: Arc<Client>,
     bucket: String,
-    rt: Arc<Runtime>,
+    handle: Handle,
+    // Only when the store had to build its own runtime; keeps it alive
+    runtime: Option<Arc<OwnedRuntime>>,
+    preconditions: Preconditions,
+    native_rejected: Arc<AtomicBool>,
+    multipart_threshold: usize,
+    part_size: usize,
+    upload_concurrency: usize,
+    connections: Connections,
+    priority: Priority,
+    encryption: Encryption,
+    customer_key: CustomerKeyHeaders,
+}
+
+// Request permits shared by every clone of a store: one of `all` per
+// request in flight, and before it one of `background`, half as many, for
+// background handles
+#[derive(Clone)]
+struct Connections {
+    all: Arc<Semaphore>,
+    background: Arc<Semaphore>,
 }
 
+impl Connections {
+    fn new(limit: usize) -> Self {
+        let limit = limit.clamp(1, Semaphore::MAX_PERMITS);
+        Self {
+            all: Arc::new(Semaphore::new(limit)),
+            background: Arc::new(Semaphore::new((limit / 2).max(1))),
+        }
+    }
+
+    // Neither semaphore is ever closed, so the permits are always there
+    async fn acquire(self, priority: Priority) -> (Option<OwnedSemaphorePermit>, Option<OwnedSemaphorePermit>) {
+        let background = match priority {
+            Priority::Background => self.background.acquire_owned().await.ok(),
+            Priority::Normal => None,
+        };
+        (background, self.all.acquire_owned().await.ok())
+    }
+}
+
+// A runtime the store started for itself. Shut down without waiting, as
+// dropping a runtime from async code the usual way panics.
+struct OwnedRuntime(Option<Runtime>);
+
+impl Drop for OwnedRuntime {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+// S3 rejects parts below 5 MiB, except the last
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
 impl S3Store {
+    /// Runs requests on the multi-thread Tokio runtime `new` is called
+    /// from, if any, and otherwise on a runtime of its own.
     pub fn new(bucket: String, client: Client) -> Self {
-        let rt = Arc::new(
-            Runtime::new().expect("Failed to create Tokio runtime")
-        );
+        let (handle, runtime) = match Handle::try_current() {
+            // A current-thread runtime only makes progress inside its own
+            // block_on, so a blocking call from outside would hang
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => (handle, None),
+            _ => {
+                let runtime = Runtime::new().expect("Failed to create Tokio runtime");
+                (runtime.handle().clone(), Some(Arc::new(OwnedRuntime(Some(runtime)))))
+            }
+        };
         Self {
             client: Arc::new(client),
             bucket,
-            rt,
+            handle,
+            runtime,
+            preconditions: Preconditions::default(),
+            native_rejected: Arc::new(AtomicBool::new(false)),
+            multipart_threshold: 64 * 1024 * 1024,
+            part_size: 16 * 1024 * 1024,
+            upload_concurrency: 4,
+            connections: Connections::new(Semaphore::MAX_PERMITS),
+            priority: Priority::default(),
+            encryption: Encryption::default(),
+            customer_key: CustomerKeyHeaders::default(),
+        }
+    }
+
+    /// Encrypts objects written from now on with `encryption`. Objects
+    /// already stored keep theirs; with a customer key, those written
+    /// under another key can no longer be read through this store.
+    pub fn with_encryption(mut self, encryption: Encryption) -> Self {
+        self.customer_key = match &encryption {
+            Encryption::CustomerKey(key) => CustomerKeyHeaders {
+                algorithm: Some("AES256".to_string()),
+                key: Some(base64::encode(key)),
+                md5: Some(base64::encode(md5::compute(key).0)),
+            },
+            _ => CustomerKeyHeaders::default(),
+        };
+        self.encryption = encryption;
+        self
+    }
+
+    /// Runs requests on `handle`'s runtime instead of the one picked by
+    /// `new`. Fails with `InvalidArgument` unless it's a multi-thread
+    /// runtime, as a current-thread one only makes progress inside its own
+    /// `block_on`.
+    pub fn with_runtime(mut self, handle: Handle) -> Result<Self> {
+        if handle.runtime_flavor() != RuntimeFlavor::MultiThread {
+            return Err(ObjectStoreError::InvalidArgument(
+                "S3Store needs a multi-thread runtime".to_string(),
+            ));
+        }
+        self.handle = handle;
+        self.runtime = None;
+        Ok(self)
+    }
+
+    // Caps how long a whole call may take, retries included
+    pub fn with_operation_timeout(self, timeout: Duration) -> Self {
+        self.with_timeouts(|timeouts| timeouts.operation_timeout(timeout))
+    }
+
+    // Caps how long each attempt of a call may take
+    pub fn with_attempt_timeout(self, timeout: Duration) -> Self {
+        self.with_timeouts(|timeouts| timeouts.operation_attempt_timeout(timeout))
+    }
+
+    pub fn with_connect_timeout(self, timeout: Duration) -> Self {
+        self.with_timeouts(|timeouts| timeouts.connect_timeout(timeout))
+    }
+
+    // Caps the wait for the first byte of a response
+    pub fn with_read_timeout(self, timeout: Duration) -> Self {
+        self.with_timeouts(|timeouts| timeouts.read_timeout(timeout))
+    }
+
+    // Attempts per call, the first included; 1 turns the SDK's retries off
+    pub fn with_max_attempts(self, attempts: u32) -> Self {
+        self.with_retries(|retries| retries.with_max_attempts(attempts.max(1)))
+    }
+
+    pub fn with_initial_backoff(self, backoff: Duration) -> Self {
+        self.with_retries(|retries| retries.with_initial_backoff(backoff))
+    }
+
+    // Adaptive retries also slow the client down while S3 is throttling it
+    pub fn with_adaptive_retries(self, adaptive: bool) -> Self {
+        let mode = if adaptive { RetryMode::Adaptive } else { RetryMode::Standard };
+        self.with_retries(|retries| retries.with_retry_mode(mode))
+    }
+
+    // Requests in flight at once across every thread using the store,
+    // multipart parts included
+    pub fn with_max_connections(mut self, connections: usize) -> Self {
+        self.connections = Connections::new(connections);
+        self
+    }
+
+    /// A copy of this store with `options` applied: the timeout caps whole
+    /// calls, attempts and backoff go to the SDK's retries, and background
+    /// priority keeps the copy to half the connections. Only a copy with a
+    /// new timeout or retry setting builds a client of its own.
+    pub fn with_options(&self, options: &StoreOptions) -> Self {
+        let mut store = self.clone();
+        if let Some(timeout) = options.timeout {
+            store = store.with_operation_timeout(timeout);
+        }
+        if let Some(attempts) = options.max_attempts {
+            store = store.with_max_attempts(attempts);
         }
+        if let Some(backoff) = options.initial_backoff {
+            store = store.with_initial_backoff(backoff);
+        }
+        if let Some(priority) = options.priority {
+            store.priority = priority;
+        }
+        store
+    }
+
+    fn with_timeouts(self, set: impl FnOnce(TimeoutConfigBuilder) -> TimeoutConfigBuilder) -> Self {
+        let config = self.client.config();
+        let timeouts = config.timeout_config().map(TimeoutConfig::to_builder).unwrap_or_default();
+        let config = config.to_builder().timeout_config(set(timeouts).build()).build();
+        Self { client: Arc::new(Client::from_conf(config)), ..self }
+    }
+
+    fn with_retries(self, set: impl FnOnce(RetryConfig) -> RetryConfig) -> Self {
+        let config = self.client.config();
+        let retries = config.retry_config().cloned().unwrap_or_else(RetryConfig::standard);
+        let config = config.to_builder().retry_config(set(retries)).build();
+        Self { client: Arc::new(Client::from_conf(config)), ..self }
+    }
+
+    // The blocking bridge behind every ObjectStore method. Called from a
+    // task on a multi-thread runtime, it hands the worker thread's other
+    // tasks off first so they keep running. A current-thread runtime has
+    // no thread to spare and no runtime may be entered from inside one, so
+    // there the call waits on a thread of its own and the runtime's other
+    // tasks wait with it.
+    fn block_on<F>(&self, future: F) -> F::Output
+    where
+        F: Future + Send,
+        F::Output: Send,
+    {
+        match Handle::try_current() {
+            Ok(current) if current.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| self.handle.block_on(future))
+            }
+            Ok(_) => std::thread::scope(|scope| {
+                scope
+                    .spawn(|| self.handle.block_on(future))
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            }),
+            Err(_) => self.handle.block_on(future),
+        }
+    }
+
+    // Encryption headers for PutObject and CreateMultipartUpload; SSE-C
+    // goes on every request that touches object data instead
+    fn encryption_headers(&self) -> (Option<ServerSideEncryption>, Option<String>, Option<bool>) {
+        match &self.encryption {
+            Encryption::S3Managed => (Some(ServerSideEncryption::Aes256), None, None),
+            Encryption::Kms { key_id, bucket_key } => (Some(ServerSideEncryption::AwsKms), key_id.clone(), Some(*bucket_key)),
+            Encryption::BucketDefault | Encryption::CustomerKey(_) => (None, None, None),
+        }
+    }
+
+    // Holds one of the `max_connections` permits for the length of `request`
+    async fn limited<T>(&self, request: impl Future<Output = T>) -> T {
+        let _permits = self.connections.clone().acquire(self.priority).await;
+        request.await
+    }
+
+    pub fn with_preconditions(mut self, preconditions: Preconditions) -> Self {
+        self.preconditions = preconditions;
+        self
+    }
+
+    // Bodies of at least `threshold` bytes go up as a multipart upload in
+    // parts of `part_size` bytes, raised to S3's 5 MiB minimum if lower
+    pub fn with_multipart(mut self, threshold: usize, part_size: usize) -> Self {
+        self.multipart_threshold = threshold;
+        self.part_size = part_size.max(MIN_PART_SIZE);
+        self
+    }
+
+    // How many parts of one multipart upload are in flight at once
+    pub fn with_upload_concurrency(mut self, parts: usize) -> Self {
+        self.upload_concurrency = parts.max(1);
+        self
     }
 
     fn compute_etag(data: &[u8]) -> String {
         format!("{:x}", md5::compute(data))
     }
+
+    // S3's ETag for a multipart object: the MD5 of the parts' MD5s, then
+    // a dash and the number of parts
+    fn multipart_etag(data: &[u8], part_size: usize) -> String {
+        let digests: Vec<u8> = data.chunks(part_size).flat_map(|part| md5::compute(part).0).collect();
+        format!("{:x}-{}", md5::compute(&digests), data.len().div_ceil(part_size))
+    }
+
+    /// Multipart uploads under `prefix` that were started but never
+    /// completed or aborted, oldest first.
+    pub fn list_multipart_uploads(&self, prefix: &str) -> Result<Vec<MultipartUpload>> {
+        self.block_on(self.limited(async {
+            let mut uploads = Vec::new();
+            let (mut key_marker, mut upload_id_marker) = (None, None);
+            loop {
+                let resp = self
+                    .client
+                    .list_multipart_uploads()
+                    .bucket(&self.bucket)
+                    .prefix(prefix)
+                    .set_key_marker(key_marker)
+                    .set_upload_id_marker(upload_id_marker)
+                    .send()
+                    .await
+                    .map_err(|e| s3_error("list multipart uploads", e))?;
+                uploads.extend(resp.uploads().iter().filter_map(|upload| {
+                    Some(MultipartUpload {
+                        key: upload.key()?.to_string(),
+                        upload_id: upload.upload_id()?.to_string(),
+                        initiated: SystemTime::try_from(*upload.initiated()?).ok()?,
+                    })
+                }));
+                if !resp.is_truncated().unwrap_or(false) {
+                    break;
+                }
+                key_marker = resp.next_key_marker().map(str::to_string);
+                upload_id_marker = resp.next_upload_id_marker().map(str::to_string);
+            }
+            uploads.sort_by_key(|upload| upload.initiated);
+            Ok(uploads)
+        }))
+    }
+
+    /// Aborts every incomplete multipart upload started more than
+    /// `older_than` ago, freeing the storage its parts hold, and returns
+    /// them. Meant to run periodically, e.g. from a `LeaderElector`'s
+    /// leader or a `Queue` task. `older_than` should comfortably exceed the
+    /// longest upload in progress, which would otherwise fail.
+    pub fn abort_stale_uploads(&self, older_than: Duration) -> Result<Vec<MultipartUpload>> {
+        let cutoff = SystemTime::now().checked_sub(older_than).unwrap_or(SystemTime::UNIX_EPOCH);
+        let stale: Vec<_> =
+            self.list_multipart_uploads("")?.into_iter().filter(|upload| upload.initiated < cutoff).collect();
+        self.block_on(async {
+            for upload in &stale {
+                let request = self
+                    .client
+                    .abort_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(&upload.key)
+                    .upload_id(&upload.upload_id)
+                    .send();
+                let aborted = self.limited(request).await;
+                match aborted {
+                    // Completed or aborted since it was listed
+                    Err(e) if e.as_service_error().is_some_and(|se| se.is_no_such_upload()) => {}
+                    Err(e) => return Err(s3_error("abort multipart upload", e)),
+                    Ok(_) => {}
+                }
+            }
+            Ok(())
+        })?;
+        Ok(stale)
+    }
+
+    // Ok(None) when the endpoint turned down the conditional headers
+    async fn put_object(&self, key: &str, body: &[u8], cond: &IfMatch<'_>, native: bool) -> Result<Option<String>> {
+        if !native {
+            self.limited(check_emulated(&self.client, &self.bucket, key, cond, &self.customer_key)).await?;
+        }
+        if body.len() >= self.multipart_threshold {
+            return self.put_multipart(key, body, cond, native).await;
+        }
+
+        let (sse, kms_key_id, bucket_key) = self.encryption_headers();
+        let sse_c = &self.customer_key;
+        let request = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .set_server_side_encryption(sse)
+            .set_ssekms_key_id(kms_key_id)
+            .set_bucket_key_enabled(bucket_key)
+            .set_sse_customer_algorithm(sse_c.algorithm.clone())
+            .set_sse_customer_key(sse_c.key.clone())
+            .set_sse_customer_key_md5(sse_c.md5.clone());
+        let request = match (cond, native) {
+            (IfMatch::Tag(expected_etag), true) => request.if_match(quote_etag(expected_etag)),
+            (IfMatch::NoneMatch, true) => request.if_none_match("*"),
+            _ => request,
+        };
+        match self.limited(request.body(ByteStream::from(body.to_vec())).send()).await {
+            // S3 returns ETag as a quoted string
+            Ok(resp) => Ok(Some(
+                resp.e_tag().map(|s| s.trim_matches('"').to_string()).unwrap_or_else(|| Self::compute_etag(body)),
+            )),
+            Err(e) => conditional_put_error(e, cond, native),
+        }
+    }
+
+    async fn put_multipart(&self, key: &str, body: &[u8], cond: &IfMatch<'_>, native: bool) -> Result<Option<String>> {
+        let (sse, kms_key_id, bucket_key) = self.encryption_headers();
+        let sse_c = &self.customer_key;
+        let request = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .set_server_side_encryption(sse)
+            .set_ssekms_key_id(kms_key_id)
+            .set_bucket_key_enabled(bucket_key)
+            .set_sse_customer_algorithm(sse_c.algorithm.clone())
+            .set_sse_customer_key(sse_c.key.clone())
+            .set_sse_customer_key_md5(sse_c.md5.clone())
+            .send();
+        let upload = self.limited(request).await.map_err(|e| s3_error("create multipart upload", e))?;
+        let upload_id = upload
+            .upload_id()
+            .ok_or_else(|| ObjectStoreError::Other(format!("S3 started no upload id for {key}")))?;
+
+        let result = self.complete_multipart(key, body, cond, native, upload_id).await;
+        if !matches!(result, Ok(Some(_))) {
+            // Parts of an abandoned upload are stored, and billed, until aborted.
+            // Should the abort fail too, the original error is the one to report.
+            let request = self.client.abort_multipart_upload().bucket(&self.bucket).key(key).upload_id(upload_id).send();
+            let _ = self.limited(request).await;
+        }
+        result
+    }
+
+    async fn complete_multipart(
+        &self,
+        key: &str,
+        body: &[u8],
+        cond: &IfMatch<'_>,
+        native: bool,
+        upload_id: &str,
+    ) -> Result<Option<String>> {
+        let mut uploads = JoinSet::new();
+        let mut parts = Vec::new();
+        for (index, chunk) in body.chunks(self.part_size).enumerate() {
+            if uploads.len() >= self.upload_concurrency {
+                parts.push(joined(uploads.join_next().await)?);
+            }
+            let request = self
+                .client
+                .upload_part()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(index as i32 + 1)
+                .set_sse_customer_algorithm(self.customer_key.algorithm.clone())
+                .set_sse_customer_key(self.customer_key.key.clone())
+                .set_sse_customer_key_md5(self.customer_key.md5.clone())
+                .body(ByteStream::from(chunk.to_vec()));
+            let permits = self.connections.clone().acquire(self.priority);
+            uploads.spawn(async move {
+                let _permits = permits.await;
+                let resp = request.send().await.map_err(|e| s3_error("upload part", e))?;
+                let part = CompletedPart::builder().part_number(index as i32 + 1).set_e_tag(resp.e_tag);
+                Ok(part.build())
+            });
+        }
+        // Dropping the set on an error cancels the parts still in flight
+        while let Some(part) = uploads.join_next().await {
+            parts.push(joined(Some(part))?);
+        }
+        parts.sort_by_key(|part| part.part_number());
+
+        let request = self
+            .client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build());
+        let request = match (cond, native) {
+            (IfMatch::Tag(expected_etag), true) => request.if_match(quote_etag(expected_etag)),
+            (IfMatch::NoneMatch, true) => request.if_none_match("*"),
+            _ => request,
+        };
+        match self.limited(request.send()).await {
+            Ok(resp) => Ok(Some(
+                resp.e_tag()
+                    .map(|s| s.trim_matches('"').to_string())
+                    .unwrap_or_else(|| Self::multipart_etag(body, self.part_size)),
+            )),
+            Err(e) => conditional_put_error(e, cond, native),
+        }
+    }
+}
+
+fn joined(part: Option<std::result::Result<Result<CompletedPart>, JoinError>>) -> Result<CompletedPart> {
+    match part {
+        Some(Ok(part)) => part,
+        Some(Err(e)) => Err(ObjectStoreError::Other(format!("S3 part upload task failed: {e}"))),
+        None => Err(ObjectStoreError::Other("S3 part upload task went missing".to_string())),
+    }
+}
+
+// The head-then-put check for endpoints without conditional writes
+async fn check_emulated(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    cond: &IfMatch<'_>,
+    sse_c: &CustomerKeyHeaders,
+) -> Result<()> {
+    match cond {
+        IfMatch::Any => Ok(()),
+        IfMatch::Tag(expected_etag) => {
+            let head = head_object(client, bucket, key, sse_c).await?;
+            let current_etag = head.as_ref().and_then(|meta| meta.e_tag()).map(|s| s.trim_matches('"'));
+            if current_etag == Some(*expected_etag) { Ok(()) } else { Err(ObjectStoreError::PreconditionFailed) }
+        }
+        IfMatch::NoneMatch => match head_object(client, bucket, key, sse_c).await? {
+            Some(_) => Err(ObjectStoreError::PreconditionFailed),
+            None => Ok(()),
+        },
+    }
+}
+
+// How a write that may have carried If-Match / If-None-Match failed; Ok(None)
+// when the endpoint does not implement the headers
+fn conditional_put_error<E>(e: SdkError<E, HttpResponse>, cond: &IfMatch<'_>, native: bool) -> Result<Option<String>>
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    match e.raw_response().map(|r| r.status().as_u16()) {
+        // 409 is a conditional write that lost to a concurrent one
+        Some(412 | 409) => Err(ObjectStoreError::PreconditionFailed),
+        // If-Match on a key that does not exist
+        Some(404) if matches!(cond, IfMatch::Tag(_)) => Err(ObjectStoreError::PreconditionFailed),
+        Some(501) if native && !matches!(cond, IfMatch::Any) => Ok(None),
+        _ => Err(s3_error("put", e)),
+    }
+}
+
+// Sorts a failed request into the error class callers act on, keeping
+// the SDK error as the source of anything unclassified
+fn s3_error<E>(op: &str, e: SdkError<E, HttpResponse>) -> ObjectStoreError
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    let message = format!("S3 {op} error: {}", DisplayErrorContext(&e));
+    let status = e.raw_response().map(|r| r.status().as_u16());
+    match (&e, status, e.code()) {
+        (SdkError::TimeoutError(_), _, _) | (_, _, Some("RequestTimeout")) => ObjectStoreError::Timeout(message),
+        (SdkError::DispatchFailure(_), _, _) => ObjectStoreError::Unavailable(message),
+        (_, Some(429), _)
+        | (_, _, Some("SlowDown" | "Throttling" | "ThrottlingException" | "TooManyRequests" | "RequestLimitExceeded")) => {
+            ObjectStoreError::Throttled(message)
+        }
+        (_, Some(403), _) | (_, _, Some("AccessDenied")) => ObjectStoreError::PermissionDenied(message),
+        (_, Some(500..=599), _) => ObjectStoreError::Unavailable(message),
+        _ => ObjectStoreError::backend(message, e),
+    }
+}
+
+// Only the typed error counts; a denied request is not a missing key
+fn is_no_such_key(e: &SdkError<GetObjectError, HttpResponse>) -> bool {
+    e.as_service_error().is_some_and(GetObjectError::is_no_such_key)
+}
+
+async fn head_object(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    sse_c: &CustomerKeyHeaders,
+) -> Result<Option<HeadObjectOutput>> {
+    let request = client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .set_sse_customer_algorithm(sse_c.algorithm.clone())
+        .set_sse_customer_key(sse_c.key.clone())
+        .set_sse_customer_key_md5(sse_c.md5.clone());
+    match request.send().await {
+        Ok(meta) => Ok(Some(meta)),
+        Err(e) if e.as_service_error().is_some_and(|se| se.is_not_found()) => Ok(None),
+        Err(e) => Err(s3_error("head", e)),
+    }
+}
+
+// A body that fails part way is a broken connection, so worth retrying
+fn body_error(e: ByteStreamError) -> ObjectStoreError {
+    ObjectStoreError::Io(io::Error::other(e))
+}
+
+// Conditional headers want quoted ETags, except for the "*" wildcard
+fn quote_etag(etag: &str) -> String {
+    if etag == "*" { etag.to_string() } else { format!("\"{etag}\"") }
 }
 
 impl ObjectStore for S3Store {
@@ -32,84 +655,40 @@ impl ObjectStore for S3Store {
         let client = self.client.clone();
         let bucket = self.bucket.clone();
         let key = key.to_string();
+        let sse_c = self.customer_key.clone();
 
-        let result = self.rt.block_on(async move {
+        self.block_on(self.limited(async move {
             let resp = client
                 .get_object()
                 .bucket(&bucket)
                 .key(&key)
+                .set_sse_customer_algorithm(sse_c.algorithm)
+                .set_sse_customer_key(sse_c.key)
+                .set_sse_customer_key_md5(sse_c.md5)
                 .send()
                 .await;
 
             match resp {
                 Ok(obj) => {
                     let data = obj.body.collect().await
-                        .map_err(|e| ObjectStoreError::Other(format!("S3 body error: {e}")))?;
+                        .map_err(body_error)?;
                     Ok(Some(data.into_bytes().to_vec()))
                 }
-                Err(e) => {
-                    let err_str = e.to_string();
-                    if err_str.contains("NoSuchKey") {
-                        Ok(None)
-                    } else {
-                        Err(ObjectStoreError::Other(format!("S3 error: {e}")))
-                    }
-                }
+                Err(e) if is_no_such_key(&e) => Ok(None),
+                Err(e) => Err(s3_error("get", e)),
             }
-        });
-
-        result
+        }))
     }
 
     fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
-        let client = self.client.clone();
-        let bucket = self.bucket.clone();
-        let key = key.to_string();
-        let body_vec = body.to_vec();
-        let etag = Self::compute_etag(body);
-
-        let result = self.rt.block_on(async move {
-            // Handle preconditions
-            match cond {
-                IfMatch::Any => {
-                    // Always write
-                }
-                IfMatch::Tag(expected_etag) => {
-                    // Fetch current ETag
-                    let head = client.head_object().bucket(&bucket).key(&key).send().await;
-                    let current_etag = match head {
-                        Ok(meta) => meta.e_tag().map(|s| s.trim_matches('"').to_string()),
-                        Err(_) => None,
-                    };
-                    if current_etag.as_deref() != Some(expected_etag) {
-                        return Err(ObjectStoreError::PreconditionFailed);
-                    }
-                }
-                IfMatch::NoneMatch => {
-                    let head = client.head_object().bucket(&bucket).key(&key).send().await;
-                    if head.is_ok() {
-                        return Err(ObjectStoreError::PreconditionFailed);
-                    }
-                }
+        let native = self.preconditions == Preconditions::Native && !self.native_rejected.load(Ordering::Relaxed);
+        match self.block_on(self.put_object(key, body, &cond, native))? {
+            Some(etag) => Ok(etag),
+            None => {
+                self.native_rejected.store(true, Ordering::Relaxed);
+                self.put(key, body, cond)
             }
-
-            // Upload
-            let resp = client
-                .put_object()
-                .bucket(&bucket)
-                .key(&key)
-                .body(ByteStream::from(body_vec))
-                .send()
-                .await
-                .map_err(|e| ObjectStoreError::Other(format!("S3 put error: {e}")))?;
-
-            // S3 returns ETag as a quoted string
-            let s3_etag = resp.e_tag().map(|s| s.trim_matches('"').to_string()).unwrap_or(etag);
-
-            Ok(s3_etag)
-        });
-
-        result
+        }
     }
 
     fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
@@ -118,7 +697,7 @@ impl ObjectStore for S3Store {
         let prefix = prefix.to_string();
         let continuation_token = continuation.clone();
 
-        let result = self.rt.block_on(async move {
+        self.block_on(self.limited(async move {
             let mut req = client
                 .list_objects_v2()
                 .bucket(&bucket)
@@ -130,7 +709,7 @@ impl ObjectStore for S3Store {
 
             let resp = req.send()
                 .await
-                .map_err(|e| ObjectStoreError::Other(format!("S3 list error: {e}")))?;
+                .map_err(|e| s3_error("list", e))?;
 
             let keys = resp
                 .contents()
@@ -141,10 +720,604 @@ impl ObjectStore for S3Store {
             let next_token = resp.next_continuation_token().map(|s| s.to_string());
 
             Ok((keys, next_token))
-        });
+        }))
+    }
 
-        result
+    fn delete(&self, key: &str) -> Result<()> {
+        let client = self.client.clone();
+        let bucket = self.bucket.clone();
+        let key = key.to_string();
+
+        self.block_on(self.limited(async move {
+            client
+                .delete_object()
+                .bucket(&bucket)
+                .key(&key)
+                .send()
+                .await
+                .map_err(|e| s3_error("delete", e))?;
+            Ok(())
+        }))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let client = self.client.clone();
+        let bucket = self.bucket.clone();
+        let key = key.to_string();
+
+        let sse_c = self.customer_key.clone();
+
+        self.block_on(self.limited(async move {
+            Ok(head_object(&client, &bucket, &key, &sse_c).await?.map(|meta| ObjectMeta {
+                size: meta.content_length().unwrap_or(0) as u64,
+                etag: meta.e_tag().map(|s| s.trim_matches('"').to_string()).unwrap_or_default(),
+            }))
+        }))
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        // HTTP ranges are inclusive and can't be empty, so answer those locally
+        if range.start >= range.end {
+            return Ok(self.head(key)?.map(|_| Vec::new()));
+        }
+
+        let client = self.client.clone();
+        let bucket = self.bucket.clone();
+        let key = key.to_string();
+        let sse_c = self.customer_key.clone();
+
+        self.block_on(self.limited(async move {
+            let resp = client
+                .get_object()
+                .bucket(&bucket)
+                .key(&key)
+                .set_sse_customer_algorithm(sse_c.algorithm)
+                .set_sse_customer_key(sse_c.key)
+                .set_sse_customer_key_md5(sse_c.md5)
+                .range(format!("bytes={}-{}", range.start, range.end - 1))
+                .send()
+                .await;
+
+            match resp {
+                Ok(obj) => {
+                    let data = obj.body.collect().await
+                        .map_err(body_error)?;
+                    Ok(Some(data.into_bytes().to_vec()))
+                }
+                Err(e) if is_no_such_key(&e) => Ok(None),
+                // Range starts past the end of an existing object
+                Err(e) if e.code() == Some("InvalidRange") => Ok(Some(Vec::new())),
+                Err(e) => Err(s3_error("get", e)),
+            }
+        }))
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        // HTTP ranges can't be empty; the fallback answers those with a head
+        if opts.range.as_ref().is_some_and(|range| range.start >= range.end) {
+            return get_opts_fallback(self, key, &opts);
+        }
+
+        let client = self.client.clone();
+        let bucket = self.bucket.clone();
+        let key_owned = key.to_string();
+        let range = opts.range.clone();
+        let if_match = opts.if_match.map(quote_etag);
+        let if_none_match = opts.if_none_match.map(quote_etag);
+        let include_metadata = opts.include_metadata;
+        let sse_c = self.customer_key.clone();
+
+        let result = self.block_on(self.limited(async move {
+            let resp = client
+                .get_object()
+                .bucket(&bucket)
+                .key(&key_owned)
+                .set_sse_customer_algorithm(sse_c.algorithm)
+                .set_sse_customer_key(sse_c.key)
+                .set_sse_customer_key_md5(sse_c.md5)
+                .set_range(range.map(|r| format!("bytes={}-{}", r.start, r.end - 1)))
+                .set_if_match(if_match)
+                .set_if_none_match(if_none_match)
+                .send()
+                .await;
+
+            match resp {
+                Ok(obj) => {
+                    let etag = obj.e_tag().map(|s| s.trim_matches('"').to_string()).unwrap_or_default();
+                    // Ranged responses carry the full size after the slash in Content-Range
+                    let size = obj
+                        .content_range()
+                        .and_then(|r| r.rsplit_once('/'))
+                        .and_then(|(_, total)| total.parse().ok())
+                        .or(obj.content_length().map(|n| n as u64))
+                        .unwrap_or(0);
+                    let data = obj.body.collect().await
+                        .map_err(body_error)?;
+                    let meta = include_metadata.then_some(ObjectMeta { size, etag });
+                    Ok(Some(GetResult::Body { data: data.into_bytes().to_vec(), meta }))
+                }
+                Err(e) => match e.raw_response().map(|r| r.status().as_u16()) {
+                    Some(304) => Ok(Some(GetResult::NotModified)),
+                    Some(412) => Err(ObjectStoreError::PreconditionFailed),
+                    _ if is_no_such_key(&e) => Ok(None),
+                    // Range starts past the end of an existing object. Preconditions
+                    // are checked before the range, so they passed.
+                    _ if e.code() == Some("InvalidRange") => {
+                        Ok(Some(GetResult::Body { data: Vec::new(), meta: None }))
+                    }
+                    _ => Err(s3_error("get", e)),
+                },
+            }
+        }))?;
+
+        match result {
+            Some(GetResult::Body { data, meta: None }) if include_metadata => match self.head(key)? {
+                Some(meta) => Ok(Some(GetResult::Body { data, meta: Some(meta) })),
+                None => Ok(None),
+            },
+            result => Ok(result),
+        }
+    }
+
+    // S3's own object tagging, which lifecycle rules and cost allocation
+    // reports can select on. S3 allows ten tags per object.
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        let tag_set = tags
+            .iter()
+            .map(|(name, value)| Tag::builder().key(name).value(value).build())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .and_then(|tag_set| Tagging::builder().set_tag_set(Some(tag_set)).build())
+            .map_err(|e| ObjectStoreError::backend("S3 put tags error: invalid tag", e))?;
+        let request =
+            self.client.put_object_tagging().bucket(&self.bucket).key(key).tagging(tag_set).send();
+        match self.block_on(self.limited(request)) {
+            Ok(_) => Ok(()),
+            Err(e) if e.code() == Some("NoSuchKey") => Err(ObjectStoreError::NotFound(key.to_string())),
+            Err(e) => Err(s3_error("put tags", e)),
+        }
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        let request = self.client.get_object_tagging().bucket(&self.bucket).key(key).send();
+        match self.block_on(self.limited(request)) {
+            Ok(resp) => Ok(Some(
+                resp.tag_set().iter().map(|tag| (tag.key().to_string(), tag.value().to_string())).collect(),
+            )),
+            Err(e) if e.code() == Some("NoSuchKey") => Ok(None),
+            Err(e) => Err(s3_error("get tags", e)),
+        }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::config::http::HttpRequest;
+    use aws_sdk_s3::config::retry::RetryConfig;
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+    use aws_sdk_s3::primitives::SdkBody;
+    use aws_smithy_runtime_api::client::http::{http_client_fn, HttpConnector, HttpConnectorFuture, SharedHttpConnector};
+    use aws_smithy_runtime_api::http::StatusCode;
+    use std::fmt;
+    use std::sync::Mutex;
+
+    type Respond = Box<dyn Fn(&HttpRequest) -> (u16, String) + Send + Sync>;
+
+    // Answers each request with `respond` and records its method and
+    // conditional headers
+    struct Mock {
+        respond: Respond,
+        requests: Mutex<Vec<String>>,
+    }
+
+    impl fmt::Debug for Mock {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Mock").field("requests", &self.requests).finish()
+        }
+    }
+
+    #[derive(Debug)]
+    struct MockConnector(Arc<Mock>);
+
+    impl HttpConnector for MockConnector {
+        fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+            let header = |name| request.headers().get(name).map(|v| format!(" {name}: {v}")).unwrap_or_default();
+            let line = format!("{}{}{}", request.method(), header("if-match"), header("if-none-match"));
+            self.0.requests.lock().unwrap().push(line);
+            let (status, body) = (self.0.respond)(&request);
+            let status = StatusCode::try_from(status).unwrap();
+            HttpConnectorFuture::ready(Ok(HttpResponse::new(status, SdkBody::from(body))))
+        }
+    }
+
+    fn mock(respond: impl Fn(&HttpRequest) -> (u16, String) + Send + Sync + 'static) -> (S3Store, Arc<Mock>) {
+        let mock = Arc::new(Mock { respond: Box::new(respond), requests: Mutex::new(Vec::new()) });
+        let connector = SharedHttpConnector::new(MockConnector(mock.clone()));
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("AKID", "SECRET", None, None, "test"))
+            .retry_config(RetryConfig::disabled())
+            .http_client(http_client_fn(move |_, _| connector.clone()))
+            .build();
+        (S3Store::new("bucket".to_string(), Client::from_conf(config)), mock)
+    }
+
+    // Answers with the responses in order, repeating the last one
+    fn scripted(responses: &[(u16, &str)]) -> (S3Store, Arc<Mock>) {
+        let responses: Mutex<Vec<_>> = Mutex::new(responses.iter().map(|(status, body)| (*status, body.to_string())).collect());
+        mock(move |_| {
+            let mut responses = responses.lock().unwrap();
+            if responses.len() > 1 { responses.remove(0) } else { responses[0].clone() }
+        })
+    }
+
+    fn mocked(status: u16, body: &str) -> S3Store {
+        scripted(&[(status, body)]).0
+    }
+
+    fn error_body(code: &str, message: &str) -> String {
+        format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?><Error><Code>{code}</Code><Message>{message}</Message></Error>")
+    }
+
+    #[test]
+    fn test_no_such_key_is_missing() {
+        let store = mocked(404, &error_body("NoSuchKey", "The specified key does not exist."));
+        assert_eq!(store.get("k").unwrap(), None);
+        assert_eq!(store.get_range("k", 0..4).unwrap(), None);
+        assert!(store.get_opts("k", GetOptions::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_head_not_found_is_missing() {
+        assert_eq!(mocked(404, "").head("k").unwrap(), None);
+    }
+
+    #[test]
+    fn test_denied_read_is_not_missing() {
+        // The message mentions NoSuchKey, which used to be taken for a missing key
+        let store = mocked(403, &error_body("AccessDenied", "Access Denied; NoSuchKey is not reported to you"));
+        assert!(matches!(store.get("k"), Err(ObjectStoreError::PermissionDenied(_))));
+        assert!(matches!(store.head("k"), Err(ObjectStoreError::PermissionDenied(_))));
+        // A failed head no longer looks like an absent object to a create-only put
+        assert!(matches!(store.put("k", b"v", IfMatch::NoneMatch), Err(ObjectStoreError::PermissionDenied(_))));
+    }
+
+    #[test]
+    fn test_server_errors_are_classified() {
+        let store = mocked(503, &error_body("SlowDown", "Please reduce your request rate."));
+        assert!(matches!(store.get("k"), Err(ObjectStoreError::Throttled(_))));
+        let store = mocked(500, &error_body("InternalError", "We encountered an internal error."));
+        assert!(matches!(store.get("k"), Err(ObjectStoreError::Unavailable(_))));
+    }
+
+    #[test]
+    fn test_conditional_puts_send_native_headers() {
+        let (store, mock) = scripted(&[(200, "")]);
+        store.put("k", b"v", IfMatch::NoneMatch).unwrap();
+        store.put("k", b"v", IfMatch::Tag("abc")).unwrap();
+        store.put("k", b"v", IfMatch::Any).unwrap();
+        // One request each, with no head in between
+        assert_eq!(*mock.requests.lock().unwrap(), ["PUT if-none-match: *", "PUT if-match: \"abc\"", "PUT"]);
+    }
+
+    #[test]
+    fn test_failed_native_conditions() {
+        let store = mocked(412, &error_body("PreconditionFailed", "At least one of the preconditions failed."));
+        assert!(matches!(store.put("k", b"v", IfMatch::Tag("abc")), Err(ObjectStoreError::PreconditionFailed)));
+        let store = mocked(409, &error_body("ConditionalRequestConflict", "A conflicting operation is in progress."));
+        assert!(matches!(store.put("k", b"v", IfMatch::NoneMatch), Err(ObjectStoreError::PreconditionFailed)));
+        let store = mocked(404, &error_body("NoSuchKey", "The specified key does not exist."));
+        assert!(matches!(store.put("k", b"v", IfMatch::Tag("abc")), Err(ObjectStoreError::PreconditionFailed)));
+    }
+
+    #[test]
+    fn test_falls_back_to_emulation_when_headers_are_rejected() {
+        let not_implemented = error_body("NotImplemented", "A header you provided implies functionality that is not implemented.");
+        let (store, mock) = scripted(&[(501, &not_implemented), (404, ""), (200, ""), (404, ""), (200, "")]);
+        store.put("k", b"v", IfMatch::NoneMatch).unwrap();
+        store.put("k", b"v", IfMatch::NoneMatch).unwrap();
+        // Later puts go straight to emulation
+        assert_eq!(*mock.requests.lock().unwrap(), ["PUT if-none-match: *", "HEAD", "PUT", "HEAD", "PUT"]);
+    }
+
+    #[test]
+    fn test_emulated_preconditions() {
+        let (store, mock) = scripted(&[(200, "")]);
+        let store = store.with_preconditions(Preconditions::Emulated);
+        assert!(matches!(store.put("k", b"v", IfMatch::NoneMatch), Err(ObjectStoreError::PreconditionFailed)));
+        assert_eq!(*mock.requests.lock().unwrap(), ["HEAD"]);
+    }
+
+    const PART: usize = MIN_PART_SIZE;
+
+    // A multipart-capable endpoint whose part uploads fail for `failing_part`
+    fn multipart_endpoint(failing_part: Option<&'static str>, complete_etag: &'static str) -> (S3Store, Arc<Mock>) {
+        let (store, mock) = mock(move |request| {
+            let uri = request.uri();
+            match request.method() {
+                "POST" if uri.contains("uploads") => (
+                    200,
+                    "<InitiateMultipartUploadResult><Bucket>bucket</Bucket><Key>k</Key>\
+                     <UploadId>up-1</UploadId></InitiateMultipartUploadResult>"
+                        .to_string(),
+                ),
+                "PUT" if failing_part.is_some_and(|part| uri.contains(part)) => {
+                    (500, error_body("InternalError", "We encountered an internal error."))
+                }
+                "PUT" => (200, String::new()),
+                "POST" => (200, format!("<CompleteMultipartUploadResult>{complete_etag}</CompleteMultipartUploadResult>")),
+                _ => (204, String::new()),
+            }
+        });
+        (store.with_multipart(PART, PART).with_upload_concurrency(2), mock)
+    }
+
+    fn count(mock: &Mock, line: &str) -> usize {
+        mock.requests.lock().unwrap().iter().filter(|request| *request == line).count()
+    }
+
+    #[test]
+    fn test_large_puts_use_multipart() {
+        let (store, mock) = multipart_endpoint(None, "<ETag>\"3858f62230ac3c915f300c664312c11f-3\"</ETag>");
+        let body = vec![7u8; 2 * PART + 1];
+        let etag = store.put("k", &body, IfMatch::NoneMatch).unwrap();
+        assert_eq!(etag, "3858f62230ac3c915f300c664312c11f-3");
+        assert_eq!(count(&mock, "PUT"), 3);
+        // The condition rides on the completion, which S3 checks atomically
+        assert_eq!(count(&mock, "POST if-none-match: *"), 1);
+        assert_eq!(count(&mock, "DELETE"), 0);
+
+        // Small bodies still go up in one request
+        store.put("small", b"v", IfMatch::Any).unwrap();
+        assert_eq!(count(&mock, "PUT"), 4);
+    }
+
+    #[test]
+    fn test_multipart_etag_without_response_tag() {
+        let (store, _) = multipart_endpoint(None, "");
+        let body: Vec<u8> = (0..PART + 10).map(|i| i as u8).collect();
+        let etag = store.put("k", &body, IfMatch::Any).unwrap();
+        let digests: Vec<u8> = [&body[..PART], &body[PART..]].iter().flat_map(|part| md5::compute(part).0).collect();
+        assert_eq!(etag, format!("{:x}-2", md5::compute(&digests)));
+    }
+
+    #[test]
+    fn test_failed_part_aborts_upload() {
+        let (store, mock) = multipart_endpoint(Some("partNumber=2"), "");
+        let body = vec![7u8; 3 * PART];
+        assert!(matches!(store.put("k", &body, IfMatch::Any), Err(ObjectStoreError::Unavailable(_))));
+        assert_eq!(count(&mock, "DELETE"), 1);
+        // Never completed
+        assert_eq!(count(&mock, "POST"), 1);
+    }
+
+    #[test]
+    fn test_abort_stale_uploads() {
+        let (store, mock) = mock(|request| match request.method() {
+            "GET" => (
+                200,
+                "<ListMultipartUploadsResult><Bucket>bucket</Bucket><IsTruncated>false</IsTruncated>\
+                 <Upload><Key>fresh</Key><UploadId>u2</UploadId><Initiated>2999-01-01T00:00:00.000Z</Initiated></Upload>\
+                 <Upload><Key>stale</Key><UploadId>u1</UploadId><Initiated>2020-01-01T00:00:00.000Z</Initiated></Upload>\
+                 </ListMultipartUploadsResult>"
+                    .to_string(),
+            ),
+            _ => (204, String::new()),
+        });
+        let uploads = store.list_multipart_uploads("").unwrap();
+        assert_eq!(uploads.iter().map(|upload| upload.key.as_str()).collect::<Vec<_>>(), ["stale", "fresh"]);
+
+        let aborted = store.abort_stale_uploads(Duration::from_secs(24 * 3600)).unwrap();
+        assert_eq!(aborted.len(), 1);
+        assert_eq!((aborted[0].key.as_str(), aborted[0].upload_id.as_str()), ("stale", "u1"));
+        assert_eq!(count(&mock, "DELETE"), 1);
+    }
+
+    #[test]
+    fn test_upload_gone_before_abort_is_fine() {
+        let (store, _) = mock(|request| match request.method() {
+            "GET" => (
+                200,
+                "<ListMultipartUploadsResult><Bucket>bucket</Bucket><IsTruncated>false</IsTruncated>\
+                 <Upload><Key>stale</Key><UploadId>u1</UploadId><Initiated>2020-01-01T00:00:00.000Z</Initiated></Upload>\
+                 </ListMultipartUploadsResult>"
+                    .to_string(),
+            ),
+            _ => (404, error_body("NoSuchUpload", "The specified upload does not exist.")),
+        });
+        assert_eq!(store.abort_stale_uploads(Duration::ZERO).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_retry_settings_reach_the_client() {
+        let (store, mock) = scripted(&[(500, &error_body("InternalError", "We encountered an internal error."))]);
+        let store = store.with_max_attempts(3).with_initial_backoff(Duration::from_millis(1));
+        assert!(store.head("k").is_err());
+        assert_eq!(count(&mock, "HEAD"), 3);
+    }
+
+    #[test]
+    fn test_timeouts_reach_the_client() {
+        let store = mocked(200, "")
+            .with_connect_timeout(Duration::from_secs(1))
+            .with_read_timeout(Duration::from_secs(2))
+            .with_attempt_timeout(Duration::from_secs(3))
+            .with_operation_timeout(Duration::from_secs(10));
+        let timeouts = store.client.config().timeout_config().unwrap();
+        assert_eq!(timeouts.connect_timeout(), Some(Duration::from_secs(1)));
+        assert_eq!(timeouts.read_timeout(), Some(Duration::from_secs(2)));
+        assert_eq!(timeouts.operation_attempt_timeout(), Some(Duration::from_secs(3)));
+        assert_eq!(timeouts.operation_timeout(), Some(Duration::from_secs(10)));
+        store.put("k", b"v", IfMatch::Any).unwrap();
+    }
+
+    #[test]
+    fn test_with_options_overrides_only_the_copy() {
+        let store = mocked(200, "").with_max_connections(4);
+        let background = store.with_options(&StoreOptions {
+            timeout: Some(Duration::from_secs(5)),
+            priority: Some(Priority::Background),
+            ..StoreOptions::default()
+        });
+        let timeout = |store: &S3Store| store.client.config().timeout_config().and_then(|t| t.operation_timeout());
+        assert_eq!(timeout(&background), Some(Duration::from_secs(5)));
+        assert_eq!(timeout(&store), None);
+        assert_eq!(background.priority, Priority::Background);
+        assert_eq!(store.priority, Priority::Normal);
+        // Both draw on the same connections; the copy on half of them
+        assert!(Arc::ptr_eq(&store.connections.all, &background.connections.all));
+        assert_eq!(background.connections.background.available_permits(), 2);
+        background.put("k", b"v", IfMatch::Any).unwrap();
+
+        let plain = store.with_options(&StoreOptions::default());
+        assert!(Arc::ptr_eq(&store.client, &plain.client));
+    }
 
+    #[test]
+    fn test_single_connection_still_uploads_parts() {
+        let (store, mock) = multipart_endpoint(None, "<ETag>\"abc-3\"</ETag>");
+        let store = store.with_max_connections(1);
+        store.put("k", &vec![7u8; 2 * PART + 1], IfMatch::Any).unwrap();
+        assert_eq!(count(&mock, "PUT"), 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_called_from_async_code() {
+        let store = mocked(404, &error_body("NoSuchKey", "The specified key does not exist."));
+        // Shares the surrounding runtime instead of starting another
+        assert!(store.runtime.is_none());
+        assert_eq!(store.get("k").unwrap(), None);
+    }
+
+    #[test]
+    fn test_uses_given_runtime() {
+        let runtime = Runtime::new().unwrap();
+        let store = mocked(404, "").with_runtime(runtime.handle().clone()).unwrap();
+        assert!(store.runtime.is_none());
+        assert_eq!(store.head("k").unwrap(), None);
+
+        let current = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let result = mocked(404, "").with_runtime(current.handle().clone());
+        assert!(matches!(result, Err(ObjectStoreError::InvalidArgument(_))));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_called_from_a_current_thread_runtime() {
+        let store = mocked(404, &error_body("NoSuchKey", "The specified key does not exist."));
+        assert!(store.runtime.is_some());
+        assert_eq!(store.get("k").unwrap(), None);
+        // Dropping the runtime the store started doesn't panic here either
+        drop(store);
+    }
+
+    // An endpoint that takes any request, recording each one's method and
+    // encryption headers
+    fn encryption_endpoint() -> (S3Store, Arc<Mutex<Vec<String>>>) {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let record = seen.clone();
+        let (store, _) = mock(move |request| {
+            let mut headers: Vec<_> = request
+                .headers()
+                .iter()
+                .filter(|(name, _)| name.starts_with("x-amz-server-side-encryption"))
+                .map(|(name, value)| format!(" {name}={value}"))
+                .collect();
+            headers.sort();
+            record.lock().unwrap().push(format!("{}{}", request.method(), headers.concat()));
+            match request.method() {
+                "POST" if request.uri().contains("uploads") => (
+                    200,
+                    "<InitiateMultipartUploadResult><UploadId>up-1</UploadId></InitiateMultipartUploadResult>".to_string(),
+                ),
+                "POST" => (200, "<CompleteMultipartUploadResult/>".to_string()),
+                _ => (200, String::new()),
+            }
+        });
+        (store.with_multipart(PART, PART), seen)
+    }
+
+    // A request line as encryption_endpoint records it
+    fn line(method: &str, headers: &[String]) -> String {
+        let mut headers: Vec<_> = headers.iter().map(|header| format!(" {header}")).collect();
+        headers.sort();
+        format!("{method}{}", headers.concat())
+    }
+
+    #[test]
+    fn test_kms_encryption_on_writes() {
+        let (store, seen) = encryption_endpoint();
+        let store = store.with_encryption(Encryption::Kms { key_id: Some("alias/blobs".to_string()), bucket_key: true });
+        store.put("small", b"v", IfMatch::Any).unwrap();
+        store.put("large", &vec![7u8; PART + 1], IfMatch::Any).unwrap();
+        let kms = [
+            "x-amz-server-side-encryption=aws:kms".to_string(),
+            "x-amz-server-side-encryption-aws-kms-key-id=alias/blobs".to_string(),
+            "x-amz-server-side-encryption-bucket-key-enabled=true".to_string(),
+        ];
+        // Parts and the completion inherit the upload's encryption
+        assert_eq!(*seen.lock().unwrap(), [line("PUT", &kms), line("POST", &kms), line("PUT", &[]), line("PUT", &[]), line("POST", &[])]);
+    }
+
+    #[test]
+    fn test_s3_managed_encryption() {
+        let (store, seen) = encryption_endpoint();
+        store.with_encryption(Encryption::S3Managed).put("k", b"v", IfMatch::Any).unwrap();
+        assert_eq!(*seen.lock().unwrap(), ["PUT x-amz-server-side-encryption=AES256"]);
+    }
+
+    #[test]
+    fn test_customer_key_on_every_data_request() {
+        let (store, seen) = encryption_endpoint();
+        let key = [42u8; 32];
+        let store = store.with_encryption(Encryption::CustomerKey(key));
+        store.put("small", b"v", IfMatch::Any).unwrap();
+        store.put("large", &vec![7u8; PART + 1], IfMatch::Any).unwrap();
+        store.get("small").unwrap();
+        store.get_range("small", 0..1).unwrap();
+        store.head("small").unwrap();
+
+        let sse_c = [
+            "x-amz-server-side-encryption-customer-algorithm=AES256".to_string(),
+            format!("x-amz-server-side-encryption-customer-key={}", base64::encode(key)),
+            format!("x-amz-server-side-encryption-customer-key-md5={}", base64::encode(md5::compute(key).0)),
+        ];
+        // Everything but completing the multipart upload carries the key
+        let expected: Vec<_> = ["PUT", "POST", "PUT", "PUT", "POST", "GET", "GET", "HEAD"]
+            .iter()
+            .enumerate()
+            .map(|(i, method)| line(method, if i == 4 { &[] } else { &sse_c }))
+            .collect();
+        assert_eq!(*seen.lock().unwrap(), expected);
+        assert!(!format!("{:?}", Encryption::CustomerKey(key)).contains("42"));
+    }
+
+    #[test]
+    fn test_tags() {
+        let sent = Arc::new(Mutex::new(String::new()));
+        let recorded = sent.clone();
+        let (store, _) = mock(move |request| {
+            if request.method() == "PUT" {
+                *recorded.lock().unwrap() = String::from_utf8(request.body().bytes().unwrap().to_vec()).unwrap();
+                return (200, String::new());
+            }
+            let tags = "<Tagging><TagSet><Tag><Key>team</Key><Value>search</Value></Tag>\
+                        <Tag><Key>tier</Key><Value>cold</Value></Tag></TagSet></Tagging>";
+            (200, tags.to_string())
+        });
+        let tags = BTreeMap::from([("team".to_string(), "search".to_string())]);
+        store.put_tags("k", &tags).unwrap();
+        assert!(sent.lock().unwrap().contains("<Tag><Key>team</Key><Value>search</Value></Tag>"));
+        let tags = store.get_tags("k").unwrap().unwrap();
+        assert_eq!(tags.get("tier").map(String::as_str), Some("cold"));
+        assert_eq!(tags.len(), 2);
+    }
+
+    #[test]
+    fn test_tags_of_missing_object() {
+        let store = mocked(404, &error_body("NoSuchKey", "The specified key does not exist."));
+        assert_eq!(store.get_tags("k").unwrap(), None);
+        let result = store.put_tags("k", &BTreeMap::new());
+        assert!(matches!(result, Err(ObjectStoreError::NotFound(key)) if key == "k"));
+    }
+}
@@ -0,0 +1,287 @@
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+// Hard cap on captured payload bytes per sample, whatever the configuration asks for
+pub const MAX_SAMPLE_BYTES: usize = 4096;
+
+/// What a sample keeps of the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    // Only the ETag, which is enough to tell whether two writes matched
+    Hash,
+    // The ETag and the first N bytes, up to MAX_SAMPLE_BYTES
+    Head(usize),
+}
+
+/// One sampled put, stored as JSON under the sample prefix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayloadSample {
+    pub key: String,
+    pub size: u64,
+    pub etag: String,
+    // Hex-encoded leading bytes; absent for hash-only samples
+    pub head: Option<String>,
+    pub context: Option<String>,
+    pub thread: Option<String>,
+    // Milliseconds since the Unix epoch
+    pub sampled_at: u64,
+}
+
+type PiiFilter = Box<dyn Fn(&str, &[u8]) -> bool + Send + Sync>;
+
+/// Wraps a store so a fraction of successful puts leave a debugging record
+/// under a sample prefix in the same store.
+///
+/// Samples hold the key, size, ETag, an optional caller context and the
+/// writing thread's name. By default they keep no payload bytes at all.
+/// With `Capture::Head` they also keep the leading bytes, capped at
+/// `MAX_SAMPLE_BYTES`. A payload the PII filter flags is recorded as a
+/// hash only. `with_max_samples` bounds how many records one wrapper writes
+/// in total. Writes under the sample prefix are never sampled.
+/// Failing to write a sample never fails the put.
+pub struct SamplingStore<S> {
+    inner: S,
+    prefix: String,
+    rate: f64,
+    capture: Capture,
+    context: Option<String>,
+    pii_filter: Option<PiiFilter>,
+    max_samples: Option<u64>,
+    written: AtomicU64,
+}
+
+impl<S: ObjectStore> SamplingStore<S> {
+    // `rate` is the fraction of puts to sample, from 0.0 to 1.0
+    pub fn new(inner: S, prefix: impl Into<String>, rate: f64) -> Self {
+        Self {
+            inner,
+            prefix: prefix.into(),
+            rate: rate.clamp(0.0, 1.0),
+            capture: Capture::Hash,
+            context: None,
+            pii_filter: None,
+            max_samples: None,
+            written: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_capture(mut self, capture: Capture) -> Self {
+        self.capture = capture;
+        self
+    }
+
+    // Free-form caller context stored with every sample, e.g. a service name
+    pub fn with_context(mut self, context: impl Into<String>) -> Self {
+        self.context = Some(context.into());
+        self
+    }
+
+    // Returns true for payloads that may hold PII; those are sampled as a hash only
+    pub fn with_pii_filter(mut self, filter: impl Fn(&str, &[u8]) -> bool + Send + Sync + 'static) -> Self {
+        self.pii_filter = Some(Box::new(filter));
+        self
+    }
+
+    pub fn with_max_samples(mut self, max: u64) -> Self {
+        self.max_samples = Some(max);
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    // Number of samples written so far
+    pub fn samples_written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    fn should_sample(&self, key: &str) -> bool {
+        if self.rate <= 0.0 || key.starts_with(&self.prefix) {
+            return false;
+        }
+        // RandomState is randomly seeded, which is all a sampling decision needs
+        let random = RandomState::new().build_hasher().finish();
+        (random as f64 / u64::MAX as f64) < self.rate
+    }
+
+    // Reserves one slot of the sample budget
+    fn reserve(&self) -> bool {
+        match self.max_samples {
+            None => {
+                self.written.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Some(max) => self
+                .written
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| (n < max).then_some(n + 1))
+                .is_ok(),
+        }
+    }
+
+    fn sample(&self, key: &str, body: &[u8], etag: &str) {
+        if !self.should_sample(key) || !self.reserve() {
+            return;
+        }
+        let head = match self.capture {
+            Capture::Head(n) if !self.pii_filter.as_ref().is_some_and(|filter| filter(key, body)) => {
+                let n = n.min(MAX_SAMPLE_BYTES).min(body.len());
+                Some(body[..n].iter().map(|b| format!("{b:02x}")).collect())
+            }
+            _ => None,
+        };
+        let sampled_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let record = PayloadSample {
+            key: key.to_string(),
+            size: body.len() as u64,
+            etag: etag.to_string(),
+            head,
+            context: self.context.clone(),
+            thread: thread::current().name().map(str::to_string),
+            sampled_at,
+        };
+        // Time-ordered names so listing the prefix reads like a log
+        let sample_key = format!("{}{sampled_at:013}-{}.json", self.prefix, Uuid::new_v4());
+        if let Ok(json) = serde_json::to_vec(&record) {
+            let _ = self.inner.put(&sample_key, &json, IfMatch::Any);
+        }
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for SamplingStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        let etag = self.inner.put(key, body, cond)?;
+        self.sample(key, body, &etag);
+        Ok(etag)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        self.inner.list(prefix, continuation)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(key)
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.inner.head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.inner.get_range(key, range)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.inner.get_opts(key, opts)
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.inner.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.inner.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::sync::list_all;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+
+    fn samples(store: &impl ObjectStore) -> Vec<PayloadSample> {
+        list_all(store, ".samples/")
+            .unwrap()
+            .iter()
+            .map(|key| serde_json::from_slice(&store.get(key).unwrap().unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_sampling_object_store() {
+        let store = SamplingStore::new(InMemoryStore::default(), ".samples/", 1.0);
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+        assert!(store.samples_written() > 0);
+    }
+
+    #[test]
+    fn test_hash_only_by_default() {
+        let store = SamplingStore::new(InMemoryStore::default(), ".samples/", 1.0).with_context("ingest-worker");
+        let etag = store.put("users/1.json", b"{\"name\":\"x\"}", IfMatch::Any).unwrap();
+
+        let found = samples(store.inner());
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].key, "users/1.json");
+        assert_eq!(found[0].size, 12);
+        assert_eq!(found[0].etag, etag);
+        assert_eq!(found[0].head, None);
+        assert_eq!(found[0].context.as_deref(), Some("ingest-worker"));
+    }
+
+    #[test]
+    fn test_head_capture_is_capped() {
+        let store =
+            SamplingStore::new(InMemoryStore::default(), ".samples/", 1.0).with_capture(Capture::Head(usize::MAX));
+        store.put("small", b"\x00AB", IfMatch::Any).unwrap();
+        store.put("big", &vec![7u8; 10_000], IfMatch::Any).unwrap();
+
+        let found = samples(store.inner());
+        let head = |key: &str| found.iter().find(|s| s.key == key).unwrap().head.clone().unwrap();
+        assert_eq!(head("small"), "004142");
+        assert_eq!(head("big").len(), MAX_SAMPLE_BYTES * 2);
+    }
+
+    #[test]
+    fn test_pii_filter_downgrades_to_hash() {
+        let store = SamplingStore::new(InMemoryStore::default(), ".samples/", 1.0)
+            .with_capture(Capture::Head(64))
+            .with_pii_filter(|_, body| body.contains(&b'@'));
+        store.put("a", b"alice@example.com", IfMatch::Any).unwrap();
+        store.put("b", b"no contact details", IfMatch::Any).unwrap();
+
+        let found = samples(store.inner());
+        assert_eq!(found.iter().find(|s| s.key == "a").unwrap().head, None);
+        assert!(found.iter().find(|s| s.key == "b").unwrap().head.is_some());
+    }
+
+    #[test]
+    fn test_rate_and_budget() {
+        let never = SamplingStore::new(InMemoryStore::default(), ".samples/", 0.0);
+        for i in 0..50 {
+            never.put(&format!("k{i}"), b"v", IfMatch::Any).unwrap();
+        }
+        assert!(samples(never.inner()).is_empty());
+
+        let capped = SamplingStore::new(InMemoryStore::default(), ".samples/", 1.0).with_max_samples(3);
+        for i in 0..50 {
+            capped.put(&format!("k{i}"), b"v", IfMatch::Any).unwrap();
+        }
+        assert_eq!(samples(capped.inner()).len(), 3);
+        assert_eq!(capped.samples_written(), 3);
+    }
+
+    #[test]
+    fn test_failed_puts_are_not_sampled() {
+        let store = SamplingStore::new(InMemoryStore::default(), ".samples/", 1.0);
+        store.put("k", b"v", IfMatch::Any).unwrap();
+        assert!(store.put("k", b"v2", IfMatch::NoneMatch).is_err());
+        assert_eq!(samples(store.inner()).len(), 1);
+    }
+}
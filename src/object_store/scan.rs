@@ -0,0 +1,254 @@
+use super::sync::list_all;
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Under the quarantine prefix, infected bodies and their scan records are
+// kept apart so no key's body can land on another's record
+const QUARANTINED_DIR: &str = "objects/";
+const RECORDS_DIR: &str = "records/";
+
+/// Outcome of scanning an upload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    Clean,
+    // Carries the scanner's description of what was found
+    Infected(String),
+}
+
+/// A content scanner invoked on every put before the body reaches the backend.
+///
+/// Scanners are `Send + Sync` so a single instance can be shared by stores used
+/// from several threads or from `spawn_blocking` tasks.
+pub trait Scanner: Send + Sync {
+    fn scan(&self, key: &str, body: &[u8]) -> Result<ScanVerdict>;
+}
+
+impl<F> Scanner for F
+where
+    F: Fn(&str, &[u8]) -> Result<ScanVerdict> + Send + Sync,
+{
+    fn scan(&self, key: &str, body: &[u8]) -> Result<ScanVerdict> {
+        self(key, body)
+    }
+}
+
+/// Wraps a store so that every put is scanned first.
+///
+/// Infected uploads are never written under their requested key. If a
+/// quarantine prefix is configured the body is stored under
+/// `<prefix>objects/<key>` and a record describing the verdict under
+/// `<prefix>records/<key>`; either way the caller gets
+/// `ObjectStoreError::Blocked`. The quarantine prefix is the wrapper's own:
+/// it is left out of listings, and reading or writing under it fails with
+/// `ObjectStoreError::InvalidKey`, so quarantined bodies are only reachable
+/// through `inner()`.
+pub struct ScanningStore<S> {
+    inner: S,
+    scanner: Box<dyn Scanner>,
+    quarantine_prefix: Option<String>,
+}
+
+impl<S: ObjectStore> ScanningStore<S> {
+    pub fn new(inner: S, scanner: impl Scanner + 'static) -> Self {
+        Self {
+            inner,
+            scanner: Box::new(scanner),
+            quarantine_prefix: None,
+        }
+    }
+
+    pub fn with_quarantine(mut self, prefix: impl Into<String>) -> Self {
+        self.quarantine_prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn is_quarantined(&self, key: &str) -> bool {
+        self.quarantine_prefix.as_ref().is_some_and(|prefix| key.starts_with(prefix.as_str()))
+    }
+
+    // Refuses keys under the quarantine prefix
+    fn check_key(&self, key: &str) -> Result<()> {
+        if self.is_quarantined(key) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        Ok(())
+    }
+
+    /// The keys of the quarantined uploads, as they were requested.
+    pub fn quarantined(&self) -> Result<Vec<String>> {
+        let Some(prefix) = &self.quarantine_prefix else {
+            return Ok(Vec::new());
+        };
+        let objects = format!("{prefix}{QUARANTINED_DIR}");
+        let keys = list_all(&self.inner, &objects)?;
+        Ok(keys.iter().filter_map(|key| key.strip_prefix(&objects)).map(str::to_string).collect())
+    }
+
+    fn quarantine(&self, prefix: &str, key: &str, body: &[u8], reason: &str) -> Result<()> {
+        let scanned_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let record = format!("key: {key}\nreason: {reason}\nscanned_at: {scanned_at}\n");
+
+        self.inner.put(&format!("{prefix}{QUARANTINED_DIR}{key}"), body, IfMatch::Any)?;
+        self.inner.put(&format!("{prefix}{RECORDS_DIR}{key}"), record.as_bytes(), IfMatch::Any)?;
+        Ok(())
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for ScanningStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.check_key(key)?;
+        self.inner.get(key)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        self.check_key(key)?;
+        match self.scanner.scan(key, body)? {
+            ScanVerdict::Clean => self.inner.put(key, body, cond),
+            ScanVerdict::Infected(reason) => {
+                if let Some(prefix) = &self.quarantine_prefix {
+                    self.quarantine(prefix, key, body, &reason)?;
+                }
+                Err(ObjectStoreError::Blocked(reason))
+            }
+        }
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let (keys, next) = self.inner.list(prefix, continuation)?;
+        Ok((keys.into_iter().filter(|key| !self.is_quarantined(key)).collect(), next))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.check_key(key)?;
+        self.inner.delete(key)
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.check_key(key)?;
+        self.inner.head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.check_key(key)?;
+        self.inner.get_range(key, range)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.check_key(key)?;
+        self.inner.get_opts(key, opts)
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.check_key(key)?;
+        self.inner.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.check_key(key)?;
+        self.inner.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use uuid::Uuid;
+
+    // Flags any body containing the EICAR marker, like a real AV engine would
+    fn eicar_scanner(_key: &str, body: &[u8]) -> Result<ScanVerdict> {
+        if body.windows(5).any(|w| w == b"EICAR") {
+            Ok(ScanVerdict::Infected("Eicar-Test-Signature".to_string()))
+        } else {
+            Ok(ScanVerdict::Clean)
+        }
+    }
+
+    #[test]
+    fn test_clean_upload_passes_through() {
+        let store = ScanningStore::new(InMemoryStore::default(), eicar_scanner);
+        store.put("ok.txt", b"harmless", IfMatch::Any).unwrap();
+        assert_eq!(store.get("ok.txt").unwrap(), Some(b"harmless".to_vec()));
+    }
+
+    #[test]
+    fn test_infected_upload_is_blocked() {
+        let store = ScanningStore::new(InMemoryStore::default(), eicar_scanner);
+        let result = store.put("bad.exe", b"xxEICARxx", IfMatch::Any);
+        assert!(matches!(result, Err(ObjectStoreError::Blocked(ref r)) if r == "Eicar-Test-Signature"));
+        assert_eq!(store.get("bad.exe").unwrap(), None);
+    }
+
+    #[test]
+    fn test_infected_upload_is_quarantined() {
+        let store = ScanningStore::new(InMemoryStore::default(), eicar_scanner)
+            .with_quarantine("quarantine/");
+        let result = store.put("uploads/bad.exe", b"xxEICARxx", IfMatch::Any);
+        assert!(matches!(result, Err(ObjectStoreError::Blocked(_))));
+
+        assert_eq!(store.get("uploads/bad.exe").unwrap(), None);
+        assert_eq!(store.quarantined().unwrap(), vec!["uploads/bad.exe"]);
+        assert_eq!(
+            store.inner().get("quarantine/objects/uploads/bad.exe").unwrap(),
+            Some(b"xxEICARxx".to_vec())
+        );
+        let record = store.inner().get("quarantine/records/uploads/bad.exe").unwrap().unwrap();
+        let record = String::from_utf8(record).unwrap();
+        assert!(record.contains("key: uploads/bad.exe"));
+        assert!(record.contains("reason: Eicar-Test-Signature"));
+    }
+
+    #[test]
+    fn test_quarantine_is_off_limits() {
+        let store = ScanningStore::new(InMemoryStore::default(), eicar_scanner)
+            .with_quarantine("quarantine/");
+        store.put("x", b"xxEICARxx", IfMatch::Any).unwrap_err();
+        store.put("x.scan", b"EICAR again", IfMatch::Any).unwrap_err();
+        store.put("clean", b"fine", IfMatch::Any).unwrap();
+
+        // Neither readable, forgeable nor listed through the wrapper
+        let body = "quarantine/objects/x";
+        assert!(matches!(store.get(body), Err(ObjectStoreError::InvalidKey(_))));
+        assert!(matches!(store.head(body), Err(ObjectStoreError::InvalidKey(_))));
+        assert!(matches!(store.get_range(body, 0..2), Err(ObjectStoreError::InvalidKey(_))));
+        assert!(matches!(store.get_opts(body, GetOptions::default()), Err(ObjectStoreError::InvalidKey(_))));
+        let forged = store.put("quarantine/records/x", b"reason: none", IfMatch::Any);
+        assert!(matches!(forged, Err(ObjectStoreError::InvalidKey(_))));
+        assert!(matches!(store.delete("quarantine/records/x"), Err(ObjectStoreError::InvalidKey(_))));
+        assert_eq!(store.list("", None).unwrap().0, vec!["clean"]);
+
+        // A key ending in .scan no longer lands on another key's record
+        let record = store.inner().get("quarantine/records/x").unwrap().unwrap();
+        assert!(String::from_utf8(record).unwrap().contains("key: x\n"));
+        let mut quarantined = store.quarantined().unwrap();
+        quarantined.sort();
+        assert_eq!(quarantined, vec!["x", "x.scan"]);
+    }
+
+    #[test]
+    fn test_scanner_error_propagates() {
+        let store = ScanningStore::new(InMemoryStore::default(), |_: &str, _: &[u8]| {
+            Err(ObjectStoreError::Other("scanner unavailable".to_string()))
+        });
+        let result = store.put("any.txt", b"data", IfMatch::Any);
+        assert!(matches!(result, Err(ObjectStoreError::Other(_))));
+        assert_eq!(store.get("any.txt").unwrap(), None);
+    }
+
+    #[test]
+    fn test_scanning_object_store() {
+        let store = ScanningStore::new(InMemoryStore::default(), eicar_scanner);
+        let prefix = format!("test/{}/", Uuid::new_v4());
+        run_object_store_tests(&store, &prefix);
+    }
+}
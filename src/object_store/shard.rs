@@ -0,0 +1,233 @@
+use super::cost::RequestCounts;
+use super::sync::list_all;
+use super::{ObjectStore, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Requests per second one key prefix sustains before the backend starts
+/// throttling.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PrefixLimits {
+    // GET and HEAD
+    pub reads_per_sec: f64,
+    // PUT, DELETE and LIST
+    pub writes_per_sec: f64,
+}
+
+impl PrefixLimits {
+    // S3's documented per-prefix rates, beyond which it answers 503 SlowDown
+    pub fn s3() -> Self {
+        Self {
+            reads_per_sec: 5500.0,
+            writes_per_sec: 3500.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ShardOptions {
+    // Path segments below the analyzed prefix that make up a group
+    pub depth: usize,
+    pub limits: PrefixLimits,
+    // Fraction of the limits a prefix may use before it counts as hot
+    pub target_utilization: f64,
+}
+
+impl Default for ShardOptions {
+    fn default() -> Self {
+        Self {
+            depth: 1,
+            limits: PrefixLimits::s3(),
+            target_utilization: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PrefixLoad {
+    pub prefix: String,
+    // Keys under the prefix in the listing
+    pub keys: u64,
+    pub reads_per_sec: f64,
+    pub writes_per_sec: f64,
+    // Share of the per-prefix limit in use, the higher of reads and writes
+    pub utilization: f64,
+    // Hash shards to spread the prefix over; 1 means leave it alone
+    pub recommended_shards: u32,
+    // How many times current traffic could grow, once sharded, before
+    // throttling; None without traffic
+    pub headroom: Option<f64>,
+}
+
+impl PrefixLoad {
+    pub fn is_hot(&self, options: &ShardOptions) -> bool {
+        self.utilization > options.target_utilization
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ShardReport {
+    // Busiest first
+    pub prefixes: Vec<PrefixLoad>,
+}
+
+impl ShardReport {
+    pub fn hot<'a>(&'a self, options: &'a ShardOptions) -> impl Iterator<Item = &'a PrefixLoad> {
+        self.prefixes.iter().filter(|load| load.is_hot(options))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("shard report serializes")
+    }
+}
+
+/// Finds the key prefixes under `prefix` that take more traffic than the
+/// backend sustains per prefix, and how many hash shards each needs.
+///
+/// The store is listed to count keys per group of `options.depth` path
+/// segments. `traffic` holds request counts per key observed over `window`,
+/// e.g. from server access logs; keys outside `prefix` are ignored. A
+/// group's recommended shard count is the smallest power of two that
+/// brings its utilization down to `options.target_utilization`; apply it by
+/// writing keys through `fan_out_key`. Sharding spreads keys, so it can't
+/// help when the traffic is on a handful of keys.
+pub fn analyze(
+    store: &dyn ObjectStore,
+    prefix: &str,
+    traffic: &BTreeMap<String, RequestCounts>,
+    window: Duration,
+    options: &ShardOptions,
+) -> Result<ShardReport> {
+    let mut groups: BTreeMap<String, (u64, RequestCounts)> = BTreeMap::new();
+    for key in list_all(store, prefix)? {
+        groups.entry(group_of(&key, prefix, options.depth)).or_default().0 += 1;
+    }
+    for (key, counts) in traffic.range(prefix.to_string()..).take_while(|(key, _)| key.starts_with(prefix)) {
+        let total = &mut groups.entry(group_of(key, prefix, options.depth)).or_default().1;
+        total.get += counts.get;
+        total.head += counts.head;
+        total.put += counts.put;
+        total.list += counts.list;
+        total.delete += counts.delete;
+    }
+
+    let seconds = window.as_secs_f64().max(f64::MIN_POSITIVE);
+    let target = options.target_utilization.clamp(f64::MIN_POSITIVE, 1.0);
+    let mut prefixes: Vec<PrefixLoad> = groups
+        .into_iter()
+        .map(|(prefix, (keys, counts))| {
+            let reads_per_sec = (counts.get + counts.head) as f64 / seconds;
+            let writes_per_sec = (counts.put + counts.delete + counts.list) as f64 / seconds;
+            let utilization =
+                (reads_per_sec / options.limits.reads_per_sec).max(writes_per_sec / options.limits.writes_per_sec);
+            let recommended_shards = ((utilization / target).ceil() as u32).max(1).next_power_of_two();
+            let headroom = (utilization > 0.0).then(|| recommended_shards as f64 / utilization);
+            PrefixLoad {
+                prefix,
+                keys,
+                reads_per_sec,
+                writes_per_sec,
+                utilization,
+                recommended_shards,
+                headroom,
+            }
+        })
+        .collect();
+    prefixes.sort_by(|a, b| b.utilization.total_cmp(&a.utilization).then_with(|| a.prefix.cmp(&b.prefix)));
+    Ok(ShardReport { prefixes })
+}
+
+/// Where `key` lands when spread over `shards` hash prefixes, taken from
+/// its MD5 and written in hex: `3f/logs/2024/01/01.gz` for 256 shards.
+/// Shard counts that aren't a power of two are rounded up to one.
+pub fn fan_out_key(key: &str, shards: u32) -> String {
+    if shards <= 1 {
+        return key.to_string();
+    }
+    let shards = shards.next_power_of_two();
+    let digest = md5::compute(key.as_bytes());
+    let hash = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) % shards;
+    let width = format!("{:x}", shards - 1).len();
+    format!("{hash:0width$x}/{key}")
+}
+
+// The first `depth` path segments of `key` below `prefix`, with their slash
+fn group_of(key: &str, prefix: &str, depth: usize) -> String {
+    let rest = &key[prefix.len()..];
+    let mut end = 0;
+    for _ in 0..depth {
+        match rest[end..].find('/') {
+            Some(slash) => end += slash + 1,
+            None => break,
+        }
+    }
+    format!("{prefix}{}", &rest[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::IfMatch;
+    use std::collections::HashSet;
+
+    fn reads(get: u64) -> RequestCounts {
+        RequestCounts {
+            get,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_group_of() {
+        assert_eq!(group_of("logs/2024/01/a", "logs/", 1), "logs/2024/");
+        assert_eq!(group_of("logs/2024/01/a", "logs/", 2), "logs/2024/01/");
+        assert_eq!(group_of("logs/a", "logs/", 2), "logs/");
+    }
+
+    #[test]
+    fn test_finds_hot_prefixes() {
+        let store = InMemoryStore::default();
+        for i in 0..10 {
+            store.put(&format!("img/hot/{i}"), b"x", IfMatch::Any).unwrap();
+            store.put(&format!("img/cold/{i}"), b"x", IfMatch::Any).unwrap();
+        }
+        // Over one minute: 15k reads/s spread over hot/, a trickle on cold/
+        let mut traffic = BTreeMap::new();
+        for i in 0..10 {
+            traffic.insert(format!("img/hot/{i}"), reads(90_000));
+        }
+        traffic.insert("img/cold/1".to_string(), reads(600));
+        traffic.insert("other/x".to_string(), reads(10_000_000));
+
+        let options = ShardOptions::default();
+        let report = analyze(&store, "img/", &traffic, Duration::from_secs(60), &options).unwrap();
+        assert_eq!(report.prefixes.len(), 2);
+
+        let hot = &report.prefixes[0];
+        assert_eq!((hot.prefix.as_str(), hot.keys), ("img/hot/", 10));
+        assert_eq!(hot.reads_per_sec, 15_000.0);
+        // 15000 / 5500 = 2.7 of a prefix; at 50% each that's 6 shards, so 8
+        assert_eq!(hot.recommended_shards, 8);
+        assert!(hot.headroom.unwrap() > 2.0);
+
+        let cold = &report.prefixes[1];
+        assert_eq!((cold.recommended_shards, cold.keys), (1, 10));
+        assert_eq!(report.hot(&options).map(|load| load.prefix.as_str()).collect::<Vec<_>>(), vec!["img/hot/"]);
+    }
+
+    #[test]
+    fn test_fan_out_key() {
+        assert_eq!(fan_out_key("a/b", 1), "a/b");
+        let shards: HashSet<String> = (0..1000)
+            .map(|i| fan_out_key(&format!("logs/{i}"), 16))
+            .map(|key| key.split_once('/').unwrap().0.to_string())
+            .collect();
+        assert_eq!(shards.len(), 16);
+        assert!(fan_out_key("logs/1", 256).ends_with("/logs/1"));
+        assert_eq!(fan_out_key("logs/1", 256).find('/'), Some(2));
+        // Stable across calls
+        assert_eq!(fan_out_key("logs/1", 16), fan_out_key("logs/1", 16));
+    }
+}
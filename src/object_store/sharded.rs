@@ -0,0 +1,248 @@
+use super::sync::for_each_concurrent;
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+fn ring_hash(data: &[u8]) -> u64 {
+    let digest = md5::compute(data);
+    u64::from_be_bytes(digest.0[..8].try_into().unwrap())
+}
+
+// Where listing left off in one shard
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum Cursor {
+    Start,
+    Next(String),
+    Done,
+}
+
+/// Spreads keys across several stores with consistent hashing, to get past
+/// per-bucket request limits.
+///
+/// Each shard is placed on a hash ring at many points (virtual nodes)
+/// derived from its name, and a key belongs to the first point after the
+/// key's own hash. Adding or removing a shard therefore only moves the keys
+/// whose points change hands, about 1/N of them, as long as the other
+/// shards keep their names. Keys are not moved automatically; copy them
+/// over with `sync` after changing the shard set.
+///
+/// `list` asks every shard for a page concurrently and merges them, so one
+/// page holds up to a page from each shard and is sorted, but pages are
+/// only ordered relative to each other within a shard.
+#[derive(Default)]
+pub struct ShardedStore {
+    shards: Vec<(String, Arc<dyn ObjectStore>)>,
+    ring: BTreeMap<u64, usize>,
+    vnodes: usize,
+    concurrency: usize,
+}
+
+impl ShardedStore {
+    pub fn new() -> Self {
+        Self {
+            vnodes: 128,
+            concurrency: 8,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_shard(mut self, name: impl Into<String>, store: Arc<dyn ObjectStore>) -> Self {
+        self.shards.push((name.into(), store));
+        self.build_ring();
+        self
+    }
+
+    // Points per shard on the ring; more spread keys more evenly
+    pub fn with_vnodes(mut self, vnodes: usize) -> Self {
+        self.vnodes = vnodes.max(1);
+        self.build_ring();
+        self
+    }
+
+    // Shards listed at once
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    fn build_ring(&mut self) {
+        self.ring.clear();
+        for (index, (name, _)) in self.shards.iter().enumerate() {
+            for vnode in 0..self.vnodes {
+                self.ring.insert(ring_hash(format!("{name}#{vnode}").as_bytes()), index);
+            }
+        }
+    }
+
+    fn shard_index(&self, key: &str) -> Result<usize> {
+        let hash = ring_hash(key.as_bytes());
+        self.ring
+            .range(hash..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, &index)| index)
+            .ok_or_else(|| ObjectStoreError::Other("ShardedStore has no shards".to_string()))
+    }
+
+    /// Name of the shard `key` lives on.
+    pub fn shard_for(&self, key: &str) -> Result<&str> {
+        Ok(&self.shards[self.shard_index(key)?].0)
+    }
+
+    fn shard(&self, key: &str) -> Result<&dyn ObjectStore> {
+        Ok(self.shards[self.shard_index(key)?].1.as_ref())
+    }
+
+    fn parse_continuation(&self, continuation: Option<String>) -> Result<Vec<Cursor>> {
+        let Some(token) = continuation else {
+            return Ok(vec![Cursor::Start; self.shards.len()]);
+        };
+        match serde_json::from_str::<Vec<Cursor>>(&token) {
+            Ok(cursors) if cursors.len() == self.shards.len() => Ok(cursors),
+            _ => Err(ObjectStoreError::InvalidArgument(format!("invalid continuation token {token:?}"))),
+        }
+    }
+}
+
+impl ObjectStore for ShardedStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.shard(key)?.get(key)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        self.shard(key)?.put(key, body, cond)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let cursors = self.parse_continuation(continuation)?;
+        let indexes: Vec<usize> = (0..self.shards.len()).collect();
+        let pages = Mutex::new(vec![None; self.shards.len()]);
+        let first_err = Mutex::new(None);
+        for_each_concurrent(&indexes, self.concurrency, |&index| {
+            let token = match &cursors[index] {
+                Cursor::Start => None,
+                Cursor::Next(token) => Some(token.clone()),
+                Cursor::Done => return,
+            };
+            match self.shards[index].1.list(prefix, token) {
+                Ok(page) => pages.lock().unwrap()[index] = Some(page),
+                Err(e) => {
+                    first_err.lock().unwrap().get_or_insert(e);
+                }
+            }
+        });
+        if let Some(e) = first_err.into_inner().unwrap() {
+            return Err(e);
+        }
+
+        let mut keys = Vec::new();
+        let mut next = Vec::with_capacity(cursors.len());
+        for page in pages.into_inner().unwrap() {
+            match page {
+                Some((page_keys, Some(token))) => {
+                    keys.extend(page_keys);
+                    next.push(Cursor::Next(token));
+                }
+                Some((page_keys, None)) => {
+                    keys.extend(page_keys);
+                    next.push(Cursor::Done);
+                }
+                None => next.push(Cursor::Done),
+            }
+        }
+        keys.sort();
+        let more = next.iter().any(|cursor| *cursor != Cursor::Done);
+        let token = more.then(|| serde_json::to_string(&next).expect("cursors serialize"));
+        Ok((keys, token))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.shard(key)?.delete(key)
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.shard(key)?.head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.shard(key)?.get_range(key, range)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.shard(key)?.get_opts(key, opts)
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.shard(key)?.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.shard(key)?.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::strict::StrictMemoryStore;
+    use crate::object_store::sync::list_all;
+    use crate::object_store::test_helpers::tests::{run_object_store_tests, run_oracle_tests};
+    use uuid::Uuid;
+
+    fn memory_shards(names: &[&str]) -> ShardedStore {
+        names.iter().fold(ShardedStore::new(), |store, name| {
+            store.with_shard(*name, Arc::new(InMemoryStore::default()))
+        })
+    }
+
+    #[test]
+    fn test_sharded_object_store() {
+        let store = memory_shards(&["a", "b", "c"]);
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+        run_oracle_tests(&store, &format!("oracle/{}/", Uuid::new_v4()));
+        assert!(ShardedStore::new().get("key").is_err());
+    }
+
+    #[test]
+    fn test_keys_spread_evenly_and_move_little() {
+        let three = memory_shards(&["a", "b", "c"]);
+        let keys: Vec<String> = (0..3000).map(|i| format!("objects/{i}")).collect();
+        for name in ["a", "b", "c"] {
+            let count = keys.iter().filter(|key| three.shard_for(key).unwrap() == name).count();
+            assert!((750..1250).contains(&count), "shard {name} got {count} keys");
+        }
+
+        // A fourth shard takes about a quarter of the keys, all from the others
+        let four = memory_shards(&["a", "b", "c", "d"]);
+        let moved: Vec<_> = keys
+            .iter()
+            .filter(|key| three.shard_for(key).unwrap() != four.shard_for(key).unwrap())
+            .collect();
+        assert!((500..1000).contains(&moved.len()), "{} keys moved", moved.len());
+        assert!(moved.iter().all(|key| four.shard_for(key).unwrap() == "d"));
+    }
+
+    #[test]
+    fn test_list_pages_across_shards() {
+        let store = ["a", "b", "c"].iter().fold(ShardedStore::new(), |store, name| {
+            store.with_shard(*name, Arc::new(StrictMemoryStore::default().with_page_size(2)))
+        });
+        let mut keys: Vec<String> = (0..20).map(|i| format!("k{i:02}")).collect();
+        for key in &keys {
+            store.put(key, b"x", IfMatch::Any).unwrap();
+        }
+
+        let (first, token) = store.list("", None).unwrap();
+        assert!(first.len() <= 6 && first.is_sorted());
+        assert!(token.is_some());
+        let mut listed = list_all(&store, "").unwrap();
+        listed.sort();
+        keys.sort();
+        assert_eq!(listed, keys);
+        let result = store.list("", Some("garbage".to_string()));
+        assert!(matches!(result, Err(ObjectStoreError::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,489 @@
+use super::memory::InMemoryStore;
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Small seeded PRNG (SplitMix64), so a simulation replays exactly from its seed.
+pub struct SimRng(u64);
+
+impl SimRng {
+    pub const fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub const fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in 0..n
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n.max(1)
+    }
+
+    pub fn chance(&mut self, probability: f64) -> bool {
+        (self.next_u64() as f64 / u64::MAX as f64) < probability
+    }
+}
+
+/// Virtual time: one tick per backend call. Fault schedules are expressed
+/// in ticks so they land on the same calls in every run.
+#[derive(Clone, Default)]
+pub struct VirtualClock(Arc<AtomicU64>);
+
+impl VirtualClock {
+    pub fn now(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn tick(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    // The call fails before reaching the backend
+    Error,
+    // The call takes effect but the caller sees an error, like a dropped response
+    LostResponse,
+}
+
+/// When the simulated backend misbehaves, in virtual-clock ticks.
+#[derive(Debug, Clone, Default)]
+pub struct FaultSchedule {
+    scripted: HashMap<u64, Fault>,
+    outages: Vec<Range<u64>>,
+    error_rate: f64,
+    lost_response_rate: f64,
+}
+
+impl FaultSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn fail_at(mut self, tick: u64, fault: Fault) -> Self {
+        self.scripted.insert(tick, fault);
+        self
+    }
+
+    // Every call in `ticks` fails with Fault::Error
+    pub fn outage(mut self, ticks: Range<u64>) -> Self {
+        self.outages.push(ticks);
+        self
+    }
+
+    // Random faults on top of the scripted ones, drawn from the simulation's RNG
+    pub fn random(mut self, error_rate: f64, lost_response_rate: f64) -> Self {
+        self.error_rate = error_rate;
+        self.lost_response_rate = lost_response_rate;
+        self
+    }
+}
+
+struct FaultState {
+    schedule: FaultSchedule,
+    rng: SimRng,
+    injected: u64,
+    lost_responses: u64,
+}
+
+/// In-memory backend that injects faults from a schedule. Cloning shares
+/// the objects, clock and schedule, so the simulation can keep a handle
+/// while the store under test owns another.
+#[derive(Clone)]
+pub struct FaultyStore {
+    objects: Arc<InMemoryStore>,
+    clock: VirtualClock,
+    faults: Arc<Mutex<FaultState>>,
+}
+
+impl FaultyStore {
+    pub fn new(schedule: FaultSchedule, seed: u64) -> Self {
+        Self {
+            objects: Arc::default(),
+            clock: VirtualClock::default(),
+            faults: Arc::new(Mutex::new(FaultState {
+                schedule,
+                rng: SimRng::new(seed),
+                injected: 0,
+                lost_responses: 0,
+            })),
+        }
+    }
+
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    // The backend's real contents, bypassing fault injection
+    pub fn objects(&self) -> &InMemoryStore {
+        &self.objects
+    }
+
+    pub fn faults_injected(&self) -> u64 {
+        self.faults.lock().unwrap().injected
+    }
+
+    pub fn lost_responses(&self) -> u64 {
+        self.faults.lock().unwrap().lost_responses
+    }
+
+    fn next_fault(&self) -> Option<Fault> {
+        let tick = self.clock.tick();
+        let mut state = self.faults.lock().unwrap();
+        let fault = if let Some(fault) = state.schedule.scripted.get(&tick) {
+            Some(*fault)
+        } else if state.schedule.outages.iter().any(|ticks| ticks.contains(&tick)) {
+            Some(Fault::Error)
+        } else {
+            let (error_rate, lost_rate) = (state.schedule.error_rate, state.schedule.lost_response_rate);
+            if state.rng.chance(error_rate) {
+                Some(Fault::Error)
+            } else if state.rng.chance(lost_rate) {
+                Some(Fault::LostResponse)
+            } else {
+                None
+            }
+        };
+        if let Some(fault) = fault {
+            state.injected += 1;
+            if fault == Fault::LostResponse {
+                state.lost_responses += 1;
+            }
+        }
+        fault
+    }
+
+    fn call<T>(&self, op: impl FnOnce() -> Result<T>) -> Result<T> {
+        let injected = |what| Err(ObjectStoreError::Io(io::Error::other(format!("simulated {what}"))));
+        match self.next_fault() {
+            None => op(),
+            Some(Fault::Error) => injected("failure"),
+            Some(Fault::LostResponse) => {
+                let _ = op();
+                injected("lost response")
+            }
+        }
+    }
+}
+
+impl ObjectStore for FaultyStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.call(|| self.objects.get(key))
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        self.call(|| self.objects.put(key, body, cond))
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        self.call(|| self.objects.list(prefix, continuation))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.call(|| self.objects.delete(key))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.call(|| self.objects.head(key))
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.call(|| self.objects.get_range(key, range))
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.call(|| self.objects.get_opts(key, opts))
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.call(|| self.objects.put_tags(key, tags))
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.call(|| self.objects.get_tags(key))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    // Index of the workload operation that exposed it
+    pub step: u64,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimReport {
+    pub seed: u64,
+    pub steps: u64,
+    pub faults_injected: u64,
+    pub violations: Vec<Violation>,
+}
+
+/// Runs a seeded random workload of reads, writes, compare-and-swaps and
+/// deletes through a stack of stores over a `FaultyStore`, checking each
+/// result against a model of what the backend may hold.
+///
+/// The model tracks, per key, every value that could be current: a failed
+/// write may or may not have taken effect. Reads must return one of
+/// those values (read-your-writes, no stale caches). A conditional put may
+/// only succeed or fail if some possible value lets it. Operations run one
+/// at a time from a single thread, so a report replays exactly from its
+/// seed. Timers inside the decorators (TTLs, backoff, cooldowns) still use
+/// wall-clock time, so give them short durations.
+pub struct Simulation {
+    seed: u64,
+    steps: u64,
+    keys: u64,
+    schedule: FaultSchedule,
+}
+
+// Values a key may hold; None means absent
+type Possible = Vec<Option<Vec<u8>>>;
+
+fn etag_of(value: &Option<Vec<u8>>) -> Option<String> {
+    value.as_ref().map(|data| format!("{:x}", md5::compute(data)))
+}
+
+fn add(possible: &mut Possible, value: Option<Vec<u8>>) {
+    if !possible.contains(&value) {
+        possible.push(value);
+    }
+}
+
+impl Simulation {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            steps: 1000,
+            keys: 4,
+            schedule: FaultSchedule::default(),
+        }
+    }
+
+    pub fn with_steps(mut self, steps: u64) -> Self {
+        self.steps = steps;
+        self
+    }
+
+    // Size of the key space; fewer keys means more contention per key
+    pub fn with_keys(mut self, keys: u64) -> Self {
+        self.keys = keys.max(1);
+        self
+    }
+
+    pub fn with_faults(mut self, schedule: FaultSchedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Runs the workload against the stack `build` puts over the faulty backend.
+    pub fn run<S: ObjectStore>(&self, build: impl FnOnce(FaultyStore) -> S) -> SimReport {
+        let backend = FaultyStore::new(self.schedule.clone(), self.seed ^ 0x5eed);
+        let store = build(backend.clone());
+        let mut rng = SimRng::new(self.seed);
+        let mut model: HashMap<String, Possible> = HashMap::new();
+        let mut violations = Vec::new();
+
+        for step in 0..self.steps {
+            let key = format!("sim/k{}", rng.below(self.keys));
+            let possible = model.entry(key.clone()).or_insert_with(|| vec![None]);
+            let body = format!("{step}").into_bytes();
+            let lost_before = backend.lost_responses();
+            let mut fail = |message: String| violations.push(Violation { step, message });
+
+            match rng.below(10) {
+                0..=3 => {
+                    if let Ok(value) = store.get(&key) {
+                        if !possible.contains(&value) {
+                            fail(format!("get {key} returned {value:?}, expected one of {possible:?}"));
+                        }
+                        *possible = vec![value];
+                    }
+                }
+                4 => {
+                    if let Ok(meta) = store.head(&key) {
+                        let etag = meta.map(|m| m.etag);
+                        if !possible.iter().any(|p| etag_of(p) == etag) {
+                            fail(format!("head {key} returned ETag {etag:?}, matching no possible value"));
+                        }
+                        possible.retain(|p| etag_of(p) == etag);
+                    }
+                }
+                5 | 6 => {
+                    let result = store.put(&key, &body, IfMatch::Any);
+                    if result.is_ok() {
+                        possible.clear();
+                    }
+                    add(possible, Some(body));
+                }
+                7 | 8 => {
+                    // Compare-and-swap against one of the values the key may hold
+                    let expected = possible[rng.below(possible.len() as u64) as usize].clone();
+                    let cond = etag_of(&expected);
+                    let matches = |p: &Option<Vec<u8>>| etag_of(p) == cond;
+                    let result = match &cond {
+                        Some(etag) => store.put(&key, &body, IfMatch::Tag(etag)),
+                        None => store.put(&key, &body, IfMatch::NoneMatch),
+                    };
+                    let lost = backend.lost_responses() > lost_before;
+                    match result {
+                        Ok(_) => {
+                            if !possible.iter().any(matches) {
+                                fail(format!("conditional put {key} succeeded, but no possible value allowed it"));
+                            }
+                            *possible = vec![Some(body)];
+                        }
+                        Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => {
+                            // Unless an earlier attempt of this very call went through
+                            possible.retain(|p| !matches(p));
+                            if lost {
+                                add(possible, Some(body));
+                            } else if possible.is_empty() {
+                                fail(format!("conditional put {key} failed its precondition, but it held"));
+                            }
+                        }
+                        Err(_) => {
+                            if possible.iter().any(matches) {
+                                add(possible, Some(body));
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    if store.delete(&key).is_ok() {
+                        possible.clear();
+                    }
+                    add(possible, None);
+                }
+            }
+            if model.get(&key).is_some_and(|p| p.is_empty()) {
+                // A violation already reported; resynchronise from the backend
+                let actual = backend.objects().get(&key).unwrap_or(None);
+                model.insert(key, vec![actual]);
+            }
+        }
+
+        SimReport {
+            seed: self.seed,
+            steps: self.steps,
+            faults_injected: backend.faults_injected(),
+            violations,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::cache::CachedStore;
+    use crate::object_store::retry::RetryingStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    fn retrying(backend: FaultyStore) -> RetryingStore<FaultyStore> {
+        RetryingStore::new(backend)
+            .with_max_attempts(3)
+            .with_backoff(Duration::ZERO, Duration::ZERO)
+    }
+
+    #[test]
+    fn test_faulty_store_without_faults() {
+        let store = FaultyStore::new(FaultSchedule::new(), 1);
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+        assert_eq!(store.faults_injected(), 0);
+    }
+
+    #[test]
+    fn test_schedule_is_followed() {
+        let schedule = FaultSchedule::new().fail_at(1, Fault::LostResponse).outage(3..5);
+        let store = FaultyStore::new(schedule, 1);
+        store.put("a", b"0", IfMatch::Any).unwrap();
+        // Applied, but reported as failed
+        assert!(store.put("a", b"1", IfMatch::Any).is_err());
+        assert_eq!(store.objects().get("a").unwrap(), Some(b"1".to_vec()));
+        store.get("a").unwrap();
+        assert!(store.get("a").is_err());
+        assert!(store.get("a").is_err());
+        store.get("a").unwrap();
+        assert_eq!((store.faults_injected(), store.lost_responses()), (3, 1));
+        assert_eq!(store.clock().now(), 6);
+    }
+
+    #[test]
+    fn test_plain_backend_has_no_violations() {
+        let report = Simulation::new(7).with_faults(FaultSchedule::new().random(0.1, 0.1)).run(|b| b);
+        assert!(report.violations.is_empty(), "{:?}", report.violations);
+        assert!(report.faults_injected > 0);
+    }
+
+    #[test]
+    fn test_retry_and_cache_stack_has_no_violations() {
+        for seed in 0..20 {
+            let report = Simulation::new(seed)
+                .with_steps(500)
+                .with_faults(FaultSchedule::new().random(0.05, 0.05).outage(100..110))
+                .run(|backend| CachedStore::new(retrying(backend), 1 << 20));
+            assert!(report.violations.is_empty(), "seed {seed}: {:?}", report.violations);
+        }
+    }
+
+    #[test]
+    fn test_runs_are_deterministic() {
+        let sim = Simulation::new(42).with_faults(FaultSchedule::new().random(0.1, 0.1));
+        assert_eq!(sim.run(retrying), sim.run(retrying));
+    }
+
+    // Forgets to invalidate when a write fails, so a write whose response
+    // was lost leaves a stale entry behind
+    struct LeakyCache<S> {
+        inner: S,
+        cached: Mutex<HashMap<String, Vec<u8>>>,
+    }
+
+    impl<S: ObjectStore> ObjectStore for LeakyCache<S> {
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            if let Some(data) = self.cached.lock().unwrap().get(key) {
+                return Ok(Some(data.clone()));
+            }
+            let data = self.inner.get(key)?;
+            if let Some(data) = &data {
+                self.cached.lock().unwrap().insert(key.to_string(), data.clone());
+            }
+            Ok(data)
+        }
+        fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+            let etag = self.inner.put(key, body, cond)?;
+            self.cached.lock().unwrap().remove(key);
+            Ok(etag)
+        }
+        fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+            self.inner.list(prefix, continuation)
+        }
+        fn delete(&self, key: &str) -> Result<()> {
+            self.inner.delete(key)?;
+            self.cached.lock().unwrap().remove(key);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_catches_stale_cache() {
+        let report = Simulation::new(3)
+            .with_faults(FaultSchedule::new().random(0.0, 0.2))
+            .run(|backend| LeakyCache {
+                inner: backend,
+                cached: Mutex::default(),
+            });
+        assert!(!report.violations.is_empty());
+    }
+}
@@ -0,0 +1,303 @@
+use super::sync::{for_each_concurrent, list_all};
+use super::versioned::{monotonic_micros, VersionedStore};
+use super::{
+    get_opts_fallback, slice_range, GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::{Bound, Range};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+// Snapshot manifests live in the store itself under this prefix
+pub const MANIFEST_PREFIX: &str = ".snapshots/";
+
+// Keys resolved to versions at once while taking a snapshot
+const CONCURRENCY: usize = 8;
+
+// Keys per page when listing a snapshot
+const PAGE_SIZE: usize = 1000;
+
+/// One object as it was when the snapshot was taken.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotObject {
+    pub version: String,
+    pub etag: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    pub id: String,
+    pub prefix: String,
+    // Milliseconds since the Unix epoch
+    pub created_at: u64,
+    pub objects: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    prefix: String,
+    created_at: u64,
+    objects: BTreeMap<String, SnapshotObject>,
+}
+
+impl Manifest {
+    fn info(&self, id: &str) -> SnapshotInfo {
+        SnapshotInfo {
+            id: id.to_string(),
+            prefix: self.prefix.clone(),
+            created_at: self.created_at,
+            objects: self.objects.len(),
+        }
+    }
+}
+
+fn manifest_key(id: &str) -> Result<String> {
+    if id.is_empty() || id.contains('/') {
+        return Err(ObjectStoreError::InvalidArgument(format!("invalid snapshot id {id:?}")));
+    }
+    Ok(format!("{MANIFEST_PREFIX}{id}.json"))
+}
+
+fn load<S: ObjectStore>(store: &VersionedStore<S>, id: &str) -> Result<Manifest> {
+    let key = manifest_key(id)?;
+    let data = store
+        .inner()
+        .get(&key)?
+        .ok_or_else(|| ObjectStoreError::NotFound(format!("snapshot {id}")))?;
+    serde_json::from_slice(&data).map_err(|e| ObjectStoreError::Other(format!("corrupt snapshot manifest {key}: {e}")))
+}
+
+// The version holding `key`'s current content, newest first since that's
+// nearly always it
+fn resolve<S: ObjectStore>(store: &VersionedStore<S>, key: &str) -> Result<Option<SnapshotObject>> {
+    let Some(meta) = store.head(key)? else {
+        return Ok(None);
+    };
+    for version in store.list_versions(key)?.into_iter().rev() {
+        if store.head_version(key, &version.id)?.is_some_and(|v| v.etag == meta.etag) {
+            return Ok(Some(SnapshotObject {
+                version: version.id,
+                etag: meta.etag,
+                size: meta.size,
+            }));
+        }
+    }
+    Err(ObjectStoreError::Other(format!("{key} has no version matching its current content")))
+}
+
+/// Records which version every object under `prefix` is at, so the set
+/// can be read back later through a `SnapshotView`.
+///
+/// Only the manifest is written, as `.snapshots/<id>.json`; the content is
+/// the versions the `VersionedStore` already keeps, so they must not be
+/// deleted while a snapshot needs them. Every object must have been
+/// written through the `VersionedStore`. Objects are resolved one by one,
+/// so writes made while the snapshot is taken may or may not be in it.
+pub fn create<S: ObjectStore>(store: &VersionedStore<S>, prefix: &str) -> Result<SnapshotInfo> {
+    let keys: Vec<String> = list_all(store, prefix)?
+        .into_iter()
+        .filter(|key| !key.starts_with(MANIFEST_PREFIX))
+        .collect();
+    let objects = Mutex::new(BTreeMap::new());
+    let first_err = Mutex::new(None);
+    for_each_concurrent(&keys, CONCURRENCY, |key| match resolve(store, key) {
+        Ok(Some(object)) => {
+            objects.lock().unwrap().insert(key.clone(), object);
+        }
+        // Deleted since it was listed
+        Ok(None) => {}
+        Err(e) => {
+            first_err.lock().unwrap().get_or_insert(e);
+        }
+    });
+    if let Some(e) = first_err.into_inner().unwrap() {
+        return Err(e);
+    }
+
+    let micros = monotonic_micros();
+    let id = format!("{micros:016}-{}", Uuid::new_v4().simple());
+    let manifest = Manifest {
+        prefix: prefix.to_string(),
+        created_at: micros / 1000,
+        objects: objects.into_inner().unwrap(),
+    };
+    let data = serde_json::to_vec(&manifest).expect("snapshot manifest serializes");
+    store.inner().put(&manifest_key(&id)?, &data, IfMatch::NoneMatch)?;
+    Ok(manifest.info(&id))
+}
+
+/// Every snapshot, oldest first.
+pub fn list_snapshots<S: ObjectStore>(store: &VersionedStore<S>) -> Result<Vec<SnapshotInfo>> {
+    let mut snapshots = Vec::new();
+    for key in list_all(store.inner(), MANIFEST_PREFIX)? {
+        let Some(id) = key.strip_prefix(MANIFEST_PREFIX).and_then(|name| name.strip_suffix(".json")) else {
+            continue;
+        };
+        snapshots.push(load(store, id)?.info(id));
+    }
+    snapshots.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(snapshots)
+}
+
+/// Deletes a snapshot's manifest; the versions it named are kept.
+pub fn delete_snapshot<S: ObjectStore>(store: &VersionedStore<S>, id: &str) -> Result<()> {
+    store.inner().delete(&manifest_key(id)?)
+}
+
+/// A read-only store showing the objects of a snapshot as they were when it
+/// was taken, read from the versions its manifest names. Writes fail with
+/// `ObjectStoreError::ReadOnly`.
+pub struct SnapshotView<S> {
+    store: VersionedStore<S>,
+    id: String,
+    manifest: Manifest,
+}
+
+impl<S: ObjectStore> SnapshotView<S> {
+    pub fn open(store: VersionedStore<S>, id: &str) -> Result<Self> {
+        let manifest = load(&store, id)?;
+        Ok(Self {
+            store,
+            id: id.to_string(),
+            manifest,
+        })
+    }
+
+    pub fn info(&self) -> SnapshotInfo {
+        self.manifest.info(&self.id)
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for SnapshotView<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let Some(object) = self.manifest.objects.get(key) else {
+            return Ok(None);
+        };
+        match self.store.get_version(key, &object.version)? {
+            Some(data) => Ok(Some(data)),
+            None => Err(ObjectStoreError::Other(format!(
+                "version {} of {key} in snapshot {} is gone",
+                object.version, self.id
+            ))),
+        }
+    }
+
+    fn put(&self, key: &str, _body: &[u8], _cond: IfMatch) -> Result<String> {
+        Err(ObjectStoreError::ReadOnly(key.to_string()))
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let start = match &continuation {
+            Some(after) => Bound::Excluded(after.as_str()),
+            None => Bound::Included(prefix),
+        };
+        let mut keys: Vec<String> = self
+            .manifest
+            .objects
+            .range::<str, _>((start, Bound::Unbounded))
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .take(PAGE_SIZE + 1)
+            .cloned()
+            .collect();
+        let next = (keys.len() > PAGE_SIZE).then(|| {
+            keys.truncate(PAGE_SIZE);
+            keys[PAGE_SIZE - 1].clone()
+        });
+        Ok((keys, next))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        Err(ObjectStoreError::ReadOnly(key.to_string()))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        Ok(self.manifest.objects.get(key).map(|object| ObjectMeta {
+            size: object.size,
+            etag: object.etag.clone(),
+        }))
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        Ok(self.get(key)?.map(|data| slice_range(&data, range).to_vec()))
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        get_opts_fallback(self, key, &opts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_view_is_frozen() {
+        let backend = Arc::new(InMemoryStore::default());
+        let store = VersionedStore::new(backend.clone());
+        store.put("build/a.o", b"a1", IfMatch::Any).unwrap();
+        store.put("build/b.o", b"b1", IfMatch::Any).unwrap();
+        store.put("build/b.o", b"b2", IfMatch::Any).unwrap();
+        store.put("src/main.rs", b"fn main", IfMatch::Any).unwrap();
+        let info = create(&store, "build/").unwrap();
+        assert_eq!(info.objects, 2);
+
+        store.put("build/a.o", b"a2", IfMatch::Any).unwrap();
+        store.delete("build/b.o").unwrap();
+        store.put("build/c.o", b"c1", IfMatch::Any).unwrap();
+
+        let view = SnapshotView::open(VersionedStore::new(backend), &info.id).unwrap();
+        assert_eq!(view.info(), info);
+        assert_eq!(view.get("build/a.o").unwrap(), Some(b"a1".to_vec()));
+        assert_eq!(view.get("build/b.o").unwrap(), Some(b"b2".to_vec()));
+        assert_eq!(view.get("build/c.o").unwrap(), None);
+        assert_eq!(view.get("src/main.rs").unwrap(), None);
+        assert_eq!(view.get_range("build/b.o", 1..2).unwrap(), Some(b"2".to_vec()));
+        assert_eq!(view.head("build/a.o").unwrap().unwrap().size, 2);
+        assert_eq!(view.list("build/", None).unwrap(), (vec!["build/a.o".to_string(), "build/b.o".to_string()], None));
+        assert!(matches!(view.put("build/a.o", b"x", IfMatch::Any), Err(ObjectStoreError::ReadOnly(_))));
+        assert!(matches!(view.delete("build/a.o"), Err(ObjectStoreError::ReadOnly(_))));
+    }
+
+    #[test]
+    fn test_list_pages() {
+        let store = VersionedStore::new(InMemoryStore::default());
+        for i in 0..1500 {
+            store.put(&format!("k/{i:04}"), b"x", IfMatch::Any).unwrap();
+        }
+        let id = create(&store, "").unwrap().id;
+        let view = SnapshotView::open(store, &id).unwrap();
+        let (first, token) = view.list("k/", None).unwrap();
+        assert_eq!(first.len(), 1000);
+        let (rest, token) = view.list("k/", token).unwrap();
+        assert_eq!(rest.len(), 500);
+        assert_eq!(rest[0], "k/1000");
+        assert!(token.is_none());
+        assert!(view.list("z/", None).unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn test_manage_snapshots() {
+        let backend = Arc::new(InMemoryStore::default());
+        let store = VersionedStore::new(backend.clone());
+        store.put("a", b"1", IfMatch::Any).unwrap();
+        let first = create(&store, "").unwrap();
+        let second = create(&store, "").unwrap();
+        // The first manifest isn't part of the second snapshot
+        assert_eq!(second.objects, 1);
+        assert_eq!(list_snapshots(&store).unwrap(), vec![first.clone(), second]);
+
+        delete_snapshot(&store, &first.id).unwrap();
+        assert_eq!(list_snapshots(&store).unwrap().len(), 1);
+        assert!(SnapshotView::open(VersionedStore::new(backend.clone()), &first.id).is_err());
+        assert!(SnapshotView::open(VersionedStore::new(backend.clone()), "../x").is_err());
+
+        // Written behind the versioning layer's back
+        backend.put("unversioned", b"x", IfMatch::Any).unwrap();
+        assert!(create(&store, "").is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use super::{get_from_body, slice_range, GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ops::Range;
+use std::sync::Mutex;
+
+type Tags = BTreeMap<String, String>;
+
+// Unfinished listings kept before the oldest is dropped
+const MAX_LISTINGS: usize = 64;
+
+struct Listing {
+    prefix: String,
+    keys: Vec<String>,
+}
+
+#[derive(Default)]
+struct State {
+    // Body, ETag and tags of each object
+    objects: BTreeMap<String, (Vec<u8>, String, Tags)>,
+    listings: HashMap<u64, Listing>,
+    // Listing ids, oldest first
+    listing_order: VecDeque<u64>,
+    next_listing: u64,
+}
+
+/// In-memory store with the strictest semantics the trait allows, used as
+/// the reference that other backends are checked against (see
+/// `test_helpers::tests::run_oracle_tests`).
+///
+/// - Every operation takes one lock, so all of them, conditional puts
+///   included, are linearizable.
+/// - ETags are the MD5 of the body, as with `InMemoryStore`.
+/// - A listing is a snapshot: the keys under the prefix are captured when
+///   the first page is requested and later pages come from that capture,
+///   whatever is written in between. Continuation tokens are only valid
+///   for the prefix they were issued for; unknown, expired or mismatched
+///   tokens are errors rather than being treated as a fresh listing.
+/// - `get_opts` always has the metadata at hand, so `include_metadata`
+///   never costs an extra lookup.
+///
+/// Pages hold `page_size` keys (1000 by default); a small page size makes
+/// pagination bugs in callers show up with few objects.
+pub struct StrictMemoryStore {
+    state: Mutex<State>,
+    page_size: usize,
+}
+
+impl Default for StrictMemoryStore {
+    fn default() -> Self {
+        Self {
+            state: Mutex::default(),
+            page_size: 1000,
+        }
+    }
+}
+
+impl StrictMemoryStore {
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    fn page(&self, state: &mut State, id: u64, offset: usize) -> (Vec<String>, Option<String>) {
+        let keys = &state.listings[&id].keys;
+        let end = (offset + self.page_size).min(keys.len());
+        let page = keys[offset..end].to_vec();
+        if end < keys.len() {
+            (page, Some(format!("{id}:{end}")))
+        } else {
+            state.listings.remove(&id);
+            state.listing_order.retain(|other| *other != id);
+            (page, None)
+        }
+    }
+}
+
+fn invalid_token(token: &str) -> ObjectStoreError {
+    ObjectStoreError::InvalidArgument(format!("unknown or expired continuation token {token:?}"))
+}
+
+impl ObjectStore for StrictMemoryStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.objects.get(key).map(|(data, _, _)| data.clone()))
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        let mut state = self.state.lock().unwrap();
+        let current = state.objects.get(key).map(|(_, etag, _)| etag.as_str());
+        let allowed = match cond {
+            IfMatch::Any => true,
+            IfMatch::Tag(expected) => current == Some(expected),
+            IfMatch::NoneMatch => current.is_none(),
+        };
+        if !allowed {
+            return Err(ObjectStoreError::PreconditionFailed);
+        }
+        let etag = format!("{:x}", md5::compute(body));
+        state.objects.insert(key.to_string(), (body.to_vec(), etag.clone(), BTreeMap::new()));
+        Ok(etag)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let mut state = self.state.lock().unwrap();
+        let Some(token) = continuation else {
+            let keys: Vec<String> = state
+                .objects
+                .range(prefix.to_string()..)
+                .map(|(key, _)| key)
+                .take_while(|key| key.starts_with(prefix))
+                .cloned()
+                .collect();
+            let id = state.next_listing;
+            state.next_listing += 1;
+            state.listings.insert(
+                id,
+                Listing {
+                    prefix: prefix.to_string(),
+                    keys,
+                },
+            );
+            state.listing_order.push_back(id);
+            if state.listing_order.len() > MAX_LISTINGS
+                && let Some(oldest) = state.listing_order.pop_front()
+            {
+                state.listings.remove(&oldest);
+            }
+            return Ok(self.page(&mut state, id, 0));
+        };
+
+        let (id, offset) = token
+            .split_once(':')
+            .and_then(|(id, offset)| Some((id.parse::<u64>().ok()?, offset.parse::<usize>().ok()?)))
+            .ok_or_else(|| invalid_token(&token))?;
+        match state.listings.get(&id) {
+            Some(listing) if listing.prefix == prefix && offset <= listing.keys.len() => {}
+            _ => return Err(invalid_token(&token)),
+        }
+        Ok(self.page(&mut state, id, offset))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.state.lock().unwrap().objects.remove(key);
+        Ok(())
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.objects.get(key).map(|(data, etag, _)| ObjectMeta {
+            size: data.len() as u64,
+            etag: etag.clone(),
+        }))
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.objects.get(key).map(|(data, _, _)| slice_range(data, range).to_vec()))
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        let state = self.state.lock().unwrap();
+        match state.objects.get(key) {
+            Some((data, etag, _)) => get_from_body(data, etag, &opts).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let (_, _, current) = state
+            .objects
+            .get_mut(key)
+            .ok_or_else(|| ObjectStoreError::NotFound(key.to_string()))?;
+        *current = tags.clone();
+        Ok(())
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.objects.get(key).map(|(_, _, tags)| tags.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::test_helpers::tests::{run_object_store_tests, run_oracle_tests};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_strict_object_store() {
+        let store = StrictMemoryStore::default();
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+        run_oracle_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_listing_is_a_snapshot() {
+        let store = StrictMemoryStore::default().with_page_size(2);
+        for key in ["a", "b", "c", "d"] {
+            store.put(key, b"x", IfMatch::Any).unwrap();
+        }
+        let (first, token) = store.list("", None).unwrap();
+        assert_eq!(first, vec!["a", "b"]);
+
+        // Writes after the first page don't show up in the rest of it
+        store.delete("c").unwrap();
+        store.put("bb", b"x", IfMatch::Any).unwrap();
+        let (second, token) = store.list("", token).unwrap();
+        assert_eq!(second, vec!["c", "d"]);
+        assert_eq!(token, None);
+    }
+
+    #[test]
+    fn test_rejects_foreign_tokens() {
+        let store = StrictMemoryStore::default().with_page_size(1);
+        for key in ["a/1", "a/2", "b/1", "b/2"] {
+            store.put(key, b"x", IfMatch::Any).unwrap();
+        }
+        let (_, token) = store.list("a/", None).unwrap();
+        assert!(store.list("b/", token.clone()).is_err());
+        assert!(store.list("a/", Some("a/1".into())).is_err());
+        let (rest, _) = store.list("a/", token.clone()).unwrap();
+        assert_eq!(rest, vec!["a/2"]);
+        // Finished listings are forgotten
+        assert!(store.list("a/", token).is_err());
+    }
+}
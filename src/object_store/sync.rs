@@ -0,0 +1,315 @@
+use super::cost::{estimate, list_pages, CostEstimate, Pricing, RequestCounts};
+use super::{IfMatch, ObjectStore, ObjectStoreError, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    // Number of objects copied or deleted at once
+    pub concurrency: usize,
+    // Remove destination keys under the prefix that the source doesn't have
+    pub delete: bool,
+    // Where keys land in the destination; defaults to the source prefix
+    pub dest_prefix: Option<String>,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            delete: false,
+            dest_prefix: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub copied: usize,
+    // Already present at the destination with the same size and ETag
+    pub skipped: usize,
+    pub deleted: usize,
+    // Per-key failures; the rest of the sync carries on past them
+    pub errors: Vec<(String, ObjectStoreError)>,
+}
+
+/// Mirrors every key under `prefix` from `src` to `dst`.
+///
+/// Both listings are compared first; keys whose size and ETag already match
+/// are skipped and the rest are copied `options.concurrency` at a time. With
+/// `options.delete`, destination keys missing from the source are removed
+/// afterwards. Listing failures abort the sync; failures on individual keys
+/// are collected in the report.
+pub fn sync(src: &dyn ObjectStore, dst: &dyn ObjectStore, prefix: &str, options: &SyncOptions) -> Result<SyncReport> {
+    let dest_prefix = options.dest_prefix.as_deref().unwrap_or(prefix);
+    let to_dest = |key: &str| format!("{dest_prefix}{}", &key[prefix.len()..]);
+
+    let src_keys = list_all(src, prefix)?;
+    let dst_keys: HashSet<String> = list_all(dst, dest_prefix)?.into_iter().collect();
+
+    let report = Mutex::new(SyncReport::default());
+    let record_err = |key: &str, e: ObjectStoreError| report.lock().unwrap().errors.push((key.to_string(), e));
+
+    for_each_concurrent(&src_keys, options.concurrency, |key| {
+        let target = to_dest(key);
+        match copy_if_changed(src, dst, key, &target, dst_keys.contains(&target)) {
+            Ok(true) => report.lock().unwrap().copied += 1,
+            Ok(false) => report.lock().unwrap().skipped += 1,
+            Err(e) => record_err(key, e),
+        }
+    });
+
+    if options.delete {
+        let wanted: HashSet<String> = src_keys.iter().map(|key| to_dest(key)).collect();
+        let extraneous: Vec<&String> = dst_keys.iter().filter(|key| !wanted.contains(*key)).collect();
+        for_each_concurrent(&extraneous, options.concurrency, |key| match dst.delete(key) {
+            Ok(()) => report.lock().unwrap().deleted += 1,
+            Err(e) => record_err(key, e),
+        });
+    }
+
+    Ok(report.into_inner().unwrap())
+}
+
+/// What `sync` would do, worked out without writing anything.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncPlan {
+    // Source keys that would be copied, with their sizes
+    pub copy: Vec<(String, u64)>,
+    // Destination keys that would be removed (only with `options.delete`)
+    pub delete: Vec<String>,
+    pub unchanged: usize,
+    // Requests the sync itself would make
+    pub src_requests: RequestCounts,
+    pub dst_requests: RequestCounts,
+}
+
+impl SyncPlan {
+    pub fn bytes(&self) -> u64 {
+        self.copy.iter().map(|(_, size)| size).sum()
+    }
+
+    pub fn estimate(&self, src_pricing: &Pricing, dst_pricing: &Pricing) -> CostEstimate {
+        estimate(self.src_requests, src_pricing, self.dst_requests, dst_pricing, self.bytes())
+    }
+}
+
+/// Dry run of `sync`. Lists both sides and heads every source key (and
+/// destination keys that exist) to find what would change; those reads are
+/// the cost of planning and aren't counted in the plan's request totals.
+pub fn plan(src: &dyn ObjectStore, dst: &dyn ObjectStore, prefix: &str, options: &SyncOptions) -> Result<SyncPlan> {
+    let dest_prefix = options.dest_prefix.as_deref().unwrap_or(prefix);
+    let to_dest = |key: &str| format!("{dest_prefix}{}", &key[prefix.len()..]);
+
+    let mut src_keys = list_all(src, prefix)?;
+    src_keys.sort();
+    let dst_keys: HashSet<String> = list_all(dst, dest_prefix)?.into_iter().collect();
+
+    let copies = Mutex::new(Vec::new());
+    let first_err = Mutex::new(None);
+    for_each_concurrent(&src_keys, options.concurrency, |key| {
+        let target = to_dest(key);
+        let exists = dst_keys.contains(&target);
+        let metas = src.head(key).and_then(|s| Ok((s, if exists { dst.head(&target)? } else { None })));
+        match metas {
+            Ok((Some(s), d)) if d.as_ref() != Some(&s) => copies.lock().unwrap().push((key.clone(), s.size)),
+            Ok(_) => {}
+            Err(e) => {
+                first_err.lock().unwrap().get_or_insert(e);
+            }
+        }
+    });
+    if let Some(e) = first_err.into_inner().unwrap() {
+        return Err(e);
+    }
+
+    let mut copy = copies.into_inner().unwrap();
+    copy.sort();
+    let delete: Vec<String> = if options.delete {
+        let wanted: HashSet<String> = src_keys.iter().map(|key| to_dest(key)).collect();
+        let mut extra: Vec<String> = dst_keys.iter().filter(|key| !wanted.contains(*key)).cloned().collect();
+        extra.sort();
+        extra
+    } else {
+        Vec::new()
+    };
+
+    let existing = src_keys.iter().filter(|key| dst_keys.contains(&to_dest(key))).count() as u64;
+    let src_requests = RequestCounts {
+        list: list_pages(src_keys.len() as u64),
+        head: existing,
+        get: copy.len() as u64,
+        ..Default::default()
+    };
+    let dst_requests = RequestCounts {
+        list: list_pages(dst_keys.len() as u64),
+        head: existing,
+        put: copy.len() as u64,
+        delete: delete.len() as u64,
+        ..Default::default()
+    };
+
+    Ok(SyncPlan {
+        unchanged: src_keys.len() - copy.len(),
+        copy,
+        delete,
+        src_requests,
+        dst_requests,
+    })
+}
+
+// Returns whether the object was copied (false if it was already up to date)
+fn copy_if_changed(src: &dyn ObjectStore, dst: &dyn ObjectStore, key: &str, target: &str, exists: bool) -> Result<bool> {
+    if exists {
+        let src_meta = src.head(key)?;
+        if src_meta.is_some() && src_meta == dst.head(target)? {
+            return Ok(false);
+        }
+    }
+    // Deleted from the source since it was listed; nothing to copy
+    let Some(data) = src.get(key)? else {
+        return Ok(false);
+    };
+    dst.put(target, &data, IfMatch::Any)?;
+    Ok(true)
+}
+
+pub(crate) fn list_all(store: &dyn ObjectStore, prefix: &str) -> Result<Vec<String>> {
+    let mut keys = Vec::new();
+    let mut token = None;
+    loop {
+        let (page, next) = store.list(prefix, token)?;
+        keys.extend(page);
+        match next {
+            Some(next) => token = Some(next),
+            None => return Ok(keys),
+        }
+    }
+}
+
+// Runs `f` over `items` on up to `concurrency` scoped threads
+pub(crate) fn for_each_concurrent<T: Sync>(items: &[T], concurrency: usize, f: impl Fn(&T) + Sync) {
+    let next = AtomicUsize::new(0);
+    let workers = concurrency.clamp(1, items.len().max(1));
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while let Some(item) = items.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    f(item);
+                }
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::scan::{ScanVerdict, ScanningStore};
+
+    fn put(store: &dyn ObjectStore, key: &str, body: &[u8]) {
+        store.put(key, body, IfMatch::Any).unwrap();
+    }
+
+    #[test]
+    fn test_sync_copies_and_skips() {
+        let src = InMemoryStore::default();
+        let dst = InMemoryStore::default();
+        for i in 0..50 {
+            put(&src, &format!("data/{i:02}"), format!("body {i}").as_bytes());
+        }
+        put(&src, "other/ignored", b"outside the prefix");
+        put(&dst, "data/00", b"body 0");
+        put(&dst, "data/01", b"stale");
+
+        let report = sync(&src, &dst, "data/", &SyncOptions::default()).unwrap();
+        assert_eq!(report.copied, 49);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(report.deleted, 0);
+        assert!(report.errors.is_empty());
+        assert_eq!(dst.get("data/01").unwrap(), Some(b"body 1".to_vec()));
+        assert_eq!(dst.get("other/ignored").unwrap(), None);
+
+        // A second pass has nothing left to do
+        let report = sync(&src, &dst, "data/", &SyncOptions::default()).unwrap();
+        assert_eq!((report.copied, report.skipped), (0, 50));
+    }
+
+    #[test]
+    fn test_sync_delete_and_dest_prefix() {
+        let src = InMemoryStore::default();
+        let dst = InMemoryStore::default();
+        put(&src, "a/1", b"one");
+        put(&src, "a/2", b"two");
+        put(&dst, "b/2", b"two");
+        put(&dst, "b/3", b"extra");
+        put(&dst, "c/keep", b"outside the prefix");
+
+        let options = SyncOptions {
+            delete: true,
+            dest_prefix: Some("b/".to_string()),
+            ..Default::default()
+        };
+        let report = sync(&src, &dst, "a/", &options).unwrap();
+        assert_eq!((report.copied, report.skipped, report.deleted), (1, 1, 1));
+        let (keys, _) = dst.list("", None).unwrap();
+        assert_eq!(keys, vec!["b/1", "b/2", "c/keep"]);
+    }
+
+    #[test]
+    fn test_sync_collects_per_key_errors() {
+        let src = InMemoryStore::default();
+        put(&src, "x/good", b"fine");
+        put(&src, "x/bad", b"EICAR");
+        let scanner = |_key: &str, body: &[u8]| {
+            if body == b"EICAR" {
+                Ok(ScanVerdict::Infected("test signature".to_string()))
+            } else {
+                Ok(ScanVerdict::Clean)
+            }
+        };
+        let dst = ScanningStore::new(InMemoryStore::default(), scanner);
+
+        let report = sync(&src, &dst, "x/", &SyncOptions::default()).unwrap();
+        assert_eq!(report.copied, 1);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].0, "x/bad");
+        assert!(matches!(report.errors[0].1, ObjectStoreError::Blocked(_)));
+    }
+
+    #[test]
+    fn test_plan_matches_sync() {
+        let src = InMemoryStore::default();
+        let dst = InMemoryStore::default();
+        put(&src, "p/same", b"same");
+        put(&src, "p/changed", b"new body");
+        put(&src, "p/new", b"brand new");
+        put(&dst, "p/same", b"same");
+        put(&dst, "p/changed", b"old");
+        put(&dst, "p/extra", b"extra");
+        let options = SyncOptions {
+            delete: true,
+            ..Default::default()
+        };
+
+        let plan = plan(&src, &dst, "p/", &options).unwrap();
+        assert_eq!(plan.copy, vec![("p/changed".to_string(), 8), ("p/new".to_string(), 9)]);
+        assert_eq!(plan.delete, vec!["p/extra"]);
+        assert_eq!(plan.unchanged, 1);
+        assert_eq!(plan.bytes(), 17);
+        assert_eq!(plan.src_requests.get, 2);
+        assert_eq!(plan.dst_requests.put, 2);
+        // Planning doesn't write
+        assert_eq!(dst.get("p/changed").unwrap(), Some(b"old".to_vec()));
+
+        let est = plan.estimate(&Pricing::s3_standard(), &Pricing::free());
+        assert!(est.dollars > 0.0);
+
+        let report = sync(&src, &dst, "p/", &options).unwrap();
+        assert_eq!((report.copied, report.skipped, report.deleted), (2, 1, 1));
+    }
+}
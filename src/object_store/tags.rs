@@ -0,0 +1,198 @@
+use super::retry::{cas_backoff, MAX_CAS_ATTEMPTS};
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+// Each object's tags are kept under here
+pub const TAGS_PREFIX: &str = ".tags/";
+
+// An object's tags, with the ETag of the content they were set on
+#[derive(Serialize, Deserialize)]
+struct TagRecord {
+    etag: String,
+    tags: BTreeMap<String, String>,
+}
+
+/// Wraps a store without native tagging so `put_tags` and `get_tags` work,
+/// keeping each object's tags in a record at `.tags/<key>`.
+///
+/// A record holds the tags and the ETag of the content they were set on.
+/// Puts and deletes of the key remove it, and a record whose ETag no longer
+/// matches reads as no tags, so a put racing `put_tags` can't leave the old
+/// tags on new content. Records are replaced conditionally on the one they
+/// replace, retrying on conflict like `Counters`. `.tags/` is hidden from
+/// `list` and reserved. A `put_tags` racing a delete can leave a record for
+/// a missing key, which reads as nothing and goes with the next put or
+/// delete of the key.
+pub struct TaggedStore<S> {
+    inner: S,
+}
+
+impl<S: ObjectStore> TaggedStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn record_key(key: &str) -> String {
+        format!("{TAGS_PREFIX}{key}")
+    }
+
+    fn check_key(key: &str) -> Result<()> {
+        if key.starts_with(TAGS_PREFIX) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for TaggedStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        Self::check_key(key)?;
+        let etag = self.inner.put(key, body, cond)?;
+        self.inner.delete(&Self::record_key(key))?;
+        Ok(etag)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let (keys, next) = self.inner.list(prefix, continuation)?;
+        Ok((keys.into_iter().filter(|key| !key.starts_with(TAGS_PREFIX)).collect(), next))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        Self::check_key(key)?;
+        self.inner.delete(key)?;
+        self.inner.delete(&Self::record_key(key))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.inner.head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.inner.get_range(key, range)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.inner.get_opts(key, opts)
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        Self::check_key(key)?;
+        let record_key = Self::record_key(key);
+        for attempt in 0..MAX_CAS_ATTEMPTS {
+            let current = self.inner.head(&record_key)?;
+            let Some(meta) = self.inner.head(key)? else {
+                return Err(ObjectStoreError::NotFound(key.to_string()));
+            };
+            let record = TagRecord {
+                etag: meta.etag,
+                tags: tags.clone(),
+            };
+            let data = serde_json::to_vec(&record).expect("tags serialize");
+            let cond = match &current {
+                Some(current) => IfMatch::Tag(&current.etag),
+                None => IfMatch::NoneMatch,
+            };
+            match self.inner.put(&record_key, &data, cond) {
+                Ok(_) => return Ok(()),
+                // A put or another put_tags got in first; look again
+                Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => cas_backoff(attempt),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(ObjectStoreError::Other(format!("tags of {key} kept changing while being set")))
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        Self::check_key(key)?;
+        let Some(meta) = self.inner.head(key)? else {
+            return Ok(None);
+        };
+        let record_key = Self::record_key(key);
+        let Some(data) = self.inner.get(&record_key)? else {
+            return Ok(Some(BTreeMap::new()));
+        };
+        let record: TagRecord = serde_json::from_slice(&data)
+            .map_err(|e| ObjectStoreError::Other(format!("corrupt tag record at {record_key}: {e}")))?;
+        // Left by a put_tags that lost a race with a put
+        if record.etag != meta.etag {
+            return Ok(Some(BTreeMap::new()));
+        }
+        Ok(Some(record.tags))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use uuid::Uuid;
+
+    fn tags(value: &str) -> BTreeMap<String, String> {
+        BTreeMap::from([("class".to_string(), value.to_string())])
+    }
+
+    #[test]
+    fn test_tagged_object_store() {
+        let store = TaggedStore::new(InMemoryStore::default());
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_records_follow_their_object() {
+        let store = TaggedStore::new(InMemoryStore::default());
+        store.put("a", b"same", IfMatch::Any).unwrap();
+        store.put_tags("a", &tags("cold")).unwrap();
+        assert_eq!(store.inner().get_tags("a").unwrap(), Some(BTreeMap::new()));
+        assert_eq!(store.list("", None).unwrap().0, vec!["a"]);
+
+        // Identical bytes keep the ETag but still drop the tags
+        store.put("a", b"same", IfMatch::Any).unwrap();
+        assert_eq!(store.get_tags("a").unwrap(), Some(BTreeMap::new()));
+        assert_eq!(store.inner().get(".tags/a").unwrap(), None);
+
+        store.put_tags("a", &tags("hot")).unwrap();
+        store.delete("a").unwrap();
+        assert_eq!(store.inner().list("", None).unwrap().0, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_records_are_reserved_and_checked() {
+        let store = TaggedStore::new(InMemoryStore::default());
+        store.put("a", b"data", IfMatch::Any).unwrap();
+        let forged = store.put(".tags/a", br#"{"etag":"x","tags":{}}"#, IfMatch::Any);
+        assert!(matches!(forged, Err(ObjectStoreError::InvalidKey(_))));
+        assert!(matches!(store.delete(".tags/a"), Err(ObjectStoreError::InvalidKey(_))));
+        assert!(matches!(store.put_tags(".tags/a", &tags("x")), Err(ObjectStoreError::InvalidKey(_))));
+
+        store.inner().put(".tags/a", b"not json", IfMatch::Any).unwrap();
+        assert!(store.get_tags("a").is_err());
+        // Replacing a corrupt record needs no read of it
+        store.put_tags("a", &tags("fixed")).unwrap();
+        assert_eq!(store.get_tags("a").unwrap(), Some(tags("fixed")));
+    }
+
+    #[test]
+    fn test_concurrent_put_tags_leave_one_record() {
+        let store = TaggedStore::new(InMemoryStore::default());
+        store.put("a", b"data", IfMatch::Any).unwrap();
+        std::thread::scope(|scope| {
+            for i in 0..8 {
+                let store = &store;
+                scope.spawn(move || store.put_tags("a", &tags(&i.to_string())).unwrap());
+            }
+        });
+        let value = store.get_tags("a").unwrap().unwrap()["class"].parse::<u32>().unwrap();
+        assert!(value < 8);
+    }
+}
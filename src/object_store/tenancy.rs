@@ -0,0 +1,289 @@
+#[cfg(feature = "metrics")]
+use super::instrument::InstrumentedStore;
+use super::prefix::PrefixedStore;
+use super::quota::{QuotaLimits, QuotaStore, Usage};
+use super::sync::list_all;
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+// Tenant configs live here, next to (and never inside) the tenants' own data
+const CONFIG_DIR: &str = ".config/";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TenantInfo {
+    pub id: String,
+    pub limits: QuotaLimits,
+    pub usage: Usage,
+}
+
+// Lowercase letters, digits, '-' and '_', starting with a letter or digit
+fn validate_id(id: &str) -> Result<()> {
+    let valid = id.len() <= 64
+        && id.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(ObjectStoreError::InvalidKey(id.to_string()))
+    }
+}
+
+/// Hands out isolated per-tenant stores from one shared backend and keeps
+/// track of the tenants.
+///
+/// Each tenant's objects live under `<root><id>/` behind a `PrefixedStore`,
+/// so a handle can't see or touch another tenant's keys, and a `QuotaStore`
+/// enforces the tenant's limits. With the `metrics` feature every handle is
+/// also instrumented with a `tenant` label. Tenants and their limits are
+/// recorded under `<root>.config/`, which is what `tenants` lists; usage
+/// comes from each tenant's quota accounting.
+pub struct Tenants<S> {
+    backend: Arc<S>,
+    root: String,
+    default_limits: QuotaLimits,
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    store_name: String,
+}
+
+impl<S: ObjectStore + 'static> Tenants<S> {
+    pub fn new(backend: S) -> Self {
+        Self {
+            backend: Arc::new(backend),
+            root: "tenants/".to_string(),
+            default_limits: QuotaLimits::default(),
+            store_name: "tenants".to_string(),
+        }
+    }
+
+    pub fn with_root(mut self, root: impl Into<String>) -> Self {
+        self.root = root.into();
+        self
+    }
+
+    // Limits for tenants created without their own
+    pub fn with_default_limits(mut self, limits: QuotaLimits) -> Self {
+        self.default_limits = limits;
+        self
+    }
+
+    // The `store` label on tenant metrics
+    pub fn with_store_name(mut self, name: impl Into<String>) -> Self {
+        self.store_name = name.into();
+        self
+    }
+
+    pub fn backend(&self) -> &S {
+        &self.backend
+    }
+
+    fn config_key(&self, id: &str) -> String {
+        format!("{}{CONFIG_DIR}{id}.json", self.root)
+    }
+
+    fn quota_store(&self, id: &str, limits: QuotaLimits) -> QuotaStore<PrefixedStore<Arc<S>>> {
+        QuotaStore::new(PrefixedStore::new(self.backend.clone(), format!("{}{id}/", self.root)), limits)
+    }
+
+    fn handle(&self, id: &str, limits: QuotaLimits) -> TenantStore {
+        let store = self.quota_store(id, limits);
+        #[cfg(feature = "metrics")]
+        let store = InstrumentedStore::new(store, self.store_name.clone()).with_label("tenant", id.to_string());
+        TenantStore {
+            id: id.to_string(),
+            store: Box::new(store),
+        }
+    }
+
+    fn read_limits(&self, id: &str) -> Result<Option<QuotaLimits>> {
+        let Some(data) = self.backend.get(&self.config_key(id))? else {
+            return Ok(None);
+        };
+        serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| ObjectStoreError::Other(format!("corrupt config for tenant {id}: {e}")))
+    }
+
+    /// Registers a new tenant and returns its store. Fails with
+    /// PreconditionFailed if the tenant already exists.
+    pub fn create(&self, id: &str, limits: Option<QuotaLimits>) -> Result<TenantStore> {
+        validate_id(id)?;
+        let limits = limits.unwrap_or(self.default_limits);
+        let json = serde_json::to_vec(&limits).expect("limits serialize");
+        self.backend.put(&self.config_key(id), &json, IfMatch::NoneMatch)?;
+        Ok(self.handle(id, limits))
+    }
+
+    /// The store of an existing tenant.
+    pub fn tenant(&self, id: &str) -> Result<Option<TenantStore>> {
+        validate_id(id)?;
+        Ok(self.read_limits(id)?.map(|limits| self.handle(id, limits)))
+    }
+
+    /// Changes a tenant's limits. Handles already given out keep the old ones.
+    pub fn set_limits(&self, id: &str, limits: QuotaLimits) -> Result<()> {
+        validate_id(id)?;
+        if self.read_limits(id)?.is_none() {
+            return Err(ObjectStoreError::NotFound(format!("tenant {id}")));
+        }
+        let json = serde_json::to_vec(&limits).expect("limits serialize");
+        self.backend.put(&self.config_key(id), &json, IfMatch::Any).map(|_| ())
+    }
+
+    pub fn usage(&self, id: &str) -> Result<Usage> {
+        validate_id(id)?;
+        self.quota_store(id, QuotaLimits::default()).usage()
+    }
+
+    /// Every tenant with its limits and usage, sorted by id.
+    pub fn tenants(&self) -> Result<Vec<TenantInfo>> {
+        let config_dir = format!("{}{CONFIG_DIR}", self.root);
+        let mut tenants = Vec::new();
+        for key in list_all(self.backend.as_ref(), &config_dir)? {
+            let Some(id) = key.strip_prefix(&config_dir).and_then(|name| name.strip_suffix(".json")) else {
+                continue;
+            };
+            // Deleted since it was listed
+            let Some(limits) = self.read_limits(id)? else {
+                continue;
+            };
+            tenants.push(TenantInfo {
+                id: id.to_string(),
+                limits,
+                usage: self.usage(id)?,
+            });
+        }
+        tenants.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(tenants)
+    }
+
+    /// Deletes a tenant and all of its objects; returns how many objects went.
+    pub fn delete(&self, id: &str) -> Result<usize> {
+        validate_id(id)?;
+        let keys = list_all(self.backend.as_ref(), &format!("{}{id}/", self.root))?;
+        for key in &keys {
+            self.backend.delete(key)?;
+        }
+        self.backend.delete(&self.config_key(id))?;
+        Ok(keys.len())
+    }
+}
+
+/// One tenant's view of the shared backend, from `Tenants`.
+pub struct TenantStore {
+    id: String,
+    store: Box<dyn ObjectStore>,
+}
+
+impl TenantStore {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl ObjectStore for TenantStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.store.get(key)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        self.store.put(key, body, cond)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        self.store.list(prefix, continuation)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.store.delete(key)
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.store.head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.store.get_range(key, range)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.store.get_opts(key, opts)
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.store.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.store.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_tenant_object_store() {
+        let tenants = Tenants::new(InMemoryStore::default());
+        let store = tenants.create("acme", None).unwrap();
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_tenants_are_isolated() {
+        let limits = QuotaLimits {
+            max_bytes: Some(10),
+            max_objects: None,
+        };
+        let tenants = Tenants::new(InMemoryStore::default()).with_default_limits(limits);
+        let acme = tenants.create("acme", None).unwrap();
+        let globex = tenants.create("globex", Some(QuotaLimits::default())).unwrap();
+
+        acme.put("logo.png", b"acme!", IfMatch::Any).unwrap();
+        globex.put("logo.png", b"globex!!!!!!", IfMatch::Any).unwrap();
+        assert_eq!(acme.get("logo.png").unwrap(), Some(b"acme!".to_vec()));
+        assert_eq!(acme.list("", None).unwrap().0, vec!["logo.png"]);
+        assert!(matches!(acme.put("big", &[0; 6], IfMatch::Any), Err(ObjectStoreError::QuotaExceeded(_))));
+        assert_eq!(tenants.backend().get("tenants/acme/logo.png").unwrap(), Some(b"acme!".to_vec()));
+
+        // Fresh handles pick up stored limits
+        let again = tenants.tenant("acme").unwrap().unwrap();
+        assert_eq!(again.id(), "acme");
+        assert!(again.put("big", &[0; 6], IfMatch::Any).is_err());
+        assert!(tenants.tenant("initech").unwrap().is_none());
+        assert!(matches!(tenants.create("acme", None), Err(ObjectStoreError::PreconditionFailed)));
+        assert!(matches!(tenants.create("../acme", None), Err(ObjectStoreError::InvalidKey(_))));
+        assert!(tenants.create(".config", None).is_err());
+    }
+
+    #[test]
+    fn test_admin_api() {
+        let tenants = Tenants::new(InMemoryStore::default()).with_root("t/");
+        tenants.create("b", None).unwrap().put("x", b"123", IfMatch::Any).unwrap();
+        let a = tenants.create("a", None).unwrap();
+        a.put("y", b"1", IfMatch::Any).unwrap();
+        a.put("z", b"12", IfMatch::Any).unwrap();
+
+        let all = tenants.tenants().unwrap();
+        let summary: Vec<_> = all.iter().map(|t| (t.id.as_str(), t.usage.bytes, t.usage.objects)).collect();
+        assert_eq!(summary, vec![("a", 3, 2), ("b", 3, 1)]);
+
+        let limits = QuotaLimits {
+            max_bytes: Some(3),
+            max_objects: None,
+        };
+        tenants.set_limits("a", limits).unwrap();
+        assert_eq!(tenants.tenants().unwrap()[0].limits, limits);
+        assert!(tenants.set_limits("nobody", limits).is_err());
+
+        // Two objects plus the usage record
+        assert_eq!(tenants.delete("a").unwrap(), 3);
+        assert_eq!(tenants.tenants().unwrap().len(), 1);
+        assert!(tenants.tenant("a").unwrap().is_none());
+    }
+}
@@ -1,11 +1,23 @@
 pub mod tests {
-    use super::*;
-    use crate::object_store::{ObjectStore, IfMatch, ObjectStoreError};
-    use uuid::Uuid;
+    use crate::object_store::ObjectStore;
 
     // Generic tests for any ObjectStore implementation
     pub fn run_object_store_tests(store: &dyn ObjectStore, prefix: &str) {
-        use crate::object_store::{IfMatch, ObjectStoreError};
+        run_tests(store, prefix, |e| e);
+    }
+
+    // The same tests for stores that attach context, comparing errors by root
+    pub fn run_object_store_tests_by_root(store: &dyn ObjectStore, prefix: &str) {
+        run_tests(store, prefix, crate::object_store::ObjectStoreError::into_root);
+    }
+
+    fn run_tests(
+        store: &dyn ObjectStore,
+        prefix: &str,
+        root: fn(crate::object_store::ObjectStoreError) -> crate::object_store::ObjectStoreError,
+    ) {
+        use crate::object_store::{GetOptions, GetResult, IfMatch, ObjectStoreError};
+        use std::collections::BTreeMap;
 
         // 1. Put and get normal value
         let key = format!("{}foo.txt", prefix);
@@ -28,11 +40,11 @@ pub mod tests {
 
         // 5. Conditional put: Tag mismatch (should fail)
         let result = store.put(&key, b"fail", IfMatch::Tag("wrong-etag"));
-        assert!(matches!(result, Err(ObjectStoreError::PreconditionFailed)));
+        assert!(matches!(result.map_err(root), Err(ObjectStoreError::PreconditionFailed)));
 
         // 6. Conditional put: NoneMatch (should fail if exists)
         let result = store.put(&key, b"fail", IfMatch::NoneMatch);
-        assert!(matches!(result, Err(ObjectStoreError::PreconditionFailed)));
+        assert!(matches!(result.map_err(root), Err(ObjectStoreError::PreconditionFailed)));
 
         // 7. Conditional put: NoneMatch (should succeed if not exists)
         let key2 = format!("{}bar.txt", prefix);
@@ -54,13 +66,13 @@ pub mod tests {
 
         // 11. Empty key (if supported)
         let empty_key = format!("{}emptykey", prefix);
-        let etag_empty = store.put(&empty_key, b"", IfMatch::Any).unwrap();
+        let _etag_empty = store.put(&empty_key, b"", IfMatch::Any).unwrap();
         let data_empty = store.get(&empty_key).unwrap();
         assert_eq!(data_empty, Some(Vec::new()));
 
         // 12. Unicode/special character key
         let special_key = format!("{}spécial-字符-!@#.bin", prefix);
-        let etag_special = store.put(&special_key, b"special", IfMatch::Any).unwrap();
+        let _etag_special = store.put(&special_key, b"special", IfMatch::Any).unwrap();
         let data_special = store.get(&special_key).unwrap();
         assert_eq!(data_special, Some(b"special".to_vec()));
 
@@ -74,7 +86,7 @@ pub mod tests {
         // 15. Large blob (adjust size as appropriate for backend, e.g. 1MB)
         let large_key = format!("{}large", prefix);
         let large_blob = vec![42u8; 1024 * 1024]; // 1MB
-        let etag_large = store.put(&large_key, &large_blob, IfMatch::Any).unwrap();
+        let _etag_large = store.put(&large_key, &large_blob, IfMatch::Any).unwrap();
         let data_large = store.get(&large_key).unwrap();
         assert_eq!(data_large, Some(large_blob));
 
@@ -86,5 +98,274 @@ pub mod tests {
         assert!(all_keys.contains(&special_key));
         assert!(all_keys.contains(&bin_key));
         assert!(all_keys.contains(&large_key));
+
+        // 17. Head reports size and the ETag returned by put
+        let meta = store.head(&bin_key).unwrap().unwrap();
+        assert_eq!(meta.size, 9);
+        assert_eq!(meta.etag, etag_bin);
+        assert!(store.head(&format!("{}doesnotexist", prefix)).unwrap().is_none());
+
+        // 18. Ranged reads, including ranges running past the end
+        let range = store.get_range(&bin_key, 2..5).unwrap();
+        assert_eq!(range, Some(vec![146, 150, 255]));
+        let tail = store.get_range(&bin_key, 7..100).unwrap();
+        assert_eq!(tail, Some(vec![2, 3]));
+        let past_end = store.get_range(&bin_key, 50..60).unwrap();
+        assert_eq!(past_end, Some(Vec::new()));
+        let missing_range = store.get_range(&format!("{}doesnotexist", prefix), 0..10).unwrap();
+        assert!(missing_range.is_none());
+
+        // 19. Delete removes the object and is idempotent
+        store.delete(&key2).unwrap();
+        assert!(store.get(&key2).unwrap().is_none());
+        let (after_delete, _) = store.list(prefix, None).unwrap();
+        assert!(!after_delete.contains(&key2));
+        store.delete(&key2).unwrap();
+
+        // 20. A deleted key can be recreated with NoneMatch
+        store.put(&key2, b"again", IfMatch::NoneMatch).unwrap();
+        assert_eq!(store.get(&key2).unwrap(), Some(b"again".to_vec()));
+
+        // 21. get_opts: ranges, metadata and conditions on the ETag
+        let plain = store.get_opts(&bin_key, GetOptions::default()).unwrap();
+        assert_eq!(plain, Some(GetResult::Body { data: vec![0, 159, 146, 150, 255, 0, 1, 2, 3], meta: None }));
+        let opts = GetOptions {
+            range: Some(2..5),
+            if_match: Some(&etag_bin),
+            include_metadata: true,
+            ..Default::default()
+        };
+        let GetResult::Body { data, meta } = store.get_opts(&bin_key, opts).unwrap().unwrap() else {
+            panic!("expected a body");
+        };
+        assert_eq!(data, vec![146, 150, 255]);
+        assert_eq!(meta.unwrap(), store.head(&bin_key).unwrap().unwrap());
+        let opts = GetOptions {
+            range: Some(50..60),
+            include_metadata: true,
+            ..Default::default()
+        };
+        let GetResult::Body { data, meta } = store.get_opts(&bin_key, opts).unwrap().unwrap() else {
+            panic!("expected a body");
+        };
+        assert!(data.is_empty());
+        assert_eq!(meta.unwrap().size, 9);
+        let fresh = GetOptions { if_none_match: Some(&etag_bin), ..Default::default() };
+        assert_eq!(store.get_opts(&bin_key, fresh).unwrap(), Some(GetResult::NotModified));
+        let stale = GetOptions { if_none_match: Some("wrong-etag"), ..Default::default() };
+        assert!(matches!(store.get_opts(&bin_key, stale).unwrap(), Some(GetResult::Body { .. })));
+        let mismatch = GetOptions { if_match: Some("wrong-etag"), ..Default::default() };
+        let result = store.get_opts(&bin_key, mismatch);
+        assert!(matches!(result.map_err(root), Err(ObjectStoreError::PreconditionFailed)));
+        let missing = GetOptions { if_match: Some(&etag_bin), ..Default::default() };
+        assert_eq!(store.get_opts(&format!("{}doesnotexist", prefix), missing).unwrap(), None);
+
+        // 22. Tags: replaced by put_tags, dropped by a put, object untouched
+        let tagged = format!("{}tagged.txt", prefix);
+        let etag = store.put(&tagged, b"tag me", IfMatch::Any).unwrap();
+        assert_eq!(store.get_tags(&tagged).unwrap(), Some(BTreeMap::new()));
+        let tags = BTreeMap::from([("team".to_string(), "storage".to_string())]);
+        store.put_tags(&tagged, &tags).unwrap();
+        assert_eq!(store.get_tags(&tagged).unwrap(), Some(tags.clone()));
+        assert_eq!(store.head(&tagged).unwrap().unwrap().etag, etag);
+        assert_eq!(store.get(&tagged).unwrap(), Some(b"tag me".to_vec()));
+        store.put(&tagged, b"retagged", IfMatch::Any).unwrap();
+        assert_eq!(store.get_tags(&tagged).unwrap(), Some(BTreeMap::new()));
+        let missing = format!("{}doesnotexist", prefix);
+        assert_eq!(store.get_tags(&missing).unwrap(), None);
+        let result = store.put_tags(&missing, &tags).map_err(ObjectStoreError::into_root);
+        assert!(matches!(result, Err(ObjectStoreError::NotFound(_))));
+    }
+
+    // Runs the same seeded sequence of operations against `store` and a
+    // StrictMemoryStore and checks that every result agrees with the oracle
+    pub fn run_oracle_tests(store: &dyn ObjectStore, prefix: &str) {
+        use crate::object_store::sim::SimRng;
+        use crate::object_store::strict::StrictMemoryStore;
+        use crate::object_store::sync::list_all;
+        use crate::object_store::{IfMatch, Result};
+        use std::collections::HashMap;
+
+        // Ok, or which error; ETags differ between stores so aren't compared
+        fn outcome(result: Result<String>) -> std::result::Result<(), &'static str> {
+            result.map(|_| ()).map_err(|e| e.kind())
+        }
+
+        fn listing(store: &dyn ObjectStore, prefix: &str) -> Vec<String> {
+            let mut keys = list_all(store, prefix).unwrap();
+            keys.sort();
+            keys
+        }
+
+        let oracle = StrictMemoryStore::default().with_page_size(3);
+        let stores: [&dyn ObjectStore; 2] = [store, &oracle];
+        // ETag each store last returned from a put, per key
+        let mut etags: [HashMap<String, String>; 2] = Default::default();
+        let mut rng = SimRng::new(0x0ac1e);
+
+        for step in 0..400 {
+            let key = format!("{prefix}k{}", rng.below(6));
+            let body = format!("body {}", rng.below(8)).into_bytes();
+            match rng.below(9) {
+                0 | 1 => {
+                    let [got, want] = stores.map(|s| s.get(&key).unwrap());
+                    assert_eq!(got, want, "step {step}: get {key}");
+                }
+                2 => {
+                    let [got, want] = stores.map(|s| s.head(&key).unwrap());
+                    assert_eq!(got.as_ref().map(|m| m.size), want.map(|m| m.size), "step {step}: head {key}");
+                    if let Some(meta) = got {
+                        assert_eq!(Some(&meta.etag), etags[0].get(&key), "step {step}: head {key} ETag");
+                    }
+                }
+                3 => {
+                    let start = rng.below(10);
+                    let range = start..start + rng.below(10);
+                    let [got, want] = stores.map(|s| s.get_range(&key, range.clone()).unwrap());
+                    assert_eq!(got, want, "step {step}: get_range {key} {range:?}");
+                }
+                4 | 5 => {
+                    // Compare-and-swap with the current ETag, or a stale one
+                    let fresh = rng.chance(0.7);
+                    let [got, want] = [0, 1].map(|i| {
+                        let etag = etags[i].get(&key).filter(|_| fresh).map_or("stale", String::as_str);
+                        let result = stores[i].put(&key, &body, IfMatch::Tag(etag));
+                        if let Ok(etag) = &result {
+                            etags[i].insert(key.clone(), etag.clone());
+                        }
+                        outcome(result)
+                    });
+                    assert_eq!(got, want, "step {step}: put {key} if-match (fresh: {fresh})");
+                }
+                6 => {
+                    let [got, want] = [0, 1].map(|i| {
+                        let result = stores[i].put(&key, &body, IfMatch::NoneMatch);
+                        if let Ok(etag) = &result {
+                            etags[i].insert(key.clone(), etag.clone());
+                        }
+                        outcome(result)
+                    });
+                    assert_eq!(got, want, "step {step}: put {key} if-none-match");
+                }
+                7 => {
+                    for i in 0..2 {
+                        let etag = stores[i].put(&key, &body, IfMatch::Any).unwrap();
+                        etags[i].insert(key.clone(), etag);
+                    }
+                }
+                _ => {
+                    for i in 0..2 {
+                        stores[i].delete(&key).unwrap();
+                        etags[i].remove(&key);
+                    }
+                }
+            }
+            if step % 50 == 0 {
+                assert_eq!(listing(store, prefix), listing(&oracle, prefix), "step {step}: list");
+            }
+        }
+        assert_eq!(listing(store, prefix), listing(&oracle, prefix), "final list");
+    }
+
+    // Forwards to `inner` and counts the calls that reach it, for checking
+    // what a wrapper store actually sends to its backend
+    pub struct CountingStore<S> {
+        pub inner: S,
+        counts: std::sync::Mutex<std::collections::HashMap<&'static str, usize>>,
+    }
+
+    impl<S: ObjectStore> CountingStore<S> {
+        pub fn new(inner: S) -> Self {
+            Self {
+                inner,
+                counts: Default::default(),
+            }
+        }
+
+        // Calls so far to one method: "get", "put", "list", "delete", "head",
+        // "get_range", "get_opts", "put_tags" or "get_tags"
+        pub fn count(&self, method: &str) -> usize {
+            self.counts.lock().unwrap().get(method).copied().unwrap_or(0)
+        }
+
+        fn hit(&self, method: &'static str) {
+            *self.counts.lock().unwrap().entry(method).or_default() += 1;
+        }
+    }
+
+    impl<S: ObjectStore> ObjectStore for CountingStore<S> {
+        fn get(&self, key: &str) -> crate::object_store::Result<Option<Vec<u8>>> {
+            self.hit("get");
+            self.inner.get(key)
+        }
+
+        fn put(&self, key: &str, body: &[u8], cond: crate::object_store::IfMatch) -> crate::object_store::Result<String> {
+            self.hit("put");
+            self.inner.put(key, body, cond)
+        }
+
+        fn list(&self, prefix: &str, continuation: Option<String>) -> crate::object_store::Result<(Vec<String>, Option<String>)> {
+            self.hit("list");
+            self.inner.list(prefix, continuation)
+        }
+
+        fn delete(&self, key: &str) -> crate::object_store::Result<()> {
+            self.hit("delete");
+            self.inner.delete(key)
+        }
+
+        fn head(&self, key: &str) -> crate::object_store::Result<Option<crate::object_store::ObjectMeta>> {
+            self.hit("head");
+            self.inner.head(key)
+        }
+
+        fn get_range(&self, key: &str, range: std::ops::Range<u64>) -> crate::object_store::Result<Option<Vec<u8>>> {
+            self.hit("get_range");
+            self.inner.get_range(key, range)
+        }
+
+        fn get_opts(
+            &self,
+            key: &str,
+            opts: crate::object_store::GetOptions,
+        ) -> crate::object_store::Result<Option<crate::object_store::GetResult>> {
+            self.hit("get_opts");
+            self.inner.get_opts(key, opts)
+        }
+
+        fn put_tags(&self, key: &str, tags: &std::collections::BTreeMap<String, String>) -> crate::object_store::Result<()> {
+            self.hit("put_tags");
+            self.inner.put_tags(key, tags)
+        }
+
+        fn get_tags(&self, key: &str) -> crate::object_store::Result<Option<std::collections::BTreeMap<String, String>>> {
+            self.hit("get_tags");
+            self.inner.get_tags(key)
+        }
+    }
+
+    // Fails every put conditional on an ETag, as if another writer always
+    // got in first, for checking that compare-and-swap loops give up
+    pub struct ContendedStore<S>(pub S);
+
+    impl<S: ObjectStore> ObjectStore for ContendedStore<S> {
+        fn get(&self, key: &str) -> crate::object_store::Result<Option<Vec<u8>>> {
+            self.0.get(key)
+        }
+
+        fn put(&self, key: &str, body: &[u8], cond: crate::object_store::IfMatch) -> crate::object_store::Result<String> {
+            match cond {
+                crate::object_store::IfMatch::Tag(_) => Err(crate::object_store::ObjectStoreError::PreconditionFailed),
+                cond => self.0.put(key, body, cond),
+            }
+        }
+
+        fn list(&self, prefix: &str, continuation: Option<String>) -> crate::object_store::Result<(Vec<String>, Option<String>)> {
+            self.0.list(prefix, continuation)
+        }
+
+        fn delete(&self, key: &str) -> crate::object_store::Result<()> {
+            self.0.delete(key)
+        }
     }
 }
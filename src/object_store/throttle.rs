@@ -0,0 +1,213 @@
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, Result};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Refills at `rate` per second up to one second's worth of burst. Takers may
+// overdraw it; the debt is paid off by sleeping, so large transfers still go
+// through but push back everything queued behind them.
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            tokens: rate,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    // Takes `amount` tokens and returns how long the caller must wait before going ahead
+    fn take(&mut self, amount: f64) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled_at = now;
+        self.tokens -= amount;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Wraps a store with request-rate and bandwidth limits shared by every
+/// thread using it.
+///
+/// Each call takes one request token; puts take their body size in byte
+/// tokens up front, and reads pay for what they fetched before returning
+/// it. Both budgets allow a one-second burst. Without
+/// a limit configured the wrapper passes calls straight through.
+pub struct ThrottledStore<S> {
+    inner: S,
+    requests: Option<Mutex<TokenBucket>>,
+    bytes: Option<Mutex<TokenBucket>>,
+}
+
+impl<S: ObjectStore> ThrottledStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            requests: None,
+            bytes: None,
+        }
+    }
+
+    pub fn with_requests_per_sec(mut self, rate: f64) -> Self {
+        self.requests = (rate > 0.0).then(|| Mutex::new(TokenBucket::new(rate)));
+        self
+    }
+
+    pub fn with_bytes_per_sec(mut self, rate: f64) -> Self {
+        self.bytes = (rate > 0.0).then(|| Mutex::new(TokenBucket::new(rate)));
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn throttle(bucket: &Option<Mutex<TokenBucket>>, amount: u64) {
+        if let Some(bucket) = bucket {
+            // Sleep outside the lock so other callers can queue up their own debt
+            let wait = bucket.lock().unwrap().take(amount as f64);
+            if !wait.is_zero() {
+                thread::sleep(wait);
+            }
+        }
+    }
+
+    fn request(&self) {
+        Self::throttle(&self.requests, 1);
+    }
+
+    // Reads can't know their size up front, so they pay after the fact
+    fn charge_read(&self, result: Result<Option<Vec<u8>>>) -> Result<Option<Vec<u8>>> {
+        if let Ok(Some(data)) = &result {
+            Self::throttle(&self.bytes, data.len() as u64);
+        }
+        result
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for ThrottledStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.request();
+        self.charge_read(self.inner.get(key))
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        self.request();
+        Self::throttle(&self.bytes, body.len() as u64);
+        self.inner.put(key, body, cond)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        self.request();
+        self.inner.list(prefix, continuation)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.request();
+        self.inner.delete(key)
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.request();
+        self.inner.head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.request();
+        self.charge_read(self.inner.get_range(key, range))
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.request();
+        let result = self.inner.get_opts(key, opts)?;
+        if let Some(GetResult::Body { data, .. }) = &result {
+            Self::throttle(&self.bytes, data.len() as u64);
+        }
+        Ok(result)
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.request();
+        self.inner.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.request();
+        self.inner.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_throttled_object_store() {
+        let store = ThrottledStore::new(InMemoryStore::default())
+            .with_requests_per_sec(10_000.0)
+            .with_bytes_per_sec(1e9);
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_request_rate() {
+        let store = ThrottledStore::new(InMemoryStore::default()).with_requests_per_sec(100.0);
+        let started = Instant::now();
+        // The first 100 ride the burst, the other 50 need half a second
+        for _ in 0..150 {
+            store.head("k").unwrap();
+        }
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(450), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+    }
+
+    #[test]
+    fn test_bandwidth_shared_between_threads() {
+        let store = ThrottledStore::new(InMemoryStore::default()).with_bytes_per_sec(10_000.0);
+        let started = Instant::now();
+        // 30 KB against a 10 KB/s budget with a 10 KB burst: about two seconds
+        thread::scope(|s| {
+            for i in 0..3 {
+                let store = &store;
+                s.spawn(move || store.put(&format!("k{i}"), &[0u8; 10_000], IfMatch::Any).unwrap());
+            }
+        });
+        assert!(started.elapsed() >= Duration::from_millis(1900), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn test_reads_are_charged() {
+        let store = ThrottledStore::new(InMemoryStore::default()).with_bytes_per_sec(10_000.0);
+        store.inner().put("big", &[0u8; 15_000], IfMatch::Any).unwrap();
+        let started = Instant::now();
+        store.get("big").unwrap();
+        store.get_range("big", 0..1).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(450), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn test_unlimited_by_default() {
+        let store = ThrottledStore::new(InMemoryStore::default());
+        let started = Instant::now();
+        for _ in 0..1000 {
+            store.put("k", &[0u8; 1000], IfMatch::Any).unwrap();
+        }
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,524 @@
+use super::sync::list_all;
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::Range;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// Locks ordering the writes and promotions of keys hashed to each
+const LOCK_STRIPES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    // Writes go to both tiers, so fresh objects start out hot
+    Through,
+    // Writes go to the cold tier only and drop any hot copy
+    Around,
+}
+
+/// When keys move between tiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TierPolicy {
+    // Reads within `window` that promote a cold key
+    pub promote_after: u32,
+    pub window: Duration,
+    // Hot keys not read for this long are demoted by `rebalance`
+    pub demote_after: Duration,
+    // Least recently read hot keys are demoted to stay under this
+    pub max_hot_bytes: Option<u64>,
+}
+
+impl Default for TierPolicy {
+    fn default() -> Self {
+        Self {
+            promote_after: 3,
+            window: Duration::from_secs(60),
+            demote_after: Duration::from_secs(3600),
+            max_hot_bytes: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TierStats {
+    pub hot_hits: u64,
+    pub cold_hits: u64,
+    pub misses: u64,
+    pub promotions: u64,
+    pub demotions: u64,
+}
+
+impl TierStats {
+    // Share of reads of existing objects served by the hot tier
+    pub fn hit_ratio(&self) -> f64 {
+        let hits = self.hot_hits + self.cold_hits;
+        if hits == 0 {
+            0.0
+        } else {
+            self.hot_hits as f64 / hits as f64
+        }
+    }
+}
+
+struct Access {
+    reads: u32,
+    window_start: Instant,
+    last_read: Instant,
+    // Size of the hot copy, if there is one
+    hot_size: Option<u64>,
+}
+
+impl Access {
+    fn new() -> Self {
+        Self {
+            reads: 0,
+            window_start: Instant::now(),
+            last_read: Instant::now(),
+            hot_size: None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Tracker {
+    keys: HashMap<String, Access>,
+    hot_bytes: u64,
+    stats: TierStats,
+}
+
+impl Tracker {
+    fn set_hot(&mut self, key: &str, size: Option<u64>) {
+        let access = self.keys.entry(key.to_string()).or_insert_with(Access::new);
+        self.hot_bytes -= access.hot_size.unwrap_or(0);
+        self.hot_bytes += size.unwrap_or(0);
+        access.hot_size = size;
+    }
+
+    // Hot keys to demote, least recently read first, so `incoming` more bytes fit
+    fn over_budget(&self, max_bytes: u64, incoming: u64) -> Vec<String> {
+        let mut hot: Vec<(&String, &Access)> = self.keys.iter().filter(|(_, a)| a.hot_size.is_some()).collect();
+        hot.sort_by_key(|(_, access)| access.last_read);
+        let mut bytes = self.hot_bytes + incoming;
+        let mut victims = Vec::new();
+        for (key, access) in hot {
+            if bytes <= max_bytes {
+                break;
+            }
+            bytes -= access.hot_size.unwrap_or(0);
+            victims.push(key.clone());
+        }
+        victims
+    }
+}
+
+/// Combines a fast hot tier, such as an `InMemoryStore` or `LocalStore`,
+/// with a cold tier such as S3.
+///
+/// The cold tier holds every object and is the one listed; the hot tier
+/// holds copies of the busy ones. Reads are served from the hot tier when
+/// it has the key and from the cold tier otherwise, and a cold key read
+/// `promote_after` times within the policy's window is copied up. Writes
+/// go to the cold tier first, which checks any condition, and then either
+/// to the hot tier as well or nowhere else, depending on the `WritePolicy`.
+/// `rebalance` demotes hot keys that haven't been read for `demote_after`,
+/// and keys are also demoted, least recently read first, to keep the hot
+/// tier under `max_hot_bytes`.
+///
+/// Read counts live in memory, so after a restart `rebalance` treats hot
+/// keys as unread. Puts, deletes and promotions of a key take a lock for
+/// it, and a promotion reads the object again under that lock, so writes
+/// through this store never leave a stale hot copy. The locks are per
+/// store, so two `TieredStore`s sharing tiers still can. Writes made
+/// directly to the cold tier aren't noticed while a hot copy exists, and
+/// both tiers must compute ETags the same way.
+pub struct TieredStore<H, C> {
+    hot: H,
+    cold: C,
+    policy: TierPolicy,
+    write_policy: WritePolicy,
+    tracker: Mutex<Tracker>,
+    locks: Vec<Mutex<()>>,
+}
+
+impl<H: ObjectStore, C: ObjectStore> TieredStore<H, C> {
+    pub fn new(hot: H, cold: C) -> Self {
+        Self {
+            hot,
+            cold,
+            policy: TierPolicy::default(),
+            write_policy: WritePolicy::Through,
+            tracker: Mutex::new(Tracker::default()),
+            locks: (0..LOCK_STRIPES).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: TierPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_write_policy(mut self, write_policy: WritePolicy) -> Self {
+        self.write_policy = write_policy;
+        self
+    }
+
+    pub fn hot(&self) -> &H {
+        &self.hot
+    }
+
+    pub fn cold(&self) -> &C {
+        &self.cold
+    }
+
+    pub fn stats(&self) -> TierStats {
+        self.tracker.lock().unwrap().stats
+    }
+
+    // Total size of the hot copies made through this store
+    pub fn hot_bytes(&self) -> u64 {
+        self.tracker.lock().unwrap().hot_bytes
+    }
+
+    // Counts a read of `key`; true if it should now be promoted
+    fn record_read(&self, key: &str, hot: bool) -> bool {
+        let mut tracker = self.tracker.lock().unwrap();
+        if hot {
+            tracker.stats.hot_hits += 1;
+        } else {
+            tracker.stats.cold_hits += 1;
+        }
+        let access = tracker.keys.entry(key.to_string()).or_insert_with(Access::new);
+        if access.window_start.elapsed() > self.policy.window {
+            access.reads = 0;
+            access.window_start = Instant::now();
+        }
+        access.reads += 1;
+        access.last_read = Instant::now();
+        !hot && access.reads >= self.policy.promote_after
+    }
+
+    fn lock(&self, key: &str) -> MutexGuard<'_, ()> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.locks[hasher.finish() as usize % LOCK_STRIPES].lock().unwrap()
+    }
+
+    fn record_miss(&self) {
+        self.tracker.lock().unwrap().stats.misses += 1;
+    }
+
+    // Copies `data` to the hot tier, demoting others to make room. Best
+    // effort: the cold tier still has the object if this fails.
+    fn copy_up(&self, key: &str, data: &[u8]) -> bool {
+        let size = data.len() as u64;
+        if let Some(max_bytes) = self.policy.max_hot_bytes {
+            if size > max_bytes {
+                return false;
+            }
+            let victims = self.tracker.lock().unwrap().over_budget(max_bytes, size);
+            for victim in victims {
+                self.demote(&victim);
+            }
+        }
+        if self.hot.put(key, data, IfMatch::Any).is_err() {
+            return false;
+        }
+        self.tracker.lock().unwrap().set_hot(key, Some(size));
+        true
+    }
+
+    // Copies the cold object up. What the caller read may already have been
+    // replaced or deleted, so it's read again under the key's lock.
+    fn promote(&self, key: &str) {
+        let _lock = self.lock(key);
+        if let Ok(Some(data)) = self.cold.get(key)
+            && self.copy_up(key, &data)
+        {
+            self.tracker.lock().unwrap().stats.promotions += 1;
+        }
+    }
+
+    fn demote(&self, key: &str) -> bool {
+        if self.hot.delete(key).is_err() {
+            return false;
+        }
+        let mut tracker = self.tracker.lock().unwrap();
+        tracker.set_hot(key, None);
+        tracker.stats.demotions += 1;
+        true
+    }
+
+    // Drops the hot copy of a key whose cold copy may have changed
+    fn invalidate(&self, key: &str) {
+        let _ = self.hot.delete(key);
+        self.tracker.lock().unwrap().set_hot(key, None);
+    }
+
+    /// Demotes hot keys that haven't been read within `demote_after`,
+    /// including any this store has no record of, and forgets idle cold
+    /// keys. Returns how many keys were demoted.
+    pub fn rebalance(&self) -> Result<usize> {
+        let mut demoted = 0;
+        for key in list_all(&self.hot, "")? {
+            let idle = match self.tracker.lock().unwrap().keys.get(&key) {
+                Some(access) => access.last_read.elapsed() >= self.policy.demote_after,
+                None => true,
+            };
+            if idle && self.demote(&key) {
+                demoted += 1;
+            }
+        }
+        let window = self.policy.window;
+        self.tracker
+            .lock()
+            .unwrap()
+            .keys
+            .retain(|_, access| access.hot_size.is_some() || access.window_start.elapsed() <= window);
+        Ok(demoted)
+    }
+}
+
+impl<H: ObjectStore, C: ObjectStore> ObjectStore for TieredStore<H, C> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        if let Ok(Some(data)) = self.hot.get(key) {
+            self.record_read(key, true);
+            return Ok(Some(data));
+        }
+        let Some(data) = self.cold.get(key)? else {
+            self.record_miss();
+            return Ok(None);
+        };
+        if self.record_read(key, false) {
+            self.promote(key);
+        }
+        Ok(Some(data))
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        let _lock = self.lock(key);
+        let etag = match self.cold.put(key, body, cond) {
+            Ok(etag) => etag,
+            Err(e) => {
+                // A failed condition means the hot copy may be stale
+                self.invalidate(key);
+                return Err(e);
+            }
+        };
+        // Drop the old hot copy first so it can't count against the budget
+        self.invalidate(key);
+        if self.write_policy == WritePolicy::Through {
+            self.copy_up(key, body);
+        }
+        Ok(etag)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        self.cold.list(prefix, continuation)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let _lock = self.lock(key);
+        self.cold.delete(key)?;
+        self.invalidate(key);
+        Ok(())
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        if let Ok(Some(meta)) = self.hot.head(key) {
+            return Ok(Some(meta));
+        }
+        self.cold.head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        if let Ok(Some(data)) = self.hot.get_range(key, range.clone()) {
+            self.record_read(key, true);
+            return Ok(Some(data));
+        }
+        let Some(data) = self.cold.get_range(key, range)? else {
+            self.record_miss();
+            return Ok(None);
+        };
+        if self.record_read(key, false) {
+            self.promote(key);
+        }
+        Ok(Some(data))
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        match self.hot.get_opts(key, opts.clone()) {
+            Ok(Some(result)) => {
+                self.record_read(key, true);
+                return Ok(Some(result));
+            }
+            // The hot copy's answer to a condition is as good as the cold one's
+            Err(e) if matches!(e.root(), ObjectStoreError::PreconditionFailed) => return Err(e),
+            _ => {}
+        }
+        let Some(result) = self.cold.get_opts(key, opts)? else {
+            self.record_miss();
+            return Ok(None);
+        };
+        if self.record_read(key, false) {
+            self.promote(key);
+        }
+        Ok(Some(result))
+    }
+
+    // The cold tier holds every object, so its tags are the ones that count
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.cold.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.cold.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::{run_object_store_tests, CountingStore};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+    use uuid::Uuid;
+
+    fn tiered() -> TieredStore<InMemoryStore, CountingStore<InMemoryStore>> {
+        TieredStore::new(InMemoryStore::default(), CountingStore::new(InMemoryStore::default()))
+    }
+
+    #[test]
+    fn test_tiered_object_store() {
+        run_object_store_tests(&tiered(), &format!("test/{}/", Uuid::new_v4()));
+        let around = tiered().with_write_policy(WritePolicy::Around);
+        run_object_store_tests(&around, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_promotes_frequently_read_keys() {
+        let store = tiered().with_write_policy(WritePolicy::Around);
+        store.put("hot", b"data", IfMatch::Any).unwrap();
+        assert_eq!(store.hot().get("hot").unwrap(), None);
+
+        for _ in 0..5 {
+            assert_eq!(store.get("hot").unwrap(), Some(b"data".to_vec()));
+        }
+        // Three reads, then the promotion reading it again
+        assert_eq!(store.cold().count("get"), 4);
+        assert_eq!(store.hot().get("hot").unwrap(), Some(b"data".to_vec()));
+        assert_eq!(store.get("missing").unwrap(), None);
+
+        let stats = store.stats();
+        assert_eq!((stats.hot_hits, stats.cold_hits, stats.misses, stats.promotions), (2, 3, 1, 1));
+        assert_eq!(stats.hit_ratio(), 0.4);
+
+        // Overwrites don't leave a stale hot copy behind
+        store.put("hot", b"new", IfMatch::Any).unwrap();
+        assert_eq!(store.get("hot").unwrap(), Some(b"new".to_vec()));
+    }
+
+    #[test]
+    fn test_demotes_idle_keys() {
+        let store = tiered().with_policy(TierPolicy {
+            demote_after: Duration::from_millis(30),
+            ..TierPolicy::default()
+        });
+        store.put("idle", b"12345", IfMatch::Any).unwrap();
+        store.put("busy", b"123", IfMatch::Any).unwrap();
+        // Hot copies this store doesn't know about, e.g. from before a restart
+        store.hot().put("stray", b"x", IfMatch::Any).unwrap();
+        assert_eq!(store.hot_bytes(), 8);
+
+        std::thread::sleep(Duration::from_millis(40));
+        store.get("busy").unwrap();
+        assert_eq!(store.rebalance().unwrap(), 2);
+        assert_eq!(list_all(store.hot(), "").unwrap(), vec!["busy"]);
+        assert_eq!(store.hot_bytes(), 3);
+        assert_eq!(store.get("idle").unwrap(), Some(b"12345".to_vec()));
+    }
+
+    #[test]
+    fn test_hot_tier_stays_under_budget() {
+        let store = tiered().with_policy(TierPolicy {
+            promote_after: 1,
+            max_hot_bytes: Some(10),
+            ..TierPolicy::default()
+        });
+        for key in ["a", "b", "c"] {
+            store.cold().put(key, &[0; 4], IfMatch::Any).unwrap();
+            store.get(key).unwrap();
+        }
+        assert_eq!(list_all(store.hot(), "").unwrap(), vec!["b", "c"]);
+        assert_eq!(store.hot_bytes(), 8);
+        assert_eq!(store.stats().demotions, 1);
+
+        // Too big to ever be hot
+        store.cold().put("big", &[0; 11], IfMatch::Any).unwrap();
+        store.get("big").unwrap();
+        assert_eq!(store.hot().get("big").unwrap(), None);
+    }
+
+    // Holds the first get once armed, between reading and returning, until
+    // the test lets it go
+    struct Paused {
+        inner: InMemoryStore,
+        armed: AtomicBool,
+        read: mpsc::Sender<()>,
+        resume: Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl ObjectStore for Paused {
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            let result = self.inner.get(key);
+            if self.armed.swap(false, Ordering::SeqCst) {
+                self.read.send(()).unwrap();
+                self.resume.lock().unwrap().recv().unwrap();
+            }
+            result
+        }
+
+        fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+            self.inner.put(key, body, cond)
+        }
+
+        fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+            self.inner.list(prefix, continuation)
+        }
+
+        fn delete(&self, key: &str) -> Result<()> {
+            self.inner.delete(key)
+        }
+    }
+
+    #[test]
+    fn test_promotion_racing_a_put_copies_the_new_body() {
+        let (read, reads) = mpsc::channel();
+        let (resume, resumes) = mpsc::channel();
+        let cold = Paused {
+            inner: InMemoryStore::default(),
+            armed: AtomicBool::new(false),
+            read,
+            resume: Mutex::new(resumes),
+        };
+        let store = TieredStore::new(InMemoryStore::default(), cold)
+            .with_policy(TierPolicy {
+                promote_after: 1,
+                ..TierPolicy::default()
+            })
+            .with_write_policy(WritePolicy::Around);
+        store.put("k", b"old", IfMatch::Any).unwrap();
+
+        store.cold().armed.store(true, Ordering::SeqCst);
+        std::thread::scope(|scope| {
+            let reader = scope.spawn(|| store.get("k").unwrap());
+            reads.recv().unwrap();
+            store.put("k", b"new", IfMatch::Any).unwrap();
+            resume.send(()).unwrap();
+            assert_eq!(reader.join().unwrap(), Some(b"old".to_vec()));
+        });
+        assert_eq!(store.hot().get("k").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(store.get("k").unwrap(), Some(b"new".to_vec()));
+    }
+}
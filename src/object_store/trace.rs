@@ -0,0 +1,207 @@
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, Result};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use tracing::field::Empty;
+use tracing::{info_span, Span};
+
+/// Wraps a store so every call runs inside a `tracing` span.
+///
+/// Spans are named `object_store` and carry the operation, the store name
+/// given to `new` and the key (the prefix for `list`). Once the call
+/// returns they also record `bytes` read or written and an `outcome`:
+/// `ok`, `not_found`, `not_modified`, or the `ObjectStoreError::kind` of
+/// a failure, with the error itself in `error`.
+pub struct TracingStore<S> {
+    inner: S,
+    name: String,
+}
+
+impl<S: ObjectStore> TracingStore<S> {
+    pub fn new(inner: S, name: impl Into<String>) -> Self {
+        Self {
+            inner,
+            name: name.into(),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn traced<T>(
+        &self,
+        op: &'static str,
+        key: &str,
+        call: impl FnOnce() -> Result<T>,
+        describe: impl FnOnce(&T, &Span),
+    ) -> Result<T> {
+        let span = info_span!(
+            "object_store",
+            op,
+            store = %self.name,
+            key,
+            bytes = Empty,
+            outcome = Empty,
+            error = Empty,
+        );
+        let result = span.in_scope(call);
+        match &result {
+            Ok(value) => {
+                span.record("outcome", "ok");
+                describe(value, &span);
+            }
+            Err(e) => {
+                span.record("outcome", e.kind());
+                span.record("error", tracing::field::debug(e));
+            }
+        }
+        result
+    }
+}
+
+// Records the size of a read, or that there was nothing to read
+fn describe_read(data: &Option<Vec<u8>>, span: &Span) {
+    match data {
+        Some(data) => span.record("bytes", data.len()),
+        None => span.record("outcome", "not_found"),
+    };
+}
+
+impl<S: ObjectStore> ObjectStore for TracingStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.traced("get", key, || self.inner.get(key), describe_read)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        self.traced("put", key, || self.inner.put(key, body, cond), |_, span| {
+            span.record("bytes", body.len());
+        })
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        self.traced("list", prefix, || self.inner.list(prefix, continuation), |_, _| {})
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.traced("delete", key, || self.inner.delete(key), |_, _| {})
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.traced("head", key, || self.inner.head(key), |meta, span| {
+            if meta.is_none() {
+                span.record("outcome", "not_found");
+            }
+        })
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.traced("get_range", key, || self.inner.get_range(key, range), describe_read)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.traced("get_opts", key, || self.inner.get_opts(key, opts), |result, span| {
+            match result {
+                Some(GetResult::Body { data, .. }) => span.record("bytes", data.len()),
+                Some(GetResult::NotModified) => span.record("outcome", "not_modified"),
+                None => span.record("outcome", "not_found"),
+            };
+        })
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        self.traced("put_tags", key, || self.inner.put_tags(key, tags), |_, _| {})
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.traced("get_tags", key, || self.inner.get_tags(key), |tags, span| {
+            if tags.is_none() {
+                span.record("outcome", "not_found");
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+    use tracing_subscriber::Registry;
+    use uuid::Uuid;
+
+    type Fields = HashMap<String, String>;
+
+    struct Visitor<'a>(&'a mut Fields);
+
+    impl Visit for Visitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.insert(field.name().to_string(), format!("{value:?}"));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    // Collects the fields of every span, in creation order
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<(Id, Fields)>>>);
+
+    impl<S: Subscriber> Layer<S> for Capture {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _: Context<'_, S>) {
+            let mut fields = Fields::new();
+            attrs.record(&mut Visitor(&mut fields));
+            self.0.lock().unwrap().push((id.clone(), fields));
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, _: Context<'_, S>) {
+            let mut spans = self.0.lock().unwrap();
+            if let Some((_, fields)) = spans.iter_mut().rev().find(|(span, _)| span == id) {
+                values.record(&mut Visitor(fields));
+            }
+        }
+    }
+
+    fn traced(f: impl FnOnce(&TracingStore<InMemoryStore>)) -> Vec<Fields> {
+        let capture = Capture::default();
+        let subscriber = Registry::default().with(capture.clone());
+        let store = TracingStore::new(InMemoryStore::default(), "primary");
+        tracing::subscriber::with_default(subscriber, || f(&store));
+        let spans = capture.0.lock().unwrap();
+        spans.iter().map(|(_, fields)| fields.clone()).collect()
+    }
+
+    #[test]
+    fn test_tracing_object_store() {
+        let store = TracingStore::new(InMemoryStore::default(), "memory");
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_spans_carry_key_bytes_and_outcome() {
+        let spans = traced(|store| {
+            store.put("a.txt", b"hello", IfMatch::Any).unwrap();
+            store.get("a.txt").unwrap();
+            store.get("missing").unwrap();
+            store.put("a.txt", b"x", IfMatch::NoneMatch).unwrap_err();
+        });
+        assert_eq!(spans.len(), 4);
+
+        let field = |i: usize, name: &str| spans[i].get(name).cloned().unwrap_or_default();
+        assert_eq!(field(0, "op"), "put");
+        assert_eq!(field(0, "store"), "primary");
+        assert_eq!(field(0, "key"), "a.txt");
+        assert_eq!(field(0, "bytes"), "5");
+        assert_eq!(field(0, "outcome"), "ok");
+        assert_eq!(field(1, "bytes"), "5");
+        assert_eq!(field(2, "outcome"), "not_found");
+        assert_eq!(field(3, "outcome"), "precondition_failed");
+        assert_eq!(field(3, "error"), "PreconditionFailed");
+    }
+}
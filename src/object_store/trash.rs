@@ -0,0 +1,193 @@
+use super::sync::list_all;
+use super::versioned::monotonic_micros;
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::time::Duration;
+use uuid::Uuid;
+
+// Deleted objects are moved under here
+pub const TRASH_PREFIX: &str = ".trash/";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashEntry {
+    pub key: String,
+    pub id: String,
+    // Milliseconds since the Unix epoch
+    pub deleted_at: u64,
+}
+
+/// Wraps a store so `delete` moves objects to `.trash/` instead of
+/// destroying them, from where `restore` brings them back and `purge`
+/// removes them for good once they're old enough.
+///
+/// A deleted object is kept at `.trash/<key>/<id>`, where the id starts
+/// with the deletion time in microseconds, so each deletion of a key is
+/// kept separately and purging needs no reads. `.trash/` is hidden from
+/// `list` and reserved. Deleting copies the object before removing it, so
+/// a crash in between leaves it in both places rather than neither.
+pub struct TrashStore<S> {
+    inner: S,
+}
+
+impl<S: ObjectStore> TrashStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn parse_entry(trash_key: &str) -> Option<TrashEntry> {
+        let (key, id) = trash_key.strip_prefix(TRASH_PREFIX)?.rsplit_once('/')?;
+        let micros: u64 = id.split('-').next()?.parse().ok()?;
+        Some(TrashEntry {
+            key: key.to_string(),
+            id: id.to_string(),
+            deleted_at: micros / 1000,
+        })
+    }
+
+    /// Trashed objects whose original key starts with `prefix`, oldest first.
+    pub fn list_trash(&self, prefix: &str) -> Result<Vec<TrashEntry>> {
+        let mut entries: Vec<TrashEntry> = list_all(&self.inner, &format!("{TRASH_PREFIX}{prefix}"))?
+            .iter()
+            .filter_map(|trash_key| Self::parse_entry(trash_key))
+            .collect();
+        entries.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(entries)
+    }
+
+    /// Puts back the most recently deleted copy of `key` and returns its
+    /// ETag. Fails with PreconditionFailed rather than overwrite a live object.
+    pub fn restore(&self, key: &str) -> Result<String> {
+        let entry = self
+            .list_trash(key)?
+            .into_iter()
+            .rfind(|entry| entry.key == key)
+            .ok_or_else(|| ObjectStoreError::NotFound(format!("{key} in the trash")))?;
+        let trash_key = format!("{TRASH_PREFIX}{key}/{}", entry.id);
+        let data = self
+            .inner
+            .get(&trash_key)?
+            .ok_or_else(|| ObjectStoreError::NotFound(format!("{key} in the trash")))?;
+        let etag = self.inner.put(key, &data, IfMatch::NoneMatch)?;
+        self.inner.delete(&trash_key)?;
+        Ok(etag)
+    }
+
+    /// Permanently deletes everything trashed more than `older_than` ago and
+    /// returns how many objects went.
+    pub fn purge(&self, older_than: Duration) -> Result<usize> {
+        let cutoff = (monotonic_micros() / 1000).saturating_sub(older_than.as_millis() as u64);
+        let mut purged = 0;
+        for entry in self.list_trash("")? {
+            if entry.deleted_at > cutoff {
+                continue;
+            }
+            self.inner.delete(&format!("{TRASH_PREFIX}{}/{}", entry.key, entry.id))?;
+            purged += 1;
+        }
+        Ok(purged)
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for TrashStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        if key.starts_with(TRASH_PREFIX) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        self.inner.put(key, body, cond)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let (keys, next) = self.inner.list(prefix, continuation)?;
+        Ok((keys.into_iter().filter(|key| !key.starts_with(TRASH_PREFIX)).collect(), next))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let Some(data) = self.inner.get(key)? else {
+            return Ok(());
+        };
+        let id = format!("{:016}-{}", monotonic_micros(), Uuid::new_v4().simple());
+        self.inner.put(&format!("{TRASH_PREFIX}{key}/{id}"), &data, IfMatch::NoneMatch)?;
+        self.inner.delete(key)
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.inner.head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.inner.get_range(key, range)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.inner.get_opts(key, opts)
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        if key.starts_with(TRASH_PREFIX) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        self.inner.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.inner.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+
+    #[test]
+    fn test_trash_object_store() {
+        let store = TrashStore::new(InMemoryStore::default());
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_delete_and_restore() {
+        let store = TrashStore::new(InMemoryStore::default());
+        store.put("docs/plan.md", b"v1", IfMatch::Any).unwrap();
+        store.delete("docs/plan.md").unwrap();
+        store.put("docs/plan.md", b"v2", IfMatch::Any).unwrap();
+        store.delete("docs/plan.md").unwrap();
+        store.put("docs/plan.md/notes", b"n", IfMatch::Any).unwrap();
+        store.delete("docs/plan.md/notes").unwrap();
+
+        assert_eq!(store.get("docs/plan.md").unwrap(), None);
+        assert_eq!(store.list("", None).unwrap().0, Vec::<String>::new());
+        let trashed = store.list_trash("docs/").unwrap();
+        assert_eq!(trashed.len(), 3);
+        assert!(trashed.iter().all(|entry| entry.deleted_at > 0));
+
+        // The most recent deletion comes back first
+        store.restore("docs/plan.md").unwrap();
+        assert_eq!(store.get("docs/plan.md").unwrap(), Some(b"v2".to_vec()));
+        assert!(matches!(store.restore("docs/plan.md"), Err(ObjectStoreError::PreconditionFailed)));
+        assert!(store.restore("missing").is_err());
+        assert!(matches!(store.put(".trash/x/1", b"forged", IfMatch::Any), Err(ObjectStoreError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn test_purge() {
+        let store = TrashStore::new(InMemoryStore::default());
+        for key in ["a", "b"] {
+            store.put(key, b"data", IfMatch::Any).unwrap();
+            store.delete(key).unwrap();
+        }
+        assert_eq!(store.purge(Duration::from_secs(3600)).unwrap(), 0);
+        assert_eq!(store.purge(Duration::ZERO).unwrap(), 2);
+        assert_eq!(store.inner().list("", None).unwrap().0, Vec::<String>::new());
+    }
+}
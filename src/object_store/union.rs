@@ -0,0 +1,256 @@
+use super::sync::list_all;
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Where whiteouts live in a layer: an empty object at `.whiteouts/<key>`
+/// hides `<key>` in the layers below.
+pub const WHITEOUT_PREFIX: &str = ".whiteouts/";
+
+fn whiteout(key: &str) -> String {
+    format!("{WHITEOUT_PREFIX}{key}")
+}
+
+struct Layer {
+    store: Arc<dyn ObjectStore>,
+    writable: bool,
+}
+
+/// Layers several stores like overlayfs, e.g. a shared base build cache
+/// under a per-user delta.
+///
+/// Layers are added top first. Reads go to each layer in turn and return
+/// the first copy found; `list` merges all layers. Puts and deletes go to
+/// the topmost writable layer, so layers above it shadow its writes and are
+/// best avoided. Deleting a key that a lower layer still has leaves a
+/// whiteout in the writable layer, and writing the key again removes it.
+///
+/// Conditional puts are checked against the visible copy; when that copy
+/// is in a lower layer the check and the write aren't atomic. `list`
+/// returns every key in one page, as it has to see all layers' keys and
+/// whiteouts to know which are visible. Tags are read from the visible
+/// copy and set in the writable layer, so tagging a key that only a lower
+/// layer has copies it up first, as overlayfs does for metadata changes.
+#[derive(Default)]
+pub struct UnionStore {
+    layers: Vec<Layer>,
+}
+
+impl UnionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_layer(mut self, store: Arc<dyn ObjectStore>) -> Self {
+        self.layers.push(Layer { store, writable: true });
+        self
+    }
+
+    pub fn with_read_only_layer(mut self, store: Arc<dyn ObjectStore>) -> Self {
+        self.layers.push(Layer { store, writable: false });
+        self
+    }
+
+    fn write_layer(&self) -> Result<usize> {
+        self.layers
+            .iter()
+            .position(|layer| layer.writable)
+            .ok_or_else(|| ObjectStoreError::Other("UnionStore has no writable layer".to_string()))
+    }
+
+    // Asks layers from `from` down until one has the key or hides it
+    fn read<T>(&self, key: &str, from: usize, call: impl Fn(&dyn ObjectStore) -> Result<Option<T>>) -> Result<Option<(usize, T)>> {
+        for (index, layer) in self.layers.iter().enumerate().skip(from) {
+            if let Some(value) = call(layer.store.as_ref())? {
+                return Ok(Some((index, value)));
+            }
+            if layer.store.head(&whiteout(key))?.is_some() {
+                return Ok(None);
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl ObjectStore for UnionStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.read(key, 0, |store| store.get(key))?.map(|(_, data)| data))
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        if key.starts_with(WHITEOUT_PREFIX) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        let top = self.write_layer()?;
+        let cond = match cond {
+            IfMatch::Any => IfMatch::Any,
+            cond => match self.read(key, 0, |store| store.head(key))? {
+                // The writable layer checks its own copy
+                Some((index, _)) if index == top => cond,
+                Some((_, meta)) => match cond {
+                    IfMatch::Tag(expected) if expected == meta.etag => IfMatch::NoneMatch,
+                    _ => return Err(ObjectStoreError::PreconditionFailed),
+                },
+                None => match cond {
+                    IfMatch::NoneMatch => IfMatch::NoneMatch,
+                    _ => return Err(ObjectStoreError::PreconditionFailed),
+                },
+            },
+        };
+        let store = &self.layers[top].store;
+        let etag = store.put(key, body, cond)?;
+        store.delete(&whiteout(key))?;
+        Ok(etag)
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        if let Some(token) = continuation {
+            return Err(ObjectStoreError::InvalidArgument(format!("invalid continuation token {token:?}")));
+        }
+        let mut keys = BTreeSet::new();
+        // Whiteouts from the layers above the current one
+        let mut hidden = BTreeSet::new();
+        for layer in &self.layers {
+            for key in list_all(layer.store.as_ref(), prefix)? {
+                if !key.starts_with(WHITEOUT_PREFIX) && !hidden.contains(&key) {
+                    keys.insert(key);
+                }
+            }
+            for marker in list_all(layer.store.as_ref(), &whiteout(prefix))? {
+                hidden.insert(marker[WHITEOUT_PREFIX.len()..].to_string());
+            }
+        }
+        Ok((keys.into_iter().collect(), None))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let top = self.write_layer()?;
+        let store = &self.layers[top].store;
+        // Whiteout first, so the lower copy never shows through in between
+        if self.read(key, top + 1, |store| store.head(key))?.is_some() {
+            store.put(&whiteout(key), b"", IfMatch::Any)?;
+        }
+        store.delete(key)
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        Ok(self.read(key, 0, |store| store.head(key))?.map(|(_, meta)| meta))
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        Ok(self.read(key, 0, |store| store.get_range(key, range.clone()))?.map(|(_, data)| data))
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        Ok(self.read(key, 0, |store| store.get_opts(key, opts.clone()))?.map(|(_, result)| result))
+    }
+
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        if key.starts_with(WHITEOUT_PREFIX) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        let top = self.write_layer()?;
+        let store = &self.layers[top].store;
+        match self.read(key, 0, |store| store.get_opts(key, GetOptions::default()))? {
+            None => return Err(ObjectStoreError::NotFound(key.to_string())),
+            Some((index, GetResult::Body { data, .. })) if index != top => {
+                store.put(key, &data, IfMatch::Any)?;
+            }
+            Some(_) => {}
+        }
+        store.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        Ok(self.read(key, 0, |store| store.get_tags(key))?.map(|(_, tags)| tags))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use uuid::Uuid;
+
+    fn base() -> Arc<InMemoryStore> {
+        let base = Arc::new(InMemoryStore::default());
+        base.put("cache/a", b"base-a", IfMatch::Any).unwrap();
+        base.put("cache/b", b"base-b", IfMatch::Any).unwrap();
+        base
+    }
+
+    #[test]
+    fn test_union_object_store() {
+        let store = UnionStore::new()
+            .with_layer(Arc::new(InMemoryStore::default()))
+            .with_read_only_layer(base());
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_delta_over_base() {
+        let (delta, base) = (Arc::new(InMemoryStore::default()), base());
+        let store = UnionStore::new().with_layer(delta.clone()).with_read_only_layer(base.clone());
+
+        assert_eq!(store.get("cache/a").unwrap(), Some(b"base-a".to_vec()));
+        store.put("cache/a", b"mine", IfMatch::Any).unwrap();
+        store.put("cache/c", b"new", IfMatch::Any).unwrap();
+        assert_eq!(store.get("cache/a").unwrap(), Some(b"mine".to_vec()));
+        assert_eq!(base.get("cache/a").unwrap(), Some(b"base-a".to_vec()));
+
+        // Deleting a base key hides it without touching the base
+        store.delete("cache/b").unwrap();
+        assert_eq!(store.get("cache/b").unwrap(), None);
+        assert_eq!(store.head("cache/b").unwrap(), None);
+        assert!(base.get("cache/b").unwrap().is_some());
+        assert_eq!(store.list("cache/", None).unwrap().0, vec!["cache/a", "cache/c"]);
+        assert_eq!(store.list("", None).unwrap().0, vec!["cache/a", "cache/c"]);
+
+        store.put("cache/b", b"back", IfMatch::Any).unwrap();
+        assert_eq!(store.get("cache/b").unwrap(), Some(b"back".to_vec()));
+        assert_eq!(delta.list(WHITEOUT_PREFIX, None).unwrap().0, Vec::<String>::new());
+        assert!(matches!(store.put(".whiteouts/x", b"", IfMatch::Any), Err(ObjectStoreError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn test_tagging_copies_up() {
+        let (delta, base) = (Arc::new(InMemoryStore::default()), base());
+        let store = UnionStore::new().with_layer(delta.clone()).with_read_only_layer(base.clone());
+        assert_eq!(store.get_tags("cache/a").unwrap(), Some(BTreeMap::new()));
+
+        let tags = BTreeMap::from([("owner".to_string(), "ci".to_string())]);
+        store.put_tags("cache/a", &tags).unwrap();
+        assert_eq!(store.get_tags("cache/a").unwrap(), Some(tags.clone()));
+        assert_eq!(delta.get("cache/a").unwrap(), Some(b"base-a".to_vec()));
+        assert_eq!(base.get_tags("cache/a").unwrap(), Some(BTreeMap::new()));
+
+        store.delete("cache/b").unwrap();
+        assert_eq!(store.get_tags("cache/b").unwrap(), None);
+        assert!(matches!(store.put_tags("cache/b", &tags), Err(ObjectStoreError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_conditions_see_lower_layers() {
+        let store = UnionStore::new()
+            .with_layer(Arc::new(InMemoryStore::default()))
+            .with_read_only_layer(base());
+        let result = store.put("cache/a", b"x", IfMatch::NoneMatch);
+        assert!(matches!(result, Err(ObjectStoreError::PreconditionFailed)));
+        assert!(matches!(store.put("cache/a", b"x", IfMatch::Tag("stale")), Err(ObjectStoreError::PreconditionFailed)));
+
+        let etag = store.head("cache/a").unwrap().unwrap().etag;
+        let etag = store.put("cache/a", b"x", IfMatch::Tag(&etag)).unwrap();
+        store.put("cache/a", b"y", IfMatch::Tag(&etag)).unwrap();
+        assert!(store.put("cache/z", b"x", IfMatch::Tag(&etag)).is_err());
+    }
+
+    #[test]
+    fn test_needs_a_writable_layer() {
+        let store = UnionStore::new().with_read_only_layer(base());
+        assert_eq!(store.get("cache/a").unwrap(), Some(b"base-a".to_vec()));
+        assert!(store.put("cache/a", b"x", IfMatch::Any).is_err());
+        assert!(store.delete("cache/a").is_err());
+    }
+}
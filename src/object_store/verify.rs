@@ -0,0 +1,554 @@
+use super::sync::{for_each_concurrent, list_all};
+use super::{ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompareMode {
+    // Size and ETag from head on both sides
+    Metadata,
+    // Fetches both bodies and hashes them, for stores whose ETags can't be trusted
+    FullHash,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompareOptions {
+    pub mode: CompareMode,
+    // Number of keys compared at once
+    pub concurrency: usize,
+    // Keys per shard; progress is checkpointed after every shard
+    pub shard_size: usize,
+    // JSON file recording progress. If it exists, the comparison resumes from it.
+    pub checkpoint: Option<PathBuf>,
+}
+
+impl Default for CompareOptions {
+    fn default() -> Self {
+        Self {
+            mode: CompareMode::Metadata,
+            concurrency: 16,
+            shard_size: 1000,
+            checkpoint: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MismatchKind {
+    MissingInDest,
+    ExtraInDest,
+    SizeDiffers,
+    ContentDiffers,
+    // Either side failed while the key was being compared
+    Error(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mismatch {
+    pub key: String,
+    pub kind: MismatchKind,
+    pub src: Option<ObjectMeta>,
+    pub dst: Option<ObjectMeta>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompareReport {
+    // Source keys compared so far
+    pub checked: u64,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl CompareReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("report serializes")
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    // Every source key up to and including this one has been compared
+    last_key: Option<String>,
+    report: CompareReport,
+}
+
+/// Checks that every key under `prefix` in `src` exists in `dst` with the
+/// same contents, and that `dst` has nothing extra under `prefix`.
+///
+/// Source keys are split into shards of `options.shard_size`. Keys within a
+/// shard are compared `options.concurrency` at a time, and after each shard
+/// the progress and mismatches so far are written to `options.checkpoint`,
+/// so an interrupted run picks up at the first unfinished shard.
+pub fn compare(src: &dyn ObjectStore, dst: &dyn ObjectStore, prefix: &str, options: &CompareOptions) -> Result<CompareReport> {
+    let mut checkpoint = match &options.checkpoint {
+        Some(path) => load_checkpoint(path)?,
+        None => None,
+    }
+    .unwrap_or(Checkpoint {
+        last_key: None,
+        report: CompareReport::default(),
+    });
+
+    let mut src_keys = list_all(src, prefix)?;
+    src_keys.sort();
+    let resume_at = match &checkpoint.last_key {
+        Some(last) => src_keys.partition_point(|key| key <= last),
+        None => 0,
+    };
+
+    for shard in src_keys[resume_at..].chunks(options.shard_size.max(1)) {
+        let found = Mutex::new(Vec::new());
+        for_each_concurrent(shard, options.concurrency, |key| {
+            if let Some(mismatch) = compare_key(src, dst, key, options.mode) {
+                found.lock().unwrap().push(mismatch);
+            }
+        });
+
+        let mut found = found.into_inner().unwrap();
+        found.sort_by(|a, b| a.key.cmp(&b.key));
+        checkpoint.report.checked += shard.len() as u64;
+        checkpoint.report.mismatches.extend(found);
+        checkpoint.last_key = shard.last().cloned();
+        if let Some(path) = &options.checkpoint {
+            save_checkpoint(path, &checkpoint)?;
+        }
+    }
+
+    let mut report = checkpoint.report;
+    let src_set: HashSet<&String> = src_keys.iter().collect();
+    let mut dst_keys = list_all(dst, prefix)?;
+    dst_keys.sort();
+    for key in dst_keys.into_iter().filter(|key| !src_set.contains(key)) {
+        let dst_meta = dst.head(&key).ok().flatten();
+        report.mismatches.push(Mismatch {
+            key,
+            kind: MismatchKind::ExtraInDest,
+            src: None,
+            dst: dst_meta,
+        });
+    }
+    Ok(report)
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckOptions {
+    // Number of objects rehashed at once
+    pub concurrency: usize,
+    // If set, `<key><suffix>` holds the object's expected MD5 in hex
+    pub sidecar_suffix: Option<String>,
+}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 16,
+            sidecar_suffix: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    // Body hash differs from the stored ETag or sidecar checksum
+    Corrupted,
+    // Object has no sidecar checksum, or vanished between listing and reading
+    Missing,
+    // Sidecar checksum whose object doesn't exist
+    Orphaned,
+    Error(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Issue {
+    pub key: String,
+    pub kind: IssueKind,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckReport {
+    // Objects rehashed, not counting sidecars
+    pub checked: u64,
+    // Of those, objects whose ETag isn't an MD5, so only a sidecar could
+    // vouch for them
+    #[serde(default)]
+    pub unverifiable: u64,
+    pub issues: Vec<Issue>,
+}
+
+impl CheckReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("report serializes")
+    }
+}
+
+/// Integrity audit of a single store.
+///
+/// Every object under `prefix` is read back and its MD5 compared with the
+/// ETag the store reports and, when `options.sidecar_suffix` is set, with
+/// the checksum in its sidecar object. Sidecars without an object are
+/// reported as orphaned. To check one store against another, use `compare`
+/// with `CompareMode::FullHash`.
+///
+/// Only ETags of 32 hex digits are compared; the rest, such as S3's `-N`
+/// multipart ETags, are counted as unverifiable instead. Some ETags look
+/// like an MD5 without being one, e.g. S3's under SSE-KMS and a
+/// `ChunkedStore`'s for chunked objects, and those objects are reported as
+/// corrupted; check such stores through sidecars.
+pub fn check(store: &dyn ObjectStore, prefix: &str, options: &CheckOptions) -> Result<CheckReport> {
+    let mut keys = list_all(store, prefix)?;
+    keys.sort();
+    let suffix = options.sidecar_suffix.as_deref().filter(|s| !s.is_empty());
+    let is_sidecar = |key: &str| suffix.is_some_and(|s| key.ends_with(s));
+    let all: HashSet<&str> = keys.iter().map(String::as_str).collect();
+
+    let objects: Vec<&String> = keys.iter().filter(|key| !is_sidecar(key)).collect();
+    let issues = Mutex::new(Vec::new());
+    let unverifiable = AtomicU64::new(0);
+    for_each_concurrent(&objects, options.concurrency, |key| {
+        let sidecar = suffix.map(|s| format!("{key}{s}"));
+        let sidecar = sidecar.as_deref().filter(|sidecar| all.contains(sidecar));
+        issues.lock().unwrap().extend(check_key(store, key, suffix.is_some(), sidecar, &unverifiable));
+    });
+
+    let mut issues = issues.into_inner().unwrap();
+    if let Some(suffix) = suffix {
+        for sidecar in keys.iter().filter(|key| is_sidecar(key)) {
+            if !all.contains(&sidecar[..sidecar.len() - suffix.len()]) {
+                issues.push(Issue {
+                    key: sidecar.clone(),
+                    kind: IssueKind::Orphaned,
+                    expected: None,
+                    actual: None,
+                });
+            }
+        }
+    }
+    issues.sort_by(|a, b| a.key.cmp(&b.key));
+
+    Ok(CheckReport {
+        checked: objects.len() as u64,
+        unverifiable: unverifiable.into_inner(),
+        issues,
+    })
+}
+
+fn check_key(
+    store: &dyn ObjectStore,
+    key: &str,
+    want_sidecar: bool,
+    sidecar: Option<&str>,
+    unverifiable: &AtomicU64,
+) -> Vec<Issue> {
+    let issue = |kind, expected: Option<&str>, actual: Option<&str>| Issue {
+        key: key.to_string(),
+        kind,
+        expected: expected.map(str::to_string),
+        actual: actual.map(str::to_string),
+    };
+    let error = |e: ObjectStoreError| vec![issue(IssueKind::Error(format!("{e:?}")), None, None)];
+
+    let meta = match store.head(key) {
+        Ok(meta) => meta,
+        Err(e) => return error(e),
+    };
+    let actual = match hash_object(store, key) {
+        Ok(actual) => actual,
+        Err(e) => return error(e),
+    };
+    let (Some(meta), Some(actual)) = (meta, actual) else {
+        return vec![issue(IssueKind::Missing, None, None)];
+    };
+
+    let mut issues = Vec::new();
+    if !is_md5(&meta.etag) {
+        unverifiable.fetch_add(1, Ordering::Relaxed);
+    } else if meta.etag.to_ascii_lowercase() != actual.etag {
+        issues.push(issue(IssueKind::Corrupted, Some(&meta.etag), Some(&actual.etag)));
+    }
+    match sidecar.map(|sidecar| store.get(sidecar)) {
+        Some(Ok(Some(data))) => {
+            let expected = String::from_utf8_lossy(&data).trim().to_ascii_lowercase();
+            if expected != actual.etag {
+                issues.push(issue(IssueKind::Corrupted, Some(&expected), Some(&actual.etag)));
+            }
+        }
+        Some(Ok(None)) | None if want_sidecar => issues.push(issue(IssueKind::Missing, None, Some(&actual.etag))),
+        Some(Err(e)) => issues.extend(error(e)),
+        _ => {}
+    }
+    issues
+}
+
+fn compare_key(src: &dyn ObjectStore, dst: &dyn ObjectStore, key: &str, mode: CompareMode) -> Option<Mismatch> {
+    let mismatch = |kind, src, dst| {
+        Some(Mismatch {
+            key: key.to_string(),
+            kind,
+            src,
+            dst,
+        })
+    };
+    let (src_meta, dst_meta) = match fetch_meta(src, dst, key, mode) {
+        Ok(metas) => metas,
+        Err(e) => return mismatch(MismatchKind::Error(format!("{e:?}")), None, None),
+    };
+
+    match (src_meta, dst_meta) {
+        // Deleted from the source since it was listed
+        (None, _) => None,
+        (Some(s), None) => mismatch(MismatchKind::MissingInDest, Some(s), None),
+        (Some(s), Some(d)) if s.size != d.size => mismatch(MismatchKind::SizeDiffers, Some(s), Some(d)),
+        (Some(s), Some(d)) if s.etag != d.etag => mismatch(MismatchKind::ContentDiffers, Some(s), Some(d)),
+        _ => None,
+    }
+}
+
+fn fetch_meta(
+    src: &dyn ObjectStore,
+    dst: &dyn ObjectStore,
+    key: &str,
+    mode: CompareMode,
+) -> Result<(Option<ObjectMeta>, Option<ObjectMeta>)> {
+    match mode {
+        CompareMode::Metadata => Ok((src.head(key)?, dst.head(key)?)),
+        CompareMode::FullHash => Ok((hash_object(src, key)?, hash_object(dst, key)?)),
+    }
+}
+
+// Metadata recomputed from the body rather than taken from the store
+fn is_md5(etag: &str) -> bool {
+    etag.len() == 32 && etag.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn hash_object(store: &dyn ObjectStore, key: &str) -> Result<Option<ObjectMeta>> {
+    Ok(store.get(key)?.map(|data| ObjectMeta {
+        size: data.len() as u64,
+        etag: format!("{:x}", md5::compute(&data)),
+    }))
+}
+
+fn load_checkpoint(path: &PathBuf) -> Result<Option<Checkpoint>> {
+    match fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| ObjectStoreError::Other(format!("corrupt checkpoint {}: {e}", path.display()))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(ObjectStoreError::Io(e)),
+    }
+}
+
+fn save_checkpoint(path: &PathBuf, checkpoint: &Checkpoint) -> Result<()> {
+    // Write then rename so a crash never leaves a half-written checkpoint
+    let tmp = path.with_extension("tmp");
+    let data = serde_json::to_vec(checkpoint).expect("checkpoint serializes");
+    fs::write(&tmp, data).map_err(ObjectStoreError::Io)?;
+    fs::rename(&tmp, path).map_err(ObjectStoreError::Io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::IfMatch;
+    use crate::object_store::memory::InMemoryStore;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tempfile::TempDir;
+
+    fn put(store: &dyn ObjectStore, key: &str, body: &[u8]) {
+        store.put(key, body, IfMatch::Any).unwrap();
+    }
+
+    fn setup() -> (InMemoryStore, InMemoryStore) {
+        let src = InMemoryStore::default();
+        let dst = InMemoryStore::default();
+        for i in 0..20 {
+            let key = format!("m/{i:02}");
+            put(&src, &key, key.as_bytes());
+            put(&dst, &key, key.as_bytes());
+        }
+        put(&dst, "m/03", b"m/xx");
+        put(&dst, "m/07", b"short");
+        dst.delete("m/11").unwrap();
+        put(&dst, "m/99", b"extra");
+        (src, dst)
+    }
+
+    fn kinds(report: &CompareReport) -> Vec<(&str, &MismatchKind)> {
+        report.mismatches.iter().map(|m| (m.key.as_str(), &m.kind)).collect()
+    }
+
+    #[test]
+    fn test_compare_finds_mismatches() {
+        let (src, dst) = setup();
+        for mode in [CompareMode::Metadata, CompareMode::FullHash] {
+            let options = CompareOptions {
+                mode,
+                shard_size: 6,
+                ..Default::default()
+            };
+            let report = compare(&src, &dst, "m/", &options).unwrap();
+            assert_eq!(report.checked, 20);
+            assert_eq!(
+                kinds(&report),
+                vec![
+                    ("m/03", &MismatchKind::ContentDiffers),
+                    ("m/07", &MismatchKind::SizeDiffers),
+                    ("m/11", &MismatchKind::MissingInDest),
+                    ("m/99", &MismatchKind::ExtraInDest),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_report_json() {
+        let (src, dst) = setup();
+        let report = compare(&src, &dst, "m/", &CompareOptions::default()).unwrap();
+        let parsed: CompareReport = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(parsed, report);
+        assert!(report.to_json().contains("\"missing_in_dest\""));
+    }
+
+    // Fails every get after a fixed number of calls, simulating a crash mid-run
+    struct FlakyStore {
+        inner: InMemoryStore,
+        heads_left: AtomicUsize,
+    }
+
+    impl ObjectStore for FlakyStore {
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            self.inner.get(key)
+        }
+        fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+            self.inner.put(key, body, cond)
+        }
+        fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+            self.inner.list(prefix, continuation)
+        }
+        fn delete(&self, key: &str) -> Result<()> {
+            self.inner.delete(key)
+        }
+        fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+            if self.heads_left.fetch_sub(1, Ordering::SeqCst) == 0 {
+                panic!("simulated crash");
+            }
+            self.inner.head(key)
+        }
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let tmp = TempDir::new().unwrap();
+        let (src, dst) = setup();
+        let options = CompareOptions {
+            shard_size: 5,
+            concurrency: 1,
+            checkpoint: Some(tmp.path().join("verify.json")),
+            ..Default::default()
+        };
+
+        // Dies partway through the third shard
+        let flaky = FlakyStore {
+            inner: src,
+            heads_left: AtomicUsize::new(12),
+        };
+        let crashed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| compare(&flaky, &dst, "m/", &options)));
+        assert!(crashed.is_err());
+
+        let saved: Checkpoint = serde_json::from_slice(&fs::read(tmp.path().join("verify.json")).unwrap()).unwrap();
+        assert_eq!(saved.last_key.as_deref(), Some("m/09"));
+        assert_eq!(saved.report.checked, 10);
+
+        flaky.heads_left.store(usize::MAX, Ordering::SeqCst);
+        let report = compare(&flaky, &dst, "m/", &options).unwrap();
+        assert_eq!(report.checked, 20);
+        assert_eq!(report.mismatches.len(), 4);
+    }
+
+    // Reports a fixed ETag for "rotten" keys, like a backend with bit rot,
+    // and a multipart-style one for "multipart" keys
+    struct WrongEtagStore(InMemoryStore);
+
+    impl ObjectStore for WrongEtagStore {
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            self.0.get(key)
+        }
+        fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+            self.0.put(key, body, cond)
+        }
+        fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+            self.0.list(prefix, continuation)
+        }
+        fn delete(&self, key: &str) -> Result<()> {
+            self.0.delete(key)
+        }
+        fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+            Ok(self.0.head(key)?.map(|meta| ObjectMeta {
+                etag: if key.ends_with("rotten") {
+                    "0".repeat(32)
+                } else if key.ends_with("multipart") {
+                    format!("{}-3", meta.etag)
+                } else {
+                    meta.etag
+                },
+                ..meta
+            }))
+        }
+    }
+
+    #[test]
+    fn test_check_store() {
+        let store = WrongEtagStore(InMemoryStore::default());
+        let md5 = |data: &[u8]| format!("{:x}", md5::compute(data));
+        put(&store, "d/good", b"good");
+        put(&store, "d/good.md5", md5(b"good").as_bytes());
+        put(&store, "d/rotten", b"rotten");
+        put(&store, "d/rotten.md5", md5(b"rotten").as_bytes());
+        put(&store, "d/tampered", b"tampered");
+        put(&store, "d/tampered.md5", md5(b"original").as_bytes());
+        put(&store, "d/unsummed", b"no sidecar");
+        put(&store, "d/gone.md5", md5(b"gone").as_bytes());
+
+        let report = check(&store, "d/", &CheckOptions::default()).unwrap();
+        assert_eq!(report.checked, 8);
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].key, "d/rotten");
+
+        let options = CheckOptions {
+            sidecar_suffix: Some(".md5".to_string()),
+            ..Default::default()
+        };
+        let report = check(&store, "d/", &options).unwrap();
+        assert_eq!(report.checked, 4);
+        let found: Vec<(&str, &IssueKind)> = report.issues.iter().map(|i| (i.key.as_str(), &i.kind)).collect();
+        assert_eq!(
+            found,
+            vec![
+                ("d/gone.md5", &IssueKind::Orphaned),
+                ("d/rotten", &IssueKind::Corrupted),
+                ("d/tampered", &IssueKind::Corrupted),
+                ("d/unsummed", &IssueKind::Missing),
+            ]
+        );
+        assert_eq!(report.issues[2].expected, Some(md5(b"original")));
+        assert_eq!(report.issues[2].actual, Some(md5(b"tampered")));
+        assert!(report.to_json().contains("\"orphaned\""));
+    }
+
+    #[test]
+    fn test_check_counts_etags_that_are_not_md5s() {
+        let store = WrongEtagStore(InMemoryStore::default());
+        put(&store, "d/multipart", b"uploaded in parts");
+        put(&store, "d/plain", b"ok");
+        let report = check(&store, "d/", &CheckOptions::default()).unwrap();
+        assert_eq!(report.checked, 2);
+        assert_eq!(report.unverifiable, 1);
+        assert!(report.issues.is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+use super::sync::list_all;
+use super::{GetOptions, GetResult, IfMatch, ObjectMeta, ObjectStore, ObjectStoreError, Result};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+// Every version of every object lives under here
+pub const VERSION_PREFIX: &str = ".versions/";
+
+// Microseconds since the Unix epoch, strictly increasing within the process
+// so ids taken in quick succession still sort in the order they were taken
+pub(crate) fn monotonic_micros() -> u64 {
+    static LAST: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0);
+    let previous = LAST.fetch_max(now, Ordering::Relaxed);
+    if now > previous {
+        now
+    } else {
+        LAST.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub id: String,
+    // Milliseconds since the Unix epoch
+    pub created_at: u64,
+}
+
+/// Wraps a store so every put is also kept as an immutable version that can
+/// be listed, read back and restored, on backends without native
+/// versioning.
+///
+/// The current content stays at the object's key, so reads and
+/// conditional puts behave exactly as on the inner store. Versions live at
+/// `.versions/<key>/<id>`, where ids start with the write time in
+/// microseconds so they list oldest first; `.versions/` is hidden from
+/// `list` and reserved. Deleting an object keeps its versions, so it can be
+/// restored; `delete_version` removes one for good. Each put writes the
+/// version first and removes it again if the put is rejected.
+pub struct VersionedStore<S> {
+    inner: S,
+}
+
+impl<S: ObjectStore> VersionedStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn version_key(key: &str, id: &str) -> Result<String> {
+        if id.is_empty() || id.contains('/') {
+            return Err(ObjectStoreError::InvalidArgument(format!("invalid version id {id:?}")));
+        }
+        Ok(format!("{VERSION_PREFIX}{key}/{id}"))
+    }
+
+    /// Versions of `key`, oldest first, including the current one.
+    pub fn list_versions(&self, key: &str) -> Result<Vec<Version>> {
+        let prefix = format!("{VERSION_PREFIX}{key}/");
+        let mut versions: Vec<Version> = list_all(&self.inner, &prefix)?
+            .into_iter()
+            .filter_map(|version_key| {
+                let id = version_key.strip_prefix(&prefix)?;
+                // Versions of keys nested under this one
+                if id.contains('/') {
+                    return None;
+                }
+                let micros: u64 = id.split('-').next()?.parse().ok()?;
+                Some(Version {
+                    id: id.to_string(),
+                    created_at: micros / 1000,
+                })
+            })
+            .collect();
+        versions.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(versions)
+    }
+
+    pub fn get_version(&self, key: &str, id: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.get(&Self::version_key(key, id)?)
+    }
+
+    pub fn head_version(&self, key: &str, id: &str) -> Result<Option<ObjectMeta>> {
+        self.inner.head(&Self::version_key(key, id)?)
+    }
+
+    /// Makes version `id` current again, as a new version; returns the new ETag.
+    pub fn restore(&self, key: &str, id: &str) -> Result<String> {
+        let data = self
+            .get_version(key, id)?
+            .ok_or_else(|| ObjectStoreError::NotFound(format!("version {id} of {key}")))?;
+        self.put(key, &data, IfMatch::Any)
+    }
+
+    pub fn delete_version(&self, key: &str, id: &str) -> Result<()> {
+        self.inner.delete(&Self::version_key(key, id)?)
+    }
+}
+
+impl<S: ObjectStore> ObjectStore for VersionedStore<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn put(&self, key: &str, body: &[u8], cond: IfMatch) -> Result<String> {
+        if key.starts_with(VERSION_PREFIX) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        let id = format!("{:016}-{}", monotonic_micros(), Uuid::new_v4().simple());
+        let version_key = Self::version_key(key, &id)?;
+        self.inner.put(&version_key, body, IfMatch::NoneMatch)?;
+        match self.inner.put(key, body, cond) {
+            Ok(etag) => Ok(etag),
+            Err(e) => {
+                let _ = self.inner.delete(&version_key);
+                Err(e)
+            }
+        }
+    }
+
+    fn list(&self, prefix: &str, continuation: Option<String>) -> Result<(Vec<String>, Option<String>)> {
+        let (keys, next) = self.inner.list(prefix, continuation)?;
+        Ok((keys.into_iter().filter(|key| !key.starts_with(VERSION_PREFIX)).collect(), next))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(key)
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        self.inner.head(key)
+    }
+
+    fn get_range(&self, key: &str, range: Range<u64>) -> Result<Option<Vec<u8>>> {
+        self.inner.get_range(key, range)
+    }
+
+    fn get_opts(&self, key: &str, opts: GetOptions) -> Result<Option<GetResult>> {
+        self.inner.get_opts(key, opts)
+    }
+
+    // Tags belong to the current content only; versions don't keep them
+    fn put_tags(&self, key: &str, tags: &BTreeMap<String, String>) -> Result<()> {
+        if key.starts_with(VERSION_PREFIX) {
+            return Err(ObjectStoreError::InvalidKey(key.to_string()));
+        }
+        self.inner.put_tags(key, tags)
+    }
+
+    fn get_tags(&self, key: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.inner.get_tags(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::local::LocalStore;
+    use crate::object_store::memory::InMemoryStore;
+    use crate::object_store::test_helpers::tests::run_object_store_tests;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_versioned_object_store() {
+        let store = VersionedStore::new(InMemoryStore::default());
+        run_object_store_tests(&store, &format!("test/{}/", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_versions_and_restore() {
+        let tmp = TempDir::new().unwrap();
+        let store = VersionedStore::new(LocalStore::new(tmp.path()));
+        store.put("config.toml", b"v1", IfMatch::Any).unwrap();
+        store.put("config.toml", b"v2", IfMatch::Any).unwrap();
+
+        let versions = store.list_versions("config.toml").unwrap();
+        assert_eq!(versions.len(), 2);
+        assert!(versions[0].created_at > 0);
+        assert_eq!(store.get_version("config.toml", &versions[0].id).unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(store.list("", None).unwrap().0, vec!["config.toml"]);
+
+        store.restore("config.toml", &versions[0].id).unwrap();
+        assert_eq!(store.get("config.toml").unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(store.list_versions("config.toml").unwrap().len(), 3);
+        assert!(store.restore("config.toml", "nope").is_err());
+        assert!(store.get_version("config.toml", "../v1").is_err());
+        assert!(matches!(store.put(".versions/x/1", b"forged", IfMatch::Any), Err(ObjectStoreError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn test_deleted_objects_can_be_restored() {
+        let store = VersionedStore::new(InMemoryStore::default());
+        store.put("report.csv", b"a,b", IfMatch::Any).unwrap();
+        store.put("report.csv/summary", b"ok", IfMatch::Any).unwrap();
+        store.delete("report.csv").unwrap();
+        assert_eq!(store.get("report.csv").unwrap(), None);
+
+        let versions = store.list_versions("report.csv").unwrap();
+        store.restore("report.csv", &versions[0].id).unwrap();
+        assert_eq!(store.get("report.csv").unwrap(), Some(b"a,b".to_vec()));
+
+        // A rejected put leaves no version behind
+        assert!(store.put("report.csv", b"c,d", IfMatch::NoneMatch).is_err());
+        assert_eq!(store.list_versions("report.csv").unwrap().len(), 2);
+
+        store.delete_version("report.csv", &versions[0].id).unwrap();
+        assert_eq!(store.list_versions("report.csv").unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,85 @@
+// Drives the blobctl binary against file:// locations
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use tempfile::TempDir;
+
+fn blobctl(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_blobctl"))
+        .args(args)
+        .output()
+        .expect("failed to run blobctl")
+}
+
+fn url(dir: &TempDir, rest: &str) -> String {
+    format!("file://{}/{rest}", dir.path().display())
+}
+
+#[test]
+fn test_cp_cat_ls_rm() {
+    let tmp = TempDir::new().unwrap();
+    fs::create_dir_all(tmp.path().join("src")).unwrap();
+    fs::write(tmp.path().join("src/hello.txt"), b"hello blobctl").unwrap();
+
+    let out = blobctl(&["cp", &url(&tmp, "src/hello.txt"), &url(&tmp, "dst/")]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(fs::read(tmp.path().join("dst/hello.txt")).unwrap(), b"hello blobctl");
+
+    let out = blobctl(&["cat", &url(&tmp, "dst/hello.txt")]);
+    assert_eq!(out.stdout, b"hello blobctl");
+
+    let out = blobctl(&["ls", &url(&tmp, "dst/")]);
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "hello.txt\n");
+
+    let out = blobctl(&["rm", &url(&tmp, "dst/")]);
+    assert!(!out.status.success());
+    let out = blobctl(&["rm", &url(&tmp, "dst/hello.txt")]);
+    assert!(out.status.success());
+    assert!(!tmp.path().join("dst/hello.txt").exists());
+
+    let out = blobctl(&["cat", &url(&tmp, "dst/hello.txt")]);
+    assert!(!out.status.success());
+}
+
+#[test]
+fn test_cp_from_stdin() {
+    let tmp = TempDir::new().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_blobctl"))
+        .args(["cp", "-", &url(&tmp, "piped.bin")])
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"from stdin").unwrap();
+    assert!(child.wait().unwrap().success());
+    assert_eq!(fs::read(tmp.path().join("piped.bin")).unwrap(), b"from stdin");
+}
+
+#[test]
+fn test_sync() {
+    let tmp = TempDir::new().unwrap();
+    for (name, data) in [("a.txt", "one"), ("sub/b.txt", "two")] {
+        let path = tmp.path().join("src").join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+    fs::create_dir_all(tmp.path().join("dst")).unwrap();
+    fs::write(tmp.path().join("dst/a.txt"), "one").unwrap();
+    fs::write(tmp.path().join("dst/stale.txt"), "old").unwrap();
+
+    let out = blobctl(&["sync", "--delete", &url(&tmp, "src/"), &url(&tmp, "dst/")]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr).trim(),
+        "1 copied, 1 up to date, 1 deleted"
+    );
+    assert_eq!(fs::read_to_string(tmp.path().join("dst/sub/b.txt")).unwrap(), "two");
+    assert!(!tmp.path().join("dst/stale.txt").exists());
+}
+
+#[test]
+fn test_usage_and_bad_urls() {
+    assert!(!blobctl(&[]).status.success());
+    let out = blobctl(&["ls", "ftp://example.com/"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("unsupported url"));
+}
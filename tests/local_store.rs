@@ -0,0 +1,76 @@
+// Conditional puts on one LocalStore root from several processes at once.
+// The workers are this test binary run again with the ignored worker test
+// selected and the root passed in the environment.
+use blob_store::object_store::local::LocalStore;
+use blob_store::object_store::{IfMatch, ObjectStore, ObjectStoreError};
+use std::env;
+use std::process::{Command, Stdio};
+use tempfile::TempDir;
+
+const ROOT_VAR: &str = "LOCAL_STORE_STRESS_ROOT";
+const PROCESSES: usize = 6;
+const INCREMENTS: usize = 40;
+
+// One read-modify-write of the counter, retried until its condition holds
+fn increment(store: &LocalStore) {
+    loop {
+        let (value, etag) = match store.head("counter").unwrap() {
+            Some(meta) => {
+                let data = store.get("counter").unwrap().unwrap();
+                (String::from_utf8(data).unwrap().parse::<usize>().unwrap(), Some(meta.etag))
+            }
+            None => (0, None),
+        };
+        let cond = match &etag {
+            Some(etag) => IfMatch::Tag(etag),
+            None => IfMatch::NoneMatch,
+        };
+        match store.put("counter", (value + 1).to_string().as_bytes(), cond) {
+            Ok(_) => return,
+            Err(ObjectStoreError::PreconditionFailed) => continue,
+            Err(e) => panic!("put failed: {e:?}"),
+        }
+    }
+}
+
+#[test]
+#[ignore = "run in child processes by test_conditional_puts_across_processes"]
+fn stress_worker() {
+    let Ok(root) = env::var(ROOT_VAR) else {
+        return;
+    };
+    let store = LocalStore::new(root);
+    let id = std::process::id();
+    // Only one process may create the object
+    if store.put("singleton", id.to_string().as_bytes(), IfMatch::NoneMatch).is_ok() {
+        store.put(&format!("created/{id}"), b"", IfMatch::Any).unwrap();
+    }
+    for _ in 0..INCREMENTS {
+        increment(&store);
+    }
+}
+
+#[test]
+fn test_conditional_puts_across_processes() {
+    let tmp = TempDir::new().unwrap();
+    let exe = env::current_exe().unwrap();
+    let children: Vec<_> = (0..PROCESSES)
+        .map(|_| {
+            Command::new(&exe)
+                .args(["stress_worker", "--exact", "--ignored", "--test-threads=1", "--quiet"])
+                .env(ROOT_VAR, tmp.path())
+                .stdout(Stdio::null())
+                .spawn()
+                .expect("failed to start worker")
+        })
+        .collect();
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+
+    let store = LocalStore::new(tmp.path());
+    // No increment was lost to another process's write
+    let counter = store.get("counter").unwrap().unwrap();
+    assert_eq!(String::from_utf8(counter).unwrap(), (PROCESSES * INCREMENTS).to_string());
+    assert_eq!(store.list("created/", None).unwrap().0.len(), 1);
+}
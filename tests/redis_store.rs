@@ -0,0 +1,18 @@
+#![cfg(feature = "redis")]
+
+#[test]
+fn test_redis_object_store() {
+    use blob_store::object_store::redis::RedisStore;
+
+    // Set this in your environment for the test, e.g. redis://127.0.0.1/
+    let Ok(url) = std::env::var("TEST_REDIS_URL") else {
+        eprintln!("skipping: TEST_REDIS_URL not set");
+        return;
+    };
+    let store = RedisStore::with_namespace(&url, "blob_store_test:").unwrap();
+
+    // Use a unique prefix for isolation
+    let prefix = format!("test/{}/", uuid::Uuid::new_v4());
+    blob_store::object_store::test_helpers::tests::run_object_store_tests(&store, &prefix);
+    blob_store::object_store::test_helpers::tests::run_oracle_tests(&store, &format!("oracle/{}/", uuid::Uuid::new_v4()));
+}
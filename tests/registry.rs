@@ -0,0 +1,176 @@
+#![cfg(all(feature = "gateway", feature = "http"))]
+// Runs the examples/registry application over HTTP: push, pull, tags,
+// auth and garbage collection against a LocalStore with a read cache.
+
+#[path = "../examples/registry/registry.rs"]
+mod registry;
+#[path = "../examples/registry/server.rs"]
+mod server;
+
+use blob_store::object_store::cache::CachedStore;
+use blob_store::object_store::local::LocalStore;
+use blob_store::object_store::ObjectStore;
+use registry::{digest_of, GcReport, Manifest, Registry};
+use server::{Access, Auth};
+use std::sync::Arc;
+use std::thread;
+use tempfile::TempDir;
+use tiny_http::Server;
+use ureq::Agent;
+
+struct Client {
+    agent: Agent,
+    base: String,
+    token: Option<&'static str>,
+}
+
+impl Client {
+    fn send(&self, method: &str, path: &str, body: &[u8]) -> (u16, Option<String>, Vec<u8>) {
+        let mut request = ureq::http::Request::builder().method(method).uri(format!("{}{path}", self.base));
+        if let Some(token) = self.token {
+            request = request.header("Authorization", format!("Bearer {token}"));
+        }
+        let mut response = self.agent.run(request.body(body.to_vec()).unwrap()).unwrap();
+        let digest = response
+            .headers()
+            .get("docker-content-digest")
+            .map(|v| v.to_str().unwrap().to_string());
+        let body = response.body_mut().read_to_vec().unwrap_or_default();
+        (response.status().as_u16(), digest, body)
+    }
+
+    fn as_user(&self, token: Option<&'static str>) -> Client {
+        Client {
+            agent: self.agent.clone(),
+            base: self.base.clone(),
+            token,
+        }
+    }
+}
+
+fn tokens() -> Auth {
+    Auth::default()
+        .with_token("push-token", Access::Push)
+        .with_token("pull-token", Access::Pull)
+}
+
+fn spawn_registry(root: &TempDir, auth: Auth) -> (Client, Arc<dyn ObjectStore>) {
+    let store: Arc<dyn ObjectStore> = Arc::new(CachedStore::new(LocalStore::new(root.path()), 1 << 20));
+    let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+    let base = format!("http://{}/v2/", server.server_addr().to_ip().unwrap());
+    let registry = Arc::new(Registry::new(store.clone()));
+    thread::spawn(move || server::serve(registry, Arc::new(auth), server, 4));
+
+    let agent = Agent::config_builder().http_status_as_error(false).build().new_agent();
+    let client = Client {
+        agent,
+        base,
+        token: Some("push-token"),
+    };
+    (client, store)
+}
+
+fn push_image(client: &Client, repo: &str, tag: &str, layers: &[&[u8]]) -> String {
+    let config = format!("{{\"tag\":\"{tag}\"}}").into_bytes();
+    let mut digests = Vec::new();
+    for blob in std::iter::once(config.as_slice()).chain(layers.iter().copied()) {
+        let digest = digest_of(blob);
+        let (status, _, _) = client.send("PUT", &format!("{repo}/blobs/{digest}"), blob);
+        assert_eq!(status, 201);
+        digests.push(digest);
+    }
+    let manifest = Manifest {
+        config: digests.remove(0),
+        layers: digests,
+    };
+    let (status, digest, _) =
+        client.send("PUT", &format!("{repo}/manifests/{tag}"), &serde_json::to_vec(&manifest).unwrap());
+    assert_eq!(status, 201);
+    digest.unwrap()
+}
+
+#[test]
+fn test_push_pull_and_gc() {
+    let root = TempDir::new().unwrap();
+    let (client, store) = spawn_registry(&root, tokens());
+    assert_eq!(client.send("GET", "", b"").0, 200);
+
+    let v1 = push_image(&client, "team/app", "v1", &[b"base layer", b"app v1"]);
+    let v2 = push_image(&client, "team/app", "v2", &[b"base layer", b"app v2"]);
+
+    // Pull by tag, then every blob the manifest names
+    let (status, digest, body) = client.send("GET", "team/app/manifests/v1", b"");
+    assert_eq!((status, digest.as_deref()), (200, Some(v1.as_str())));
+    let manifest: Manifest = serde_json::from_slice(&body).unwrap();
+    let (status, _, layer) = client.send("GET", &format!("team/app/blobs/{}", manifest.layers[1]), b"");
+    assert_eq!((status, layer.as_slice()), (200, b"app v1".as_slice()));
+    assert_eq!(client.send("HEAD", &format!("team/app/blobs/{}", manifest.layers[0]), b"").0, 200);
+    assert_eq!(client.send("GET", &format!("team/app/manifests/{v2}"), b"").0, 200);
+
+    let (_, _, tags) = client.send("GET", "team/app/tags/list", b"");
+    let tags: serde_json::Value = serde_json::from_slice(&tags).unwrap();
+    assert_eq!(tags["tags"], serde_json::json!(["v1", "v2"]));
+
+    // Untagging v1 frees its manifest, config and its own layer, not the shared base
+    assert_eq!(client.send("DELETE", "team/app/manifests/v1", b"").0, 202);
+    let (status, _, report) = client.send("POST", "_gc", b"");
+    assert_eq!(status, 200);
+    let report: serde_json::Value = serde_json::from_slice(&report).unwrap();
+    assert_eq!(report, serde_json::to_value(GcReport { manifests_deleted: 1, blobs_deleted: 2 }).unwrap());
+    assert_eq!(client.send("GET", &format!("team/app/manifests/{v1}"), b"").0, 404);
+    assert_eq!(client.send("GET", &format!("team/app/blobs/{}", digest_of(b"app v1")), b"").0, 404);
+    assert_eq!(client.send("GET", &format!("team/app/blobs/{}", digest_of(b"base layer")), b"").0, 200);
+    assert_eq!(client.send("GET", "team/app/manifests/v2", b"").0, 200);
+
+    // What's left is exactly the v2 image plus its tag
+    let (keys, _) = store.list("", None).unwrap();
+    assert_eq!(keys.len(), 1 + 1 + 3);
+}
+
+#[test]
+fn test_rejects_bad_pushes() {
+    let root = TempDir::new().unwrap();
+    let (client, _store) = spawn_registry(&root, tokens());
+
+    // Content must match the digest it's pushed under
+    let wrong = digest_of(b"something else");
+    assert_eq!(client.send("PUT", &format!("app/blobs/{wrong}"), b"layer").0, 400);
+    assert_eq!(client.send("PUT", "app/blobs/sha256:nothex", b"layer").0, 400);
+
+    // Manifests may only reference uploaded blobs
+    let manifest = Manifest {
+        config: digest_of(b"config"),
+        layers: vec![],
+    };
+    let body = serde_json::to_vec(&manifest).unwrap();
+    assert_eq!(client.send("PUT", "app/manifests/latest", &body).0, 400);
+    assert_eq!(client.send("GET", "app/manifests/latest", b"").0, 404);
+    assert_eq!(client.send("PUT", "../etc/manifests/latest", &body).0, 400);
+}
+
+#[test]
+fn test_auth() {
+    let root = TempDir::new().unwrap();
+    let (client, _store) = spawn_registry(&root, tokens());
+    push_image(&client, "app", "v1", &[b"layer"]);
+
+    let anonymous = client.as_user(None);
+    assert_eq!(anonymous.send("GET", "app/manifests/v1", b"").0, 401);
+    let bogus = client.as_user(Some("guess"));
+    assert_eq!(bogus.send("GET", "", b"").0, 401);
+
+    let reader = client.as_user(Some("pull-token"));
+    assert_eq!(reader.send("GET", "app/manifests/v1", b"").0, 200);
+    let digest = digest_of(b"new layer");
+    assert_eq!(reader.send("PUT", &format!("app/blobs/{digest}"), b"new layer").0, 403);
+    assert_eq!(reader.send("DELETE", "app/manifests/v1", b"").0, 403);
+    assert_eq!(reader.send("POST", "_gc", b"").0, 403);
+
+    // Public registries let anyone pull, but pushing still needs a token
+    let root = TempDir::new().unwrap();
+    let (client, _store) = spawn_registry(&root, tokens().with_anonymous(Access::Pull));
+    push_image(&client, "app", "v1", &[b"layer"]);
+    let anonymous = client.as_user(None);
+    assert_eq!(anonymous.send("GET", "app/manifests/v1", b"").0, 200);
+    assert_eq!(anonymous.send("DELETE", "app/manifests/v1", b"").0, 403);
+}